@@ -0,0 +1,235 @@
+//! Cross-origin access policy.
+//!
+//! By default no cross-origin access is allowed: no CORS headers are sent
+//! and browser WebSocket upgrades from any page are rejected. Origins must
+//! be allowlisted explicitly.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderName, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
+
+use super::types::ErrorResponse;
+
+/// Origin value that allows every origin.
+pub const ANY_ORIGIN: &str = "*";
+
+/// CORS configuration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CorsConfig {
+    /// Allowed origins (e.g. `https://app.example.com`, or `*` for any).
+    pub allowed_origins: Vec<String>,
+    /// Allowed request methods.
+    pub allowed_methods: Vec<String>,
+    /// Allowed request headers.
+    pub allowed_headers: Vec<String>,
+    /// Allow credentials (cookies, `Authorization`) on cross-origin requests.
+    pub allow_credentials: bool,
+    /// How long browsers may cache preflight responses.
+    pub max_age: Option<Duration>,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            allowed_origins: Vec::new(),
+            allowed_methods: vec!["GET".into(), "POST".into(), "DELETE".into()],
+            allowed_headers: vec!["authorization".into(), "content-type".into()],
+            allow_credentials: false,
+            max_age: None,
+        }
+    }
+}
+
+impl CorsConfig {
+    /// Allow every origin, method and header (development only).
+    pub fn permissive() -> Self {
+        Self {
+            allowed_origins: vec![ANY_ORIGIN.into()],
+            allowed_methods: vec![ANY_ORIGIN.into()],
+            allowed_headers: vec![ANY_ORIGIN.into()],
+            ..Default::default()
+        }
+    }
+
+    /// Allow an origin.
+    pub fn with_origin(mut self, origin: impl Into<String>) -> Self {
+        self.allowed_origins.push(origin.into());
+        self
+    }
+
+    /// Check if any cross-origin access is enabled.
+    pub fn is_enabled(&self) -> bool {
+        !self.allowed_origins.is_empty()
+    }
+
+    /// Check if an `Origin` header value is allowlisted.
+    pub fn allows_origin(&self, origin: &str) -> bool {
+        self.allowed_origins
+            .iter()
+            .any(|allowed| allowed == ANY_ORIGIN || allowed.eq_ignore_ascii_case(origin))
+    }
+
+    /// Check the configuration for values the CORS layer cannot use.
+    pub fn validate(&self) -> Result<(), String> {
+        let wildcard = |values: &[String]| values.iter().any(|v| v == ANY_ORIGIN);
+        if self.allow_credentials
+            && (wildcard(&self.allowed_origins)
+                || wildcard(&self.allowed_methods)
+                || wildcard(&self.allowed_headers))
+        {
+            return Err("credentials cannot be combined with '*'".to_string());
+        }
+
+        for origin in self.allowed_origins.iter().filter(|o| *o != ANY_ORIGIN) {
+            HeaderValue::from_str(origin).map_err(|_| format!("invalid origin '{}'", origin))?;
+        }
+        for method in self.allowed_methods.iter().filter(|m| *m != ANY_ORIGIN) {
+            Method::from_bytes(method.as_bytes())
+                .map_err(|_| format!("invalid method '{}'", method))?;
+        }
+        for name in self.allowed_headers.iter().filter(|h| *h != ANY_ORIGIN) {
+            HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| format!("invalid header '{}'", name))?;
+        }
+
+        Ok(())
+    }
+
+    /// Build the CORS layer, or `None` when cross-origin access is disabled.
+    ///
+    /// Invalid entries are skipped; use [`validate`](Self::validate) to report them.
+    pub fn layer(&self) -> Option<CorsLayer> {
+        if !self.is_enabled() {
+            return None;
+        }
+
+        let mut layer = CorsLayer::new().allow_credentials(self.allow_credentials);
+
+        layer = if self.allowed_origins.iter().any(|o| o == ANY_ORIGIN) {
+            layer.allow_origin(Any)
+        } else {
+            let origins: Vec<HeaderValue> = self
+                .allowed_origins
+                .iter()
+                .filter_map(|o| HeaderValue::from_str(o).ok())
+                .collect();
+            layer.allow_origin(AllowOrigin::list(origins))
+        };
+
+        layer = if self.allowed_methods.iter().any(|m| m == ANY_ORIGIN) {
+            layer.allow_methods(Any)
+        } else {
+            let methods: Vec<Method> = self
+                .allowed_methods
+                .iter()
+                .filter_map(|m| Method::from_bytes(m.as_bytes()).ok())
+                .collect();
+            layer.allow_methods(methods)
+        };
+
+        layer = if self.allowed_headers.iter().any(|h| h == ANY_ORIGIN) {
+            layer.allow_headers(Any)
+        } else {
+            let headers: Vec<HeaderName> = self
+                .allowed_headers
+                .iter()
+                .filter_map(|h| HeaderName::from_bytes(h.as_bytes()).ok())
+                .collect();
+            layer.allow_headers(headers)
+        };
+
+        if let Some(max_age) = self.max_age {
+            layer = layer.max_age(max_age);
+        }
+
+        Some(layer)
+    }
+}
+
+/// Reject WebSocket upgrades from browser origins that are not allowlisted.
+///
+/// Browsers always send `Origin` on WebSocket handshakes and do not apply
+/// CORS to them, so without this check any page could open a shell.
+/// Non-browser clients that send no `Origin` header are unaffected.
+pub async fn ws_origin_middleware(
+    State(cors): State<Arc<CorsConfig>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let is_upgrade = request
+        .headers()
+        .get(header::UPGRADE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.eq_ignore_ascii_case("websocket"));
+
+    if is_upgrade {
+        if let Some(origin) = request.headers().get(header::ORIGIN) {
+            let allowed = origin.to_str().is_ok_and(|o| cors.allows_origin(o));
+            if !allowed {
+                return (
+                    StatusCode::FORBIDDEN,
+                    Json(ErrorResponse::new(
+                        "ORIGIN_NOT_ALLOWED",
+                        "WebSocket connections from this origin are not allowed",
+                    )),
+                )
+                    .into_response();
+            }
+        }
+    }
+
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cors_disabled_by_default() {
+        let config = CorsConfig::default();
+        assert!(!config.is_enabled());
+        assert!(config.layer().is_none());
+        assert!(!config.allows_origin("https://evil.example"));
+    }
+
+    #[test]
+    fn test_cors_allows_listed_origin() {
+        let config = CorsConfig::default().with_origin("https://app.example.com");
+        assert!(config.is_enabled());
+        assert!(config.layer().is_some());
+        assert!(config.allows_origin("https://app.example.com"));
+        assert!(!config.allows_origin("https://evil.example"));
+    }
+
+    #[test]
+    fn test_cors_permissive() {
+        let config = CorsConfig::permissive();
+        assert!(config.allows_origin("http://localhost:8000"));
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_cors_validate() {
+        let mut config = CorsConfig::permissive();
+        config.allow_credentials = true;
+        assert!(config.validate().is_err());
+
+        let config = CorsConfig::default().with_origin("bad\norigin");
+        assert!(config.validate().is_err());
+
+        let config = CorsConfig {
+            allowed_methods: vec!["GE T".into()],
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+    }
+}
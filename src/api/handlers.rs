@@ -1,33 +1,308 @@
 //! REST API handlers.
 
+use std::collections::HashMap;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use axum::{
     extract::{Path, State},
     http::StatusCode,
-    Json,
+    Extension, Json,
 };
 
 use super::types::{
-    CreateSessionRequest, CreateSessionResponse, ErrorResponse, ExecuteCommandRequest,
-    ExecuteCommandResponse, ListSessionsResponse, SessionStatusResponse, SessionSummary,
+    BatchRequest, BatchResponse, BatchStepResponse, BatchSummary, CreateSessionRequest,
+    CreateSessionResponse, DecideApprovalRequest, DecideApprovalResponse, DeleteSessionResponse,
+    ErrorResponse, ExecuteCommandRequest, ExecuteCommandResponse, ExpectRequest, ExpectResponse,
+    ListApprovalsResponse, ListSessionsResponse, SessionStatusResponse, SessionSummary,
+};
+use crate::audit::{AuditEntry, AuditLog};
+use crate::execution::{Command, CommandExecutor, ExecutionResult, DEFAULT_TIMEOUT};
+use crate::output::Redactor;
+use crate::pty::{ResourceLimits, RunAs, Sandbox};
+use crate::security::{
+    ApprovalDecision, ApprovalError, ApprovalQueue, ApprovalTicket, ClientIp, CommandValidator,
+    Identity, PendingApproval, PolicyViolation, ValidationError, WorkdirJail, SCOPE_ADMIN,
+    SCOPE_EXECUTE, SCOPE_READ,
+};
+use crate::session::{
+    CgroupRoot, KilledProcesses, Session, SessionConfig, SessionId, SessionState, SessionStore,
+    Terminal,
 };
-use crate::execution::{Command, CommandExecutor};
-use crate::session::{SessionConfig, SessionId, SessionState, SessionStore};
 
 /// Shared application state.
 #[derive(Clone)]
 pub struct AppState {
     pub store: Arc<SessionStore>,
     pub executor: Arc<CommandExecutor>,
+    pub validator: Arc<CommandValidator>,
+    pub audit: Arc<AuditLog>,
+    pub redactor: Arc<Redactor>,
+    pub approvals: Arc<ApprovalQueue>,
+    pub run_as: Option<RunAs>,
+    pub limits: ResourceLimits,
+    pub cgroups: Option<Arc<CgroupRoot>>,
+    pub raw_attach: bool,
 }
 
 impl AppState {
     pub fn new() -> Self {
         let store = Arc::new(SessionStore::new());
         let executor = Arc::new(CommandExecutor::new(Arc::clone(&store)));
-        Self { store, executor }
+        Self {
+            store,
+            executor,
+            validator: Arc::new(CommandValidator::default()),
+            audit: Arc::new(AuditLog::disabled()),
+            redactor: Arc::new(Redactor::builtin()),
+            approvals: Arc::new(ApprovalQueue::disabled()),
+            run_as: None,
+            limits: ResourceLimits::default(),
+            cgroups: None,
+            raw_attach: false,
+        }
+    }
+
+    /// Validate commands with the given validator.
+    pub fn with_validator(mut self, validator: CommandValidator) -> Self {
+        self.validator = Arc::new(validator);
+        self
+    }
+
+    /// Record executed commands in the given audit log.
+    pub fn with_audit(mut self, audit: AuditLog) -> Self {
+        self.audit = Arc::new(audit);
+        self
+    }
+
+    /// Redact command output with the given redactor.
+    pub fn with_redactor(mut self, redactor: Redactor) -> Self {
+        self.redactor = Arc::new(redactor);
+        self
+    }
+
+    /// Hold commands needing approval in the given queue.
+    pub fn with_approvals(mut self, approvals: ApprovalQueue) -> Self {
+        self.approvals = Arc::new(approvals);
+        self
+    }
+
+    /// Run commands as the given account unless the caller has its own.
+    pub fn with_run_as(mut self, run_as: Option<RunAs>) -> Self {
+        self.run_as = run_as;
+        self
+    }
+
+    /// The account a command runs as: the session's, else the caller's,
+    /// else the server default.
+    pub(crate) fn run_as_for(
+        &self,
+        identity: Option<&Identity>,
+        session: Option<&Session>,
+    ) -> Option<RunAs> {
+        match session {
+            Some(session) => session.config.run_as.clone(),
+            None => identity
+                .and_then(|identity| identity.run_as.clone())
+                .or_else(|| self.run_as.clone()),
+        }
+    }
+
+    /// Limit the resources of every command.
+    pub fn with_limits(mut self, limits: ResourceLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Run each session in its own cgroup under the given root.
+    pub fn with_cgroups(mut self, cgroups: CgroupRoot) -> Self {
+        self.cgroups = Some(Arc::new(cgroups));
+        self
+    }
+
+    /// Allow or forbid attaching raw WebSockets to session terminals.
+    pub fn with_raw_attach(mut self, enabled: bool) -> Self {
+        self.raw_attach = enabled;
+        self
+    }
+
+    /// The resource limits of a command: the session's, which include the
+    /// server's, or the server's, tightened by the requested ones.
+    pub(crate) fn limits_for(
+        &self,
+        session: Option<&Session>,
+        requested: &ResourceLimits,
+    ) -> ResourceLimits {
+        match session {
+            Some(session) => session.config.limits,
+            None => self.limits,
+        }
+        .tighten(requested)
+    }
+
+    /// The sandbox of a command: the session's, else the requested one
+    /// within the caller's, if either is set.
+    pub(crate) fn sandbox_for(
+        &self,
+        identity: Option<&Identity>,
+        session: Option<&Session>,
+        requested: Option<&Sandbox>,
+    ) -> Option<Sandbox> {
+        if let Some(session) = session {
+            return session.config.sandbox.clone();
+        }
+        let required = identity.and_then(|identity| identity.sandbox.as_ref());
+        match (required, requested) {
+            (Some(required), Some(requested)) => Some(required.restrict(requested)),
+            (required, requested) => required.or(requested).cloned(),
+        }
+    }
+
+    /// Probe where a session's shell is after a command ran.
+    ///
+    /// Returns the policy violation if the shell left the session's roots.
+    pub(crate) fn probe_cwd(&self, id: &SessionId) -> Option<PolicyViolation> {
+        self.store.probe_cwd(id).ok().flatten()
+    }
+
+    /// Remove secrets from a result before it is returned.
+    pub(crate) fn redact(&self, result: &mut ExecutionResult) {
+        self.redactor.redact_in_place(&mut result.text_output);
+    }
+
+    /// Check a command against the validator, applying the allowlist of the
+    /// caller's scopes.
+    pub(crate) fn validate(
+        &self,
+        identity: Option<&Identity>,
+        command: &str,
+    ) -> Result<(), ValidationError> {
+        self.validator.validate_command_for(command, identity)
+    }
+
+    /// Check a requested timeout in seconds against the validator's bounds.
+    pub(crate) fn validate_timeout(&self, secs: Option<u64>) -> Result<(), ValidationError> {
+        match secs {
+            Some(secs) => self.validator.validate_timeout(secs).map(drop),
+            None => Ok(()),
+        }
+    }
+
+    /// Check the timeouts of a command request.
+    pub(crate) fn validate_timeouts(
+        &self,
+        req: &ExecuteCommandRequest,
+    ) -> Result<(), ValidationError> {
+        self.validate_timeout(req.timeout_secs)?;
+        self.validate_timeout(req.idle_timeout_secs)
+    }
+
+    /// Check the environment variables a request sets, applying the
+    /// allowlist of the caller's scopes.
+    pub(crate) fn validate_env(
+        &self,
+        identity: Option<&Identity>,
+        env: &HashMap<String, String>,
+    ) -> Result<(), ValidationError> {
+        self.validator
+            .validate_env_for(env.keys().map(String::as_str), identity)
+    }
+
+    /// The jail for the caller: the roots of its key, or the server roots.
+    pub(crate) fn jail_for(
+        &self,
+        identity: Option<&Identity>,
+    ) -> Result<WorkdirJail, ValidationError> {
+        match identity {
+            Some(identity) if !identity.root_dirs.is_empty() => {
+                WorkdirJail::new(&identity.root_dirs)
+            }
+            _ => WorkdirJail::new(self.validator.root_dirs()),
+        }
+    }
+
+    /// Resolve the directory a command runs in.
+    ///
+    /// Commands in a session are confined to the session roots and start in
+    /// the session's working directory; other commands use the caller's
+    /// jail. Returns `None` to run in the server's directory.
+    pub(crate) fn working_dir(
+        &self,
+        identity: Option<&Identity>,
+        session: Option<&Session>,
+        requested: Option<&str>,
+    ) -> Result<Option<PathBuf>, ValidationError> {
+        let jail = match session {
+            Some(session) if !session.config.root_dirs.is_empty() => session.jail()?,
+            _ => self.jail_for(identity)?,
+        };
+        let base = session
+            .and_then(|session| session.context.cwd())
+            .map(PathBuf::as_path);
+
+        match requested {
+            Some(dir) if jail.is_restricted() => jail.resolve(dir, base).map(Some),
+            Some(dir) => {
+                self.validator.validate_working_dir(dir)?;
+                Ok(Some(PathBuf::from(dir)))
+            }
+            None => match base {
+                Some(cwd) if jail.is_restricted() => jail.resolve(".", Some(cwd)).map(Some),
+                Some(cwd) => Ok(Some(cwd.to_path_buf())),
+                None => Ok(jail.default_dir().map(|dir| dir.to_path_buf())),
+            },
+        }
+    }
+
+    /// Park the command of an audit entry for approval.
+    ///
+    /// Returns `None` if approvals are disabled.
+    pub(crate) fn park(&self, entry: &AuditEntry, reason: &str) -> Option<ApprovalTicket> {
+        let mut pending = PendingApproval::new(self.redactor.redact(&entry.command), reason)
+            .with_identity(entry.identity.as_deref())
+            .with_client_ip(entry.client_ip);
+        if let Some(session_id) = entry.session_id {
+            pending = pending.with_session(session_id);
+        }
+
+        let ticket = self.approvals.submit(pending)?;
+        tracing::info!(
+            "Command held for approval {}: {}",
+            ticket.pending().id,
+            ticket.pending().reason
+        );
+        Some(ticket)
+    }
+
+    /// Wait for the decision on a parked command.
+    ///
+    /// Returns the audit entry marked with the approver, or the error for a
+    /// denied or expired command after recording it in the audit log.
+    pub(crate) async fn await_approval(
+        &self,
+        entry: AuditEntry,
+        ticket: ApprovalTicket,
+    ) -> Result<AuditEntry, ErrorResponse> {
+        let id = ticket.pending().id;
+        let error = match ticket.wait().await {
+            ApprovalDecision::Approved { by } => {
+                return Ok(entry.approved(by.unwrap_or_else(|| "anonymous".to_string())));
+            }
+            ApprovalDecision::Denied { by, reason } => {
+                let mut message = format!(
+                    "Command denied by {}",
+                    by.as_deref().unwrap_or("an operator")
+                );
+                if let Some(reason) = reason {
+                    message = format!("{}: {}", message, reason);
+                }
+                ErrorResponse::approval_denied(message)
+            }
+            ApprovalDecision::Expired => ErrorResponse::approval_expired(id),
+        };
+        self.audit.record(entry.blocked(error.message.clone()));
+        Err(error)
     }
 }
 
@@ -37,6 +312,115 @@ impl Default for AppState {
     }
 }
 
+/// Check that the caller was granted a scope.
+///
+/// Requests without an identity (authentication disabled) are always allowed.
+pub(crate) fn require_scope(
+    identity: Option<&Identity>,
+    scope: &str,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    match identity {
+        Some(identity) if !identity.has_scope(scope) => {
+            Err((StatusCode::FORBIDDEN, Json(ErrorResponse::forbidden(scope))))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Check that the caller may access a session.
+pub(crate) fn can_access(identity: Option<&Identity>, session: &Session) -> bool {
+    identity.map_or(true, |identity| identity.can_access(session.owner()))
+}
+
+/// Start an audit entry for a command run by the caller.
+pub(crate) fn audit_entry(
+    identity: Option<&Identity>,
+    client_ip: ClientIp,
+    command: &str,
+) -> AuditEntry {
+    let entry = AuditEntry::new(command).with_client_ip(client_ip.0);
+    match identity {
+        Some(identity) => entry.with_identity(&identity.name),
+        None => entry,
+    }
+}
+
+/// Validate the command and working directory of a REST request, waiting
+/// for approval if the validator asks for it.
+///
+/// Returns the audit entry to record the outcome with and the directory to
+/// run in; rejected commands are recorded here.
+async fn authorize(
+    state: &AppState,
+    identity: Option<&Identity>,
+    session: Option<&Session>,
+    entry: AuditEntry,
+    req: &ExecuteCommandRequest,
+) -> Result<(AuditEntry, Option<PathBuf>), (StatusCode, Json<ErrorResponse>)> {
+    let working_dir = match state.working_dir(identity, session, req.working_dir.as_deref()) {
+        Ok(dir) => dir,
+        Err(e) => return Err(reject(state, entry, e)),
+    };
+    let validated = state
+        .validate_timeouts(req)
+        .and_then(|()| state.validate_env(identity, &req.env))
+        .and_then(|()| state.validate(identity, &req.command));
+    let error = match validated {
+        Ok(()) => return Ok((entry, working_dir)),
+        Err(e) => e,
+    };
+    if let ValidationError::RequiresApproval { ref reason } = error {
+        if let Some(ticket) = state.park(&entry, reason) {
+            return state
+                .await_approval(entry, ticket)
+                .await
+                .map(|entry| (entry, working_dir))
+                .map_err(|e| (StatusCode::FORBIDDEN, Json(e)));
+        }
+    }
+
+    Err(reject(state, entry, error))
+}
+
+/// Record a rejected command and build its error response.
+fn reject(
+    state: &AppState,
+    entry: AuditEntry,
+    error: ValidationError,
+) -> (StatusCode, Json<ErrorResponse>) {
+    state.audit.record(entry.blocked(error.to_string()));
+    (
+        StatusCode::BAD_REQUEST,
+        Json(ErrorResponse::command_blocked(error.to_string())),
+    )
+}
+
+/// Look up a session the caller may access.
+///
+/// Sessions owned by other identities are reported as not found.
+fn find_session(
+    state: &AppState,
+    identity: Option<&Identity>,
+    session_id: u64,
+) -> Result<Session, (StatusCode, Json<ErrorResponse>)> {
+    state
+        .store
+        .get(&SessionId::from_raw(session_id))
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::internal_error(e.to_string())),
+            )
+        })?
+        .filter(|session| can_access(identity, session))
+        .ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                Json(ErrorResponse::session_not_found(&session_id.to_string())),
+            )
+        })
+}
+
 /// Health check endpoint.
 pub async fn health() -> &'static str {
     "OK"
@@ -54,7 +438,11 @@ pub async fn api_info() -> Json<serde_json::Value> {
 /// List all sessions.
 pub async fn list_sessions(
     State(state): State<AppState>,
+    identity: Option<Extension<Identity>>,
 ) -> Result<Json<ListSessionsResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let identity = identity.as_deref();
+    require_scope(identity, SCOPE_READ)?;
+
     let ids = state.store.list_ids().map_err(|e| {
         (
             StatusCode::INTERNAL_SERVER_ERROR,
@@ -65,10 +453,18 @@ pub async fn list_sessions(
     let mut sessions = Vec::with_capacity(ids.len());
     for id in ids {
         if let Ok(Some(session)) = state.store.get(&id) {
+            if !can_access(identity, &session) {
+                continue;
+            }
             sessions.push(SessionSummary {
                 session_id: session.id.as_u64(),
                 state: format!("{:?}", session.state),
+                owner: session.owner().map(str::to_string),
                 idle_seconds: session.idle_duration().as_secs_f64(),
+                resources: session
+                    .cgroup
+                    .as_ref()
+                    .and_then(|cgroup| cgroup.usage().ok()),
             });
         }
     }
@@ -82,12 +478,55 @@ pub async fn list_sessions(
 /// Create a new session.
 pub async fn create_session(
     State(state): State<AppState>,
+    identity: Option<Extension<Identity>>,
     Json(req): Json<CreateSessionRequest>,
 ) -> Result<(StatusCode, Json<CreateSessionResponse>), (StatusCode, Json<ErrorResponse>)> {
+    let identity = identity.as_deref();
+    require_scope(identity, SCOPE_EXECUTE)?;
+
+    let bad_request = |e: ValidationError| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse::bad_request(e.to_string())),
+        )
+    };
+    state
+        .validate_env(identity, &req.env)
+        .map_err(bad_request)?;
+    let mut jail = state.jail_for(identity).map_err(bad_request)?;
+    if let Some(root) = &req.root_dir {
+        jail = jail.narrow(root).map_err(bad_request)?;
+    }
+    let working_dir = match &req.working_dir {
+        Some(dir) if jail.is_restricted() => Some(jail.resolve(dir, None).map_err(bad_request)?),
+        Some(dir) => {
+            state
+                .validator
+                .validate_working_dir(dir)
+                .map_err(bad_request)?;
+            Some(PathBuf::from(dir))
+        }
+        None => jail.default_dir().map(|dir| dir.to_path_buf()),
+    };
+    let sandbox = match &req.sandbox {
+        Some(sandbox) => Some(sandbox.canonicalize().map_err(|e| {
+            (
+                StatusCode::BAD_REQUEST,
+                Json(ErrorResponse::bad_request(e.to_string())),
+            )
+        })?),
+        None => None,
+    };
+
     let config = SessionConfig {
         shell: req.shell,
-        working_dir: req.working_dir,
+        working_dir: working_dir.map(|dir| dir.to_string_lossy().into_owned()),
         env: req.env,
+        owner: identity.map(|identity| identity.name.clone()),
+        root_dirs: jail.roots().to_vec(),
+        run_as: state.run_as_for(identity, None),
+        limits: state.limits_for(None, &req.limits),
+        sandbox: state.sandbox_for(identity, None, sandbox.as_ref()),
     };
 
     let session_id = state.store.create(config).map_err(|e| {
@@ -97,10 +536,25 @@ pub async fn create_session(
         )
     })?;
 
+    let cgroup = match &state.cgroups {
+        Some(cgroups) => match cgroups.create_session(session_id) {
+            Ok(cgroup) => Some(cgroup),
+            Err(e) => {
+                state.store.remove(&session_id).ok();
+                return Err((
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(ErrorResponse::internal_error(e.to_string())),
+                ));
+            }
+        },
+        None => None,
+    };
+
     // Transition to Idle state (ready for commands)
     state
         .store
         .update(&session_id, |s| {
+            s.cgroup = cgroup;
             let _ = s.state.transition_to(SessionState::Idle);
         })
         .ok();
@@ -114,25 +568,13 @@ pub async fn create_session(
 /// Get session status.
 pub async fn get_session(
     State(state): State<AppState>,
+    identity: Option<Extension<Identity>>,
     Path(session_id): Path<u64>,
 ) -> Result<Json<SessionStatusResponse>, (StatusCode, Json<ErrorResponse>)> {
-    let id = SessionId::from_raw(session_id);
+    let identity = identity.as_deref();
+    require_scope(identity, SCOPE_READ)?;
 
-    let session = state
-        .store
-        .get(&id)
-        .map_err(|e| {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ErrorResponse::internal_error(e.to_string())),
-            )
-        })?
-        .ok_or_else(|| {
-            (
-                StatusCode::NOT_FOUND,
-                Json(ErrorResponse::session_not_found(&session_id.to_string())),
-            )
-        })?;
+    let session = find_session(&state, identity, session_id)?;
 
     Ok(Json(SessionStatusResponse::from_session(&session)))
 }
@@ -140,9 +582,13 @@ pub async fn get_session(
 /// Delete a session.
 pub async fn delete_session(
     State(state): State<AppState>,
+    identity: Option<Extension<Identity>>,
     Path(session_id): Path<u64>,
-) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
-    let id = SessionId::from_raw(session_id);
+) -> Result<Json<DeleteSessionResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let identity = identity.as_deref();
+    require_scope(identity, SCOPE_EXECUTE)?;
+
+    let id = find_session(&state, identity, session_id)?.id;
 
     // First mark as terminated
     state
@@ -158,71 +604,352 @@ pub async fn delete_session(
         })?;
 
     // Then remove from store
-    state.store.remove(&id).map_err(|e| {
+    let removed = state.store.remove(&id).map_err(|e| {
         (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(ErrorResponse::internal_error(e.to_string())),
         )
     })?;
 
-    Ok(StatusCode::NO_CONTENT)
+    // Kill whatever the session's commands left running
+    let killed = match removed {
+        Some(session) => tokio::task::spawn_blocking(move || session.kill_processes())
+            .await
+            .map_err(|e| {
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(ErrorResponse::internal_error(e.to_string())),
+                )
+            })?,
+        None => KilledProcesses::default(),
+    };
+    if !killed.leftover_pids.is_empty() {
+        tracing::warn!(
+            "Processes {:?} of session {} survived being killed",
+            killed.leftover_pids,
+            id
+        );
+    }
+
+    Ok(Json(DeleteSessionResponse {
+        session_id,
+        killed_pids: killed.killed_pids,
+        leftover_pids: killed.leftover_pids,
+    }))
 }
 
 /// Execute a command in a session.
 pub async fn execute_command(
     State(state): State<AppState>,
+    identity: Option<Extension<Identity>>,
+    client_ip: ClientIp,
     Path(session_id): Path<u64>,
     Json(req): Json<ExecuteCommandRequest>,
 ) -> Result<Json<ExecuteCommandResponse>, (StatusCode, Json<ErrorResponse>)> {
-    let id = SessionId::from_raw(session_id);
+    let identity = identity.as_deref();
+    require_scope(identity, SCOPE_EXECUTE)?;
 
     // Verify session exists and is in valid state
-    let session = state
-        .store
-        .get(&id)
-        .map_err(|e| {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ErrorResponse::internal_error(e.to_string())),
-            )
-        })?
-        .ok_or_else(|| {
-            (
-                StatusCode::NOT_FOUND,
-                Json(ErrorResponse::session_not_found(&session_id.to_string())),
-            )
-        })?;
+    let session = executable_session(&state, identity, session_id)?;
+    let result = run_in_session(&state, identity, client_ip, &session, &req, None).await?;
+
+    Ok(Json(ExecuteCommandResponse::from_result(&result)))
+}
+
+/// Execute several commands in a session, one after another.
+pub async fn execute_batch(
+    State(state): State<AppState>,
+    identity: Option<Extension<Identity>>,
+    client_ip: ClientIp,
+    Path(session_id): Path<u64>,
+    Json(req): Json<BatchRequest>,
+) -> Result<Json<BatchResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let identity = identity.as_deref();
+    require_scope(identity, SCOPE_EXECUTE)?;
+
+    let session = executable_session(&state, identity, session_id)?;
+    state.validate_timeout(req.timeout_secs).map_err(|e| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse::bad_request(e.to_string())),
+        )
+    })?;
+
+    let mut batch = Batch::new(&req);
+    while let Some((_, step, budget)) = batch.next_step() {
+        let step = run_in_session(&state, identity, client_ip, &session, step, budget)
+            .await
+            .map(|result| ExecuteCommandResponse::from_result(&result))
+            .map_err(|(_, Json(error))| error);
+        batch.record(step);
+    }
+
+    Ok(Json(batch.finish()))
+}
+
+/// Wait for output on a session's terminal, optionally writing input
+/// before and after.
+pub async fn expect_output(
+    State(state): State<AppState>,
+    identity: Option<Extension<Identity>>,
+    client_ip: ClientIp,
+    Path(session_id): Path<u64>,
+    Json(req): Json<ExpectRequest>,
+) -> Result<Json<ExpectResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let identity = identity.as_deref();
+    require_scope(identity, SCOPE_EXECUTE)?;
+
+    let session = find_session(&state, identity, session_id)?;
+    if session.state.is_terminal() {
+        return Err((
+            StatusCode::CONFLICT,
+            Json(ErrorResponse::invalid_state(session.state)),
+        ));
+    }
+    let pattern = req.regex().map_err(|e| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse::bad_request(e.to_string())),
+        )
+    })?;
+    state.validate_timeout(req.timeout_secs).map_err(|e| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse::bad_request(e.to_string())),
+        )
+    })?;
+    let terminal = state.store.terminal(&session.id).map_err(internal_error)?;
+
+    if let Some(input) = &req.input {
+        write_input(&state, identity, client_ip, &session, &terminal, input).await?;
+    }
+    let timeout = req.timeout().unwrap_or(DEFAULT_TIMEOUT);
+    let mut expectation = terminal
+        .expect(&pattern, timeout)
+        .await
+        .map_err(internal_error)?;
+    if let (Some(send), Some(_)) = (&req.send, &expectation.matched) {
+        write_input(&state, identity, client_ip, &session, &terminal, send).await?;
+    }
+    state.store.update(&session.id, |s| s.touch()).ok();
+    if let Some(violation) = state.probe_cwd(&session.id) {
+        let input = req.input.as_deref().unwrap_or_default();
+        let entry = audit_entry(identity, client_ip, input).with_session(session.id.as_u64());
+        state.audit.record(entry.violation(violation.to_string()));
+    }
+
+    // Remove secrets from everything the terminal showed
+    let redact = |text: &mut String| state.redactor.redact_in_place(text);
+    redact(&mut expectation.output);
+    expectation.matched.iter_mut().for_each(redact);
+    expectation.groups.iter_mut().flatten().for_each(redact);
+    expectation.named.values_mut().for_each(redact);
+    let mut screen = terminal.screen().map_err(internal_error)?;
+    redact(&mut screen);
+
+    Ok(Json(ExpectResponse::new(expectation, screen)))
+}
+
+/// Write input to a session's terminal.
+///
+/// The shell may read the input as a command line, so it is validated and
+/// audited like one. Blank input, such as a lone newline, is let through.
+async fn write_input(
+    state: &AppState,
+    identity: Option<&Identity>,
+    client_ip: ClientIp,
+    session: &Session,
+    terminal: &Terminal,
+    input: &str,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    if !input.trim().is_empty() {
+        let entry = audit_entry(identity, client_ip, input).with_session(session.id.as_u64());
+        let req = ExecuteCommandRequest {
+            command: input.to_string(),
+            ..Default::default()
+        };
+        let (entry, _) = authorize(state, identity, Some(session), entry, &req).await?;
+        state.audit.record(entry);
+    }
+    terminal
+        .write(input.as_bytes())
+        .await
+        .map_err(internal_error)
+}
+
+/// Build the response for an unexpected failure.
+fn internal_error(error: impl std::fmt::Display) -> (StatusCode, Json<ErrorResponse>) {
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(ErrorResponse::internal_error(error.to_string())),
+    )
+}
 
+/// Progress of a batch of commands run one after another.
+pub(crate) struct Batch<'a> {
+    req: &'a BatchRequest,
+    start: Instant,
+    deadline: Option<Instant>,
+    steps: Vec<BatchStepResponse>,
+    failed_step: Option<usize>,
+    timed_out: bool,
+    stopped: bool,
+}
+
+impl<'a> Batch<'a> {
+    pub(crate) fn new(req: &'a BatchRequest) -> Self {
+        let start = Instant::now();
+        Self {
+            req,
+            start,
+            // A deadline too far out to represent is no deadline
+            deadline: req.timeout().and_then(|timeout| start.checked_add(timeout)),
+            steps: Vec::new(),
+            failed_step: None,
+            timed_out: false,
+            stopped: false,
+        }
+    }
+
+    /// The next command to run, with its index and the time left for it.
+    pub(crate) fn next_step(
+        &mut self,
+    ) -> Option<(usize, &'a ExecuteCommandRequest, Option<Duration>)> {
+        let index = self.steps.len();
+        let step = self.req.commands.get(index).filter(|_| !self.stopped)?;
+        let budget = match self.deadline {
+            Some(deadline) => {
+                let left = deadline.saturating_duration_since(Instant::now());
+                if left.is_zero() {
+                    self.timed_out = true;
+                    self.stopped = true;
+                    return None;
+                }
+                Some(left)
+            }
+            None => None,
+        };
+        Some((index, step, budget))
+    }
+
+    /// Record the outcome of the command last returned by `next_step`.
+    pub(crate) fn record(&mut self, result: Result<ExecuteCommandResponse, ErrorResponse>) {
+        let index = self.steps.len();
+        if result.as_ref().map_or(true, |response| !response.success) {
+            self.failed_step.get_or_insert(index);
+            self.stopped |= self.req.stop_on_error;
+        }
+        // A command cut short by the batch's deadline ends the batch
+        if let (Ok(response), Some(deadline)) = (&result, self.deadline) {
+            if response.timed_out && Instant::now() >= deadline {
+                self.timed_out = true;
+                self.stopped = true;
+            }
+        }
+
+        let (result, error) = match result {
+            Ok(response) => (Some(response), None),
+            Err(error) => (None, Some(error)),
+        };
+        self.steps.push(BatchStepResponse {
+            index,
+            result,
+            error,
+        });
+    }
+
+    /// The outcome of the batch so far.
+    pub(crate) fn summary(&self) -> BatchSummary {
+        BatchSummary {
+            success: self.failed_step.is_none() && !self.timed_out,
+            executed: self.steps.len(),
+            skipped: self.req.commands.len() - self.steps.len(),
+            failed_step: self.failed_step,
+            timed_out: self.timed_out,
+            duration_ms: self.start.elapsed().as_millis() as u64,
+        }
+    }
+
+    pub(crate) fn finish(self) -> BatchResponse {
+        BatchResponse {
+            summary: self.summary(),
+            steps: self.steps,
+        }
+    }
+}
+
+/// Look up a session the caller may run commands in.
+fn executable_session(
+    state: &AppState,
+    identity: Option<&Identity>,
+    session_id: u64,
+) -> Result<Session, (StatusCode, Json<ErrorResponse>)> {
+    let session = find_session(state, identity, session_id)?;
     if !session.state.can_execute() {
         return Err((
             StatusCode::CONFLICT,
             Json(ErrorResponse::invalid_state(session.state)),
         ));
     }
+    Ok(session)
+}
+
+/// Authorize and run a request in a session, recording it in the audit log
+/// and the session's history.
+///
+/// The command's timeout is capped at `budget` if given. The returned
+/// result is already redacted.
+async fn run_in_session(
+    state: &AppState,
+    identity: Option<&Identity>,
+    client_ip: ClientIp,
+    session: &Session,
+    req: &ExecuteCommandRequest,
+    budget: Option<Duration>,
+) -> Result<ExecutionResult, (StatusCode, Json<ErrorResponse>)> {
+    let id = session.id;
+    let entry = audit_entry(identity, client_ip, &req.command)
+        .with_session(id.as_u64())
+        .with_working_dir(req.working_dir.as_deref());
+    let (entry, working_dir) = authorize(state, identity, Some(session), entry, req).await?;
 
     // Build command
-    let mut cmd = Command::new(&req.command);
-    if let Some(dir) = &req.working_dir {
-        cmd = cmd.working_dir(PathBuf::from(dir));
+    let mut cmd = Command::new(&req.command)
+        .run_as(session.config.run_as.clone())
+        .limits(state.limits_for(Some(session), &req.limits))
+        .sandbox(session.config.sandbox.clone())
+        .cgroup(session.cgroup.as_ref().map(|c| c.path().to_path_buf()));
+    if let Some(dir) = working_dir {
+        cmd = cmd.working_dir(dir);
     }
-    if let Some(timeout) = req.timeout() {
+    if let Some(timeout) = req.timeout_within(budget) {
         cmd = cmd.timeout(timeout);
     }
+    if let Some(idle) = req.idle_timeout() {
+        cmd = cmd.idle_timeout(idle);
+    }
+    cmd = cmd.max_output(state.validator.max_output_size());
     for (key, value) in &req.env {
         cmd = cmd.env(key, value);
     }
 
     // Execute
-    let result = state
-        .executor
-        .execute_in_session(&id, &cmd)
-        .await
-        .map_err(|e| {
-            (
+    let mut result = match state.executor.execute_in_session(&id, &cmd).await {
+        Ok(result) => result,
+        Err(e) => {
+            state.audit.record(entry.failed(e.to_string()));
+            return Err((
                 StatusCode::INTERNAL_SERVER_ERROR,
                 Json(ErrorResponse::internal_error(e.to_string())),
-            )
-        })?;
+            ));
+        }
+    };
+    let mut entry = entry.with_result(&result);
+    if let Some(violation) = state.probe_cwd(&id) {
+        entry = entry.violation(violation.to_string());
+    }
+    state.audit.record(entry);
+    state.redact(&mut result);
 
     // Update session context
     state
@@ -232,40 +959,117 @@ pub async fn execute_command(
         })
         .ok();
 
-    Ok(Json(ExecuteCommandResponse::from_result(&result)))
+    Ok(result)
 }
 
 /// Execute a command without session (one-shot).
 pub async fn execute_oneshot(
     State(state): State<AppState>,
+    identity: Option<Extension<Identity>>,
+    client_ip: ClientIp,
     Json(req): Json<ExecuteCommandRequest>,
 ) -> Result<Json<ExecuteCommandResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let identity = identity.as_deref();
+    require_scope(identity, SCOPE_EXECUTE)?;
+
+    let entry =
+        audit_entry(identity, client_ip, &req.command).with_working_dir(req.working_dir.as_deref());
+    let (entry, working_dir) = authorize(&state, identity, None, entry, &req).await?;
+
     // Build command
-    let mut cmd = Command::new(&req.command);
-    if let Some(dir) = &req.working_dir {
-        cmd = cmd.working_dir(PathBuf::from(dir));
+    let mut cmd = Command::new(&req.command)
+        .run_as(state.run_as_for(identity, None))
+        .limits(state.limits_for(None, &req.limits))
+        .sandbox(state.sandbox_for(identity, None, None));
+    if let Some(dir) = working_dir {
+        cmd = cmd.working_dir(dir);
     }
     if let Some(timeout) = req.timeout() {
         cmd = cmd.timeout(timeout);
     }
+    if let Some(idle) = req.idle_timeout() {
+        cmd = cmd.idle_timeout(idle);
+    }
+    cmd = cmd.max_output(state.validator.max_output_size());
     for (key, value) in &req.env {
         cmd = cmd.env(key, value);
     }
 
     // Execute directly without session
-    let result = state.executor.execute_sync(&cmd).map_err(|e| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ErrorResponse::internal_error(e.to_string())),
-        )
-    })?;
+    let mut result = match state.executor.execute(&cmd).await {
+        Ok(result) => result,
+        Err(e) => {
+            state.audit.record(entry.failed(e.to_string()));
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::internal_error(e.to_string())),
+            ));
+        }
+    };
+    state.audit.record(entry.with_result(&result));
+    state.redact(&mut result);
 
     Ok(Json(ExecuteCommandResponse::from_result(&result)))
 }
 
+/// List commands waiting for approval.
+pub async fn list_approvals(
+    State(state): State<AppState>,
+    identity: Option<Extension<Identity>>,
+) -> Result<Json<ListApprovalsResponse>, (StatusCode, Json<ErrorResponse>)> {
+    require_scope(identity.as_deref(), SCOPE_ADMIN)?;
+
+    let approvals = state.approvals.list();
+    Ok(Json(ListApprovalsResponse {
+        count: approvals.len(),
+        approvals,
+    }))
+}
+
+/// Approve or deny a command waiting for approval.
+pub async fn decide_approval(
+    State(state): State<AppState>,
+    identity: Option<Extension<Identity>>,
+    Path(approval_id): Path<u64>,
+    Json(req): Json<DecideApprovalRequest>,
+) -> Result<Json<DecideApprovalResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let identity = identity.as_deref();
+    require_scope(identity, SCOPE_ADMIN)?;
+
+    let by = identity.map(|identity| identity.name.as_str());
+    let result = if req.approve {
+        state.approvals.approve(approval_id, by)
+    } else {
+        state.approvals.deny(approval_id, by, req.reason)
+    };
+    match result {
+        Ok(approval) => {
+            tracing::info!(
+                "Approval {} {} by {}",
+                approval_id,
+                if req.approve { "granted" } else { "denied" },
+                by.unwrap_or("anonymous")
+            );
+            Ok(Json(DecideApprovalResponse {
+                approved: req.approve,
+                approval,
+            }))
+        }
+        Err(ApprovalError::NotFound(id)) => Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse::approval_not_found(id)),
+        )),
+        Err(e @ ApprovalError::SelfApproval) => Err((
+            StatusCode::FORBIDDEN,
+            Json(ErrorResponse::new("SELF_APPROVAL", e.to_string())),
+        )),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::security::ValidationConfig;
 
     #[test]
     fn test_app_state_new() {
@@ -273,6 +1077,400 @@ mod tests {
         assert_eq!(state.store.count(), 0);
     }
 
+    #[test]
+    fn test_require_scope() {
+        assert!(require_scope(None, SCOPE_EXECUTE).is_ok());
+
+        let reader = Identity::new("reader", [SCOPE_READ]);
+        assert!(require_scope(Some(&reader), SCOPE_READ).is_ok());
+        let (status, _) = require_scope(Some(&reader), SCOPE_EXECUTE).unwrap_err();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[test]
+    fn test_find_session_hides_other_owners() {
+        let state = AppState::new();
+        let id = state
+            .store
+            .create(SessionConfig {
+                owner: Some("alice".to_string()),
+                ..Default::default()
+            })
+            .unwrap();
+
+        let alice = Identity::new("alice", [SCOPE_READ]);
+        let bob = Identity::new("bob", [SCOPE_READ]);
+        assert!(find_session(&state, Some(&alice), id.as_u64()).is_ok());
+        assert!(find_session(&state, None, id.as_u64()).is_ok());
+
+        let (status, _) = find_session(&state, Some(&bob), id.as_u64()).unwrap_err();
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn test_validate() {
+        let state = AppState::new();
+        assert!(state.validate(None, "echo hello").is_ok());
+        assert!(state.validate(None, "shutdown -h now").is_err());
+
+        let state = AppState::new().with_validator(CommandValidator::new(
+            ValidationConfig::default().with_scope_allowlist("ci", ["cargo test"]),
+        ));
+        let ci = Identity::new("ci", [SCOPE_EXECUTE, "ci"]);
+        assert!(state.validate(Some(&ci), "cargo test").is_ok());
+        assert!(matches!(
+            state.validate(Some(&ci), "echo hello"),
+            Err(ValidationError::NotAllowed { .. })
+        ));
+        assert!(state.validate(None, "echo hello").is_ok());
+    }
+
+    #[test]
+    fn test_validate_timeouts() {
+        let state = AppState::new();
+        let mut req = ExecuteCommandRequest {
+            command: "sleep 1".to_string(),
+            timeout_secs: Some(30),
+            ..Default::default()
+        };
+        assert!(state.validate_timeouts(&req).is_ok());
+
+        req.idle_timeout_secs = Some(u64::MAX);
+        assert!(matches!(
+            state.validate_timeouts(&req),
+            Err(ValidationError::TimeoutTooLong { .. })
+        ));
+        req.idle_timeout_secs = None;
+        req.timeout_secs = Some(0);
+        assert!(matches!(
+            state.validate_timeouts(&req),
+            Err(ValidationError::TimeoutTooShort { .. })
+        ));
+    }
+
+    #[test]
+    fn test_working_dir() {
+        let state = AppState::new();
+        assert_eq!(
+            state.working_dir(None, None, Some("/tmp")),
+            Ok(Some(PathBuf::from("/tmp")))
+        );
+        assert_eq!(state.working_dir(None, None, None), Ok(None));
+        assert_eq!(
+            state.working_dir(None, None, Some("../etc")),
+            Err(ValidationError::PathTraversal)
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("project")).unwrap();
+        let root = std::fs::canonicalize(dir.path()).unwrap();
+        let agent = Identity::new("agent", [SCOPE_EXECUTE]).with_root_dirs([dir.path()]);
+        assert_eq!(
+            state.working_dir(Some(&agent), None, None),
+            Ok(Some(root.clone()))
+        );
+        assert_eq!(
+            state.working_dir(Some(&agent), None, Some("project")),
+            Ok(Some(root.join("project")))
+        );
+        assert!(matches!(
+            state.working_dir(Some(&agent), None, Some("/etc")),
+            Err(ValidationError::OutsideRoot { .. })
+        ));
+
+        let id = state
+            .store
+            .create(SessionConfig {
+                working_dir: Some(root.join("project").display().to_string()),
+                root_dirs: vec![root.join("project")],
+                ..Default::default()
+            })
+            .unwrap();
+        let session = state.store.get(&id).unwrap().unwrap();
+        assert_eq!(
+            state.working_dir(Some(&agent), Some(&session), None),
+            Ok(Some(root.join("project")))
+        );
+        assert!(matches!(
+            state.working_dir(Some(&agent), Some(&session), Some("..")),
+            Err(ValidationError::OutsideRoot { .. })
+        ));
+    }
+
+    #[test]
+    fn test_run_as_for() {
+        let state = AppState::new().with_run_as(Some(RunAs::user("agents")));
+        let ci = Identity::new("ci", [SCOPE_EXECUTE]).with_run_as(Some(RunAs::user("ci")));
+        let other = Identity::new("other", [SCOPE_EXECUTE]);
+        assert_eq!(state.run_as_for(Some(&ci), None), Some(RunAs::user("ci")));
+        assert_eq!(
+            state.run_as_for(Some(&other), None),
+            Some(RunAs::user("agents"))
+        );
+        assert_eq!(state.run_as_for(None, None), Some(RunAs::user("agents")));
+
+        let id = state
+            .store
+            .create(SessionConfig {
+                run_as: Some(RunAs::user("ci")),
+                ..Default::default()
+            })
+            .unwrap();
+        let session = state.store.get(&id).unwrap().unwrap();
+        assert_eq!(
+            state.run_as_for(Some(&other), Some(&session)),
+            Some(RunAs::user("ci"))
+        );
+    }
+
+    #[test]
+    fn test_limits_for() {
+        let server = ResourceLimits {
+            cpu_secs: Some(60),
+            ..Default::default()
+        };
+        let state = AppState::new().with_limits(server);
+        let looser = ResourceLimits {
+            cpu_secs: Some(600),
+            open_files: Some(64),
+            ..Default::default()
+        };
+        let limits = state.limits_for(None, &looser);
+        assert_eq!(limits.cpu_secs, Some(60));
+        assert_eq!(limits.open_files, Some(64));
+
+        let id = state
+            .store
+            .create(SessionConfig {
+                limits,
+                ..Default::default()
+            })
+            .unwrap();
+        let session = state.store.get(&id).unwrap().unwrap();
+        let tighter = ResourceLimits {
+            open_files: Some(16),
+            ..Default::default()
+        };
+        let limits = state.limits_for(Some(&session), &tighter);
+        assert_eq!(limits.cpu_secs, Some(60));
+        assert_eq!(limits.open_files, Some(16));
+    }
+
+    #[test]
+    fn test_sandbox_for() {
+        let work = tempfile::tempdir().unwrap();
+        let state = AppState::new();
+        let agent = Identity::new("agent", [SCOPE_EXECUTE])
+            .with_sandbox(Some(Sandbox::new().with_writable_dir(work.path())));
+        let other = Identity::new("other", [SCOPE_EXECUTE]);
+        let isolated = Sandbox::new().with_isolated_network();
+
+        assert_eq!(state.sandbox_for(Some(&other), None, None), None);
+        assert_eq!(
+            state.sandbox_for(Some(&other), None, Some(&isolated)),
+            Some(isolated.clone())
+        );
+        // A key's sandbox cannot be left, only narrowed
+        let sandbox = state.sandbox_for(Some(&agent), None, None).unwrap();
+        assert!(!sandbox.isolate_network);
+        let sandbox = state
+            .sandbox_for(Some(&agent), None, Some(&isolated))
+            .unwrap();
+        assert!(sandbox.isolate_network);
+        assert_eq!(
+            sandbox.writable_dirs,
+            vec![work.path().canonicalize().unwrap()]
+        );
+
+        let id = state
+            .store
+            .create(SessionConfig {
+                sandbox: Some(isolated.clone()),
+                ..Default::default()
+            })
+            .unwrap();
+        let session = state.store.get(&id).unwrap().unwrap();
+        assert_eq!(
+            state.sandbox_for(Some(&agent), Some(&session), None),
+            Some(isolated)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_blocked_command_is_audited() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        let audit = AuditLog::open(&crate::audit::AuditConfig::new(&path)).unwrap();
+        let state = AppState::new().with_audit(audit);
+
+        let identity = Identity::new("agent", [SCOPE_EXECUTE]);
+        let req = ExecuteCommandRequest {
+            command: "reboot".to_string(),
+            working_dir: None,
+            env: Default::default(),
+            timeout_secs: None,
+            idle_timeout_secs: None,
+            limits: Default::default(),
+        };
+        let client_ip = ClientIp(Some([192, 0, 2, 1].into()));
+        let (status, Json(error)) = execute_oneshot(
+            State(state),
+            Some(Extension(identity)),
+            client_ip,
+            Json(req),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(error.code, "COMMAND_BLOCKED");
+
+        let line = std::fs::read_to_string(&path).unwrap();
+        let entry: AuditEntry = serde_json::from_str(line.trim()).unwrap();
+        assert!(entry.blocked);
+        assert_eq!(entry.identity.as_deref(), Some("agent"));
+        assert_eq!(entry.client_ip, client_ip.0);
+        assert_eq!(entry.command, "reboot");
+        assert!(entry.session_id.is_none());
+    }
+
+    fn batch_request(commands: &[&str], stop_on_error: bool) -> BatchRequest {
+        BatchRequest {
+            commands: commands
+                .iter()
+                .map(|command| ExecuteCommandRequest {
+                    command: command.to_string(),
+                    ..Default::default()
+                })
+                .collect(),
+            stop_on_error,
+            timeout_secs: None,
+        }
+    }
+
+    fn step_response(exit_code: i32) -> ExecuteCommandResponse {
+        let result = ExecutionResult::default().with_exit_code(exit_code);
+        ExecuteCommandResponse::from_result(&result)
+    }
+
+    #[test]
+    fn test_batch_stops_on_error() {
+        let req = batch_request(&["true", "false", "true"], true);
+        let mut batch = Batch::new(&req);
+
+        let (index, step, budget) = batch.next_step().unwrap();
+        assert_eq!((index, step.command.as_str(), budget), (0, "true", None));
+        batch.record(Ok(step_response(0)));
+        assert_eq!(batch.next_step().unwrap().0, 1);
+        batch.record(Ok(step_response(1)));
+        assert!(batch.next_step().is_none());
+
+        let response = batch.finish();
+        assert!(!response.summary.success);
+        assert_eq!(response.summary.executed, 2);
+        assert_eq!(response.summary.skipped, 1);
+        assert_eq!(response.summary.failed_step, Some(1));
+        assert_eq!(response.steps[1].index, 1);
+    }
+
+    #[test]
+    fn test_batch_continues_after_error() {
+        let req = batch_request(&["reboot", "false", "true"], false);
+        let mut batch = Batch::new(&req);
+        batch.next_step().unwrap();
+        batch.record(Err(ErrorResponse::command_blocked("blocked")));
+        batch.next_step().unwrap();
+        batch.record(Ok(step_response(1)));
+        batch.next_step().unwrap();
+        batch.record(Ok(step_response(0)));
+        assert!(batch.next_step().is_none());
+
+        let response = batch.finish();
+        assert!(!response.summary.success);
+        assert_eq!(response.summary.executed, 3);
+        assert_eq!(response.summary.skipped, 0);
+        // The first failure is reported
+        assert_eq!(response.summary.failed_step, Some(0));
+        let json = serde_json::to_value(&response).unwrap();
+        assert_eq!(json["steps"][0]["error"]["code"], "COMMAND_BLOCKED");
+        assert!(json["steps"][0].get("success").is_none());
+        assert_eq!(json["steps"][1]["exit_code"], 1);
+    }
+
+    #[test]
+    fn test_batch_budget() {
+        let mut req = batch_request(&["true", "true"], true);
+        req.timeout_secs = Some(0);
+        let mut batch = Batch::new(&req);
+        assert!(batch.next_step().is_none());
+        let summary = batch.summary();
+        assert!(summary.timed_out);
+        assert!(!summary.success);
+        assert_eq!(summary.skipped, 2);
+
+        req.timeout_secs = Some(u64::MAX);
+        let mut batch = Batch::new(&req);
+        let (_, _, budget) = batch.next_step().unwrap();
+        assert!(budget.is_none());
+
+        req.timeout_secs = Some(60);
+        let mut batch = Batch::new(&req);
+        let (_, step, budget) = batch.next_step().unwrap();
+        let timeout = step.timeout_within(budget).unwrap();
+        assert!(timeout <= DEFAULT_TIMEOUT && timeout > Duration::ZERO);
+        let budget = budget.unwrap();
+        assert!(budget <= Duration::from_secs(60) && budget > Duration::from_secs(50));
+    }
+
+    #[tokio::test]
+    #[ignore] // PTY tests need special handling
+    async fn test_execute_batch() {
+        let state = AppState::new();
+        let id = state.store.create(SessionConfig::default()).unwrap();
+        state
+            .store
+            .update(&id, |s| s.state = SessionState::Idle)
+            .unwrap();
+        let client_ip = ClientIp(None);
+
+        let req = batch_request(&["echo one", "exit 3", "echo three"], true);
+        let Json(response) = execute_batch(
+            State(state.clone()),
+            None,
+            client_ip,
+            Path(id.as_u64()),
+            Json(req),
+        )
+        .await
+        .unwrap();
+        assert!(!response.summary.success);
+        assert_eq!(response.summary.failed_step, Some(1));
+        assert_eq!(response.summary.skipped, 1);
+        let first = response.steps[0].result.as_ref().unwrap();
+        assert!(first.output.contains("one"));
+        assert_eq!(
+            response.steps[1].result.as_ref().unwrap().exit_code,
+            Some(3)
+        );
+
+        // The batch's deadline cuts the running command short
+        let mut req = batch_request(&["sleep 5", "echo never"], true);
+        req.timeout_secs = Some(1);
+        let Json(response) = execute_batch(
+            State(state.clone()),
+            None,
+            client_ip,
+            Path(id.as_u64()),
+            Json(req),
+        )
+        .await
+        .unwrap();
+        assert!(response.summary.timed_out);
+        assert_eq!(response.summary.executed, 1);
+        assert_eq!(response.summary.skipped, 1);
+        assert!(response.steps[0].result.as_ref().unwrap().timed_out);
+        assert!(response.summary.duration_ms < 4000);
+    }
+
     #[tokio::test]
     async fn test_health_endpoint() {
         let response = health().await;
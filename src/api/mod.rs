@@ -15,8 +15,14 @@
 //! - `GET /api/v1/sessions/{id}` - Get session status
 //! - `DELETE /api/v1/sessions/{id}` - Delete a session
 //! - `POST /api/v1/sessions/{id}/execute` - Execute command in session
+//! - `POST /api/v1/sessions/{id}/batch` - Execute several commands in session
+//! - `POST /api/v1/sessions/{id}/expect` - Wait for output on the session's terminal
 //! - `WS /api/v1/sessions/{id}/ws` - WebSocket for streaming
 //!
+//! ### Approvals
+//! - `GET /api/v1/approvals` - List commands waiting for approval
+//! - `POST /api/v1/approvals/{id}` - Approve or deny a command
+//!
 //! ### One-shot Execution
 //! - `POST /api/v1/execute` - Execute command without session
 //! - `WS /api/v1/ws` - WebSocket for one-shot streaming
@@ -33,18 +39,26 @@
 //! }
 //! ```
 
+pub mod cors;
 pub mod handlers;
 pub mod router;
+pub mod tls;
 pub mod types;
+pub mod unix;
 pub mod websocket;
 
 // Re-export commonly used types
+pub use cors::CorsConfig;
 pub use handlers::AppState;
 pub use router::{
     create_router, create_router_with_state, create_secure_router, serve, serve_with_state,
     SecurityConfig, ServerConfig,
 };
+pub use tls::{TlsConfig, TlsListener, TlsReloader};
 pub use types::{
-    CreateSessionRequest, CreateSessionResponse, ErrorResponse, ExecuteCommandRequest,
-    ExecuteCommandResponse, ListSessionsResponse, SessionStatusResponse, WsMessage,
+    BatchRequest, BatchResponse, BatchStepResponse, BatchSummary, CreateSessionRequest,
+    CreateSessionResponse, DecideApprovalRequest, DecideApprovalResponse, DeleteSessionResponse,
+    ErrorResponse, ExecuteCommandRequest, ExecuteCommandResponse, ExpectRequest, ExpectResponse,
+    ListApprovalsResponse, ListSessionsResponse, SessionStatusResponse, WsMessage,
 };
+pub use unix::UnixSocketConfig;
@@ -1,27 +1,36 @@
 //! API router configuration.
 
-use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 
 use axum::{
-    extract::connect_info::IntoMakeServiceWithConnectInfo,
+    extract::connect_info::Connected,
     middleware,
     routing::{any, get, post},
+    serve::{IncomingStream, Listener},
     Router,
 };
-use tower_http::{
-    cors::{Any, CorsLayer},
-    trace::TraceLayer,
-};
+use tower_http::trace::TraceLayer;
+
+use super::cors::{ws_origin_middleware, CorsConfig};
 
 use super::handlers::{
-    api_info, create_session, delete_session, execute_command, execute_oneshot, get_session,
-    health, list_sessions, AppState,
+    api_info, create_session, decide_approval, delete_session, execute_batch, execute_command,
+    execute_oneshot, expect_output, get_session, health, list_approvals, list_sessions, AppState,
 };
+use super::tls::{TlsConfig, TlsListener, TlsReloader};
+use super::unix::UnixSocketConfig;
 use super::websocket::{ws_handler, ws_oneshot_handler};
+use crate::audit::{AuditConfig, AuditLog};
+use crate::output::Redactor;
+use crate::pty::{ResourceLimits, RunAs};
 use crate::security::{
-    auth_middleware, rate_limit_middleware, ApiKeyStore, AuthConfig, RateLimitConfig, RateLimiter,
+    auth_failure_limit_middleware, auth_middleware, client_ip_middleware, ip_filter_middleware,
+    rate_limit_middleware, ApiKeyStore, ApprovalQueue, AuthConfig, CommandValidator, Identity,
+    IpFilter, IpRules, PeerInfo, RateLimitConfig, RateLimiter, TrustedProxies, ValidationConfig,
 };
+use crate::session::{CgroupConfig, CgroupRoot};
 
 /// Security configuration for the server.
 #[derive(Debug, Clone)]
@@ -32,6 +41,32 @@ pub struct SecurityConfig {
     pub rate_limit: RateLimitConfig,
     /// API keys to pre-register.
     pub api_keys: Vec<String>,
+    /// API keys to pre-register with their own identity.
+    pub scoped_api_keys: Vec<(String, Identity)>,
+    /// Client certificate identities to pre-register.
+    pub client_identities: Vec<Identity>,
+    /// Cross-origin access policy.
+    pub cors: CorsConfig,
+    /// Proxies whose forwarding headers are trusted.
+    pub trusted_proxies: TrustedProxies,
+    /// Network allowlist and denylist, shared with the reload handler.
+    pub ip_filter: Arc<IpFilter>,
+    /// Command audit log.
+    pub audit: AuditConfig,
+    /// Secret redaction for output and audit entries.
+    pub redaction: Redactor,
+    /// Command validation rules.
+    pub validation: ValidationConfig,
+    /// How long commands wait for approval (approvals disabled if `None`).
+    pub approval_timeout: Option<Duration>,
+    /// Account commands run as unless the caller has its own.
+    pub run_as: Option<RunAs>,
+    /// Resource limits of every command.
+    pub limits: ResourceLimits,
+    /// Per-session cgroups (disabled if `None`).
+    pub cgroup: Option<CgroupConfig>,
+    /// Whether raw WebSockets may attach to session terminals.
+    pub raw_attach: bool,
 }
 
 impl Default for SecurityConfig {
@@ -40,6 +75,19 @@ impl Default for SecurityConfig {
             auth: AuthConfig::disabled(), // Disabled by default for ease of use
             rate_limit: RateLimitConfig::default(),
             api_keys: Vec::new(),
+            scoped_api_keys: Vec::new(),
+            client_identities: Vec::new(),
+            cors: CorsConfig::default(),
+            trusted_proxies: TrustedProxies::default(),
+            ip_filter: Arc::default(),
+            audit: AuditConfig::default(),
+            redaction: Redactor::builtin(),
+            validation: ValidationConfig::default(),
+            approval_timeout: None,
+            run_as: None,
+            limits: ResourceLimits::default(),
+            cgroup: None,
+            raw_attach: false,
         }
     }
 }
@@ -51,6 +99,19 @@ impl SecurityConfig {
             auth: AuthConfig::default(),
             rate_limit: RateLimitConfig::default(),
             api_keys: Vec::new(),
+            scoped_api_keys: Vec::new(),
+            client_identities: Vec::new(),
+            cors: CorsConfig::default(),
+            trusted_proxies: TrustedProxies::default(),
+            ip_filter: Arc::default(),
+            audit: AuditConfig::default(),
+            redaction: Redactor::builtin(),
+            validation: ValidationConfig::default(),
+            approval_timeout: None,
+            run_as: None,
+            limits: ResourceLimits::default(),
+            cgroup: None,
+            raw_attach: false,
         }
     }
 
@@ -60,6 +121,19 @@ impl SecurityConfig {
             auth: AuthConfig::disabled(),
             rate_limit: RateLimitConfig::relaxed(),
             api_keys: Vec::new(),
+            scoped_api_keys: Vec::new(),
+            client_identities: Vec::new(),
+            cors: CorsConfig::default(),
+            trusted_proxies: TrustedProxies::default(),
+            ip_filter: Arc::default(),
+            audit: AuditConfig::default(),
+            redaction: Redactor::builtin(),
+            validation: ValidationConfig::default(),
+            approval_timeout: None,
+            run_as: None,
+            limits: ResourceLimits::default(),
+            cgroup: None,
+            raw_attach: false,
         }
     }
 
@@ -68,6 +142,88 @@ impl SecurityConfig {
         self.api_keys.push(key.into());
         self
     }
+
+    /// Add an API key that authenticates as the given identity.
+    pub fn with_api_key_for(mut self, key: impl Into<String>, identity: Identity) -> Self {
+        self.scoped_api_keys.push((key.into(), identity));
+        self
+    }
+
+    /// Set the cross-origin access policy.
+    pub fn with_cors(mut self, cors: CorsConfig) -> Self {
+        self.cors = cors;
+        self
+    }
+
+    /// Add a client certificate identity.
+    pub fn with_client_identity(mut self, identity: Identity) -> Self {
+        self.client_identities.push(identity);
+        self
+    }
+
+    /// Set the proxies whose forwarding headers are trusted.
+    pub fn with_trusted_proxies(mut self, proxies: TrustedProxies) -> Self {
+        self.trusted_proxies = proxies;
+        self
+    }
+
+    /// Record executed commands in an audit log.
+    pub fn with_audit(mut self, audit: AuditConfig) -> Self {
+        self.audit = audit;
+        self
+    }
+
+    /// Redact secrets with the given redactor.
+    pub fn with_redaction(mut self, redactor: Redactor) -> Self {
+        self.redaction = redactor;
+        self
+    }
+
+    /// Validate commands with the given configuration.
+    pub fn with_validation(mut self, validation: ValidationConfig) -> Self {
+        self.validation = validation;
+        self
+    }
+
+    /// Hold commands needing approval for up to `timeout`.
+    ///
+    /// Commands flagged by ask rules always need approval; set
+    /// [`ValidationConfig::approve_dangerous`] to hold dangerous commands
+    /// too instead of rejecting them.
+    pub fn with_approvals(mut self, timeout: Duration) -> Self {
+        self.approval_timeout = Some(timeout);
+        self
+    }
+
+    /// Run commands as the given account, for callers without their own.
+    pub fn with_run_as(mut self, run_as: RunAs) -> Self {
+        self.run_as = Some(run_as);
+        self
+    }
+
+    /// Limit the resources of every command.
+    pub fn with_limits(mut self, limits: ResourceLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Run each session in its own cgroup.
+    pub fn with_cgroup(mut self, cgroup: CgroupConfig) -> Self {
+        self.cgroup = Some(cgroup);
+        self
+    }
+
+    /// Let clients with the `attach` scope type into session terminals.
+    pub fn with_raw_attach(mut self) -> Self {
+        self.raw_attach = true;
+        self
+    }
+
+    /// Set the network allowlist and denylist.
+    pub fn with_ip_rules(mut self, rules: IpRules) -> Self {
+        self.ip_filter = Arc::new(IpFilter::new(rules));
+        self
+    }
 }
 
 /// Create the API router with all routes configured.
@@ -76,33 +232,11 @@ pub fn create_router() -> Router {
 }
 
 /// Create the API router with custom state (no security).
+///
+/// Cross-origin access is disabled; use [`create_secure_router`] with a
+/// [`CorsConfig`] to allow origins.
 pub fn create_router_with_state(state: AppState) -> Router {
-    // Session routes
-    let session_routes = Router::new()
-        .route("/", get(list_sessions).post(create_session))
-        .route("/{id}", get(get_session).delete(delete_session))
-        .route("/{id}/execute", post(execute_command))
-        .route("/{id}/ws", any(ws_handler));
-
-    // API v1 routes
-    let api_v1 = Router::new()
-        .route("/", get(api_info))
-        .route("/execute", post(execute_oneshot))
-        .route("/ws", any(ws_oneshot_handler))
-        .nest("/sessions", session_routes);
-
-    // Build main router
-    Router::new()
-        .route("/health", get(health))
-        .nest("/api/v1", api_v1)
-        .layer(TraceLayer::new_for_http())
-        .layer(
-            CorsLayer::new()
-                .allow_origin(Any)
-                .allow_methods(Any)
-                .allow_headers(Any),
-        )
-        .with_state(state)
+    with_common_layers(api_routes(), &CorsConfig::default()).with_state(state)
 }
 
 /// Create the API router with security enabled.
@@ -118,43 +252,84 @@ pub fn create_secure_router(
     for key in &security.api_keys {
         auth_store.add_key(key);
     }
+    for (key, identity) in security.scoped_api_keys {
+        auth_store.add_key_for(key, identity);
+    }
+    for identity in security.client_identities {
+        auth_store.add_client_identity(identity);
+    }
+
+    // Build main router with security layers. Authentication runs before
+    // the rate limiter so it can key requests by identity; failed attempts
+    // are limited by client address ahead of authentication.
+    let router = api_routes()
+        .layer(middleware::from_fn_with_state(
+            Arc::clone(&rate_limiter),
+            rate_limit_middleware,
+        ))
+        .layer(middleware::from_fn_with_state(
+            Arc::clone(&auth_store),
+            auth_middleware,
+        ))
+        .layer(middleware::from_fn_with_state(
+            Arc::clone(&rate_limiter),
+            auth_failure_limit_middleware,
+        ))
+        .layer(middleware::from_fn_with_state(
+            security.ip_filter,
+            ip_filter_middleware,
+        ))
+        .layer(middleware::from_fn_with_state(
+            Arc::new(security.trusted_proxies),
+            client_ip_middleware,
+        ));
+    let router = with_common_layers(router, &security.cors).with_state(state);
 
+    (router, auth_store, rate_limiter)
+}
+
+/// Build the health and `/api/v1` routes shared by every router.
+fn api_routes() -> Router<AppState> {
     // Session routes
     let session_routes = Router::new()
         .route("/", get(list_sessions).post(create_session))
         .route("/{id}", get(get_session).delete(delete_session))
         .route("/{id}/execute", post(execute_command))
+        .route("/{id}/batch", post(execute_batch))
+        .route("/{id}/expect", post(expect_output))
         .route("/{id}/ws", any(ws_handler));
 
+    // Approval routes
+    let approval_routes = Router::new()
+        .route("/", get(list_approvals))
+        .route("/{id}", post(decide_approval));
+
     // API v1 routes
     let api_v1 = Router::new()
         .route("/", get(api_info))
         .route("/execute", post(execute_oneshot))
         .route("/ws", any(ws_oneshot_handler))
-        .nest("/sessions", session_routes);
+        .nest("/sessions", session_routes)
+        .nest("/approvals", approval_routes);
 
-    // Build main router with security layers
-    let router = Router::new()
+    Router::new()
         .route("/health", get(health))
         .nest("/api/v1", api_v1)
+}
+
+/// Add the WebSocket origin check, tracing and CORS layers.
+fn with_common_layers(router: Router<AppState>, cors: &CorsConfig) -> Router<AppState> {
+    let router = router
         .layer(middleware::from_fn_with_state(
-            Arc::clone(&auth_store),
-            auth_middleware,
-        ))
-        .layer(middleware::from_fn_with_state(
-            Arc::clone(&rate_limiter),
-            rate_limit_middleware,
+            Arc::new(cors.clone()),
+            ws_origin_middleware,
         ))
-        .layer(TraceLayer::new_for_http())
-        .layer(
-            CorsLayer::new()
-                .allow_origin(Any)
-                .allow_methods(Any)
-                .allow_headers(Any),
-        )
-        .with_state(state);
+        .layer(TraceLayer::new_for_http());
 
-    (router, auth_store, rate_limiter)
+    match cors.layer() {
+        Some(layer) => router.layer(layer),
+        None => router,
+    }
 }
 
 /// Server configuration.
@@ -168,6 +343,12 @@ pub struct ServerConfig {
     pub security: SecurityConfig,
     /// Enable graceful shutdown on SIGTERM/SIGINT.
     pub graceful_shutdown: bool,
+    /// Serve HTTPS/WSS with this certificate (plain HTTP if `None`).
+    pub tls: Option<TlsConfig>,
+    /// Listen on this Unix domain socket instead of `host:port`.
+    pub unix_socket: Option<UnixSocketConfig>,
+    /// Configuration file re-read on SIGHUP to reload the IP filter.
+    pub config_path: Option<PathBuf>,
 }
 
 impl ServerConfig {
@@ -177,6 +358,9 @@ impl ServerConfig {
             port,
             security: SecurityConfig::default(),
             graceful_shutdown: true,
+            tls: None,
+            unix_socket: None,
+            config_path: None,
         }
     }
 
@@ -195,6 +379,24 @@ impl ServerConfig {
         self.graceful_shutdown = false;
         self
     }
+
+    /// Enable TLS with the given certificate and key.
+    pub fn with_tls(mut self, tls: TlsConfig) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Listen on a Unix domain socket instead of TCP.
+    pub fn with_unix_socket(mut self, socket: UnixSocketConfig) -> Self {
+        self.unix_socket = Some(socket);
+        self
+    }
+
+    /// Set the configuration file to reload the IP filter from on SIGHUP.
+    pub fn with_config_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config_path = Some(path.into());
+        self
+    }
 }
 
 impl Default for ServerConfig {
@@ -204,6 +406,9 @@ impl Default for ServerConfig {
             port: 3000,
             security: SecurityConfig::default(),
             graceful_shutdown: true,
+            tls: None,
+            unix_socket: None,
+            config_path: None,
         }
     }
 }
@@ -214,15 +419,79 @@ pub async fn serve(config: ServerConfig) -> crate::Result<()> {
 }
 
 /// Start the API server with custom state.
-pub async fn serve_with_state(config: ServerConfig, state: AppState) -> crate::Result<()> {
+pub async fn serve_with_state(config: ServerConfig, mut state: AppState) -> crate::Result<()> {
     let addr = config.bind_address();
 
+    state = state
+        .with_validator(CommandValidator::new(config.security.validation.clone()))
+        .with_redactor(config.security.redaction.clone())
+        .with_limits(config.security.limits);
+    if !config.security.redaction.is_enabled() {
+        tracing::warn!("Secret redaction is disabled");
+    }
+    if let Some(timeout) = config.security.approval_timeout {
+        state = state.with_approvals(ApprovalQueue::new(timeout));
+        tracing::info!(
+            "Approvals enabled; commands wait up to {}s for a decision",
+            timeout.as_secs()
+        );
+    }
+
+    if config.security.raw_attach {
+        state = state.with_raw_attach(true);
+        tracing::warn!("Raw terminal attach enabled; keystrokes bypass command validation");
+    }
+
+    if let Some(ref cgroup) = config.security.cgroup {
+        let cgroups = CgroupRoot::open(cgroup)?;
+        tracing::info!("Sessions run in cgroups under {}", cgroups.path().display());
+        state = state.with_cgroups(cgroups);
+    }
+
+    if let Some(ref run_as) = config.security.run_as {
+        state = state.with_run_as(Some(run_as.clone()));
+        tracing::info!("Commands run as {}", run_as);
+    }
+
+    if config.security.audit.is_enabled() {
+        let audit = AuditLog::open(&config.security.audit)?
+            .with_redactor(config.security.redaction.clone());
+        state = state.with_audit(audit);
+        match config.security.audit.path {
+            Some(ref path) => tracing::info!("Audit log enabled at {}", path.display()),
+            None => tracing::info!("Audit log enabled (syslog only)"),
+        }
+    }
+
     // Create router with security
     let (router, auth_store, _rate_limiter) = create_secure_router(state, config.security.clone());
 
+    let ip_rules = config.security.ip_filter.rules();
+    if !ip_rules.is_empty() {
+        tracing::info!(
+            "IP filter enabled with {} allowed and {} denied network(s)",
+            ip_rules.allow.len(),
+            ip_rules.deny.len()
+        );
+    }
+    #[cfg(unix)]
+    if let Some(ref path) = config.config_path {
+        spawn_ip_filter_reload_handler(path.clone(), Arc::clone(&config.security.ip_filter));
+    }
+
+    let client_certs = config
+        .tls
+        .as_ref()
+        .is_some_and(|tls| tls.client_ca_path.is_some());
+
     // Log API key if auth is enabled and keys are registered
     if auth_store.is_enabled() {
-        if auth_store.count() == 0 {
+        if client_certs {
+            tracing::info!(
+                "Client certificate authentication enabled with {} identity mapping(s)",
+                auth_store.client_identity_count()
+            );
+        } else if auth_store.count() == 0 {
             // Generate and register a key if none provided
             let key = crate::security::generate_api_key();
             auth_store.add_key(&key);
@@ -234,38 +503,166 @@ pub async fn serve_with_state(config: ServerConfig, state: AppState) -> crate::R
         );
     } else {
         tracing::warn!("Authentication is DISABLED - server is open to all requests");
+        if client_certs {
+            tracing::warn!("Client certificates are verified but ignored while auth is disabled");
+        }
     }
 
-    tracing::info!("Starting shell-tunnel API server on {}", addr);
+    if let Some(socket) = config.unix_socket {
+        if config.tls.is_some() {
+            return Err(crate::error::ShellTunnelError::Tls(
+                "TLS is not supported on Unix sockets".to_string(),
+            ));
+        }
+        return serve_unix(socket, router, config.graceful_shutdown).await;
+    }
 
     let listener = tokio::net::TcpListener::bind(&addr)
         .await
         .map_err(crate::error::ShellTunnelError::Io)?;
 
+    match config.tls {
+        Some(tls) => {
+            let tls = Arc::new(TlsReloader::new(tls)?);
+            #[cfg(unix)]
+            spawn_reload_handler(Arc::clone(&tls));
+
+            tracing::info!("Starting shell-tunnel API server on https://{}", addr);
+            let listener = TlsListener::new(listener, tls)?;
+            run(listener, router, config.graceful_shutdown).await
+        }
+        None => {
+            tracing::info!("Starting shell-tunnel API server on http://{}", addr);
+            run(listener, router, config.graceful_shutdown).await
+        }
+    }
+}
+
+/// Serve the router on a Unix domain socket.
+#[cfg(unix)]
+async fn serve_unix(
+    socket: UnixSocketConfig,
+    router: Router,
+    graceful_shutdown: bool,
+) -> crate::Result<()> {
+    let listener = socket.bind()?;
+    tracing::info!(
+        "Starting shell-tunnel API server on unix:{} (mode {:o})",
+        socket.path.display(),
+        socket.mode
+    );
+
+    let result = run(listener, router, graceful_shutdown).await;
+    let _ = std::fs::remove_file(&socket.path);
+    result
+}
+
+/// Serve the router on a Unix domain socket.
+#[cfg(not(unix))]
+async fn serve_unix(
+    _socket: UnixSocketConfig,
+    _router: Router,
+    _graceful_shutdown: bool,
+) -> crate::Result<()> {
+    Err(crate::error::ShellTunnelError::Io(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "Unix sockets are not supported on this platform",
+    )))
+}
+
+/// Serve the router on an already bound listener.
+async fn run<L>(listener: L, router: Router, graceful_shutdown: bool) -> crate::Result<()>
+where
+    L: Listener,
+    L::Addr: std::fmt::Debug,
+    for<'a> PeerInfo: Connected<IncomingStream<'a, L>>,
+{
     // Create service with connection info for rate limiting
-    let service: IntoMakeServiceWithConnectInfo<Router, SocketAddr> =
-        router.into_make_service_with_connect_info::<SocketAddr>();
+    let service = router.into_make_service_with_connect_info::<PeerInfo>();
 
-    if config.graceful_shutdown {
+    if graceful_shutdown {
         // Serve with graceful shutdown
         axum::serve(listener, service)
             .with_graceful_shutdown(shutdown_signal())
             .await
-            .map_err(|e| {
-                crate::error::ShellTunnelError::Io(std::io::Error::other(e.to_string()))
-            })?;
+            .map_err(crate::error::ShellTunnelError::Io)?;
 
         tracing::info!("Server shutdown complete");
     } else {
         // Serve without graceful shutdown
-        axum::serve(listener, service).await.map_err(|e| {
-            crate::error::ShellTunnelError::Io(std::io::Error::other(e.to_string()))
-        })?;
+        axum::serve(listener, service)
+            .await
+            .map_err(crate::error::ShellTunnelError::Io)?;
     }
 
     Ok(())
 }
 
+/// Reload the TLS certificate whenever the process receives SIGHUP.
+#[cfg(unix)]
+fn spawn_reload_handler(tls: Arc<TlsReloader>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            tracing::warn!(
+                "Failed to install SIGHUP handler, TLS reload disabled: {}",
+                e
+            );
+            return;
+        }
+    };
+
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            match tls.reload() {
+                Ok(()) => tracing::info!(
+                    "Reloaded TLS certificate from {}",
+                    tls.config().cert_path.display()
+                ),
+                Err(e) => tracing::error!("Failed to reload TLS certificate: {}", e),
+            }
+        }
+    });
+}
+
+/// Reload the IP filter from the configuration file whenever the process
+/// receives SIGHUP.
+#[cfg(unix)]
+fn spawn_ip_filter_reload_handler(path: PathBuf, filter: Arc<IpFilter>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            tracing::warn!(
+                "Failed to install SIGHUP handler, IP filter reload disabled: {}",
+                e
+            );
+            return;
+        }
+    };
+
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            let rules = crate::config::Config::from_file(&path).and_then(|c| c.security.ip_rules());
+            match rules {
+                Ok(rules) => {
+                    tracing::info!(
+                        "Reloaded IP filter from {} ({} allowed, {} denied)",
+                        path.display(),
+                        rules.allow.len(),
+                        rules.deny.len()
+                    );
+                    filter.update(rules);
+                }
+                Err(e) => tracing::error!("Failed to reload IP filter: {}", e),
+            }
+        }
+    });
+}
+
 /// Wait for shutdown signal (Ctrl+C or SIGTERM).
 async fn shutdown_signal() {
     let ctrl_c = async {
@@ -306,6 +703,7 @@ mod tests {
         assert_eq!(config.port, 3000);
         assert_eq!(config.bind_address(), "127.0.0.1:3000");
         assert!(config.graceful_shutdown);
+        assert!(config.unix_socket.is_none());
     }
 
     #[test]
@@ -328,6 +726,8 @@ mod tests {
         let config = SecurityConfig::default();
         assert!(!config.auth.enabled); // Disabled by default
         assert!(config.rate_limit.enabled);
+        assert!(!config.cors.is_enabled()); // No cross-origin access by default
+        assert!(!config.raw_attach);
     }
 
     #[test]
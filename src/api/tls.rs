@@ -0,0 +1,329 @@
+//! TLS termination for the API listener.
+//!
+//! Serves HTTPS and WSS directly using rustls. The certificate and key are
+//! held behind a [`TlsReloader`] so they can be swapped at runtime (on
+//! SIGHUP) without dropping established connections.
+
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use axum::extract::connect_info::Connected;
+use axum::serve::{IncomingStream, Listener};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::danger::ClientCertVerifier;
+use rustls::server::WebPkiClientVerifier;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+
+use crate::error::ShellTunnelError;
+use crate::security::{ClientCertificate, PeerInfo};
+use crate::Result;
+
+/// Maximum time a client may take to complete the TLS handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Number of completed handshakes buffered for the server.
+const ACCEPT_QUEUE_SIZE: usize = 64;
+
+/// TLS configuration for the API server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsConfig {
+    /// Path to the PEM-encoded certificate chain.
+    pub cert_path: PathBuf,
+    /// Path to the PEM-encoded private key.
+    pub key_path: PathBuf,
+    /// Path to a PEM bundle of CAs trusted to sign client certificates.
+    ///
+    /// When set, clients may authenticate with a certificate instead of an
+    /// API key. Clients without a certificate are still accepted.
+    pub client_ca_path: Option<PathBuf>,
+}
+
+impl TlsConfig {
+    /// Create a TLS configuration from certificate and key paths.
+    pub fn new(cert_path: impl Into<PathBuf>, key_path: impl Into<PathBuf>) -> Self {
+        Self {
+            cert_path: cert_path.into(),
+            key_path: key_path.into(),
+            client_ca_path: None,
+        }
+    }
+
+    /// Verify client certificates against the CAs in the given PEM bundle.
+    pub fn with_client_ca(mut self, path: impl Into<PathBuf>) -> Self {
+        self.client_ca_path = Some(path.into());
+        self
+    }
+
+    /// Load the certificate chain and private key into a rustls server config.
+    pub fn load(&self) -> Result<rustls::ServerConfig> {
+        let certs = CertificateDer::pem_file_iter(&self.cert_path)
+            .and_then(|iter| iter.collect::<std::result::Result<Vec<_>, _>>())
+            .map_err(|e| {
+                ShellTunnelError::Tls(format!(
+                    "failed to read certificate {}: {}",
+                    self.cert_path.display(),
+                    e
+                ))
+            })?;
+
+        if certs.is_empty() {
+            return Err(ShellTunnelError::Tls(format!(
+                "no certificates found in {}",
+                self.cert_path.display()
+            )));
+        }
+
+        let key = PrivateKeyDer::from_pem_file(&self.key_path).map_err(|e| {
+            ShellTunnelError::Tls(format!(
+                "failed to read private key {}: {}",
+                self.key_path.display(),
+                e
+            ))
+        })?;
+
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let builder = rustls::ServerConfig::builder_with_provider(Arc::clone(&provider))
+            .with_safe_default_protocol_versions()
+            .map_err(|e| ShellTunnelError::Tls(e.to_string()))?;
+
+        let builder = match &self.client_ca_path {
+            Some(path) => builder.with_client_cert_verifier(client_verifier(path, provider)?),
+            None => builder.with_no_client_auth(),
+        };
+
+        let mut config = builder
+            .with_single_cert(certs, key)
+            .map_err(|e| ShellTunnelError::Tls(e.to_string()))?;
+
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+        Ok(config)
+    }
+}
+
+/// Build a verifier that checks optional client certificates against a CA bundle.
+fn client_verifier(
+    path: &Path,
+    provider: Arc<rustls::crypto::CryptoProvider>,
+) -> Result<Arc<dyn ClientCertVerifier>> {
+    let cas = CertificateDer::pem_file_iter(path)
+        .and_then(|iter| iter.collect::<std::result::Result<Vec<_>, _>>())
+        .map_err(|e| {
+            ShellTunnelError::Tls(format!(
+                "failed to read client CA bundle {}: {}",
+                path.display(),
+                e
+            ))
+        })?;
+
+    let mut roots = rustls::RootCertStore::empty();
+    let (added, _) = roots.add_parsable_certificates(cas);
+    if added == 0 {
+        return Err(ShellTunnelError::Tls(format!(
+            "no CA certificates found in {}",
+            path.display()
+        )));
+    }
+
+    // Bearer keys stay usable on the same listener, so certificates are optional.
+    WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+        .allow_unauthenticated()
+        .build()
+        .map_err(|e| ShellTunnelError::Tls(e.to_string()))
+}
+
+/// Holds the active TLS server config and reloads it from disk on demand.
+pub struct TlsReloader {
+    config: TlsConfig,
+    current: RwLock<Arc<rustls::ServerConfig>>,
+}
+
+impl TlsReloader {
+    /// Load the initial certificate and key.
+    pub fn new(config: TlsConfig) -> Result<Self> {
+        let current = Arc::new(config.load()?);
+        Ok(Self {
+            config,
+            current: RwLock::new(current),
+        })
+    }
+
+    /// Re-read the certificate and key from disk.
+    ///
+    /// On failure the previously loaded certificate stays active.
+    pub fn reload(&self) -> Result<()> {
+        let loaded = Arc::new(self.config.load()?);
+        let mut current = self
+            .current
+            .write()
+            .map_err(|_| ShellTunnelError::LockPoisoned)?;
+        *current = loaded;
+        Ok(())
+    }
+
+    /// Get the TLS configuration this reloader reads from.
+    pub fn config(&self) -> &TlsConfig {
+        &self.config
+    }
+
+    /// Get an acceptor for the currently active certificate.
+    pub fn acceptor(&self) -> TlsAcceptor {
+        let current = match self.current.read() {
+            Ok(current) => Arc::clone(&current),
+            Err(poisoned) => Arc::clone(&poisoned.into_inner()),
+        };
+        TlsAcceptor::from(current)
+    }
+}
+
+/// A listener that accepts TCP connections and completes the TLS handshake.
+///
+/// Handshakes run concurrently in background tasks, so a slow or stalled
+/// client cannot hold up other connections.
+pub struct TlsListener {
+    local_addr: SocketAddr,
+    rx: mpsc::Receiver<(TlsStream<TcpStream>, SocketAddr)>,
+}
+
+impl TlsListener {
+    /// Wrap a bound TCP listener with TLS.
+    pub fn new(listener: TcpListener, tls: Arc<TlsReloader>) -> io::Result<Self> {
+        let local_addr = listener.local_addr()?;
+        let (tx, rx) = mpsc::channel(ACCEPT_QUEUE_SIZE);
+
+        tokio::spawn(accept_loop(listener, tls, tx));
+
+        Ok(Self { local_addr, rx })
+    }
+}
+
+impl Listener for TlsListener {
+    type Io = TlsStream<TcpStream>;
+    type Addr = SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        match self.rx.recv().await {
+            Some(conn) => conn,
+            // The accept loop only exits once this listener is dropped.
+            None => std::future::pending().await,
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        Ok(self.local_addr)
+    }
+}
+
+impl Connected<IncomingStream<'_, TlsListener>> for PeerInfo {
+    fn connect_info(stream: IncomingStream<'_, TlsListener>) -> Self {
+        let peer = PeerInfo::new(*stream.remote_addr());
+
+        // Only present when the client CA verifier accepted the chain.
+        let (_, session) = stream.io().get_ref();
+        match session
+            .peer_certificates()
+            .and_then(|certs| certs.first())
+            .and_then(ClientCertificate::from_der)
+        {
+            Some(cert) => peer.with_client_cert(cert),
+            None => peer,
+        }
+    }
+}
+
+/// Accept TCP connections and hand completed TLS streams to the listener.
+async fn accept_loop(
+    listener: TcpListener,
+    tls: Arc<TlsReloader>,
+    tx: mpsc::Sender<(TlsStream<TcpStream>, SocketAddr)>,
+) {
+    loop {
+        let (stream, addr) = tokio::select! {
+            _ = tx.closed() => break,
+            accepted = listener.accept() => match accepted {
+                Ok(conn) => conn,
+                Err(e) => {
+                    tracing::debug!("TCP accept error: {}", e);
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                    continue;
+                }
+            },
+        };
+
+        let acceptor = tls.acceptor();
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                Ok(Ok(stream)) => {
+                    let _ = tx.send((stream, addr)).await;
+                }
+                Ok(Err(e)) => tracing::debug!("TLS handshake with {} failed: {}", addr, e),
+                Err(_) => tracing::debug!("TLS handshake with {} timed out", addr),
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_tls_config_new() {
+        let config = TlsConfig::new("/etc/cert.pem", "/etc/key.pem");
+        assert_eq!(config.cert_path, PathBuf::from("/etc/cert.pem"));
+        assert_eq!(config.key_path, PathBuf::from("/etc/key.pem"));
+        assert!(config.client_ca_path.is_none());
+
+        let config = config.with_client_ca("/etc/clients.pem");
+        assert_eq!(
+            config.client_ca_path,
+            Some(PathBuf::from("/etc/clients.pem"))
+        );
+    }
+
+    #[test]
+    fn test_load_missing_files() {
+        let config = TlsConfig::new("/nonexistent/cert.pem", "/nonexistent/key.pem");
+        let err = config.load().unwrap_err();
+        assert!(matches!(err, ShellTunnelError::Tls(_)));
+        assert!(err.to_string().contains("cert.pem"));
+    }
+
+    #[test]
+    fn test_load_empty_certificate() {
+        let mut cert = NamedTempFile::new().unwrap();
+        cert.write_all(b"not a certificate\n").unwrap();
+
+        let config = TlsConfig::new(cert.path(), "/nonexistent/key.pem");
+        let err = config.load().unwrap_err();
+        assert!(err.to_string().contains("no certificates"));
+    }
+
+    #[test]
+    fn test_load_empty_client_ca() {
+        let fixtures = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/tls");
+        let mut ca = NamedTempFile::new().unwrap();
+        ca.write_all(b"not a certificate\n").unwrap();
+
+        let config = TlsConfig::new(fixtures.join("server.pem"), fixtures.join("server.key"))
+            .with_client_ca(ca.path());
+        let err = config.load().unwrap_err();
+        assert!(err.to_string().contains("no CA certificates"));
+    }
+
+    #[test]
+    fn test_reloader_requires_valid_config() {
+        let config = TlsConfig::new("/nonexistent/cert.pem", "/nonexistent/key.pem");
+        assert!(TlsReloader::new(config).is_err());
+    }
+}
@@ -3,9 +3,13 @@
 use std::collections::HashMap;
 use std::time::Duration;
 
+use regex::Regex;
 use serde::{Deserialize, Serialize};
 
-use crate::session::{SessionId, SessionState};
+use crate::execution::{Termination, DEFAULT_TIMEOUT};
+use crate::pty::{LimitExceeded, ResourceLimits, Sandbox};
+use crate::security::PendingApproval;
+use crate::session::{CgroupUsage, Expectation, SessionId, SessionState};
 
 /// Request to create a new session.
 #[derive(Debug, Clone, Deserialize, Default)]
@@ -16,6 +20,19 @@ pub struct CreateSessionRequest {
     /// Initial working directory.
     #[serde(default)]
     pub working_dir: Option<String>,
+    /// Directory to confine the session's commands to.
+    ///
+    /// Must lie inside the roots of the caller's key, if any.
+    #[serde(default)]
+    pub root_dir: Option<String>,
+    /// Resource limits for the session's commands, on top of the server's.
+    #[serde(default)]
+    pub limits: ResourceLimits,
+    /// Run the session's commands in a sandbox (Linux only).
+    ///
+    /// Applied within the sandbox of the caller's key, if any.
+    #[serde(default)]
+    pub sandbox: Option<Sandbox>,
     /// Environment variables to set.
     #[serde(default)]
     pub env: HashMap<String, String>,
@@ -39,6 +56,17 @@ impl CreateSessionResponse {
     }
 }
 
+/// Response for a deleted session.
+#[derive(Debug, Clone, Serialize)]
+pub struct DeleteSessionResponse {
+    /// The deleted session ID.
+    pub session_id: u64,
+    /// Processes that were killed with the session.
+    pub killed_pids: Vec<u32>,
+    /// Processes that survived being killed.
+    pub leftover_pids: Vec<u32>,
+}
+
 /// Response for session status query.
 #[derive(Debug, Clone, Serialize)]
 pub struct SessionStatusResponse {
@@ -46,9 +74,27 @@ pub struct SessionStatusResponse {
     pub session_id: u64,
     /// Current state.
     pub state: String,
+    /// Identity that owns the session.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
     /// Working directory (if known).
     #[serde(skip_serializing_if = "Option::is_none")]
     pub working_dir: Option<String>,
+    /// Directories the session is confined to.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub root_dirs: Vec<String>,
+    /// Account the session's commands run as.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub run_as: Option<String>,
+    /// Resource limits of the session's commands.
+    #[serde(skip_serializing_if = "ResourceLimits::is_empty")]
+    pub limits: ResourceLimits,
+    /// Sandbox of the session's commands.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sandbox: Option<Sandbox>,
+    /// Live resource usage from the session's cgroup.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resources: Option<CgroupUsage>,
     /// Last exit code (if available).
     #[serde(skip_serializing_if = "Option::is_none")]
     pub last_exit_code: Option<i32>,
@@ -63,10 +109,24 @@ impl SessionStatusResponse {
         Self {
             session_id: session.id.as_u64(),
             state: format!("{:?}", session.state),
+            owner: session.owner().map(str::to_string),
             working_dir: session
                 .context
                 .cwd()
                 .map(|p| p.to_string_lossy().to_string()),
+            root_dirs: session
+                .config
+                .root_dirs
+                .iter()
+                .map(|p| p.to_string_lossy().to_string())
+                .collect(),
+            run_as: session.config.run_as.as_ref().map(ToString::to_string),
+            limits: session.config.limits,
+            sandbox: session.config.sandbox.clone(),
+            resources: session
+                .cgroup
+                .as_ref()
+                .and_then(|cgroup| cgroup.usage().ok()),
             last_exit_code: session.context.last_exit_code(),
             execution_count: session.context.execution_count(),
             idle_seconds: session.idle_duration().as_secs_f64(),
@@ -75,7 +135,7 @@ impl SessionStatusResponse {
 }
 
 /// Request to execute a command.
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ExecuteCommandRequest {
     /// The command line to execute.
     pub command: String,
@@ -88,12 +148,30 @@ pub struct ExecuteCommandRequest {
     /// Timeout in seconds.
     #[serde(default)]
     pub timeout_secs: Option<u64>,
+    /// Seconds the command may go without producing output.
+    #[serde(default)]
+    pub idle_timeout_secs: Option<u64>,
+    /// Resource limits, on top of the session's or the server's.
+    #[serde(default)]
+    pub limits: ResourceLimits,
 }
 
 impl ExecuteCommandRequest {
     pub fn timeout(&self) -> Option<Duration> {
         self.timeout_secs.map(Duration::from_secs)
     }
+
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        self.idle_timeout_secs.map(Duration::from_secs)
+    }
+
+    /// The timeout, capped at `budget` if given.
+    pub fn timeout_within(&self, budget: Option<Duration>) -> Option<Duration> {
+        match budget {
+            Some(budget) => Some(self.timeout().unwrap_or(DEFAULT_TIMEOUT).min(budget)),
+            None => self.timeout(),
+        }
+    }
 }
 
 /// Response for command execution.
@@ -101,9 +179,12 @@ impl ExecuteCommandRequest {
 pub struct ExecuteCommandResponse {
     /// Whether execution was successful.
     pub success: bool,
-    /// Exit code (if process completed).
+    /// Exit code (if process exited on its own).
     #[serde(skip_serializing_if = "Option::is_none")]
     pub exit_code: Option<i32>,
+    /// Why the command ended.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub termination: Option<Termination>,
     /// Cleaned output text.
     pub output: String,
     /// Raw output (base64 encoded if binary content detected).
@@ -113,6 +194,15 @@ pub struct ExecuteCommandResponse {
     pub duration_ms: u64,
     /// Whether the command timed out.
     pub timed_out: bool,
+    /// Whether the command was killed for producing no output.
+    pub idle_timed_out: bool,
+    /// Resource limit that ended the command (`cpu_time`, `file_size` or
+    /// `memory`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit_exceeded: Option<LimitExceeded>,
+    /// Processes of the command that survived being killed.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub leftover_pids: Vec<u32>,
 }
 
 impl ExecuteCommandResponse {
@@ -120,10 +210,14 @@ impl ExecuteCommandResponse {
         Self {
             success: result.exit_code.map(|c| c == 0).unwrap_or(false) && !result.timed_out,
             exit_code: result.exit_code,
+            termination: result.termination.clone(),
             output: result.text_output.clone(),
             raw_output: None, // Only include if requested
             duration_ms: result.duration.as_millis() as u64,
             timed_out: result.timed_out,
+            idle_timed_out: result.idle_timed_out,
+            limit_exceeded: result.limit_exceeded,
+            leftover_pids: result.leftover_pids.clone(),
         }
     }
 
@@ -136,6 +230,144 @@ impl ExecuteCommandResponse {
     }
 }
 
+/// Request to run several commands in a session, one after another.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchRequest {
+    /// The commands to run, in order.
+    pub commands: Vec<ExecuteCommandRequest>,
+    /// Whether to skip the remaining commands once one fails.
+    #[serde(default = "default_stop_on_error")]
+    pub stop_on_error: bool,
+    /// Timeout in seconds for the whole batch.
+    #[serde(default)]
+    pub timeout_secs: Option<u64>,
+}
+
+fn default_stop_on_error() -> bool {
+    true
+}
+
+impl BatchRequest {
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout_secs.map(Duration::from_secs)
+    }
+}
+
+/// Outcome of a batch as a whole.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchSummary {
+    /// Whether every command ran and succeeded.
+    pub success: bool,
+    /// Number of commands that were run.
+    pub executed: usize,
+    /// Number of commands that were skipped.
+    pub skipped: usize,
+    /// Index of the first command that failed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failed_step: Option<usize>,
+    /// Whether the batch ran out of time.
+    pub timed_out: bool,
+    /// Duration of the whole batch in milliseconds.
+    pub duration_ms: u64,
+}
+
+/// Outcome of one command of a batch.
+#[derive(Debug, Clone, Serialize)]
+pub struct BatchStepResponse {
+    /// Position of the command in the request.
+    pub index: usize,
+    /// The command's result, if it ran.
+    #[serde(flatten)]
+    pub result: Option<ExecuteCommandResponse>,
+    /// Why the command could not run.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorResponse>,
+}
+
+/// Response for batch execution.
+#[derive(Debug, Clone, Serialize)]
+pub struct BatchResponse {
+    #[serde(flatten)]
+    pub summary: BatchSummary,
+    /// Outcome of each command that was run.
+    pub steps: Vec<BatchStepResponse>,
+}
+
+/// Request to wait for output on a session's terminal.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ExpectRequest {
+    /// Regular expression to wait for.
+    pub pattern: String,
+    /// Whether `pattern` is literal text rather than a regular expression.
+    #[serde(default)]
+    pub literal: bool,
+    /// Input to write before waiting, such as a command line.
+    #[serde(default)]
+    pub input: Option<String>,
+    /// Input to send once the pattern matched, such as an answer to a
+    /// prompt.
+    #[serde(default)]
+    pub send: Option<String>,
+    /// Seconds to wait for the pattern.
+    #[serde(default)]
+    pub timeout_secs: Option<u64>,
+}
+
+impl ExpectRequest {
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout_secs.map(Duration::from_secs)
+    }
+
+    /// Compile the pattern.
+    pub fn regex(&self) -> Result<Regex, regex::Error> {
+        if self.literal {
+            Regex::new(&regex::escape(&self.pattern))
+        } else {
+            Regex::new(&self.pattern)
+        }
+    }
+}
+
+/// Response for waiting on a session's terminal.
+#[derive(Debug, Clone, Serialize)]
+pub struct ExpectResponse {
+    /// Whether the pattern matched.
+    pub matched: bool,
+    /// Output up to the end of the match, or everything not matched yet if
+    /// the pattern did not match.
+    pub output: String,
+    /// The text the pattern matched.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub matched_text: Option<String>,
+    /// Capture groups in order, `null` for groups that took no part.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<Option<String>>,
+    /// Named capture groups that took part.
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub named_groups: HashMap<String, String>,
+    /// Whether the timeout ran out before the pattern matched.
+    pub timed_out: bool,
+    /// Whether the terminal's shell exited before the pattern matched.
+    pub closed: bool,
+    /// The terminal's screen as it looks now.
+    pub screen: String,
+}
+
+impl ExpectResponse {
+    pub fn new(expectation: Expectation, screen: String) -> Self {
+        Self {
+            matched: expectation.matched.is_some(),
+            output: expectation.output,
+            matched_text: expectation.matched,
+            groups: expectation.groups,
+            named_groups: expectation.named,
+            timed_out: expectation.timed_out,
+            closed: expectation.closed,
+            screen,
+        }
+    }
+}
+
 /// Generic API error response.
 #[derive(Debug, Clone, Serialize)]
 pub struct ErrorResponse {
@@ -183,6 +415,77 @@ impl ErrorResponse {
     pub fn bad_request(message: impl Into<String>) -> Self {
         Self::new("BAD_REQUEST", message)
     }
+
+    pub fn forbidden(scope: &str) -> Self {
+        Self::new("FORBIDDEN", format!("Missing required scope '{}'", scope))
+    }
+
+    pub fn command_blocked(reason: impl Into<String>) -> Self {
+        Self::new("COMMAND_BLOCKED", reason)
+    }
+
+    pub fn approval_denied(message: impl Into<String>) -> Self {
+        Self::new("APPROVAL_DENIED", message)
+    }
+
+    pub fn approval_expired(id: u64) -> Self {
+        Self::new(
+            "APPROVAL_EXPIRED",
+            format!("Approval {} expired without a decision", id),
+        )
+    }
+
+    pub fn approval_not_found(id: u64) -> Self {
+        Self::new("APPROVAL_NOT_FOUND", format!("Approval {} not found", id))
+    }
+}
+
+/// Pending approvals response.
+#[derive(Debug, Clone, Serialize)]
+pub struct ListApprovalsResponse {
+    /// Number of commands waiting for approval.
+    pub count: usize,
+    /// Pending commands, oldest first.
+    pub approvals: Vec<PendingApproval>,
+}
+
+/// Request to approve or deny a pending command.
+#[derive(Debug, Clone, Deserialize)]
+pub struct DecideApprovalRequest {
+    /// Whether the command may run.
+    pub approve: bool,
+    /// Reason given to the requester when denying.
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+/// Response for an approval decision.
+#[derive(Debug, Clone, Serialize)]
+pub struct DecideApprovalResponse {
+    /// Whether the command was approved.
+    pub approved: bool,
+    /// The decided command.
+    pub approval: PendingApproval,
+}
+
+/// Query parameters of a session's WebSocket.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct WsQuery {
+    /// How messages are framed.
+    #[serde(default)]
+    pub mode: WsMode,
+}
+
+/// Framing of a session's WebSocket.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WsMode {
+    /// JSON messages for running commands.
+    #[default]
+    Json,
+    /// Raw bytes of the session's terminal in binary frames, for terminal
+    /// emulators such as xterm.js.
+    Raw,
 }
 
 /// WebSocket message types.
@@ -194,6 +497,8 @@ pub enum WsMessage {
         command: String,
         #[serde(default)]
         timeout_secs: Option<u64>,
+        #[serde(default)]
+        idle_timeout_secs: Option<u64>,
     },
     /// Server sends output chunk.
     Output {
@@ -202,12 +507,42 @@ pub enum WsMessage {
         #[serde(default)]
         is_final: bool,
     },
+    /// Client sends commands to run one after another.
+    Batch(BatchRequest),
     /// Server sends execution result.
     Result {
+        /// Position of the command in a batch.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        index: Option<usize>,
         success: bool,
         exit_code: Option<i32>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        termination: Option<Termination>,
         duration_ms: u64,
         timed_out: bool,
+        #[serde(default)]
+        idle_timed_out: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        limit_exceeded: Option<LimitExceeded>,
+        #[serde(default)]
+        cancelled: bool,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        leftover_pids: Vec<u32>,
+    },
+    /// Client resizes the session's terminal (raw mode).
+    Resize {
+        cols: u16,
+        rows: u16,
+    },
+    /// Server sends the outcome of a batch after its last result.
+    BatchResult(BatchSummary),
+    /// Client cancels the running command.
+    Cancel,
+    /// Server parked the command until an operator decides on it.
+    ApprovalRequired {
+        approval_id: u64,
+        reason: String,
+        expires_at: String,
     },
     /// Error message.
     Error {
@@ -233,7 +568,11 @@ pub struct ListSessionsResponse {
 pub struct SessionSummary {
     pub session_id: u64,
     pub state: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
     pub idle_seconds: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resources: Option<CgroupUsage>,
 }
 
 #[cfg(test)]
@@ -245,6 +584,7 @@ mod tests {
         let req: CreateSessionRequest = serde_json::from_str("{}").unwrap();
         assert!(req.shell.is_none());
         assert!(req.working_dir.is_none());
+        assert!(req.root_dir.is_none());
         assert!(req.env.is_empty());
     }
 
@@ -262,6 +602,11 @@ mod tests {
         let req: ExecuteCommandRequest = serde_json::from_str(json).unwrap();
         assert_eq!(req.command, "echo hello");
         assert_eq!(req.timeout(), Some(Duration::from_secs(30)));
+        assert_eq!(req.idle_timeout(), None);
+
+        let json = r#"{"command": "tail -f log", "idle_timeout_secs": 5}"#;
+        let req: ExecuteCommandRequest = serde_json::from_str(json).unwrap();
+        assert_eq!(req.idle_timeout(), Some(Duration::from_secs(5)));
     }
 
     #[test]
@@ -278,12 +623,110 @@ mod tests {
         let msg = WsMessage::Execute {
             command: "ls".to_string(),
             timeout_secs: Some(10),
+            idle_timeout_secs: None,
         };
         let json = serde_json::to_string(&msg).unwrap();
         assert!(json.contains("execute"));
         assert!(json.contains("ls"));
     }
 
+    #[test]
+    fn test_decide_approval_request() {
+        let req: DecideApprovalRequest = serde_json::from_str(r#"{"approve": true}"#).unwrap();
+        assert!(req.approve);
+        assert!(req.reason.is_none());
+
+        let json = r#"{"approve": false, "reason": "not during the freeze"}"#;
+        let req: DecideApprovalRequest = serde_json::from_str(json).unwrap();
+        assert!(!req.approve);
+        assert_eq!(req.reason.as_deref(), Some("not during the freeze"));
+    }
+
+    #[test]
+    fn test_batch_request_defaults() {
+        let req: BatchRequest =
+            serde_json::from_str(r#"{"commands": [{"command": "make"}]}"#).unwrap();
+        assert!(req.stop_on_error);
+        assert!(req.timeout().is_none());
+        assert_eq!(req.commands[0].command, "make");
+    }
+
+    #[test]
+    fn test_ws_message_batch() {
+        let json = r#"{"type": "batch", "commands": [{"command": "ls"}], "stop_on_error": false}"#;
+        match serde_json::from_str(json).unwrap() {
+            WsMessage::Batch(req) => {
+                assert!(!req.stop_on_error);
+                assert_eq!(req.commands.len(), 1);
+            }
+            _ => panic!("Expected Batch message"),
+        }
+
+        let msg = WsMessage::Result {
+            index: Some(2),
+            success: true,
+            exit_code: Some(0),
+            termination: None,
+            duration_ms: 5,
+            timed_out: false,
+            idle_timed_out: false,
+            limit_exceeded: None,
+            cancelled: false,
+            leftover_pids: Vec::new(),
+        };
+        let json = serde_json::to_value(&msg).unwrap();
+        assert_eq!(json["type"], "result");
+        assert_eq!(json["index"], 2);
+    }
+
+    #[test]
+    fn test_expect_request_pattern() {
+        let req: ExpectRequest = serde_json::from_str(r#"{"pattern": "a.c"}"#).unwrap();
+        assert!(req.regex().unwrap().is_match("abc"));
+        assert!(req.timeout().is_none());
+
+        let req: ExpectRequest =
+            serde_json::from_str(r#"{"pattern": "a.c", "literal": true}"#).unwrap();
+        assert!(!req.regex().unwrap().is_match("abc"));
+        assert!(req.regex().unwrap().is_match("a.c"));
+
+        let req: ExpectRequest = serde_json::from_str(r#"{"pattern": "("}"#).unwrap();
+        assert!(req.regex().is_err());
+    }
+
+    #[test]
+    fn test_expect_response() {
+        let expectation = Expectation {
+            output: "Password: ".to_string(),
+            matched: Some("Password: ".to_string()),
+            groups: vec![None],
+            ..Default::default()
+        };
+        let json = serde_json::to_value(ExpectResponse::new(expectation, String::new())).unwrap();
+        assert_eq!(json["matched"], true);
+        assert_eq!(json["matched_text"], "Password: ");
+        assert_eq!(json["groups"], serde_json::json!([null]));
+        assert!(json.get("named_groups").is_none());
+    }
+
+    #[test]
+    fn test_ws_query() {
+        let query: WsQuery = serde_json::from_str("{}").unwrap();
+        assert_eq!(query.mode, WsMode::Json);
+        let query: WsQuery = serde_json::from_str(r#"{"mode": "raw"}"#).unwrap();
+        assert_eq!(query.mode, WsMode::Raw);
+        assert!(serde_json::from_str::<WsQuery>(r#"{"mode": "tty"}"#).is_err());
+
+        let json = r#"{"type": "resize", "cols": 120, "rows": 40}"#;
+        assert!(matches!(
+            serde_json::from_str(json).unwrap(),
+            WsMessage::Resize {
+                cols: 120,
+                rows: 40
+            }
+        ));
+    }
+
     #[test]
     fn test_ws_message_output() {
         let msg = WsMessage::Output {
@@ -0,0 +1,207 @@
+//! Unix domain socket listener.
+//!
+//! Lets local agents reach the API without exposing anything on the
+//! network. Access is controlled by the socket file's mode and group.
+
+use std::path::PathBuf;
+
+#[cfg(unix)]
+use std::path::Path;
+
+#[cfg(unix)]
+use axum::extract::connect_info::Connected;
+#[cfg(unix)]
+use axum::serve::IncomingStream;
+#[cfg(unix)]
+use tokio::net::UnixListener;
+
+#[cfg(unix)]
+use crate::security::PeerInfo;
+
+/// Default permissions for the socket file (owner read/write only).
+pub const DEFAULT_SOCKET_MODE: u32 = 0o600;
+
+/// Unix domain socket configuration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnixSocketConfig {
+    /// Path of the socket file.
+    pub path: PathBuf,
+    /// Permission bits applied to the socket file.
+    pub mode: u32,
+    /// Group (name or numeric gid) that owns the socket file.
+    pub group: Option<String>,
+}
+
+impl UnixSocketConfig {
+    /// Create a socket configuration with owner-only permissions.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            mode: DEFAULT_SOCKET_MODE,
+            group: None,
+        }
+    }
+
+    /// Set the permission bits of the socket file.
+    pub fn with_mode(mut self, mode: u32) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Set the group that owns the socket file.
+    pub fn with_group(mut self, group: impl Into<String>) -> Self {
+        self.group = Some(group.into());
+        self
+    }
+
+    /// Bind the socket with its permissions already applied.
+    ///
+    /// The socket is bound under a temporary name and renamed into place
+    /// once its mode and group are set, so it is never reachable with the
+    /// default permissions. A stale socket left at the path is replaced.
+    #[cfg(unix)]
+    pub fn bind(&self) -> std::io::Result<UnixListener> {
+        use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+        if let Ok(meta) = std::fs::symlink_metadata(&self.path) {
+            if !meta.file_type().is_socket() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::AlreadyExists,
+                    format!("{} exists and is not a socket", self.path.display()),
+                ));
+            }
+        }
+
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(format!(".{}.tmp", std::process::id()));
+        let tmp = PathBuf::from(tmp);
+        let _ = std::fs::remove_file(&tmp);
+
+        let listener = UnixListener::bind(&tmp)?;
+        let prepared = std::fs::set_permissions(&tmp, std::fs::Permissions::from_mode(self.mode))
+            .and_then(|()| match &self.group {
+                Some(group) => chown_group(&tmp, group),
+                None => Ok(()),
+            })
+            .and_then(|()| std::fs::rename(&tmp, &self.path));
+
+        if let Err(e) = prepared {
+            let _ = std::fs::remove_file(&tmp);
+            return Err(e);
+        }
+
+        Ok(listener)
+    }
+}
+
+/// Change the group of a file, resolving group names through the system database.
+#[cfg(unix)]
+fn chown_group(path: &Path, group: &str) -> std::io::Result<()> {
+    let gid = match group.parse::<libc::gid_t>() {
+        Ok(gid) => gid,
+        Err(_) => lookup_gid(group)?,
+    };
+    std::os::unix::fs::chown(path, None, Some(gid))
+}
+
+/// Look up a group id by name.
+#[cfg(unix)]
+fn lookup_gid(name: &str) -> std::io::Result<libc::gid_t> {
+    use std::ffi::CString;
+
+    let not_found = || {
+        std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("unknown group '{}'", name),
+        )
+    };
+    let c_name = CString::new(name).map_err(|_| not_found())?;
+
+    let mut buf = vec![0 as libc::c_char; 4096];
+    loop {
+        let mut group: libc::group = unsafe { std::mem::zeroed() };
+        let mut result: *mut libc::group = std::ptr::null_mut();
+
+        // SAFETY: all pointers are valid for the duration of the call and
+        // `buf.len()` matches the buffer size.
+        let rc = unsafe {
+            libc::getgrnam_r(
+                c_name.as_ptr(),
+                &mut group,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            )
+        };
+
+        match rc {
+            0 if result.is_null() => return Err(not_found()),
+            0 => return Ok(group.gr_gid),
+            libc::ERANGE if buf.len() < 1 << 20 => buf.resize(buf.len() * 2, 0),
+            rc => return Err(std::io::Error::from_raw_os_error(rc)),
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Connected<IncomingStream<'_, UnixListener>> for PeerInfo {
+    fn connect_info(stream: IncomingStream<'_, UnixListener>) -> Self {
+        PeerInfo::unix(stream.io().peer_cred().ok().map(|cred| cred.uid()))
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+    #[test]
+    fn test_unix_socket_config() {
+        let config = UnixSocketConfig::new("/run/shell-tunnel.sock");
+        assert_eq!(config.mode, DEFAULT_SOCKET_MODE);
+        assert!(config.group.is_none());
+
+        let config = config.with_mode(0o660).with_group("agents");
+        assert_eq!(config.mode, 0o660);
+        assert_eq!(config.group.as_deref(), Some("agents"));
+    }
+
+    #[tokio::test]
+    async fn test_bind_applies_mode() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("api.sock");
+
+        let _listener = UnixSocketConfig::new(&path)
+            .with_mode(0o660)
+            .bind()
+            .unwrap();
+
+        let meta = std::fs::metadata(&path).unwrap();
+        assert!(meta.file_type().is_socket());
+        assert_eq!(meta.permissions().mode() & 0o777, 0o660);
+    }
+
+    #[tokio::test]
+    async fn test_bind_replaces_stale_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("api.sock");
+
+        drop(UnixSocketConfig::new(&path).bind().unwrap());
+        assert!(UnixSocketConfig::new(&path).bind().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_bind_refuses_regular_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("api.sock");
+        std::fs::write(&path, "data").unwrap();
+
+        assert!(UnixSocketConfig::new(&path).bind().is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "data");
+    }
+
+    #[test]
+    fn test_unknown_group() {
+        assert!(lookup_gid("no-such-group-shell-tunnel").is_err());
+    }
+}
@@ -1,32 +1,301 @@
 //! WebSocket handler for real-time command streaming.
 
+use std::path::PathBuf;
 use std::time::Duration;
 
 use axum::{
     extract::{
         ws::{Message, WebSocket, WebSocketUpgrade},
-        Path, State,
+        Path, Query, State,
     },
-    response::IntoResponse,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Extension, Json,
 };
+use futures_util::stream::SplitStream;
 use futures_util::{SinkExt, StreamExt};
+use tokio::sync::{broadcast, mpsc};
 
-use super::handlers::AppState;
-use super::types::WsMessage;
-use crate::execution::Command;
-use crate::session::SessionId;
+use super::handlers::{audit_entry, can_access, require_scope, AppState, Batch};
+use super::types::{
+    ErrorResponse, ExecuteCommandRequest, ExecuteCommandResponse, WsMessage, WsMode, WsQuery,
+};
+use crate::audit::AuditEntry;
+use crate::execution::{CancelHandle, Command, ExecutionResult, OutputChunk};
+use crate::pty::PtySize;
+use crate::security::{ClientIp, Identity, ValidationError, SCOPE_ATTACH, SCOPE_EXECUTE};
+use crate::session::{Session, SessionId};
+
+/// Command recorded in the audit log when a client attaches to a terminal.
+const ATTACH_COMMAND: &str = "[attach]";
+
+/// Longest run of keystrokes audited as one line.
+const MAX_INPUT_LINE: usize = 4096;
+
+/// How long output must stay quiet before a held-back partial line, such as
+/// a prompt, is sent.
+const PARTIAL_LINE_DELAY: Duration = Duration::from_millis(50);
 
 /// WebSocket upgrade handler.
+///
+/// With `?mode=raw` the socket is attached to the session's terminal
+/// instead, which must be enabled and needs an identity with the `attach`
+/// scope.
 pub async fn ws_handler(
     ws: WebSocketUpgrade,
     State(state): State<AppState>,
+    identity: Option<Extension<Identity>>,
+    client_ip: ClientIp,
     Path(session_id): Path<u64>,
-) -> impl IntoResponse {
-    ws.on_upgrade(move |socket| handle_socket(socket, state, session_id))
+    Query(query): Query<WsQuery>,
+) -> Response {
+    let identity = identity.as_deref();
+    if let Err(rejection) = require_ws_scopes(&state, identity, query.mode) {
+        return rejection.into_response();
+    }
+
+    // Sessions owned by other identities are reported as not found
+    let id = SessionId::from_raw(session_id);
+    if let Ok(Some(session)) = state.store.get(&id) {
+        if !can_access(identity, &session) {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(ErrorResponse::session_not_found(&session_id.to_string())),
+            )
+                .into_response();
+        }
+    }
+
+    if query.mode == WsMode::Raw {
+        tracing::info!(
+            "{} attached to the terminal of session {}",
+            identity.map_or("Anonymous client", |identity| identity.name.as_str()),
+            session_id
+        );
+        let identity = identity.cloned();
+        return ws.on_upgrade(move |socket| {
+            handle_raw_socket(socket, state, session_id, identity, client_ip)
+        });
+    }
+
+    let identity = identity.cloned();
+    ws.on_upgrade(move |socket| handle_socket(socket, state, session_id, identity, client_ip))
+}
+
+/// Check that the caller may open a session's socket in the given mode.
+///
+/// Keystrokes sent in raw mode get around the command validator, so it is
+/// refused unless enabled, to anonymous callers, and whenever commands are
+/// held to an allowlist or approvals.
+fn require_ws_scopes(
+    state: &AppState,
+    identity: Option<&Identity>,
+    mode: WsMode,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    require_scope(identity, SCOPE_EXECUTE)?;
+    if mode != WsMode::Raw {
+        return Ok(());
+    }
+    let refuse = |message: &str| {
+        Err((
+            StatusCode::FORBIDDEN,
+            Json(ErrorResponse::new("RAW_ATTACH_DISABLED", message)),
+        ))
+    };
+    if !state.raw_attach {
+        return refuse("Raw terminal attach is disabled");
+    }
+    if state.validator.has_allowlist_or_approvals() || state.approvals.is_enabled() {
+        return refuse("Raw terminal attach is unavailable with an allowlist or approvals");
+    }
+    match identity {
+        Some(identity) => require_scope(Some(identity), SCOPE_ATTACH),
+        None => refuse("Raw terminal attach requires authentication"),
+    }
+}
+
+/// Attach a socket to a session's terminal.
+///
+/// Binary frames carry the terminal's bytes both ways, starting with a
+/// redraw of the current screen. Text frames carry JSON control messages:
+/// `resize` and `ping` from the client, and `error` from the server.
+///
+/// Keystrokes bypass the command validator, so the attach and every line
+/// typed are audited instead. Output is redacted like command output.
+async fn handle_raw_socket(
+    socket: WebSocket,
+    state: AppState,
+    session_id: u64,
+    identity: Option<Identity>,
+    client_ip: ClientIp,
+) {
+    let (mut sink, mut stream) = socket.split();
+    let id = SessionId::from_raw(session_id);
+    let audit = |command: &str| {
+        let entry = audit_entry(identity.as_ref(), client_ip, command).with_session(session_id);
+        state.audit.record(entry);
+    };
+
+    let attached = match state.store.get(&id) {
+        Ok(Some(session)) if session.state.is_terminal() => {
+            Err(ErrorResponse::invalid_state(session.state))
+        }
+        Ok(_) => state
+            .store
+            .terminal(&id)
+            .and_then(|terminal| Ok((terminal.attach()?, terminal)))
+            .map_err(|e| match e {
+                crate::error::ShellTunnelError::SessionNotFound(_) => {
+                    ErrorResponse::session_not_found(&session_id.to_string())
+                }
+                e => ErrorResponse::internal_error(e.to_string()),
+            }),
+        Err(e) => Err(ErrorResponse::internal_error(e.to_string())),
+    };
+    let ((redraw, mut output), terminal) = match attached {
+        Ok(attached) => attached,
+        Err(e) => {
+            send_error(&mut sink, &e.code, e.message).await;
+            return;
+        }
+    };
+    audit(ATTACH_COMMAND);
+    let mut input = InputLines::default();
+    let redact = |data: &[u8]| {
+        state
+            .redactor
+            .redact(&String::from_utf8_lossy(data))
+            .into_owned()
+    };
+    if !send_binary(&mut sink, redact(&redraw)).await {
+        return;
+    }
+
+    // Hold back partial lines for redaction
+    let mut redactor = state.redactor.stream();
+    loop {
+        let holding = redactor.is_holding();
+        tokio::select! {
+            chunk = output.recv() => match chunk {
+                // The terminal closed
+                Ok(data) if data.is_empty() => break,
+                Ok(data) => {
+                    if !send_binary(&mut sink, redactor.push(&data)).await {
+                        break;
+                    }
+                }
+                // Fell behind, redraw the screen instead of replaying output
+                Err(broadcast::error::RecvError::Lagged(_)) => match terminal.attach() {
+                    Ok((redraw, rx)) => {
+                        output = rx;
+                        redactor = state.redactor.stream();
+                        if !send_binary(&mut sink, redact(&redraw)).await {
+                            break;
+                        }
+                    }
+                    Err(_) => break,
+                },
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            _ = tokio::time::sleep(PARTIAL_LINE_DELAY), if holding => {
+                if !send_binary(&mut sink, redactor.flush()).await {
+                    break;
+                }
+            }
+            msg = stream.next() => match msg {
+                Some(Ok(Message::Binary(data))) => {
+                    input.push(&data).iter().for_each(|line| audit(line));
+                    // Only a closed terminal ends the attachment
+                    if let Err(e) = terminal.write(&data).await {
+                        send_error(&mut sink, "TERMINAL_ERROR", e).await;
+                        if terminal.is_closed() {
+                            break;
+                        }
+                    }
+                }
+                Some(Ok(Message::Text(text))) => match serde_json::from_str(&text) {
+                    Ok(WsMessage::Resize { cols, rows }) if cols > 0 && rows > 0 => {
+                        if let Err(e) = terminal.resize(PtySize::new(rows, cols)) {
+                            send_error(&mut sink, "TERMINAL_ERROR", e).await;
+                        }
+                    }
+                    Ok(WsMessage::Ping) => send_message(&mut sink, &WsMessage::Pong).await,
+                    _ => {
+                        send_error(&mut sink, "PARSE_ERROR", "Expected a resize or ping message")
+                            .await;
+                    }
+                },
+                Some(Ok(Message::Ping(data))) => {
+                    let _ = sink.send(Message::Pong(data)).await;
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+    if let Some(line) = input.finish() {
+        audit(&line);
+    }
+    send_binary(&mut sink, redactor.finish()).await;
+    let _ = sink.send(Message::Close(None)).await;
+}
+
+/// Send terminal output as a binary frame, returning `false` once the
+/// client is gone.
+async fn send_binary<S>(sink: &mut S, data: String) -> bool
+where
+    S: SinkExt<Message> + Unpin,
+{
+    data.is_empty() || sink.send(Message::Binary(data.into())).await.is_ok()
+}
+
+/// Keystrokes sent to an attached terminal, split into lines for the
+/// audit log.
+#[derive(Debug, Default)]
+struct InputLines {
+    pending: Vec<u8>,
+}
+
+impl InputLines {
+    /// Add keystrokes, returning the lines they completed.
+    ///
+    /// A line ends at a carriage return or newline, or once it reaches
+    /// [`MAX_INPUT_LINE`] bytes. Blank lines are skipped.
+    fn push(&mut self, data: &[u8]) -> Vec<String> {
+        let mut lines = Vec::new();
+        for &byte in data {
+            if byte == b'\r' || byte == b'\n' {
+                lines.extend(self.take());
+            } else {
+                self.pending.push(byte);
+                if self.pending.len() >= MAX_INPUT_LINE {
+                    lines.extend(self.take());
+                }
+            }
+        }
+        lines
+    }
+
+    /// The unfinished line, if any.
+    fn finish(mut self) -> Option<String> {
+        self.take()
+    }
+
+    fn take(&mut self) -> Option<String> {
+        let line = String::from_utf8_lossy(&self.pending).into_owned();
+        self.pending.clear();
+        (!line.trim().is_empty()).then_some(line)
+    }
 }
 
 /// Handle WebSocket connection.
-async fn handle_socket(socket: WebSocket, state: AppState, session_id: u64) {
+async fn handle_socket(
+    socket: WebSocket,
+    state: AppState,
+    session_id: u64,
+    identity: Option<Identity>,
+    client_ip: ClientIp,
+) {
     let id = SessionId::from_raw(session_id);
 
     // Verify session exists
@@ -43,6 +312,12 @@ async fn handle_socket(socket: WebSocket, state: AppState, session_id: u64) {
     }
 
     let (mut sink, mut stream) = socket.split();
+    let client = SessionClient {
+        state: &state,
+        identity: identity.as_ref(),
+        client_ip,
+        session_id,
+    };
 
     // Process incoming messages
     while let Some(msg) = stream.next().await {
@@ -76,81 +351,41 @@ async fn handle_socket(socket: WebSocket, state: AppState, session_id: u64) {
             WsMessage::Execute {
                 command,
                 timeout_secs,
+                idle_timeout_secs,
             } => {
-                // Build command
-                let mut cmd = Command::new(&command);
-                if let Some(secs) = timeout_secs {
-                    cmd = cmd.timeout(Duration::from_secs(secs));
+                let req = ExecuteCommandRequest {
+                    command,
+                    timeout_secs,
+                    idle_timeout_secs,
+                    ..Default::default()
+                };
+                let (_, connected) =
+                    run_in_session(&client, &mut sink, &mut stream, &req, None, None).await;
+                if !connected {
+                    break;
                 }
-
-                // Execute with streaming
-                match state.executor.execute_async(&cmd).await {
-                    Ok((mut rx, handle)) => {
-                        // Stream output chunks
-                        while let Some(chunk) = rx.recv().await {
-                            let output = WsMessage::Output {
-                                data: String::from_utf8_lossy(&chunk.raw).to_string(),
-                                is_final: false,
-                            };
-                            if let Ok(json) = serde_json::to_string(&output) {
-                                if sink.send(Message::Text(json.into())).await.is_err() {
-                                    break;
-                                }
-                            }
-                        }
-
-                        // Wait for completion and send result
-                        match handle.await {
-                            Ok(Ok(result)) => {
-                                // Update session context
-                                state
-                                    .store
-                                    .update(&id, |s| {
-                                        s.context.record_execution(&command, result.exit_code);
-                                    })
-                                    .ok();
-
-                                let result_msg = WsMessage::Result {
-                                    success: result.exit_code.map(|c| c == 0).unwrap_or(false)
-                                        && !result.timed_out,
-                                    exit_code: result.exit_code,
-                                    duration_ms: result.duration.as_millis() as u64,
-                                    timed_out: result.timed_out,
-                                };
-                                if let Ok(json) = serde_json::to_string(&result_msg) {
-                                    let _ = sink.send(Message::Text(json.into())).await;
-                                }
-                            }
-                            Ok(Err(e)) => {
-                                let err = WsMessage::Error {
-                                    code: "EXECUTION_ERROR".to_string(),
-                                    message: e.to_string(),
-                                };
-                                if let Ok(json) = serde_json::to_string(&err) {
-                                    let _ = sink.send(Message::Text(json.into())).await;
-                                }
-                            }
-                            Err(e) => {
-                                let err = WsMessage::Error {
-                                    code: "TASK_ERROR".to_string(),
-                                    message: e.to_string(),
-                                };
-                                if let Ok(json) = serde_json::to_string(&err) {
-                                    let _ = sink.send(Message::Text(json.into())).await;
-                                }
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        let err = WsMessage::Error {
-                            code: "EXECUTION_ERROR".to_string(),
-                            message: e.to_string(),
-                        };
-                        if let Ok(json) = serde_json::to_string(&err) {
-                            let _ = sink.send(Message::Text(json.into())).await;
-                        }
+            }
+            WsMessage::Batch(req) => {
+                if let Err(e) = state.validate_timeout(req.timeout_secs) {
+                    send_error(&mut sink, "BAD_REQUEST", e).await;
+                    continue;
+                }
+                let mut batch = Batch::new(&req);
+                let mut connected = true;
+                while let Some((index, step, budget)) = batch.next_step() {
+                    let (result, still_connected) =
+                        run_in_session(&client, &mut sink, &mut stream, step, budget, Some(index))
+                            .await;
+                    batch.record(result);
+                    connected = still_connected;
+                    if !connected {
+                        break;
                     }
                 }
+                if !connected {
+                    break;
+                }
+                send_message(&mut sink, &WsMessage::BatchResult(batch.summary())).await;
             }
             WsMessage::Ping => {
                 let pong = WsMessage::Pong;
@@ -169,12 +404,24 @@ async fn handle_socket(socket: WebSocket, state: AppState, session_id: u64) {
 pub async fn ws_oneshot_handler(
     ws: WebSocketUpgrade,
     State(state): State<AppState>,
-) -> impl IntoResponse {
-    ws.on_upgrade(move |socket| handle_oneshot_socket(socket, state))
+    identity: Option<Extension<Identity>>,
+    client_ip: ClientIp,
+) -> Response {
+    if let Err(rejection) = require_scope(identity.as_deref(), SCOPE_EXECUTE) {
+        return rejection.into_response();
+    }
+
+    let identity = identity.map(|Extension(identity)| identity);
+    ws.on_upgrade(move |socket| handle_oneshot_socket(socket, state, identity, client_ip))
 }
 
 /// Handle one-shot WebSocket connection.
-async fn handle_oneshot_socket(socket: WebSocket, state: AppState) {
+async fn handle_oneshot_socket(
+    socket: WebSocket,
+    state: AppState,
+    identity: Option<Identity>,
+    client_ip: ClientIp,
+) {
     let (mut sink, mut stream) = socket.split();
 
     while let Some(msg) = stream.next().await {
@@ -207,40 +454,62 @@ async fn handle_oneshot_socket(socket: WebSocket, state: AppState) {
             WsMessage::Execute {
                 command,
                 timeout_secs,
+                idle_timeout_secs,
             } => {
-                let mut cmd = Command::new(&command);
+                let entry = audit_entry(identity.as_ref(), client_ip, &command);
+                let checked = state
+                    .validate_timeout(timeout_secs)
+                    .and_then(|()| state.validate_timeout(idle_timeout_secs));
+                if let Err(e) = checked {
+                    state.audit.record(entry.blocked(e.to_string()));
+                    send_error(&mut sink, "COMMAND_BLOCKED", e).await;
+                    continue;
+                }
+                let Ok((entry, working_dir)) = authorize(
+                    &state,
+                    &mut sink,
+                    identity.as_ref(),
+                    None,
+                    entry,
+                    &command,
+                    None,
+                )
+                .await
+                else {
+                    continue;
+                };
+
+                let mut cmd = Command::new(&command)
+                    .run_as(state.run_as_for(identity.as_ref(), None))
+                    .limits(state.limits_for(None, &Default::default()))
+                    .sandbox(state.sandbox_for(identity.as_ref(), None, None));
+                if let Some(dir) = working_dir {
+                    cmd = cmd.working_dir(dir);
+                }
                 if let Some(secs) = timeout_secs {
                     cmd = cmd.timeout(Duration::from_secs(secs));
                 }
+                if let Some(secs) = idle_timeout_secs {
+                    cmd = cmd.idle_timeout(Duration::from_secs(secs));
+                }
+                cmd = cmd.max_output(state.validator.max_output_size());
 
                 match state.executor.execute_async(&cmd).await {
-                    Ok((mut rx, handle)) => {
-                        while let Some(chunk) = rx.recv().await {
-                            let output = WsMessage::Output {
-                                data: String::from_utf8_lossy(&chunk.raw).to_string(),
-                                is_final: false,
-                            };
-                            if let Ok(json) = serde_json::to_string(&output) {
-                                if sink.send(Message::Text(json.into())).await.is_err() {
-                                    break;
-                                }
-                            }
-                        }
+                    Ok((rx, handle, cancel)) => {
+                        let connected =
+                            stream_output(&state, &mut sink, &mut stream, rx, &cancel).await;
 
                         match handle.await {
                             Ok(Ok(result)) => {
-                                let result_msg = WsMessage::Result {
-                                    success: result.exit_code.map(|c| c == 0).unwrap_or(false)
-                                        && !result.timed_out,
-                                    exit_code: result.exit_code,
-                                    duration_ms: result.duration.as_millis() as u64,
-                                    timed_out: result.timed_out,
-                                };
+                                state.audit.record(entry.with_result(&result));
+
+                                let result_msg = result_message(&result, None);
                                 if let Ok(json) = serde_json::to_string(&result_msg) {
                                     let _ = sink.send(Message::Text(json.into())).await;
                                 }
                             }
                             Ok(Err(e)) => {
+                                state.audit.record(entry.failed(e.to_string()));
                                 let err = WsMessage::Error {
                                     code: "EXECUTION_ERROR".to_string(),
                                     message: e.to_string(),
@@ -250,6 +519,7 @@ async fn handle_oneshot_socket(socket: WebSocket, state: AppState) {
                                 }
                             }
                             Err(e) => {
+                                state.audit.record(entry.failed(e.to_string()));
                                 let err = WsMessage::Error {
                                     code: "TASK_ERROR".to_string(),
                                     message: e.to_string(),
@@ -259,8 +529,12 @@ async fn handle_oneshot_socket(socket: WebSocket, state: AppState) {
                                 }
                             }
                         }
+                        if !connected {
+                            break;
+                        }
                     }
                     Err(e) => {
+                        state.audit.record(entry.failed(e.to_string()));
                         let err = WsMessage::Error {
                             code: "EXECUTION_ERROR".to_string(),
                             message: e.to_string(),
@@ -282,9 +556,361 @@ async fn handle_oneshot_socket(socket: WebSocket, state: AppState) {
     }
 }
 
+/// A client of a session's socket.
+struct SessionClient<'a> {
+    state: &'a AppState,
+    identity: Option<&'a Identity>,
+    client_ip: ClientIp,
+    session_id: u64,
+}
+
+/// Authorize and run a command in the session, streaming its output and
+/// sending its result tagged with `index`.
+///
+/// The command's timeout is capped at `budget` if given. Returns the
+/// command's response, or the error the client was sent if it could not
+/// run, and whether the client is still connected.
+async fn run_in_session<S>(
+    client: &SessionClient<'_>,
+    sink: &mut S,
+    stream: &mut SplitStream<WebSocket>,
+    req: &ExecuteCommandRequest,
+    budget: Option<Duration>,
+    index: Option<usize>,
+) -> (Result<ExecuteCommandResponse, ErrorResponse>, bool)
+where
+    S: SinkExt<Message> + Unpin,
+{
+    let state = client.state;
+    let id = SessionId::from_raw(client.session_id);
+    let session = match state.store.get(&id).ok().flatten() {
+        Some(session) if session.state.can_execute() => session,
+        Some(session) => {
+            let error = ErrorResponse::invalid_state(session.state);
+            return (
+                Err(send_error(sink, &error.code, error.message).await),
+                true,
+            );
+        }
+        None => {
+            let error = ErrorResponse::session_not_found(&client.session_id.to_string());
+            return (
+                Err(send_error(sink, &error.code, error.message).await),
+                true,
+            );
+        }
+    };
+    let entry = audit_entry(client.identity, client.client_ip, &req.command)
+        .with_session(client.session_id)
+        .with_working_dir(req.working_dir.as_deref());
+    let checked = state
+        .validate_timeouts(req)
+        .and_then(|()| state.validate_env(client.identity, &req.env));
+    if let Err(e) = checked {
+        state.audit.record(entry.blocked(e.to_string()));
+        return (Err(send_error(sink, "COMMAND_BLOCKED", e).await), true);
+    }
+    let authorized = authorize(
+        state,
+        sink,
+        client.identity,
+        Some(&session),
+        entry,
+        &req.command,
+        req.working_dir.as_deref(),
+    )
+    .await;
+    let (entry, working_dir) = match authorized {
+        Ok(authorized) => authorized,
+        Err(error) => return (Err(error), true),
+    };
+
+    // Build command
+    let mut cmd = Command::new(&req.command)
+        .run_as(state.run_as_for(client.identity, Some(&session)))
+        .limits(state.limits_for(Some(&session), &req.limits))
+        .sandbox(state.sandbox_for(client.identity, Some(&session), None))
+        .cgroup(session.cgroup.as_ref().map(|c| c.path().to_path_buf()));
+    if let Some(dir) = working_dir {
+        cmd = cmd.working_dir(dir);
+    }
+    if let Some(timeout) = req.timeout_within(budget) {
+        cmd = cmd.timeout(timeout);
+    }
+    if let Some(idle) = req.idle_timeout() {
+        cmd = cmd.idle_timeout(idle);
+    }
+    cmd = cmd.max_output(state.validator.max_output_size());
+    for (key, value) in &req.env {
+        cmd = cmd.env(key, value);
+    }
+
+    // Execute with streaming
+    let (rx, handle, cancel) = match state.executor.execute_async_in_session(&id, &cmd).await {
+        Ok(execution) => execution,
+        Err(e) => {
+            state.audit.record(entry.failed(e.to_string()));
+            return (Err(send_error(sink, "EXECUTION_ERROR", e).await), true);
+        }
+    };
+    let connected = stream_output(state, sink, stream, rx, &cancel).await;
+
+    // Wait for completion and send result
+    let result = match handle.await {
+        Ok(Ok(result)) => result,
+        Ok(Err(e)) => {
+            state.audit.record(entry.failed(e.to_string()));
+            return (Err(send_error(sink, "EXECUTION_ERROR", e).await), connected);
+        }
+        Err(e) => {
+            state.audit.record(entry.failed(e.to_string()));
+            return (Err(send_error(sink, "TASK_ERROR", e).await), connected);
+        }
+    };
+    let mut entry = entry.with_result(&result);
+    if let Some(violation) = state.probe_cwd(&id) {
+        entry = entry.violation(violation.to_string());
+    }
+    state.audit.record(entry);
+
+    // Update session context
+    state
+        .store
+        .update(&id, |s| {
+            s.context.record_execution(&req.command, result.exit_code);
+        })
+        .ok();
+
+    send_message(sink, &result_message(&result, index)).await;
+    (Ok(ExecuteCommandResponse::from_result(&result)), connected)
+}
+
+/// The message reporting a command's result.
+fn result_message(result: &ExecutionResult, index: Option<usize>) -> WsMessage {
+    WsMessage::Result {
+        index,
+        success: result.exit_code.map(|c| c == 0).unwrap_or(false) && !result.failed(),
+        exit_code: result.exit_code,
+        termination: result.termination.clone(),
+        duration_ms: result.duration.as_millis() as u64,
+        timed_out: result.timed_out,
+        idle_timed_out: result.idle_timed_out,
+        limit_exceeded: result.limit_exceeded,
+        cancelled: result.cancelled,
+        leftover_pids: result.leftover_pids.clone(),
+    }
+}
+
+/// Send an error message, returning it as an API error.
+async fn send_error<S>(sink: &mut S, code: &str, message: impl ToString) -> ErrorResponse
+where
+    S: SinkExt<Message> + Unpin,
+{
+    let err = WsMessage::Error {
+        code: code.to_string(),
+        message: message.to_string(),
+    };
+    send_message(sink, &err).await;
+    ErrorResponse::new(code, message.to_string())
+}
+
+/// Stream a running command's output to the client until it finishes.
+///
+/// A `cancel` message or the client going away cancels the command, other
+/// messages are turned away while it runs. Returns `false` once the client is
+/// gone.
+async fn stream_output<S>(
+    state: &AppState,
+    sink: &mut S,
+    stream: &mut SplitStream<WebSocket>,
+    mut rx: mpsc::Receiver<OutputChunk>,
+    cancel: &CancelHandle,
+) -> bool
+where
+    S: SinkExt<Message> + Unpin,
+{
+    // Hold back partial lines for redaction
+    let mut redactor = state.redactor.stream();
+    let mut connected = true;
+    loop {
+        let holding = redactor.is_holding();
+        tokio::select! {
+            chunk = rx.recv() => match chunk {
+                Some(chunk) => {
+                    if connected && !send_output(sink, redactor.push(&chunk.raw)).await {
+                        connected = false;
+                        cancel.cancel();
+                    }
+                }
+                None => break,
+            },
+            _ = tokio::time::sleep(PARTIAL_LINE_DELAY), if holding && connected => {
+                if !send_output(sink, redactor.flush()).await {
+                    connected = false;
+                    cancel.cancel();
+                }
+            }
+            msg = stream.next(), if connected => match msg {
+                Some(Ok(Message::Text(text))) => match serde_json::from_str(&text) {
+                    Ok(WsMessage::Cancel) => cancel.cancel(),
+                    Ok(WsMessage::Ping) => send_message(sink, &WsMessage::Pong).await,
+                    _ => {
+                        let err = WsMessage::Error {
+                            code: "BUSY".to_string(),
+                            message: "A command is already running".to_string(),
+                        };
+                        send_message(sink, &err).await;
+                    }
+                },
+                Some(Ok(Message::Ping(data))) => {
+                    let _ = sink.send(Message::Pong(data)).await;
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => {
+                    connected = false;
+                    cancel.cancel();
+                }
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+    if connected {
+        send_output(sink, redactor.finish()).await;
+    }
+    connected
+}
+
+/// Send a chunk of redacted output.
+///
+/// Returns `false` once the client is gone. Empty chunks are skipped.
+async fn send_output<S>(sink: &mut S, data: String) -> bool
+where
+    S: SinkExt<Message> + Unpin,
+{
+    if data.is_empty() {
+        return true;
+    }
+    let output = WsMessage::Output {
+        data,
+        is_final: false,
+    };
+    match serde_json::to_string(&output) {
+        Ok(json) => sink.send(Message::Text(json.into())).await.is_ok(),
+        Err(_) => true,
+    }
+}
+
+/// Validate a command, waiting for approval if the validator asks for it.
+///
+/// The client is told when the command is parked or rejected. Returns the
+/// audit entry to record the outcome with and the directory to run in, or
+/// `None` if the command may not run.
+async fn authorize<S>(
+    state: &AppState,
+    sink: &mut S,
+    identity: Option<&Identity>,
+    session: Option<&Session>,
+    entry: AuditEntry,
+    command: &str,
+    requested_dir: Option<&str>,
+) -> Result<(AuditEntry, Option<PathBuf>), ErrorResponse>
+where
+    S: SinkExt<Message> + Unpin,
+{
+    let (working_dir, error) = match state.working_dir(identity, session, requested_dir) {
+        Ok(dir) => match state.validate(identity, command) {
+            Ok(()) => return Ok((entry, dir)),
+            Err(e) => (dir, e),
+        },
+        Err(e) => (None, e),
+    };
+    if let ValidationError::RequiresApproval { ref reason } = error {
+        if let Some(ticket) = state.park(&entry, reason) {
+            let pending = ticket.pending();
+            let parked = WsMessage::ApprovalRequired {
+                approval_id: pending.id,
+                reason: pending.reason.clone(),
+                expires_at: pending.expires_at.clone(),
+            };
+            send_message(sink, &parked).await;
+
+            return match state.await_approval(entry, ticket).await {
+                Ok(entry) => Ok((entry, working_dir)),
+                Err(e) => Err(send_error(sink, &e.code, e.message).await),
+            };
+        }
+    }
+
+    state.audit.record(entry.blocked(error.to_string()));
+    Err(send_error(sink, "COMMAND_BLOCKED", error).await)
+}
+
+/// Send a message, ignoring failures.
+async fn send_message<S>(sink: &mut S, message: &WsMessage)
+where
+    S: SinkExt<Message> + Unpin,
+{
+    if let Ok(json) = serde_json::to_string(message) {
+        let _ = sink.send(Message::Text(json.into())).await;
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::security::{ApprovalQueue, CommandValidator, ValidationConfig};
+
+    #[test]
+    fn test_raw_mode_needs_attach_scope() {
+        let agent = Identity::new("agent", [SCOPE_EXECUTE]);
+        let human = Identity::new("human", [SCOPE_EXECUTE, SCOPE_ATTACH]);
+        let state = AppState::new().with_raw_attach(true);
+
+        assert!(require_ws_scopes(&state, Some(&agent), WsMode::Json).is_ok());
+        let (status, _) = require_ws_scopes(&state, Some(&agent), WsMode::Raw).unwrap_err();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert!(require_ws_scopes(&state, Some(&human), WsMode::Raw).is_ok());
+        // Anonymous callers hold every scope but may not attach
+        assert!(require_ws_scopes(&state, None, WsMode::Json).is_ok());
+        assert!(require_ws_scopes(&state, None, WsMode::Raw).is_err());
+    }
+
+    #[test]
+    fn test_raw_mode_refused() {
+        let human = Identity::new("human", [SCOPE_EXECUTE, SCOPE_ATTACH]);
+        let refused = |state: AppState| {
+            let (status, Json(e)) =
+                require_ws_scopes(&state, Some(&human), WsMode::Raw).unwrap_err();
+            assert_eq!(status, StatusCode::FORBIDDEN);
+            e.code
+        };
+
+        // Off by default
+        assert_eq!(refused(AppState::new()), "RAW_ATTACH_DISABLED");
+
+        let state = AppState::new().with_raw_attach(true);
+        let allowlist = CommandValidator::new(ValidationConfig::default().allowlist(["ls"]));
+        assert_eq!(
+            refused(state.clone().with_validator(allowlist)),
+            "RAW_ATTACH_DISABLED"
+        );
+        let approvals = ApprovalQueue::new(Duration::from_secs(60));
+        assert_eq!(
+            refused(state.with_approvals(approvals)),
+            "RAW_ATTACH_DISABLED"
+        );
+    }
+
+    #[test]
+    fn test_input_lines() {
+        let mut input = InputLines::default();
+        assert!(input.push(b"ec").is_empty());
+        assert_eq!(input.push(b"ho hi\r\rls\n"), vec!["echo hi", "ls"]);
+        assert!(input.push(b"  \r").is_empty());
+
+        let long = vec![b'x'; MAX_INPUT_LINE + 1];
+        assert_eq!(input.push(&long).len(), 1);
+        assert_eq!(input.finish().as_deref(), Some("x"));
+    }
 
     #[test]
     fn test_ws_message_execute_parse() {
@@ -296,6 +922,12 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_ws_message_cancel_parse() {
+        let msg: WsMessage = serde_json::from_str(r#"{"type": "cancel"}"#).unwrap();
+        assert!(matches!(msg, WsMessage::Cancel));
+    }
+
     #[test]
     fn test_ws_message_ping_parse() {
         let json = r#"{"type": "ping"}"#;
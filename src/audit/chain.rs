@@ -0,0 +1,272 @@
+//! Hash chain linking audit entries.
+//!
+//! Every entry carries the SHA-256 of the previous entry's line in
+//! `prev_hash`, so editing, inserting or deleting a line breaks the link to
+//! the line after it. Chains continue across rotated files.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+
+use super::AuditEntry;
+
+/// `prev_hash` of the first entry ever written.
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Hash an audit line as it appears in the file (without the newline).
+pub fn hash_line(line: &str) -> String {
+    let digest = Sha256::digest(line.as_bytes());
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Read the last line of a file, if it has one.
+///
+/// Used to continue the chain after a restart.
+pub fn last_line(path: &Path) -> io::Result<Option<String>> {
+    /// Longest line we look back for; entries are far shorter.
+    const TAIL: u64 = 64 * 1024;
+
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let len = file.metadata()?.len();
+    file.seek(SeekFrom::Start(len.saturating_sub(TAIL)))?;
+
+    let mut tail = String::new();
+    file.read_to_string(&mut tail)?;
+    Ok(tail
+        .lines()
+        .rev()
+        .find(|line| !line.trim().is_empty())
+        .map(str::to_string))
+}
+
+/// Where a chain check failed.
+#[derive(Debug)]
+pub struct ChainError {
+    /// File containing the bad line.
+    pub file: PathBuf,
+    /// 1-based line number within the file.
+    pub line: usize,
+    /// What went wrong.
+    pub kind: ChainErrorKind,
+}
+
+/// Kinds of chain failures.
+#[derive(Debug)]
+pub enum ChainErrorKind {
+    /// The file could not be read.
+    Io(io::Error),
+    /// The line is not a valid audit entry.
+    Malformed(String),
+    /// `prev_hash` does not match the hash of the previous line.
+    BrokenLink { expected: String, found: String },
+}
+
+impl fmt::Display for ChainError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: ", self.file.display(), self.line)?;
+        match &self.kind {
+            ChainErrorKind::Io(e) => write!(f, "read error: {}", e),
+            ChainErrorKind::Malformed(e) => write!(f, "malformed entry: {}", e),
+            ChainErrorKind::BrokenLink { expected, found } => write!(
+                f,
+                "broken link: prev_hash is {} but previous entry hashes to {}",
+                found, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ChainError {}
+
+/// Result of a successful verification.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChainReport {
+    /// Number of entries checked.
+    pub entries: usize,
+    /// Whether the chain starts at the genesis hash.
+    ///
+    /// `false` when the first file continues a chain from an older rotated
+    /// file that was not checked.
+    pub from_genesis: bool,
+    /// Hash of the last entry.
+    pub last_hash: String,
+}
+
+/// Verify the chain across files given oldest first.
+///
+/// The first entry's `prev_hash` is taken as the anchor; every later entry
+/// must link to the line before it, including across file boundaries.
+pub fn verify_files<P: AsRef<Path>>(paths: &[P]) -> Result<ChainReport, ChainError> {
+    let mut report = ChainReport {
+        entries: 0,
+        from_genesis: false,
+        last_hash: GENESIS_HASH.to_string(),
+    };
+    let mut expected: Option<String> = None;
+
+    for path in paths {
+        let path = path.as_ref();
+        let error = |line, kind| ChainError {
+            file: path.to_path_buf(),
+            line,
+            kind,
+        };
+
+        let file = File::open(path).map_err(|e| error(0, ChainErrorKind::Io(e)))?;
+        for (index, line) in BufReader::new(file).lines().enumerate() {
+            let number = index + 1;
+            let line = line.map_err(|e| error(number, ChainErrorKind::Io(e)))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let entry: AuditEntry = serde_json::from_str(&line)
+                .map_err(|e| error(number, ChainErrorKind::Malformed(e.to_string())))?;
+
+            match expected {
+                Some(ref expected) if *expected != entry.prev_hash => {
+                    return Err(error(
+                        number,
+                        ChainErrorKind::BrokenLink {
+                            expected: expected.clone(),
+                            found: entry.prev_hash,
+                        },
+                    ));
+                }
+                Some(_) => {}
+                None => report.from_genesis = entry.prev_hash == GENESIS_HASH,
+            }
+
+            let hash = hash_line(&line);
+            expected = Some(hash.clone());
+            report.last_hash = hash;
+            report.entries += 1;
+        }
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    /// Write a chained log of the given commands and return its lines.
+    fn write_chain(path: &Path, commands: &[&str]) -> Vec<String> {
+        let mut prev = GENESIS_HASH.to_string();
+        let mut lines = Vec::new();
+        for command in commands {
+            let entry = AuditEntry {
+                prev_hash: prev,
+                ..AuditEntry::new(command)
+            };
+            let line = serde_json::to_string(&entry).unwrap();
+            prev = hash_line(&line);
+            lines.push(line);
+        }
+        std::fs::write(path, lines.join("\n") + "\n").unwrap();
+        lines
+    }
+
+    #[test]
+    fn test_hash_line() {
+        assert_eq!(
+            hash_line("abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn test_verify_intact_chain() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        let lines = write_chain(&path, &["ls", "pwd", "whoami"]);
+
+        let report = verify_files(&[&path]).unwrap();
+        assert_eq!(report.entries, 3);
+        assert!(report.from_genesis);
+        assert_eq!(report.last_hash, hash_line(&lines[2]));
+    }
+
+    #[test]
+    fn test_verify_detects_edit() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        let lines = write_chain(&path, &["ls", "rm -rf build", "pwd"]);
+
+        let edited = lines[1].replace("rm -rf build", "ls build");
+        let content = [lines[0].as_str(), &edited, &lines[2]].join("\n");
+        std::fs::write(&path, content).unwrap();
+
+        let err = verify_files(&[&path]).unwrap_err();
+        assert_eq!(err.line, 3);
+        assert!(matches!(err.kind, ChainErrorKind::BrokenLink { .. }));
+    }
+
+    #[test]
+    fn test_verify_detects_deletion() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        let lines = write_chain(&path, &["ls", "curl evil.example", "pwd"]);
+        std::fs::write(&path, format!("{}\n{}\n", lines[0], lines[2])).unwrap();
+
+        let err = verify_files(&[&path]).unwrap_err();
+        assert_eq!(err.line, 2);
+    }
+
+    #[test]
+    fn test_verify_across_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let old = dir.path().join("audit.jsonl.1");
+        let current = dir.path().join("audit.jsonl");
+        let lines = write_chain(&old, &["ls", "pwd", "whoami"]);
+
+        // Split the chain over two files
+        std::fs::write(&old, format!("{}\n{}\n", lines[0], lines[1])).unwrap();
+        std::fs::write(&current, format!("{}\n", lines[2])).unwrap();
+
+        let report = verify_files(&[&old, &current]).unwrap();
+        assert_eq!(report.entries, 3);
+
+        // The newer file alone continues an unchecked chain
+        let report = verify_files(&[&current]).unwrap();
+        assert!(!report.from_genesis);
+
+        // Out of order files do not link
+        assert!(verify_files(&[&current, &old]).is_err());
+    }
+
+    #[test]
+    fn test_verify_malformed_line() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        write_chain(&path, &["ls"]);
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        writeln!(file, "not json").unwrap();
+
+        let err = verify_files(&[&path]).unwrap_err();
+        assert_eq!(err.line, 2);
+        assert!(matches!(err.kind, ChainErrorKind::Malformed(_)));
+    }
+
+    #[test]
+    fn test_last_line() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        assert_eq!(last_line(&path).unwrap(), None);
+
+        std::fs::write(&path, "one\ntwo\n").unwrap();
+        assert_eq!(last_line(&path).unwrap().as_deref(), Some("two"));
+    }
+}
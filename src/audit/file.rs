@@ -0,0 +1,184 @@
+//! Append-only audit file with size and age based rotation.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// An append-only file that is rotated when it grows too large or too old.
+///
+/// Rotated files are renamed to `<path>.1`, `<path>.2`, ... with `.1` the
+/// most recent. Files beyond `max_files` are deleted.
+#[derive(Debug)]
+pub struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    created: SystemTime,
+    max_size: u64,
+    max_age: Option<Duration>,
+    max_files: usize,
+}
+
+impl RotatingFile {
+    /// Open (or create) the file for appending.
+    pub fn open(
+        path: impl Into<PathBuf>,
+        max_size: u64,
+        max_age: Option<Duration>,
+        max_files: usize,
+    ) -> io::Result<Self> {
+        let path = path.into();
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let file = open_append(&path)?;
+        let meta = file.metadata()?;
+        let created = meta
+            .created()
+            .or_else(|_| meta.modified())
+            .unwrap_or_else(|_| SystemTime::now());
+
+        Ok(Self {
+            path,
+            file,
+            size: meta.len(),
+            created,
+            max_size,
+            max_age,
+            max_files,
+        })
+    }
+
+    /// Get the path of the active file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append one line, rotating first if it would exceed the limits.
+    pub fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let len = line.len() as u64 + 1;
+        if self.size > 0 && (self.size + len > self.max_size || self.is_expired()) {
+            self.rotate()?;
+        }
+
+        let mut buf = Vec::with_capacity(line.len() + 1);
+        buf.extend_from_slice(line.as_bytes());
+        buf.push(b'\n');
+
+        // A single write keeps concurrent appenders from interleaving lines
+        self.file.write_all(&buf)?;
+        self.file.flush()?;
+        self.size += len;
+        Ok(())
+    }
+
+    fn is_expired(&self) -> bool {
+        self.max_age.is_some_and(|max_age| {
+            self.created
+                .elapsed()
+                .is_ok_and(|elapsed| elapsed >= max_age)
+        })
+    }
+
+    /// Shift the rotated files up by one and start a new active file.
+    fn rotate(&mut self) -> io::Result<()> {
+        if self.max_files == 0 {
+            std::fs::remove_file(&self.path)?;
+        } else {
+            let _ = std::fs::remove_file(self.rotated_path(self.max_files));
+            for n in (1..self.max_files).rev() {
+                let from = self.rotated_path(n);
+                if from.exists() {
+                    std::fs::rename(&from, self.rotated_path(n + 1))?;
+                }
+            }
+            std::fs::rename(&self.path, self.rotated_path(1))?;
+        }
+
+        self.file = open_append(&self.path)?;
+        self.size = 0;
+        self.created = SystemTime::now();
+        Ok(())
+    }
+
+    /// Path of the `n`th rotated file.
+    pub fn rotated_path(&self, n: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", n));
+        PathBuf::from(path)
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.create(true).append(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotate_by_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.log");
+        let mut file = RotatingFile::open(&path, 10, None, 2).unwrap();
+
+        file.write_line("first").unwrap();
+        file.write_line("second").unwrap();
+        file.write_line("third").unwrap();
+        file.write_line("fourth").unwrap();
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "fourth\n");
+        assert_eq!(
+            std::fs::read_to_string(file.rotated_path(1)).unwrap(),
+            "third\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(file.rotated_path(2)).unwrap(),
+            "second\n"
+        );
+        assert!(!file.rotated_path(3).exists());
+    }
+
+    #[test]
+    fn test_rotate_by_age() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.log");
+        let mut file = RotatingFile::open(&path, u64::MAX, Some(Duration::ZERO), 1).unwrap();
+
+        file.write_line("old").unwrap();
+        file.write_line("new").unwrap();
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "new\n");
+        assert_eq!(
+            std::fs::read_to_string(file.rotated_path(1)).unwrap(),
+            "old\n"
+        );
+    }
+
+    #[test]
+    fn test_reopen_appends() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nested").join("audit.log");
+
+        RotatingFile::open(&path, 1024, None, 1)
+            .unwrap()
+            .write_line("one")
+            .unwrap();
+        RotatingFile::open(&path, 1024, None, 1)
+            .unwrap()
+            .write_line("two")
+            .unwrap();
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "one\ntwo\n");
+    }
+}
@@ -0,0 +1,97 @@
+//! Minimal syslog output over the local `/dev/log` socket.
+
+use std::io;
+
+/// Local syslog socket path.
+#[cfg(unix)]
+pub const SYSLOG_SOCKET: &str = "/dev/log";
+
+/// Facility `authpriv` (10), severity `info` (6).
+const PRIORITY: u8 = (10 << 3) | 6;
+
+/// Sends messages to the local syslog daemon.
+#[derive(Debug)]
+pub struct Syslog {
+    #[cfg(unix)]
+    socket: std::os::unix::net::UnixDatagram,
+    tag: String,
+}
+
+impl Syslog {
+    /// Connect to the local syslog socket.
+    #[cfg(unix)]
+    pub fn connect(tag: impl Into<String>) -> io::Result<Self> {
+        Self::connect_to(SYSLOG_SOCKET, tag)
+    }
+
+    /// Connect to a syslog socket at the given path.
+    #[cfg(unix)]
+    pub fn connect_to(
+        path: impl AsRef<std::path::Path>,
+        tag: impl Into<String>,
+    ) -> io::Result<Self> {
+        let socket = std::os::unix::net::UnixDatagram::unbound()?;
+        socket.connect(path)?;
+        Ok(Self {
+            socket,
+            tag: tag.into(),
+        })
+    }
+
+    /// Syslog is only available on Unix.
+    #[cfg(not(unix))]
+    pub fn connect(_tag: impl Into<String>) -> io::Result<Self> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "syslog is not supported on this platform",
+        ))
+    }
+
+    /// Send one message.
+    pub fn send(&self, message: &str) -> io::Result<()> {
+        let line = format_message(&self.tag, message);
+        #[cfg(unix)]
+        {
+            self.socket.send(line.as_bytes())?;
+            Ok(())
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = line;
+            Err(io::Error::from(io::ErrorKind::Unsupported))
+        }
+    }
+}
+
+/// Format a message the way the local syslog daemon expects (RFC 3164 without a timestamp).
+fn format_message(tag: &str, message: &str) -> String {
+    format!("<{}>{}[{}]: {}", PRIORITY, tag, std::process::id(), message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_message() {
+        let line = format_message("shell-tunnel", "{}");
+        assert!(line.starts_with("<86>shell-tunnel["));
+        assert!(line.ends_with("]: {}"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_send_to_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log.sock");
+        let server = std::os::unix::net::UnixDatagram::bind(&path).unwrap();
+
+        let syslog = Syslog::connect_to(&path, "shell-tunnel").unwrap();
+        syslog.send("hello").unwrap();
+
+        let mut buf = [0u8; 256];
+        let n = server.recv(&mut buf).unwrap();
+        let received = std::str::from_utf8(&buf[..n]).unwrap();
+        assert!(received.ends_with(": hello"));
+    }
+}
@@ -15,12 +15,22 @@ pub struct Args {
     pub port: u16,
     /// Path to configuration file.
     pub config: Option<PathBuf>,
+    /// Unix domain socket to listen on instead of TCP.
+    pub unix_socket: Option<PathBuf>,
     /// API key for authentication (overrides config file).
     pub api_key: Option<String>,
     /// Disable authentication.
     pub no_auth: bool,
     /// Disable rate limiting.
     pub no_rate_limit: bool,
+    /// TLS certificate chain (PEM).
+    pub tls_cert: Option<PathBuf>,
+    /// TLS private key (PEM).
+    pub tls_key: Option<PathBuf>,
+    /// CA bundle for verifying client certificates (PEM).
+    pub tls_client_ca: Option<PathBuf>,
+    /// Audit log file (JSON Lines).
+    pub audit_log: Option<PathBuf>,
     /// Log level (error, warn, info, debug, trace).
     pub log_level: Option<String>,
     /// Show version and exit.
@@ -33,6 +43,18 @@ pub struct Args {
     pub update: bool,
     /// Disable automatic update check on startup.
     pub no_update_check: bool,
+    /// Subcommand to run instead of starting the server.
+    pub subcommand: Option<Subcommand>,
+}
+
+/// Subcommands that run and exit instead of starting the server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Subcommand {
+    /// `audit verify <FILE>...`: check the audit log hash chain.
+    AuditVerify {
+        /// Audit log files, oldest first.
+        files: Vec<PathBuf>,
+    },
 }
 
 impl Default for Args {
@@ -41,15 +63,21 @@ impl Default for Args {
             host: "127.0.0.1".parse().unwrap(),
             port: 3000,
             config: None,
+            unix_socket: None,
             api_key: None,
             no_auth: false,
             no_rate_limit: false,
+            tls_cert: None,
+            tls_key: None,
+            tls_client_ca: None,
+            audit_log: None,
             log_level: None,
             version: false,
             help: false,
             check_update: false,
             update: false,
             no_update_check: false,
+            subcommand: None,
         }
     }
 }
@@ -92,6 +120,9 @@ where
             Short('c') | Long("config") => {
                 result.config = Some(parser.value()?.parse()?);
             }
+            Long("unix-socket") => {
+                result.unix_socket = Some(parser.value()?.parse()?);
+            }
             Short('k') | Long("api-key") => {
                 result.api_key = Some(parser.value()?.parse()?);
             }
@@ -101,6 +132,18 @@ where
             Long("no-rate-limit") => {
                 result.no_rate_limit = true;
             }
+            Long("tls-cert") => {
+                result.tls_cert = Some(parser.value()?.parse()?);
+            }
+            Long("tls-key") => {
+                result.tls_key = Some(parser.value()?.parse()?);
+            }
+            Long("tls-client-ca") => {
+                result.tls_client_ca = Some(parser.value()?.parse()?);
+            }
+            Long("audit-log") => {
+                result.audit_log = Some(parser.value()?.parse()?);
+            }
             Short('l') | Long("log-level") => {
                 result.log_level = Some(parser.value()?.parse()?);
             }
@@ -113,6 +156,9 @@ where
             Long("no-update-check") => {
                 result.no_update_check = true;
             }
+            Value(val) if result.subcommand.is_none() && val == "audit" => {
+                result.subcommand = Some(parse_audit_subcommand(&mut parser)?);
+            }
             Value(val) => {
                 return Err(ArgsError::UnexpectedArgument(val.to_string_lossy().into()));
             }
@@ -123,6 +169,23 @@ where
     Ok(result)
 }
 
+/// Parse the arguments following `audit`.
+fn parse_audit_subcommand(parser: &mut lexopt::Parser) -> Result<Subcommand, ArgsError> {
+    use lexopt::prelude::*;
+
+    let action: String = parser.value()?.parse()?;
+    match action.as_str() {
+        "verify" => {
+            let files: Vec<PathBuf> = parser.raw_args()?.map(PathBuf::from).collect();
+            if files.is_empty() {
+                return Err(ArgsError::MissingArgument("audit verify <FILE>..."));
+            }
+            Ok(Subcommand::AuditVerify { files })
+        }
+        _ => Err(ArgsError::UnexpectedArgument(format!("audit {}", action))),
+    }
+}
+
 /// Print help message.
 pub fn print_help() {
     let version = env!("CARGO_PKG_VERSION");
@@ -132,15 +195,26 @@ Ultra-lightweight shell tunnel for AI agent integration
 
 USAGE:
     shell-tunnel [OPTIONS]
+    shell-tunnel audit verify <FILE>...
+
+SUBCOMMANDS:
+    audit verify <FILE>...  Check the audit log hash chain (rotated files oldest first)
 
 OPTIONS:
     -H, --host <ADDR>       Host address to bind [default: 127.0.0.1]
     -p, --port <PORT>       Port to listen on [default: 3000]
     -c, --config <FILE>     Path to configuration file (JSON)
+        --unix-socket <PATH>
+                            Listen on a Unix domain socket instead of TCP
     -k, --api-key <KEY>     API key for authentication
     -l, --log-level <LVL>   Log level (error, warn, info, debug, trace)
         --no-auth           Disable authentication
         --no-rate-limit     Disable rate limiting
+        --tls-cert <FILE>   TLS certificate chain (PEM), enables HTTPS
+        --tls-key <FILE>    TLS private key (PEM)
+        --tls-client-ca <FILE>
+                            Accept client certificates signed by this CA (PEM)
+        --audit-log <FILE>  Record executed commands to this file (JSON Lines)
         --check-update      Check for updates and exit
         --update            Download and install latest version
         --no-update-check   Disable automatic update check on startup
@@ -150,7 +224,16 @@ OPTIONS:
 ENVIRONMENT VARIABLES:
     SHELL_TUNNEL_HOST       Host address (overrides config)
     SHELL_TUNNEL_PORT       Port number (overrides config)
+    SHELL_TUNNEL_UNIX_SOCKET
+                            Unix socket path (overrides config)
     SHELL_TUNNEL_API_KEY    API key (overrides config)
+    SHELL_TUNNEL_TLS_CERT   TLS certificate path (overrides config)
+    SHELL_TUNNEL_TLS_KEY    TLS private key path (overrides config)
+    SHELL_TUNNEL_TLS_CLIENT_CA
+                            Client CA bundle path (overrides config)
+    SHELL_TUNNEL_TRUSTED_PROXIES
+                            Comma-separated trusted proxy CIDRs (overrides config)
+    SHELL_TUNNEL_AUDIT_LOG  Audit log path (overrides config)
     SHELL_TUNNEL_LOG_LEVEL  Log level (overrides config)
     RUST_LOG                Alternative log level setting
 
@@ -164,6 +247,15 @@ EXAMPLES:
     # Start with config file
     shell-tunnel -c /etc/shell-tunnel/config.json
 
+    # Serve local agents over a Unix socket only
+    shell-tunnel --no-auth --unix-socket /run/shell-tunnel/api.sock
+
+    # Serve HTTPS/WSS directly (send SIGHUP to reload the certificate)
+    shell-tunnel -k my-secret-key --tls-cert cert.pem --tls-key key.pem
+
+    # Authenticate agents by client certificate
+    shell-tunnel --tls-cert cert.pem --tls-key key.pem --tls-client-ca agents-ca.pem
+
     # Development mode (no security)
     shell-tunnel --no-auth --no-rate-limit
 
@@ -190,6 +282,8 @@ pub enum ArgsError {
     InvalidValue(&'static str, String),
     /// Unexpected positional argument.
     UnexpectedArgument(String),
+    /// Required positional argument missing.
+    MissingArgument(&'static str),
 }
 
 impl std::fmt::Display for ArgsError {
@@ -202,6 +296,9 @@ impl std::fmt::Display for ArgsError {
             Self::UnexpectedArgument(arg) => {
                 write!(f, "unexpected argument: '{}'", arg)
             }
+            Self::MissingArgument(usage) => {
+                write!(f, "missing argument, usage: shell-tunnel {}", usage)
+            }
         }
     }
 }
@@ -271,6 +368,49 @@ mod tests {
         assert!(result.no_rate_limit);
     }
 
+    #[test]
+    fn test_tls_options() {
+        let result =
+            parse_args_from(args(&["--tls-cert", "cert.pem", "--tls-key", "key.pem"])).unwrap();
+        assert_eq!(result.tls_cert, Some(PathBuf::from("cert.pem")));
+        assert_eq!(result.tls_key, Some(PathBuf::from("key.pem")));
+        assert!(result.tls_client_ca.is_none());
+
+        let result = parse_args_from(args(&["--tls-client-ca", "ca.pem"])).unwrap();
+        assert_eq!(result.tls_client_ca, Some(PathBuf::from("ca.pem")));
+    }
+
+    #[test]
+    fn test_audit_log_option() {
+        let result = parse_args_from(args(&["--audit-log", "audit.jsonl"])).unwrap();
+        assert_eq!(result.audit_log, Some(PathBuf::from("audit.jsonl")));
+    }
+
+    #[test]
+    fn test_audit_verify_subcommand() {
+        let result =
+            parse_args_from(args(&["audit", "verify", "audit.jsonl.1", "audit.jsonl"])).unwrap();
+        assert_eq!(
+            result.subcommand,
+            Some(Subcommand::AuditVerify {
+                files: vec![PathBuf::from("audit.jsonl.1"), PathBuf::from("audit.jsonl")],
+            })
+        );
+
+        assert!(matches!(
+            parse_args_from(args(&["audit", "verify"])),
+            Err(ArgsError::MissingArgument(_))
+        ));
+        assert!(parse_args_from(args(&["audit", "rewrite", "a"])).is_err());
+        assert!(parse_args_from(args(&["verify"])).is_err());
+    }
+
+    #[test]
+    fn test_unix_socket_option() {
+        let result = parse_args_from(args(&["--unix-socket", "/run/st.sock"])).unwrap();
+        assert_eq!(result.unix_socket, Some(PathBuf::from("/run/st.sock")));
+    }
+
     #[test]
     fn test_help_flag() {
         let result = parse_args_from(args(&["-h"])).unwrap();
@@ -63,6 +63,22 @@ pub enum ShellTunnelError {
     /// Update error.
     #[error("update error: {0}")]
     Update(String),
+
+    /// Commands could not be run as the configured account.
+    #[error("cannot run as configured user: {0}")]
+    RunAs(String),
+
+    /// The command sandbox could not be set up.
+    #[error("cannot set up sandbox: {0}")]
+    Sandbox(String),
+
+    /// Session cgroups could not be set up or used.
+    #[error("cgroup error: {0}")]
+    Cgroup(String),
+
+    /// TLS configuration or handshake error.
+    #[error("TLS error: {0}")]
+    Tls(String),
 }
 
 /// Convenience Result type for shell-tunnel operations.
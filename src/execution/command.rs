@@ -4,6 +4,8 @@ use std::collections::HashMap;
 use std::path::PathBuf;
 use std::time::Duration;
 
+use crate::pty::{ResourceLimits, RunAs, Sandbox, SpawnOptions};
+
 /// A command to be executed in a shell session.
 #[derive(Debug, Clone)]
 pub struct Command {
@@ -15,8 +17,20 @@ pub struct Command {
     pub env: HashMap<String, String>,
     /// Maximum execution time.
     pub timeout: Option<Duration>,
+    /// Maximum time without any output.
+    pub idle_timeout: Option<Duration>,
+    /// Maximum bytes of output before the command is killed.
+    pub max_output: Option<usize>,
     /// Whether to capture output.
     pub capture_output: bool,
+    /// Account to run as (the server's own if `None`).
+    pub run_as: Option<RunAs>,
+    /// Resource limits of the command's process.
+    pub limits: ResourceLimits,
+    /// Sandbox the command runs in (unconfined if `None`).
+    pub sandbox: Option<Sandbox>,
+    /// cgroup v2 directory the command's process joins.
+    pub cgroup: Option<PathBuf>,
 }
 
 impl Command {
@@ -27,7 +41,13 @@ impl Command {
             working_dir: None,
             env: HashMap::new(),
             timeout: None,
+            idle_timeout: None,
+            max_output: None,
             capture_output: true,
+            run_as: None,
+            limits: ResourceLimits::default(),
+            sandbox: None,
+            cgroup: None,
         }
     }
 
@@ -62,11 +82,56 @@ impl Command {
         self
     }
 
+    /// Set how long the command may go without producing output.
+    pub fn idle_timeout(mut self, duration: Duration) -> Self {
+        self.idle_timeout = Some(duration);
+        self
+    }
+
+    /// Kill the command once it printed more than `bytes` of output.
+    pub fn max_output(mut self, bytes: usize) -> Self {
+        self.max_output = Some(bytes);
+        self
+    }
+
     /// Set whether to capture output.
     pub fn capture_output(mut self, capture: bool) -> Self {
         self.capture_output = capture;
         self
     }
+
+    /// Run as another account; requires the server to run as root.
+    pub fn run_as(mut self, run_as: Option<RunAs>) -> Self {
+        self.run_as = run_as;
+        self
+    }
+
+    /// Set resource limits.
+    pub fn limits(mut self, limits: ResourceLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Run in a sandbox; only supported on Linux.
+    pub fn sandbox(mut self, sandbox: Option<Sandbox>) -> Self {
+        self.sandbox = sandbox;
+        self
+    }
+
+    /// Run in the given cgroup; only supported on Linux.
+    pub fn cgroup(mut self, cgroup: Option<PathBuf>) -> Self {
+        self.cgroup = cgroup;
+        self
+    }
+
+    /// Options for spawning the command's process.
+    pub(crate) fn spawn_options(&self) -> SpawnOptions {
+        SpawnOptions::default()
+            .with_run_as(self.run_as.clone())
+            .with_limits(self.limits)
+            .with_sandbox(self.sandbox.clone())
+            .with_cgroup(self.cgroup.clone())
+    }
 }
 
 impl Default for Command {
@@ -82,6 +147,7 @@ pub struct CommandBuilder {
     working_dir: Option<PathBuf>,
     env: HashMap<String, String>,
     timeout: Option<Duration>,
+    idle_timeout: Option<Duration>,
     capture_output: bool,
 }
 
@@ -118,6 +184,12 @@ impl CommandBuilder {
         self
     }
 
+    /// Set how long the command may go without producing output.
+    pub fn idle_timeout(mut self, duration: Duration) -> Self {
+        self.idle_timeout = Some(duration);
+        self
+    }
+
     /// Set whether to capture output.
     pub fn capture_output(mut self, capture: bool) -> Self {
         self.capture_output = capture;
@@ -133,7 +205,13 @@ impl CommandBuilder {
             working_dir: self.working_dir,
             env: self.env,
             timeout: self.timeout,
+            idle_timeout: self.idle_timeout,
+            max_output: None,
             capture_output: self.capture_output,
+            run_as: None,
+            limits: ResourceLimits::default(),
+            sandbox: None,
+            cgroup: None,
         })
     }
 }
@@ -149,7 +227,9 @@ mod tests {
         assert!(cmd.working_dir.is_none());
         assert!(cmd.env.is_empty());
         assert!(cmd.timeout.is_none());
+        assert!(cmd.idle_timeout.is_none());
         assert!(cmd.capture_output);
+        assert!(cmd.run_as.is_none());
     }
 
     #[test]
@@ -158,12 +238,14 @@ mod tests {
             .working_dir("/project")
             .env("RUST_LOG", "debug")
             .timeout(Duration::from_secs(60))
+            .idle_timeout(Duration::from_secs(10))
             .capture_output(true);
 
         assert_eq!(cmd.command_line, "cargo build");
         assert_eq!(cmd.working_dir, Some(PathBuf::from("/project")));
         assert_eq!(cmd.env.get("RUST_LOG"), Some(&"debug".to_string()));
         assert_eq!(cmd.timeout, Some(Duration::from_secs(60)));
+        assert_eq!(cmd.idle_timeout, Some(Duration::from_secs(10)));
     }
 
     #[test]
@@ -1,17 +1,18 @@
 //! Command execution engine.
 
-use std::io::Read;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, MutexGuard};
 use std::time::{Duration, Instant};
 
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, watch};
 
 use super::command::Command;
 use super::result::{ExecutionResult, OutputChunk};
 use crate::error::ShellTunnelError;
 use crate::output::OutputSanitizer;
-use crate::pty::NativePty;
-use crate::session::{SessionState, SessionStore};
+use crate::pty::{
+    AsyncMaster, ExitWatcher, NativePty, ProcessGroup, ResourceLimits, SpawnedShell, KILL_GRACE,
+};
+use crate::session::{SessionId, SessionState, SessionStore, Terminal};
 use crate::Result;
 
 /// Default execution timeout.
@@ -20,7 +21,21 @@ pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
 /// Default buffer size for reading PTY output.
 const READ_BUFFER_SIZE: usize = 4096;
 
+/// How long output is still awaited after a command exited while background
+/// jobs keep its terminal open.
+const EXIT_DRAIN: Duration = Duration::from_millis(100);
+
+/// Output receiver, result handle and cancel handle of a streamed command.
+pub type StreamingExecution = (
+    mpsc::Receiver<OutputChunk>,
+    tokio::task::JoinHandle<Result<ExecutionResult>>,
+    CancelHandle,
+);
+
 /// Command executor for running commands in shell sessions.
+///
+/// Output and exit of running commands are driven by tokio's reactor, so
+/// waiting commands do not occupy threads.
 pub struct CommandExecutor {
     store: Arc<SessionStore>,
 }
@@ -31,189 +46,393 @@ impl CommandExecutor {
         Self { store }
     }
 
+    /// Execute a command and wait for completion or timeout.
+    ///
+    /// The command's whole process group is killed on timeout, and
+    /// background jobs still running when it exits are killed too.
+    pub async fn execute(&self, command: &Command) -> Result<ExecutionResult> {
+        Running::spawn(command)?.collect(None).await
+    }
+
     /// Execute a command synchronously (blocking).
     ///
-    /// This runs the command and waits for completion or timeout.
+    /// Like [`execute`](Self::execute), on a runtime of its own. Must not be
+    /// called from within an async runtime.
     pub fn execute_sync(&self, command: &Command) -> Result<ExecutionResult> {
-        let start = Instant::now();
-        let timeout_duration = command.timeout.unwrap_or(DEFAULT_TIMEOUT);
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?
+            .block_on(self.execute(command))
+    }
 
-        // Create PTY and spawn command directly (non-interactive)
-        let mut pty = NativePty::new();
-        let mut shell = pty.spawn_command(&command.command_line, command.working_dir.as_deref())?;
-        let mut reader = shell.take_reader()?;
+    /// Execute a command asynchronously.
+    ///
+    /// Returns a receiver for streaming output chunks, a handle resolving to
+    /// the result and a handle to cancel the command with.
+    pub async fn execute_async(&self, command: &Command) -> Result<StreamingExecution> {
+        self.stream(command)
+    }
 
-        // Collect output with timeout
-        let mut raw_output = Vec::new();
-        let mut buf = [0u8; READ_BUFFER_SIZE];
+    /// Execute a command in an existing session.
+    ///
+    /// The command runs in the session's terminal, in the same shell people
+    /// attached to the terminal see, so `cd`, variables and background jobs
+    /// carry over to later commands. Background jobs are killed when the
+    /// session is deleted.
+    pub async fn execute_in_session(
+        &self,
+        session_id: &SessionId,
+        command: &Command,
+    ) -> Result<ExecutionResult> {
+        let terminal = self.session_terminal(session_id)?;
+        let cancel = CancelHandle::new(None);
+        run_in_session(&self.store, session_id, &terminal, command, None, &cancel).await
+    }
 
-        loop {
-            if start.elapsed() > timeout_duration {
-                let text = OutputSanitizer::strip_ansi(&raw_output);
-                return Ok(ExecutionResult::timeout(raw_output, text, start.elapsed()));
-            }
+    /// Execute a command asynchronously in an existing session.
+    ///
+    /// Like [`execute_in_session`](Self::execute_in_session), streaming the
+    /// output like [`execute_async`](Self::execute_async).
+    pub async fn execute_async_in_session(
+        &self,
+        session_id: &SessionId,
+        command: &Command,
+    ) -> Result<StreamingExecution> {
+        let terminal = self.session_terminal(session_id)?;
+        let (tx, rx) = mpsc::channel::<OutputChunk>(64);
+        let cancel = CancelHandle::new(None);
+        let handle = tokio::spawn({
+            let (store, id, command, cancel) = (
+                Arc::clone(&self.store),
+                *session_id,
+                command.clone(),
+                cancel.clone(),
+            );
+            async move { run_in_session(&store, &id, &terminal, &command, Some(&tx), &cancel).await }
+        });
+        Ok((rx, handle, cancel))
+    }
 
-            match reader.read(&mut buf) {
-                Ok(0) => break, // EOF
-                Ok(n) => {
-                    raw_output.extend_from_slice(&buf[..n]);
-                }
-                Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                    std::thread::sleep(Duration::from_millis(10));
-                    continue;
-                }
-                Err(e) => return Err(ShellTunnelError::Io(e)),
-            }
+    /// The terminal of a session that can execute commands.
+    fn session_terminal(&self, session_id: &SessionId) -> Result<Arc<Terminal>> {
+        let session = self
+            .store
+            .get(session_id)?
+            .ok_or_else(|| ShellTunnelError::SessionNotFound(session_id.to_string()))?;
+        if !session.state.can_execute() {
+            return Err(ShellTunnelError::NotExecutable(session.state));
+        }
+        self.store.terminal(session_id)
+    }
 
-            // Check if child has exited
-            if let Ok(Some(_)) = shell.try_wait() {
-                // Read any remaining output
-                while let Ok(n) = reader.read(&mut buf) {
-                    if n == 0 {
-                        break;
-                    }
-                    raw_output.extend_from_slice(&buf[..n]);
-                }
-                break;
-            }
+    /// Spawn a command and collect its output on a task of its own.
+    fn stream(&self, command: &Command) -> Result<StreamingExecution> {
+        let (tx, rx) = mpsc::channel::<OutputChunk>(64);
+        let running = Running::spawn(command)?;
+        let cancel = running.cancel.clone();
+        let handle = tokio::spawn(async move { running.collect(Some(&tx)).await });
+        Ok((rx, handle, cancel))
+    }
+}
+
+/// Cancels a running command.
+#[derive(Debug, Clone)]
+pub struct CancelHandle {
+    cancelled: Arc<watch::Sender<bool>>,
+    /// The command's process group, until its leader is reaped.
+    group: Arc<Mutex<Option<ProcessGroup>>>,
+}
+
+impl CancelHandle {
+    pub(crate) fn new(group: Option<ProcessGroup>) -> Self {
+        Self {
+            cancelled: Arc::new(watch::channel(false).0),
+            group: Arc::new(Mutex::new(group)),
         }
+    }
 
-        let duration = start.elapsed();
-        let exit_status = shell.wait().ok();
-        let exit_code = exit_status.map(|s| {
-            if s.success() {
-                0i32
-            } else {
-                s.exit_code() as i32
+    /// Kill the command together with its process group.
+    ///
+    /// The command's result is reported as cancelled.
+    pub fn cancel(&self) {
+        self.cancelled.send_replace(true);
+        #[cfg(unix)]
+        if let Some(group) = *self.lock_group() {
+            if let Err(e) = group.signal(libc::SIGKILL) {
+                tracing::warn!("Failed to kill process group {}: {}", group, e);
             }
-        });
+        }
+    }
 
-        let text = OutputSanitizer::strip_ansi(&raw_output);
-        let mut result = ExecutionResult::new(raw_output, text, duration);
-        if let Some(code) = exit_code {
-            result = result.with_exit_code(code);
+    /// The command's process group, unless its leader was reaped.
+    fn group(&self) -> Option<ProcessGroup> {
+        *self.lock_group()
+    }
+
+    /// Stop signalling the command's process group, whose leader is about
+    /// to be reaped and its id free to be reused.
+    fn release(&self) -> Option<ProcessGroup> {
+        self.lock_group().take()
+    }
+
+    fn lock_group(&self) -> MutexGuard<'_, Option<ProcessGroup>> {
+        match self.group.lock() {
+            Ok(group) => group,
+            Err(poisoned) => poisoned.into_inner(),
         }
+    }
 
-        Ok(result)
+    /// Whether the command was cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        *self.cancelled.borrow()
     }
 
-    /// Execute a command asynchronously.
-    ///
-    /// Returns a receiver for streaming output chunks.
-    pub async fn execute_async(
-        &self,
-        command: &Command,
-    ) -> Result<(
-        mpsc::Receiver<OutputChunk>,
-        tokio::task::JoinHandle<Result<ExecutionResult>>,
-    )> {
-        let (tx, rx) = mpsc::channel::<OutputChunk>(64);
-        let timeout_duration = command.timeout.unwrap_or(DEFAULT_TIMEOUT);
-        let cmd_line = command.command_line.clone();
-        let working_dir = command.working_dir.clone();
-
-        let handle = tokio::task::spawn_blocking(move || {
-            let start = Instant::now();
-
-            // Create PTY and spawn command directly (non-interactive)
-            let mut pty = NativePty::new();
-            let mut shell = pty.spawn_command(&cmd_line, working_dir.as_deref())?;
-            let mut reader = shell.take_reader()?;
-
-            // Collect output
-            let mut raw_output = Vec::new();
-            let mut buf = [0u8; READ_BUFFER_SIZE];
-
-            loop {
-                if start.elapsed() > timeout_duration {
-                    let text = OutputSanitizer::strip_ansi(&raw_output);
-                    return Ok(ExecutionResult::timeout(raw_output, text, start.elapsed()));
-                }
+    /// Wait until the command is cancelled.
+    pub(crate) async fn cancelled(&self) {
+        let mut cancelled = self.cancelled.subscribe();
+        // The sender lives as long as self
+        let _ = cancelled.wait_for(|c| *c).await;
+    }
+}
 
-                match reader.read(&mut buf) {
-                    Ok(0) => break,
-                    Ok(n) => {
-                        let chunk_data = buf[..n].to_vec();
-                        raw_output.extend_from_slice(&chunk_data);
+/// How collecting a command's output ended.
+enum End {
+    Exited,
+    TimedOut,
+    Idle,
+    OutputLimit,
+    Cancelled,
+    Failed(std::io::Error),
+}
 
-                        // Send chunk (ignore if receiver dropped)
-                        let _ = tx.blocking_send(OutputChunk::combined(chunk_data));
-                    }
-                    Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                        std::thread::sleep(Duration::from_millis(10));
-                        continue;
-                    }
-                    Err(e) => return Err(ShellTunnelError::Io(e)),
-                }
+/// A spawned command whose output is being collected.
+struct Running {
+    shell: SpawnedShell,
+    output: AsyncMaster,
+    exit: ExitWatcher,
+    start: Instant,
+    /// When the command times out (never if the timeout overflows).
+    deadline: Option<tokio::time::Instant>,
+    idle_timeout: Option<Duration>,
+    max_output: Option<usize>,
+    limits: ResourceLimits,
+    cancel: CancelHandle,
+}
+
+impl Running {
+    /// Spawn a command on a new PTY.
+    ///
+    /// Must be called within a tokio runtime.
+    fn spawn(command: &Command) -> Result<Self> {
+        let start = Instant::now();
+        let timeout = command.timeout.unwrap_or(DEFAULT_TIMEOUT);
 
-                if let Ok(Some(_)) = shell.try_wait() {
-                    while let Ok(n) = reader.read(&mut buf) {
-                        if n == 0 {
-                            break;
+        // Create PTY and spawn command directly (non-interactive)
+        let mut pty = NativePty::new().with_options(command.spawn_options());
+        let mut shell = pty.spawn_command(&command.command_line, command.working_dir.as_deref())?;
+        let watched = shell
+            .async_master()
+            .and_then(|output| Ok((output, shell.exit_watcher()?)));
+        let (output, exit) = match watched {
+            Ok(watched) => watched,
+            Err(e) => {
+                let _ = shell.kill();
+                let _ = shell.wait();
+                return Err(e);
+            }
+        };
+        let cancel = CancelHandle::new(shell.process_group());
+
+        Ok(Self {
+            shell,
+            output,
+            exit,
+            start,
+            deadline: start
+                .checked_add(timeout)
+                .map(tokio::time::Instant::from_std),
+            idle_timeout: command.idle_timeout,
+            max_output: command.max_output,
+            limits: command.limits,
+            cancel,
+        })
+    }
+
+    /// Collect output until the command exits, times out or is cancelled,
+    /// forwarding each chunk to `tx`.
+    async fn collect(mut self, tx: Option<&mpsc::Sender<OutputChunk>>) -> Result<ExecutionResult> {
+        let mut raw_output = Vec::new();
+        let mut buf = [0u8; READ_BUFFER_SIZE];
+        let mut eof = false;
+        // Set once the command exited
+        let mut drain_until: Option<tokio::time::Instant> = None;
+        let idle_after =
+            |now: tokio::time::Instant| self.idle_timeout.and_then(|idle| now.checked_add(idle));
+        let mut idle_until = idle_after(tokio::time::Instant::now());
+
+        let end = loop {
+            if eof && drain_until.is_some() {
+                break End::Exited;
+            }
+            let drained = sleep_until(drain_until);
+            let idle = sleep_until(idle_until.filter(|_| drain_until.is_none()));
+
+            tokio::select! {
+                _ = self.cancel.cancelled() => break End::Cancelled,
+                _ = sleep_until(self.deadline) => break End::TimedOut,
+                _ = idle => break End::Idle,
+                // Background jobs still hold the terminal
+                _ = drained => break End::Exited,
+                read = self.output.read(&mut buf), if !eof => match read {
+                    Ok(0) => eof = true,
+                    Ok(n) => {
+                        idle_until = idle_after(tokio::time::Instant::now());
+                        let room = self.max_output.map_or(n, |max| max - raw_output.len());
+                        record(&buf[..n.min(room)], &mut raw_output, tx).await;
+                        if n > room {
+                            break End::OutputLimit;
                         }
-                        let chunk_data = buf[..n].to_vec();
-                        raw_output.extend_from_slice(&chunk_data);
-                        let _ = tx.blocking_send(OutputChunk::combined(chunk_data));
                     }
-                    break;
+                    Err(e) => break End::Failed(e),
+                },
+                _ = self.exit.changed(), if drain_until.is_none() => {
+                    if !matches!(self.shell.has_exited(), Ok(false)) {
+                        drain_until = Some(tokio::time::Instant::now() + EXIT_DRAIN);
+                    }
                 }
             }
+        };
 
-            let duration = start.elapsed();
-            let exit_status = shell.wait().ok();
-            let exit_code = exit_status.map(|s| {
-                if s.success() {
-                    0i32
-                } else {
-                    s.exit_code() as i32
+        match end {
+            // Killing the group may end the output first
+            _ if self.cancel.is_cancelled() => {
+                Ok(self.kill(raw_output, ExecutionResult::cancelled).await)
+            }
+            End::Cancelled => Ok(self.kill(raw_output, ExecutionResult::cancelled).await),
+            End::TimedOut => Ok(self.kill(raw_output, ExecutionResult::timeout).await),
+            End::Idle => Ok(self.kill(raw_output, ExecutionResult::idle_timeout).await),
+            End::OutputLimit => Ok(self.kill(raw_output, ExecutionResult::output_limit).await),
+            End::Failed(e) => {
+                self.kill(raw_output, ExecutionResult::new).await;
+                Err(ShellTunnelError::Io(e))
+            }
+            End::Exited => {
+                // Background jobs go with the command. The leader is not
+                // reaped yet, so the group id is still its own.
+                #[cfg(unix)]
+                if let Some(group) = self.cancel.release() {
+                    if let Err(e) = group.signal(libc::SIGKILL) {
+                        tracing::warn!("Failed to kill process group {}: {}", group, e);
+                    }
                 }
-            });
-
-            let text = OutputSanitizer::strip_ansi(&raw_output);
-            let mut result = ExecutionResult::new(raw_output, text, duration);
-            if let Some(code) = exit_code {
-                result = result.with_exit_code(code);
+                Ok(finish(
+                    &mut self.shell,
+                    raw_output,
+                    self.start,
+                    &self.limits,
+                ))
             }
-
-            Ok(result)
-        });
-
-        Ok((rx, handle))
+        }
     }
 
-    /// Execute a command in an existing session.
-    pub async fn execute_in_session(
-        &self,
-        session_id: &crate::session::SessionId,
-        command: &Command,
-    ) -> Result<ExecutionResult> {
-        // Verify session exists and is executable
-        let session = self
-            .store
-            .get(session_id)?
-            .ok_or_else(|| ShellTunnelError::SessionNotFound(session_id.to_string()))?;
-
-        if !session.state.can_execute() {
-            return Err(ShellTunnelError::NotExecutable(session.state));
+    /// Kill the command's process group and build a result with `make`.
+    async fn kill(
+        &mut self,
+        raw_output: Vec<u8>,
+        make: fn(Vec<u8>, String, Duration) -> ExecutionResult,
+    ) -> ExecutionResult {
+        if let Err(e) = self.shell.kill() {
+            tracing::warn!("Failed to kill command: {}", e);
+        }
+        while matches!(self.shell.has_exited(), Ok(false)) {
+            self.exit.changed().await;
+        }
+        // The leader is left a zombie meanwhile, so that no other group
+        // can take over the id
+        let leftover_pids = match self.cancel.group() {
+            Some(group) => tokio::task::spawn_blocking(move || group.wait_gone(KILL_GRACE))
+                .await
+                .unwrap_or_default(),
+            None => Vec::new(),
+        };
+        self.cancel.release();
+        // Reap the leader so it does not linger as a zombie
+        let _ = self.shell.wait();
+        if !leftover_pids.is_empty() {
+            tracing::warn!("Processes {:?} survived being killed", leftover_pids);
         }
+        let text = OutputSanitizer::strip_ansi(&raw_output);
+        make(raw_output, text, self.start.elapsed()).with_leftover_pids(leftover_pids)
+    }
+}
 
-        // Mark session as active
-        self.store.update(session_id, |s| {
-            let _ = s.state.transition_to(SessionState::Active);
-            s.touch();
-        })?;
+/// Sleep until the given instant, or forever without one.
+pub(crate) async fn sleep_until(until: Option<tokio::time::Instant>) {
+    match until {
+        Some(until) => tokio::time::sleep_until(until).await,
+        None => std::future::pending().await,
+    }
+}
 
-        // Execute command
-        let result = self.execute_sync(command);
+/// Append a chunk of output, forwarding it if streaming.
+pub(crate) async fn record(
+    data: &[u8],
+    raw_output: &mut Vec<u8>,
+    tx: Option<&mpsc::Sender<OutputChunk>>,
+) {
+    raw_output.extend_from_slice(data);
+    if let Some(tx) = tx {
+        // Ignore if receiver dropped
+        let _ = tx.send(OutputChunk::combined(data.to_vec())).await;
+    }
+}
 
-        // Mark session as idle
-        self.store.update(session_id, |s| {
-            let _ = s.state.transition_to(SessionState::Idle);
-            s.touch();
-        })?;
+/// Run a command in a session's terminal, marking the session active
+/// meanwhile.
+async fn run_in_session(
+    store: &SessionStore,
+    session_id: &SessionId,
+    terminal: &Terminal,
+    command: &Command,
+    tx: Option<&mpsc::Sender<OutputChunk>>,
+    cancel: &CancelHandle,
+) -> Result<ExecutionResult> {
+    store.update(session_id, |s| {
+        let _ = s.state.transition_to(SessionState::Active);
+        s.touch();
+    })?;
+
+    let result = terminal.run(command, tx, cancel).await;
+
+    store.update(session_id, |s| {
+        let _ = s.state.transition_to(SessionState::Idle);
+        s.touch();
+    })?;
+
+    result
+}
 
-        result
+/// Wait for an exited command and build its result.
+fn finish(
+    shell: &mut SpawnedShell,
+    raw_output: Vec<u8>,
+    start: Instant,
+    limits: &ResourceLimits,
+) -> ExecutionResult {
+    let duration = start.elapsed();
+    let text = OutputSanitizer::strip_ansi(&raw_output);
+    let mut result = ExecutionResult::new(raw_output, text, duration);
+    if let Ok(status) = shell.wait() {
+        result = result.with_exit_status(status);
+        #[cfg(unix)]
+        if let Some(signal) = status.signal() {
+            result = result.with_limit_exceeded(limits.exceeded_by(signal));
+        }
     }
+    #[cfg(not(unix))]
+    let _ = limits;
+
+    result
 }
 
 /// Simple one-shot command execution.
@@ -235,6 +454,7 @@ pub fn execute_with_timeout(command_line: &str, timeout: Duration) -> Result<Exe
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::pty::RunAs;
 
     #[test]
     fn test_executor_new() {
@@ -266,6 +486,286 @@ mod tests {
         assert!(!result.timed_out);
     }
 
+    #[cfg(unix)]
+    #[test]
+    #[ignore] // PTY tests need special handling
+    fn test_execute_as_user() {
+        // SAFETY: geteuid has no preconditions.
+        if unsafe { libc::geteuid() } != 0 {
+            return;
+        }
+        let store = Arc::new(SessionStore::new());
+        let executor = CommandExecutor::new(store);
+        let cmd = Command::new("id -u; id -G").run_as(Some(RunAs::user("nobody")));
+        let result = executor.execute_sync(&cmd).unwrap();
+        let nobody = RunAs::user("nobody").resolve().unwrap();
+        let lines: Vec<_> = result.text_output.lines().map(str::trim).collect();
+        assert_eq!(lines[0], nobody.uid.to_string());
+        assert_eq!(lines[1], nobody.gid.to_string());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    #[ignore] // PTY tests need special handling
+    fn test_execute_reports_exceeded_limit() {
+        use crate::pty::LimitExceeded;
+
+        let store = Arc::new(SessionStore::new());
+        let executor = CommandExecutor::new(store);
+        let cpu = ResourceLimits {
+            cpu_secs: Some(1),
+            ..Default::default()
+        };
+        let cmd = Command::new("while :; do :; done").limits(cpu);
+        let result = executor.execute_sync(&cmd).unwrap();
+        assert_eq!(result.limit_exceeded, Some(LimitExceeded::CpuTime));
+        assert!(!result.success());
+
+        let dir = tempfile::tempdir().unwrap();
+        let file = ResourceLimits {
+            file_size_bytes: Some(1024),
+            ..Default::default()
+        };
+        let cmd = Command::new("exec head -c 4096 /dev/zero > big")
+            .working_dir(dir.path())
+            .limits(file);
+        let result = executor.execute_sync(&cmd).unwrap();
+        assert_eq!(result.limit_exceeded, Some(LimitExceeded::FileSize));
+
+        let result = execute_simple("exit 3").unwrap();
+        assert_eq!(result.limit_exceeded, None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    #[ignore] // PTY tests need special handling
+    fn test_execute_in_sandbox() {
+        use crate::pty::Sandbox;
+        use std::os::unix::fs::PermissionsExt;
+
+        let work = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        let mut run_as = None;
+        // SAFETY: geteuid has no preconditions.
+        if unsafe { libc::geteuid() } == 0 {
+            // Exercise the unprivileged user namespace path
+            for dir in [work.path(), outside.path()] {
+                std::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o777)).unwrap();
+            }
+            run_as = Some(RunAs::user("nobody"));
+        }
+
+        let store = Arc::new(SessionStore::new());
+        let executor = CommandExecutor::new(store);
+        let cmd = Command::new(format!(
+            "touch inside; touch {}/outside",
+            outside.path().display()
+        ))
+        .working_dir(work.path())
+        .run_as(run_as)
+        .sandbox(Some(Sandbox::new()));
+        let result = executor.execute_sync(&cmd).unwrap();
+        assert!(!result.success());
+        assert!(work.path().join("inside").exists());
+        assert!(!outside.path().join("outside").exists());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    #[ignore] // PTY tests need special handling
+    fn test_timeout_kills_process_group() {
+        let store = Arc::new(SessionStore::new());
+        let executor = CommandExecutor::new(store);
+        let cmd = Command::new("sleep 60 & nohup sleep 60 >/dev/null 2>&1 & echo $$; sleep 60")
+            .timeout(Duration::from_millis(500));
+
+        let result = executor.execute_sync(&cmd).unwrap();
+        assert!(result.timed_out);
+        assert!(result.leftover_pids.is_empty());
+        let pgid: u32 = result
+            .output_lines()
+            .next()
+            .unwrap()
+            .trim()
+            .parse()
+            .unwrap();
+        assert!(ProcessGroup::new(pgid).members().is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    #[ignore] // PTY tests need special handling
+    fn test_termination_reason() {
+        use crate::execution::Termination;
+
+        let result = execute_simple("exit 300").unwrap();
+        assert_eq!(result.exit_code, Some(44));
+        assert_eq!(result.termination, Some(Termination::Exited { code: 44 }));
+
+        let result = execute_simple("kill -TERM $$").unwrap();
+        assert_eq!(result.exit_code, None);
+        assert_eq!(
+            result.termination,
+            Some(Termination::Signaled {
+                signal: libc::SIGTERM,
+                name: "SIGTERM".to_string(),
+                core_dumped: false,
+            })
+        );
+
+        let store = Arc::new(SessionStore::new());
+        let executor = CommandExecutor::new(store);
+        let cmd = Command::new("while :; do echo spam; done").max_output(1000);
+        let result = executor.execute_sync(&cmd).unwrap();
+        assert_eq!(result.termination, Some(Termination::OutputLimit));
+        assert_eq!(result.raw_output.len(), 1000);
+    }
+
+    #[test]
+    #[ignore] // PTY tests need special handling
+    fn test_session_exit_code_is_the_commands() {
+        use crate::session::SessionConfig;
+
+        let store = Arc::new(SessionStore::new());
+        let id = store.create(SessionConfig::default()).unwrap();
+        store.update(&id, |s| s.state = SessionState::Idle).unwrap();
+        let executor = CommandExecutor::new(Arc::clone(&store));
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+
+        for (command, code) in [("true; false", 1), ("false; exit 7", 7), ("false; true", 0)] {
+            let result = runtime
+                .block_on(executor.execute_in_session(&id, &Command::new(command)))
+                .unwrap();
+            assert_eq!(result.exit_code, Some(code), "{}", command);
+        }
+    }
+
+    #[test]
+    #[ignore] // PTY tests need special handling
+    fn test_idle_timeout() {
+        let store = Arc::new(SessionStore::new());
+        let executor = CommandExecutor::new(store);
+
+        let busy = Command::new("for i in 1 2 3 4 5; do echo $i; sleep 0.2; done")
+            .idle_timeout(Duration::from_millis(800));
+        let result = executor.execute_sync(&busy).unwrap();
+        assert!(result.success());
+        assert!(!result.idle_timed_out);
+
+        let silent =
+            Command::new("echo started; sleep 60").idle_timeout(Duration::from_millis(300));
+        let result = executor.execute_sync(&silent).unwrap();
+        assert!(result.idle_timed_out);
+        assert!(!result.timed_out);
+        assert!(result.text_output.contains("started"));
+        assert!(result.duration < Duration::from_secs(5));
+    }
+
+    #[test]
+    #[ignore] // PTY tests need special handling
+    fn test_huge_timeouts_never_expire() {
+        let store = Arc::new(SessionStore::new());
+        let executor = CommandExecutor::new(store);
+
+        let cmd = Command::new("echo done")
+            .timeout(Duration::from_secs(u64::MAX))
+            .idle_timeout(Duration::from_secs(u64::MAX));
+        let result = executor.execute_sync(&cmd).unwrap();
+        assert!(result.success());
+        assert!(!result.timed_out && !result.idle_timed_out);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_cancel_leaves_released_group_alone() {
+        use std::os::unix::process::CommandExt;
+
+        let mut leader = std::process::Command::new("sleep")
+            .arg("30")
+            .process_group(0)
+            .spawn()
+            .unwrap();
+        let cancel = CancelHandle::new(Some(ProcessGroup::new(leader.id())));
+        assert_eq!(cancel.release(), Some(ProcessGroup::new(leader.id())));
+        cancel.cancel();
+        assert!(cancel.is_cancelled());
+        assert!(leader.try_wait().unwrap().is_none());
+
+        leader.kill().unwrap();
+        leader.wait().unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    #[ignore] // PTY tests need special handling
+    fn test_background_jobs_killed_on_exit() {
+        let start = Instant::now();
+        let result = execute_simple("sleep 60 & echo $$").unwrap();
+        assert!(result.success());
+        assert!(start.elapsed() < Duration::from_secs(10));
+        let pgid: u32 = result.output_trimmed().parse().unwrap();
+        assert!(ProcessGroup::new(pgid).wait_gone(KILL_GRACE).is_empty());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore] // PTY tests need special handling
+    async fn test_concurrent_commands_share_one_thread() {
+        let executor = Arc::new(CommandExecutor::new(Arc::new(SessionStore::new())));
+        let start = Instant::now();
+        let runs: Vec<_> = (0..50)
+            .map(|i| {
+                let executor = Arc::clone(&executor);
+                tokio::spawn(async move {
+                    let cmd = Command::new(format!("sleep 1; echo {}", i));
+                    executor.execute(&cmd).await.unwrap()
+                })
+            })
+            .collect();
+        for (i, run) in runs.into_iter().enumerate() {
+            let result = run.await.unwrap();
+            assert!(result.success());
+            assert_eq!(result.output_trimmed(), i.to_string());
+        }
+        // The commands ran side by side
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    #[ignore] // PTY tests need special handling
+    fn test_session_background_jobs_killed_with_session() {
+        use crate::session::SessionConfig;
+
+        let store = Arc::new(SessionStore::new());
+        let id = store.create(SessionConfig::default()).unwrap();
+        store.update(&id, |s| s.state = SessionState::Idle).unwrap();
+        let executor = CommandExecutor::new(Arc::clone(&store));
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+
+        // The job survives the hangup, in a process group of its own
+        let cmd = Command::new("trap '' HUP; sleep 60 & echo $!");
+        let result = runtime
+            .block_on(executor.execute_in_session(&id, &cmd))
+            .unwrap();
+        assert!(result.success());
+        // After bash's job notice
+        let job: u32 = result
+            .output_lines()
+            .last()
+            .unwrap()
+            .trim()
+            .parse()
+            .unwrap();
+        assert!(!ProcessGroup::new(job).members().is_empty());
+
+        let session = store.get(&id).unwrap().unwrap();
+        let killed = session.kill_processes();
+        assert!(killed.killed_pids.contains(&job));
+        assert!(killed.leftover_pids.is_empty());
+        assert!(ProcessGroup::new(job).members().is_empty());
+    }
+
     #[test]
     fn test_default_timeout() {
         assert_eq!(DEFAULT_TIMEOUT, Duration::from_secs(30));
@@ -26,5 +26,9 @@ mod executor;
 mod result;
 
 pub use command::{Command, CommandBuilder};
-pub use executor::{execute_simple, execute_with_timeout, CommandExecutor, DEFAULT_TIMEOUT};
-pub use result::{ExecutionResult, OutputChunk, OutputSource};
+pub use executor::{
+    execute_simple, execute_with_timeout, CancelHandle, CommandExecutor, StreamingExecution,
+    DEFAULT_TIMEOUT,
+};
+pub(crate) use executor::{record, sleep_until};
+pub use result::{ExecutionResult, OutputChunk, OutputSource, Termination};
@@ -2,6 +2,54 @@
 
 use std::time::Duration;
 
+use serde::{Deserialize, Serialize};
+
+use crate::pty::{signal_name, ExitStatus, LimitExceeded};
+
+/// Why a command ended.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum Termination {
+    /// The command exited with a status code.
+    Exited {
+        /// The exit code.
+        code: i32,
+    },
+    /// The command was killed by a signal.
+    Signaled {
+        /// The signal number.
+        signal: i32,
+        /// The signal name, such as `SIGSEGV`.
+        name: String,
+        /// Whether the command dumped core.
+        core_dumped: bool,
+    },
+    /// The command ran out of time.
+    TimedOut,
+    /// The command produced no output for too long.
+    IdleTimedOut,
+    /// The command was cancelled.
+    Cancelled,
+    /// The command produced more output than allowed.
+    OutputLimit,
+}
+
+impl From<ExitStatus> for Termination {
+    fn from(status: ExitStatus) -> Self {
+        match status {
+            ExitStatus::Exited(code) => Self::Exited { code },
+            ExitStatus::Signaled {
+                signal,
+                core_dumped,
+            } => Self::Signaled {
+                signal,
+                name: signal_name(signal),
+                core_dumped,
+            },
+        }
+    }
+}
+
 /// Result of command execution.
 #[derive(Debug, Clone)]
 pub struct ExecutionResult {
@@ -9,12 +57,22 @@ pub struct ExecutionResult {
     pub raw_output: Vec<u8>,
     /// Sanitized text output (ANSI codes stripped).
     pub text_output: String,
-    /// Exit code (if command completed).
+    /// Exit code (if command exited on its own).
     pub exit_code: Option<i32>,
+    /// Why the command ended (if known).
+    pub termination: Option<Termination>,
     /// Execution duration.
     pub duration: Duration,
     /// Whether execution timed out.
     pub timed_out: bool,
+    /// Whether the command was killed for producing no output for too long.
+    pub idle_timed_out: bool,
+    /// The resource limit that ended the command, if any.
+    pub limit_exceeded: Option<LimitExceeded>,
+    /// Whether the command was cancelled.
+    pub cancelled: bool,
+    /// Processes of the command's group that survived being killed.
+    pub leftover_pids: Vec<u32>,
 }
 
 impl ExecutionResult {
@@ -24,8 +82,13 @@ impl ExecutionResult {
             raw_output,
             text_output,
             exit_code: None,
+            termination: None,
             duration,
             timed_out: false,
+            idle_timed_out: false,
+            limit_exceeded: None,
+            cancelled: false,
+            leftover_pids: Vec::new(),
         }
     }
 
@@ -35,14 +98,66 @@ impl ExecutionResult {
             raw_output,
             text_output,
             exit_code: None,
+            termination: Some(Termination::TimedOut),
             duration,
             timed_out: true,
+            idle_timed_out: false,
+            limit_exceeded: None,
+            cancelled: false,
+            leftover_pids: Vec::new(),
+        }
+    }
+
+    /// Create a result indicating the command produced no output for too
+    /// long.
+    pub fn idle_timeout(raw_output: Vec<u8>, text_output: String, duration: Duration) -> Self {
+        Self {
+            idle_timed_out: true,
+            termination: Some(Termination::IdleTimedOut),
+            ..Self::new(raw_output, text_output, duration)
+        }
+    }
+
+    /// Create a result indicating the command was cancelled.
+    pub fn cancelled(raw_output: Vec<u8>, text_output: String, duration: Duration) -> Self {
+        Self {
+            cancelled: true,
+            termination: Some(Termination::Cancelled),
+            ..Self::new(raw_output, text_output, duration)
+        }
+    }
+
+    /// Create a result indicating the command produced too much output.
+    pub fn output_limit(raw_output: Vec<u8>, text_output: String, duration: Duration) -> Self {
+        Self {
+            termination: Some(Termination::OutputLimit),
+            ..Self::new(raw_output, text_output, duration)
         }
     }
 
+    /// Record processes that survived killing the command's group.
+    pub fn with_leftover_pids(mut self, pids: Vec<u32>) -> Self {
+        self.leftover_pids = pids;
+        self
+    }
+
     /// Set the exit code.
     pub fn with_exit_code(mut self, code: i32) -> Self {
         self.exit_code = Some(code);
+        self.termination = Some(Termination::Exited { code });
+        self
+    }
+
+    /// Record how the command's process ended.
+    pub fn with_exit_status(mut self, status: ExitStatus) -> Self {
+        self.exit_code = status.code();
+        self.termination = Some(status.into());
+        self
+    }
+
+    /// Record the resource limit that ended the command.
+    pub fn with_limit_exceeded(mut self, limit: Option<LimitExceeded>) -> Self {
+        self.limit_exceeded = limit;
         self
     }
 
@@ -51,9 +166,14 @@ impl ExecutionResult {
         self.exit_code == Some(0)
     }
 
-    /// Check if command failed (non-zero exit code or timeout).
+    /// Check if command failed (non-zero exit code, signal, timeout,
+    /// cancellation or output limit).
     pub fn failed(&self) -> bool {
-        self.timed_out || matches!(self.exit_code, Some(c) if c != 0)
+        match &self.termination {
+            Some(Termination::Exited { code }) => *code != 0,
+            Some(_) => true,
+            None => self.timed_out || self.idle_timed_out || self.cancelled,
+        }
     }
 
     /// Get output as string, trimmed.
@@ -73,8 +193,13 @@ impl Default for ExecutionResult {
             raw_output: Vec::new(),
             text_output: String::new(),
             exit_code: None,
+            termination: None,
             duration: Duration::ZERO,
             timed_out: false,
+            idle_timed_out: false,
+            limit_exceeded: None,
+            cancelled: false,
+            leftover_pids: Vec::new(),
         }
     }
 }
@@ -159,6 +284,53 @@ mod tests {
         assert!(result.failed());
     }
 
+    #[test]
+    fn test_execution_result_idle_timeout() {
+        let result = ExecutionResult::idle_timeout(vec![], String::new(), Duration::from_secs(5));
+        assert!(result.idle_timed_out);
+        assert!(!result.timed_out);
+        assert!(result.failed());
+    }
+
+    #[test]
+    fn test_execution_result_signaled() {
+        let status = ExitStatus::Signaled {
+            signal: 11,
+            core_dumped: true,
+        };
+        let result = ExecutionResult::default().with_exit_status(status);
+        assert_eq!(result.exit_code, None);
+        assert!(result.failed());
+        let termination = serde_json::to_value(result.termination.unwrap()).unwrap();
+        assert_eq!(termination["reason"], "signaled");
+        assert_eq!(termination["signal"], 11);
+        assert_eq!(termination["core_dumped"], true);
+
+        let result = ExecutionResult::default().with_exit_status(ExitStatus::Exited(0));
+        assert!(result.success());
+        assert_eq!(result.termination, Some(Termination::Exited { code: 0 }));
+    }
+
+    #[test]
+    fn test_execution_result_output_limit() {
+        let result = ExecutionResult::output_limit(vec![], String::new(), Duration::ZERO);
+        assert!(result.failed());
+        assert_eq!(
+            serde_json::to_string(&result.termination).unwrap(),
+            r#"{"reason":"output_limit"}"#
+        );
+    }
+
+    #[test]
+    fn test_execution_result_cancelled() {
+        let result = ExecutionResult::cancelled(vec![], String::new(), Duration::from_secs(1))
+            .with_leftover_pids(vec![42]);
+        assert!(result.cancelled);
+        assert!(!result.timed_out);
+        assert!(result.failed());
+        assert_eq!(result.leftover_pids, vec![42]);
+    }
+
     #[test]
     fn test_output_trimmed() {
         let result = ExecutionResult::new(vec![], "  hello world  \n".to_string(), Duration::ZERO);
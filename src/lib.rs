@@ -55,6 +55,7 @@
 //! ```
 
 pub mod api;
+pub mod audit;
 pub mod cli;
 pub mod config;
 pub mod error;
@@ -69,7 +70,7 @@ pub mod update;
 // Re-export commonly used types
 pub use error::{Result, ShellTunnelError};
 pub use execution::{Command, CommandExecutor, ExecutionResult};
-pub use output::{OutputSanitizer, VirtualScreen};
+pub use output::{OutputSanitizer, Redactor, VirtualScreen};
 pub use pty::{AsyncPtyReader, AsyncPtyWriter, NativePty, PtyHandle, PtySize};
 pub use session::{
     Session, SessionConfig, SessionContext, SessionId, SessionState, SessionStore, StateProbe,
@@ -82,5 +83,5 @@ pub use api::{AppState, ServerConfig};
 pub use security::{ApiKeyStore, AuthConfig, CommandValidator, RateLimiter, ValidationConfig};
 
 // Re-export CLI and config types
-pub use cli::{parse_args, print_help, print_version, Args};
+pub use cli::{parse_args, print_help, print_version, Args, Subcommand};
 pub use config::{Config, ConfigError};
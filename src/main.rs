@@ -1,6 +1,8 @@
 //! Shell-tunnel binary entry point.
 
-use shell_tunnel::{api::serve, logging, parse_args, print_help, print_version, update, Config};
+use shell_tunnel::{
+    api::serve, audit, logging, parse_args, print_help, print_version, update, Config, Subcommand,
+};
 use tracing::info;
 
 #[tokio::main]
@@ -26,6 +28,23 @@ async fn main() -> shell_tunnel::Result<()> {
         return Ok(());
     }
 
+    if let Some(Subcommand::AuditVerify { ref files }) = args.subcommand {
+        match audit::verify_files(files) {
+            Ok(report) => {
+                println!("OK: {} entries, chain intact", report.entries);
+                if !report.from_genesis {
+                    println!("Note: the chain continues from an older file that was not checked");
+                }
+                println!("Last hash: {}", report.last_hash);
+            }
+            Err(e) => {
+                eprintln!("FAILED: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return Ok(());
+    }
+
     // Handle update commands
     if args.check_update {
         match update::check_update() {
@@ -84,7 +103,7 @@ async fn main() -> shell_tunnel::Result<()> {
     }
 
     // Convert to server config
-    let server_config = match config.to_server_config() {
+    let mut server_config = match config.to_server_config() {
         Ok(c) => c,
         Err(e) => {
             eprintln!("Configuration error: {}", e);
@@ -92,11 +111,19 @@ async fn main() -> shell_tunnel::Result<()> {
         }
     };
 
+    // Re-read the config file on SIGHUP
+    if let Some(ref path) = args.config {
+        server_config = server_config.with_config_path(path);
+    }
+
     // Start the server
-    info!(
-        "Starting server on {}:{}",
-        server_config.host, server_config.port
-    );
+    match server_config.unix_socket {
+        Some(ref socket) => info!("Starting server on unix:{}", socket.path.display()),
+        None => info!(
+            "Starting server on {}:{}",
+            server_config.host, server_config.port
+        ),
+    }
 
     serve(server_config).await
 }
@@ -3,6 +3,7 @@
 //! This module provides tools for processing terminal output:
 //! - ANSI escape code stripping
 //! - Virtual terminal screen emulation
+//! - Secret redaction
 //!
 //! # Example
 //!
@@ -20,8 +21,10 @@
 //! let lines = screen.non_empty_lines();
 //! ```
 
+mod redact;
 mod sanitizer;
 mod screen;
 
-pub use sanitizer::OutputSanitizer;
+pub use redact::{RedactionConfig, RedactionPattern, Redactor, StreamRedactor};
+pub use sanitizer::{AnsiStripper, OutputSanitizer};
 pub use screen::VirtualScreen;
@@ -24,6 +24,36 @@ impl OutputSanitizer {
     }
 }
 
+/// Strips ANSI escape codes from output that arrives in chunks.
+///
+/// Escape sequences and UTF-8 characters split across chunks are handled.
+pub struct AnsiStripper {
+    parser: Parser,
+    extractor: PlainTextExtractor,
+}
+
+impl AnsiStripper {
+    /// Create a stripper at the start of a stream.
+    pub fn new() -> Self {
+        Self {
+            parser: Parser::new(),
+            extractor: PlainTextExtractor::new(),
+        }
+    }
+
+    /// Strip the next chunk, returning the text it completes.
+    pub fn push(&mut self, input: &[u8]) -> String {
+        self.parser.advance(&mut self.extractor, input);
+        String::from_utf8_lossy(&std::mem::take(&mut self.extractor.output)).into_owned()
+    }
+}
+
+impl Default for AnsiStripper {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// VTE performer that extracts plain text.
 struct PlainTextExtractor {
     output: Vec<u8>,
@@ -91,6 +121,14 @@ impl Perform for PlainTextExtractor {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_stripper_handles_split_sequences() {
+        let mut stripper = AnsiStripper::new();
+        assert_eq!(stripper.push(b"\x1b[3"), "");
+        assert_eq!(stripper.push(b"1mred\x1b[0m \xc3"), "red ");
+        assert_eq!(stripper.push(b"\xa9"), "\u{e9}");
+    }
+
     #[test]
     fn test_plain_text() {
         let input = b"hello world";
@@ -74,6 +74,17 @@ impl VirtualScreen {
         self.contents().trim().is_empty()
     }
 
+    /// Resize the screen, keeping its contents where they fit.
+    pub fn resize(&mut self, cols: u16, rows: u16) {
+        self.parser.screen_mut().set_size(rows, cols);
+    }
+
+    /// Escape codes that redraw the screen as it looks now on a blank
+    /// terminal of the same size, including the cursor and input modes.
+    pub fn formatted(&self) -> Vec<u8> {
+        self.parser.screen().state_formatted()
+    }
+
     /// Clear the screen (reset to initial state).
     pub fn clear(&mut self) {
         let (rows, cols) = self.size();
@@ -104,6 +115,27 @@ mod tests {
         assert_eq!(screen.size(), (40, 120));
     }
 
+    #[test]
+    fn test_resize() {
+        let mut screen = VirtualScreen::new();
+        screen.process(b"kept");
+        screen.resize(120, 40);
+        assert_eq!(screen.size(), (40, 120));
+        assert!(screen.contents().contains("kept"));
+    }
+
+    #[test]
+    fn test_formatted_redraws_screen() {
+        let mut screen = VirtualScreen::new();
+        screen.process(b"\x1b[31mred\x1b[0m\r\nnext");
+
+        let mut copy = VirtualScreen::new();
+        copy.process(b"stale output");
+        copy.process(&screen.formatted());
+        assert_eq!(copy.contents(), screen.contents());
+        assert_eq!(copy.cursor_position(), screen.cursor_position());
+    }
+
     #[test]
     fn test_process_text() {
         let mut screen = VirtualScreen::new();
@@ -0,0 +1,216 @@
+//! Process groups of spawned commands.
+//!
+//! Every command is spawned as a session leader, so its process id is also
+//! the id of a process group holding everything it starts, including
+//! background jobs started with `&` or `nohup`. Killing the group ends the
+//! whole tree, except for processes that started a session of their own.
+
+use std::time::{Duration, Instant};
+
+/// How long to wait for a killed group to disappear.
+pub const KILL_GRACE: Duration = Duration::from_secs(1);
+
+/// The process group of a spawned command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ProcessGroup {
+    pgid: u32,
+}
+
+impl ProcessGroup {
+    /// The group led by the given process.
+    pub fn new(pgid: u32) -> Self {
+        Self { pgid }
+    }
+
+    /// The process group id.
+    pub fn id(&self) -> u32 {
+        self.pgid
+    }
+
+    /// Send a signal to every process in the group.
+    #[cfg(unix)]
+    pub fn signal(&self, signal: i32) -> std::io::Result<()> {
+        // SAFETY: killpg has no memory safety preconditions.
+        if unsafe { libc::killpg(self.pgid as libc::pid_t, signal) } == -1 {
+            let error = std::io::Error::last_os_error();
+            // The group is already gone
+            if error.raw_os_error() != Some(libc::ESRCH) {
+                return Err(error);
+            }
+        }
+        Ok(())
+    }
+
+    /// Ids of the live processes in the group.
+    ///
+    /// Zombies are not counted. Outside Linux only the leader can be
+    /// reported.
+    pub fn members(&self) -> Vec<u32> {
+        self.scan(Field::Group)
+    }
+
+    /// Ids of the live processes in the session the group's leader leads.
+    ///
+    /// That holds the jobs an interactive shell started, each in a group of
+    /// its own. Outside Linux only the leader's group can be reported.
+    pub fn session_members(&self) -> Vec<u32> {
+        self.scan(Field::Session)
+    }
+
+    /// Ids of the live processes whose group or session is the leader's.
+    fn scan(&self, field: Field) -> Vec<u32> {
+        #[cfg(target_os = "linux")]
+        {
+            let Ok(entries) = std::fs::read_dir("/proc") else {
+                return Vec::new();
+            };
+            entries
+                .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse::<u32>().ok())
+                .filter(|pid| {
+                    let Ok(stat) = std::fs::read_to_string(format!("/proc/{}/stat", pid)) else {
+                        return false;
+                    };
+                    // The command name may contain spaces and parentheses
+                    let Some((_, fields)) = stat.rsplit_once(") ") else {
+                        return false;
+                    };
+                    // State, parent, group, session
+                    let fields: Vec<_> = fields.splitn(5, ' ').collect();
+                    let id = fields
+                        .get(field as usize)
+                        .and_then(|id| id.parse::<u32>().ok());
+                    id == Some(self.pgid) && !matches!(fields.first(), Some(&"Z") | Some(&"X"))
+                })
+                .collect()
+        }
+        #[cfg(all(unix, not(target_os = "linux")))]
+        {
+            let _ = field;
+            // SAFETY: signal 0 only checks that the group exists.
+            if unsafe { libc::killpg(self.pgid as libc::pid_t, 0) } == 0 {
+                vec![self.pgid]
+            } else {
+                Vec::new()
+            }
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = field;
+            Vec::new()
+        }
+    }
+
+    /// Wait up to `grace` for the group to disappear, returning the ids of
+    /// processes still in it.
+    pub fn wait_gone(&self, grace: Duration) -> Vec<u32> {
+        let deadline = Instant::now() + grace;
+        loop {
+            let members = self.members();
+            if members.is_empty() || Instant::now() >= deadline {
+                return members;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// Kill every process in the group and confirm it is gone.
+    ///
+    /// Returns the ids of processes that survived [`KILL_GRACE`].
+    #[cfg(unix)]
+    pub fn kill(&self) -> Vec<u32> {
+        if let Err(e) = self.signal(libc::SIGKILL) {
+            tracing::warn!("Failed to kill process group {}: {}", self.pgid, e);
+        }
+        self.wait_gone(KILL_GRACE)
+    }
+
+    /// Kill every process in the leader's session and confirm they are
+    /// gone.
+    ///
+    /// Returns the ids of processes that survived [`KILL_GRACE`].
+    #[cfg(unix)]
+    pub fn kill_session(&self) -> Vec<u32> {
+        let deadline = Instant::now() + KILL_GRACE;
+        loop {
+            let members = self.session_members();
+            if members.is_empty() || Instant::now() >= deadline {
+                return members;
+            }
+            // Again on every round, for processes forked in between
+            for pid in &members {
+                // SAFETY: kill has no memory safety preconditions.
+                unsafe { libc::kill(*pid as libc::pid_t, libc::SIGKILL) };
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
+
+/// A field of `/proc/<pid>/stat` after the command name.
+#[derive(Debug, Clone, Copy)]
+enum Field {
+    Group = 2,
+    Session = 3,
+}
+
+impl std::fmt::Display for ProcessGroup {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.pgid)
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::process::CommandExt;
+
+    #[test]
+    fn test_kill_group() {
+        let mut leader = std::process::Command::new("/bin/sh")
+            .arg("-c")
+            .arg("sleep 30 & sleep 30 & wait")
+            .process_group(0)
+            .spawn()
+            .unwrap();
+        let group = ProcessGroup::new(leader.id());
+
+        // Wait for the background jobs to start
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while group.members().len() < 3 && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert!(group.members().contains(&leader.id()));
+
+        group.signal(libc::SIGKILL).unwrap();
+        leader.wait().unwrap();
+        assert_eq!(group.wait_gone(KILL_GRACE), Vec::<u32>::new());
+        // Killing a vanished group is not an error
+        assert!(group.signal(libc::SIGKILL).is_ok());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_kill_session() {
+        let mut command = std::process::Command::new("/bin/sh");
+        command.arg("-c").arg("sleep 30 & sleep 30 & wait");
+        // SAFETY: setsid is async-signal-safe.
+        unsafe {
+            command.pre_exec(|| {
+                libc::setsid();
+                Ok(())
+            })
+        };
+        let mut leader = command.spawn().unwrap();
+        let group = ProcessGroup::new(leader.id());
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while group.session_members().len() < 3 && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(group.session_members().len(), 3);
+
+        let reaper = std::thread::spawn(move || leader.wait());
+        assert_eq!(group.kill_session(), Vec::<u32>::new());
+        reaper.join().unwrap().unwrap();
+    }
+}
@@ -0,0 +1,227 @@
+//! Resource limits for spawned processes.
+//!
+//! [`ResourceLimits`] are applied with `setrlimit` in the child before
+//! `exec`, so they bind the command and everything it starts. Limits only
+//! ever tighten: combining two sets keeps the lower value of each.
+
+use serde::{Deserialize, Serialize};
+
+/// `setrlimit` limits for a command (unchanged where `None`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ResourceLimits {
+    /// CPU time in seconds; the command gets `SIGXCPU` when it runs out.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpu_secs: Option<u64>,
+    /// Address space (virtual memory) in bytes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address_space_bytes: Option<u64>,
+    /// Processes of the account the command runs as.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_processes: Option<u64>,
+    /// Open file descriptors.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub open_files: Option<u64>,
+    /// Size of files written, in bytes; writing past it raises `SIGXFSZ`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_size_bytes: Option<u64>,
+    /// Size of core dumps in bytes (0 disables them).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub core_size_bytes: Option<u64>,
+}
+
+impl ResourceLimits {
+    /// Check if no limit is set.
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Combine with other limits, keeping the lower value of each.
+    pub fn tighten(self, other: &Self) -> Self {
+        fn min(a: Option<u64>, b: Option<u64>) -> Option<u64> {
+            match (a, b) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            }
+        }
+        Self {
+            cpu_secs: min(self.cpu_secs, other.cpu_secs),
+            address_space_bytes: min(self.address_space_bytes, other.address_space_bytes),
+            max_processes: min(self.max_processes, other.max_processes),
+            open_files: min(self.open_files, other.open_files),
+            file_size_bytes: min(self.file_size_bytes, other.file_size_bytes),
+            core_size_bytes: min(self.core_size_bytes, other.core_size_bytes),
+        }
+    }
+
+    /// Work out which limit ended a command killed by `signal`.
+    #[cfg(unix)]
+    pub fn exceeded_by(&self, signal: i32) -> Option<LimitExceeded> {
+        match signal {
+            libc::SIGXCPU => Some(LimitExceeded::CpuTime),
+            // Past the soft limit, the hard CPU limit kills outright
+            libc::SIGKILL if self.cpu_secs.is_some() => Some(LimitExceeded::CpuTime),
+            libc::SIGKILL => Some(LimitExceeded::Memory),
+            libc::SIGXFSZ => Some(LimitExceeded::FileSize),
+            _ => None,
+        }
+    }
+
+    /// The limits as `setrlimit` arguments, capped at the current hard
+    /// limits so that unprivileged servers can apply them.
+    #[cfg(unix)]
+    pub(crate) fn to_rlimits(self) -> Vec<(Resource, libc::rlimit)> {
+        let limits = [
+            (libc::RLIMIT_CPU, self.cpu_secs),
+            (libc::RLIMIT_AS, self.address_space_bytes),
+            (libc::RLIMIT_NPROC, self.max_processes),
+            (libc::RLIMIT_NOFILE, self.open_files),
+            (libc::RLIMIT_FSIZE, self.file_size_bytes),
+            (libc::RLIMIT_CORE, self.core_size_bytes),
+        ];
+        limits
+            .into_iter()
+            .filter_map(|(resource, limit)| {
+                // rlim_t is not u64 on every platform
+                #[allow(clippy::unnecessary_cast)]
+                let limit = limit?.min(libc::RLIM_INFINITY as u64) as libc::rlim_t;
+                let mut current = libc::rlimit {
+                    rlim_cur: 0,
+                    rlim_max: 0,
+                };
+                // SAFETY: getrlimit only writes into `current`.
+                if unsafe { libc::getrlimit(resource, &mut current) } == -1 {
+                    return None;
+                }
+                let cap = |value: libc::rlim_t| value.min(current.rlim_max);
+                let rlimit = if resource == libc::RLIMIT_CPU {
+                    // Leave a second between SIGXCPU and SIGKILL
+                    libc::rlimit {
+                        rlim_cur: cap(limit),
+                        rlim_max: cap(limit.saturating_add(1)),
+                    }
+                } else {
+                    libc::rlimit {
+                        rlim_cur: cap(limit),
+                        rlim_max: cap(limit),
+                    }
+                };
+                Some((resource, rlimit))
+            })
+            .collect()
+    }
+}
+
+/// Resource argument of `setrlimit`.
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+pub(crate) type Resource = libc::__rlimit_resource_t;
+#[cfg(all(unix, not(all(target_os = "linux", target_env = "gnu"))))]
+pub(crate) type Resource = libc::c_int;
+
+/// Apply prepared limits in the forked child.
+///
+/// Only makes async-signal-safe calls.
+#[cfg(unix)]
+pub(crate) fn apply_rlimits(rlimits: &[(Resource, libc::rlimit)]) -> std::io::Result<()> {
+    for (resource, rlimit) in rlimits {
+        // SAFETY: setrlimit only reads `rlimit`.
+        if unsafe { libc::setrlimit(*resource, rlimit) } == -1 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// A resource limit that ended a command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LimitExceeded {
+    /// The CPU time limit (`SIGXCPU`).
+    CpuTime,
+    /// The file size limit (`SIGXFSZ`).
+    FileSize,
+    /// Killed with `SIGKILL`, as the kernel's out-of-memory killer does.
+    Memory,
+}
+
+impl LimitExceeded {
+    /// Name used in API responses.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::CpuTime => "cpu_time",
+            Self::FileSize => "file_size",
+            Self::Memory => "memory",
+        }
+    }
+}
+
+impl std::fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tighten() {
+        let session = ResourceLimits {
+            cpu_secs: Some(60),
+            open_files: Some(256),
+            ..Default::default()
+        };
+        let command = ResourceLimits {
+            cpu_secs: Some(120),
+            file_size_bytes: Some(1 << 20),
+            ..Default::default()
+        };
+        let limits = session.tighten(&command);
+        assert_eq!(limits.cpu_secs, Some(60));
+        assert_eq!(limits.open_files, Some(256));
+        assert_eq!(limits.file_size_bytes, Some(1 << 20));
+        assert_eq!(limits.address_space_bytes, None);
+        assert!(ResourceLimits::default().is_empty());
+        assert!(!limits.is_empty());
+    }
+
+    #[test]
+    fn test_serde() {
+        let limits: ResourceLimits =
+            serde_json::from_str(r#"{"cpu_secs": 10, "core_size_bytes": 0}"#).unwrap();
+        assert_eq!(limits.cpu_secs, Some(10));
+        assert_eq!(limits.core_size_bytes, Some(0));
+        assert_eq!(
+            serde_json::to_string(&limits).unwrap(),
+            r#"{"cpu_secs":10,"core_size_bytes":0}"#
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_to_rlimits() {
+        let limits = ResourceLimits {
+            cpu_secs: Some(5),
+            core_size_bytes: Some(0),
+            ..Default::default()
+        };
+        let rlimits = limits.to_rlimits();
+        assert_eq!(rlimits.len(), 2);
+        let (resource, cpu) = rlimits[0];
+        assert_eq!(resource, libc::RLIMIT_CPU);
+        assert!(cpu.rlim_cur <= 5);
+        assert!(cpu.rlim_max <= 6);
+        assert_eq!(rlimits[1].1.rlim_cur, 0);
+
+        assert_eq!(
+            limits.exceeded_by(libc::SIGXCPU),
+            Some(LimitExceeded::CpuTime)
+        );
+        assert_eq!(
+            ResourceLimits::default().exceeded_by(libc::SIGKILL),
+            Some(LimitExceeded::Memory)
+        );
+        assert_eq!(limits.exceeded_by(libc::SIGTERM), None);
+    }
+}
@@ -4,10 +4,24 @@
 //! pseudo-terminals. It supports both Unix PTY and Windows ConPTY.
 
 mod async_adapter;
+mod group;
+mod limits;
 mod native;
+mod reactor;
+mod sandbox;
+mod spawn;
+mod status;
 
 pub use async_adapter::{AsyncPtyReader, AsyncPtyWriter};
+pub use group::{ProcessGroup, KILL_GRACE};
+pub use limits::{LimitExceeded, ResourceLimits};
 pub use native::{default_shell, NativePty, SpawnedShell};
+pub use reactor::{AsyncMaster, ExitWatcher};
+pub use sandbox::Sandbox;
+#[cfg(unix)]
+pub use spawn::Credentials;
+pub use spawn::{RunAs, SpawnOptions};
+pub use status::{signal_name, ExitStatus};
 
 use std::io::{Read, Write};
 
@@ -3,6 +3,10 @@
 use portable_pty::{native_pty_system, CommandBuilder, PtySize as NativePtySize};
 use std::io::{Read, Write};
 
+use super::group::ProcessGroup;
+use super::reactor::{AsyncMaster, ExitWatcher};
+use super::spawn::{spawn_on_pty, SpawnOptions};
+use super::status::ExitStatus;
 use super::{PtyHandle, PtySize};
 use crate::error::ShellTunnelError;
 use crate::Result;
@@ -25,6 +29,7 @@ pub fn default_shell() -> &'static str {
 /// Wrapper around the native PTY system.
 pub struct NativePty {
     pty_system: Box<dyn portable_pty::PtySystem + Send>,
+    options: SpawnOptions,
 }
 
 impl NativePty {
@@ -32,9 +37,16 @@ impl NativePty {
     pub fn new() -> Self {
         Self {
             pty_system: native_pty_system(),
+            options: SpawnOptions::default(),
         }
     }
 
+    /// Set up spawned processes with the given options.
+    pub fn with_options(mut self, options: SpawnOptions) -> Self {
+        self.options = options;
+        self
+    }
+
     /// Spawn a shell process in a new PTY.
     ///
     /// # Arguments
@@ -67,10 +79,7 @@ impl NativePty {
         // Note: We don't use -l flag as it can cause issues with some shells
         // The environment is inherited from the parent process
 
-        let child = pair
-            .slave
-            .spawn_command(cmd)
-            .map_err(|e| ShellTunnelError::Pty(e.to_string()))?;
+        let child = spawn_on_pty(&pair, cmd, &self.options)?;
 
         let pid = child.process_id().unwrap_or(0);
 
@@ -105,9 +114,21 @@ impl NativePty {
     /// This is a convenience method for command execution that spawns
     /// a shell with default size and optionally sets the working directory.
     pub fn spawn_shell(&mut self, working_dir: Option<&std::path::Path>) -> Result<SpawnedShell> {
+        self.spawn_interactive(default_shell(), working_dir, PtySize::default())
+    }
+
+    /// Spawn an interactive shell in a terminal of the given size.
+    ///
+    /// `TERM` is set so that programs emit xterm escape sequences.
+    pub fn spawn_interactive(
+        &mut self,
+        shell: &str,
+        working_dir: Option<&std::path::Path>,
+        size: PtySize,
+    ) -> Result<SpawnedShell> {
         let native_size = NativePtySize {
-            rows: 24,
-            cols: 80,
+            rows: size.rows,
+            cols: size.cols,
             pixel_width: 0,
             pixel_height: 0,
         };
@@ -117,23 +138,16 @@ impl NativePty {
             .openpty(native_size)
             .map_err(|e| ShellTunnelError::Pty(e.to_string()))?;
 
-        let mut cmd = CommandBuilder::new(default_shell());
+        let mut cmd = CommandBuilder::new(shell);
+        cmd.env("TERM", "xterm-256color");
 
         if let Some(dir) = working_dir {
             cmd.cwd(dir);
         }
 
-        let child = pair
-            .slave
-            .spawn_command(cmd)
-            .map_err(|e| ShellTunnelError::Pty(e.to_string()))?;
+        let child = spawn_on_pty(&pair, cmd, &self.options)?;
 
-        Ok(SpawnedShell {
-            master: pair.master,
-            child,
-            reader: None,
-            writer: None,
-        })
+        Ok(SpawnedShell::new(pair.master, child))
     }
 
     /// Spawn a command directly (non-interactive).
@@ -177,29 +191,46 @@ impl NativePty {
             cmd.cwd(dir);
         }
 
-        let child = pair
-            .slave
-            .spawn_command(cmd)
-            .map_err(|e| ShellTunnelError::Pty(e.to_string()))?;
+        let child = spawn_on_pty(&pair, cmd, &self.options)?;
 
-        Ok(SpawnedShell {
-            master: pair.master,
-            child,
-            reader: None,
-            writer: None,
-        })
+        Ok(SpawnedShell::new(pair.master, child))
     }
 }
 
 /// A spawned shell process with PTY.
+///
+/// On Unix the child is reaped with `waitpid` rather than through
+/// portable_pty, which does not know about it. Once the child was reaped its
+/// pid may be reused by an unrelated process, so from then on nothing may
+/// signal or wait for it: `pid` is cleared and `child` is only kept to be
+/// dropped.
 pub struct SpawnedShell {
     master: Box<dyn portable_pty::MasterPty + Send>,
+    #[cfg_attr(unix, allow(dead_code))]
     child: Box<dyn portable_pty::Child + Send + Sync>,
+    /// The child's pid, until it was reaped.
+    pid: Option<u32>,
     reader: Option<Box<dyn Read + Send>>,
     writer: Option<Box<dyn Write + Send>>,
+    /// Exit status, once the child was reaped.
+    status: Option<ExitStatus>,
 }
 
 impl SpawnedShell {
+    fn new(
+        master: Box<dyn portable_pty::MasterPty + Send>,
+        child: Box<dyn portable_pty::Child + Send + Sync>,
+    ) -> Self {
+        Self {
+            master,
+            pid: child.process_id(),
+            child,
+            reader: None,
+            writer: None,
+            status: None,
+        }
+    }
+
     /// Take the writer (can only be called once).
     pub fn take_writer(&mut self) -> Result<Box<dyn Write + Send>> {
         if let Some(writer) = self.writer.take() {
@@ -221,13 +252,166 @@ impl SpawnedShell {
     }
 
     /// Try to wait for the child process without blocking.
-    pub fn try_wait(&mut self) -> std::io::Result<Option<portable_pty::ExitStatus>> {
-        self.child.try_wait()
+    pub fn try_wait(&mut self) -> std::io::Result<Option<ExitStatus>> {
+        if self.status.is_none() {
+            self.status = self.reap(false)?;
+            if self.status.is_some() {
+                self.pid = None;
+            }
+        }
+        Ok(self.status)
     }
 
     /// Wait for the child process to exit.
-    pub fn wait(&mut self) -> std::io::Result<portable_pty::ExitStatus> {
-        self.child.wait()
+    pub fn wait(&mut self) -> std::io::Result<ExitStatus> {
+        loop {
+            if let Some(status) = self.status {
+                return Ok(status);
+            }
+            self.status = self.reap(true)?;
+            if self.status.is_some() {
+                self.pid = None;
+            }
+        }
+    }
+
+    /// Whether the child exited, without reaping it.
+    ///
+    /// Until the child is reaped its pid, and so its process group id, is
+    /// not reused, so the group can still safely be signalled.
+    #[cfg(unix)]
+    pub fn has_exited(&mut self) -> std::io::Result<bool> {
+        let Some(pid) = self.pid else {
+            return Ok(true);
+        };
+        loop {
+            // SAFETY: siginfo_t is plain data, and waitid only writes to it.
+            let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+            let options = libc::WEXITED | libc::WNOHANG | libc::WNOWAIT;
+            // SAFETY: info is valid for writes.
+            if unsafe { libc::waitid(libc::P_PID, pid as libc::id_t, &mut info, options) } == -1 {
+                let error = std::io::Error::last_os_error();
+                if error.kind() != std::io::ErrorKind::Interrupted {
+                    return Err(error);
+                }
+                continue;
+            }
+            // SAFETY: waitid filled in si_pid, zero if the child is running.
+            return Ok(unsafe { info.si_pid() } != 0);
+        }
+    }
+
+    /// Whether the child exited.
+    #[cfg(not(unix))]
+    pub fn has_exited(&mut self) -> std::io::Result<bool> {
+        Ok(self.try_wait()?.is_some())
+    }
+
+    /// Reap the child if it exited, waiting for it if `block` is set.
+    #[cfg(unix)]
+    fn reap(&mut self, block: bool) -> std::io::Result<Option<ExitStatus>> {
+        let pid = self
+            .pid
+            .ok_or_else(|| std::io::Error::other("child has no process id"))?;
+        let options = if block { 0 } else { libc::WNOHANG };
+        let mut status = 0;
+        loop {
+            // SAFETY: status is valid for writes.
+            match unsafe { libc::waitpid(pid as libc::pid_t, &mut status, options) } {
+                0 => return Ok(None),
+                -1 => {
+                    let error = std::io::Error::last_os_error();
+                    if error.kind() != std::io::ErrorKind::Interrupted {
+                        return Err(error);
+                    }
+                }
+                _ => return Ok(Some(ExitStatus::from_raw(status))),
+            }
+        }
+    }
+
+    /// Reap the child if it exited, waiting for it if `block` is set.
+    #[cfg(not(unix))]
+    fn reap(&mut self, block: bool) -> std::io::Result<Option<ExitStatus>> {
+        let status = if block {
+            Some(self.child.wait()?)
+        } else {
+            self.child.try_wait()?
+        };
+        Ok(status.map(|status| ExitStatus::Exited(status.exit_code() as i32)))
+    }
+
+    /// Change the size of the terminal, signalling `SIGWINCH` to the
+    /// foreground job.
+    pub fn resize(&self, size: PtySize) -> Result<()> {
+        self.master
+            .resize(NativePtySize {
+                rows: size.rows,
+                cols: size.cols,
+                pixel_width: 0,
+                pixel_height: 0,
+            })
+            .map_err(|e| ShellTunnelError::Pty(e.to_string()))
+    }
+
+    /// The process group of the child and everything it starts, until the
+    /// child was reaped.
+    ///
+    /// The child leads its own session, so the group id is its pid.
+    pub fn process_group(&self) -> Option<ProcessGroup> {
+        self.pid.map(ProcessGroup::new)
+    }
+
+    /// The process group in the foreground of the terminal: the child's
+    /// while it waits for input, or a job it started.
+    #[cfg(unix)]
+    pub fn foreground_group(&self) -> Option<ProcessGroup> {
+        self.master
+            .process_group_leader()
+            .map(|pgid| ProcessGroup::new(pgid as u32))
+    }
+
+    /// Kill the child together with its process group, unless it was
+    /// reaped already.
+    ///
+    /// The child still has to be reaped.
+    pub fn kill(&mut self) -> std::io::Result<()> {
+        #[cfg(unix)]
+        match self.process_group() {
+            Some(group) => group.signal(libc::SIGKILL),
+            None => Ok(()),
+        }
+        #[cfg(not(unix))]
+        match self.status {
+            Some(_) => Ok(()),
+            None => self.child.kill(),
+        }
+    }
+
+    /// Get a non-blocking reader of the PTY output.
+    ///
+    /// Must be called within a tokio runtime.
+    pub fn async_master(&mut self) -> Result<AsyncMaster> {
+        #[cfg(unix)]
+        {
+            let fd = self
+                .master
+                .as_raw_fd()
+                .ok_or_else(|| ShellTunnelError::Pty("PTY master has no descriptor".into()))?;
+            Ok(AsyncMaster::new(fd)?)
+        }
+        #[cfg(not(unix))]
+        Ok(AsyncMaster::new(self.take_reader()?))
+    }
+
+    /// Watch for the child exiting.
+    ///
+    /// Must be called within a tokio runtime.
+    pub fn exit_watcher(&self) -> Result<ExitWatcher> {
+        let pid = self
+            .pid
+            .ok_or_else(|| ShellTunnelError::Pty("child has no process id".into()))?;
+        Ok(ExitWatcher::new(pid)?)
     }
 }
 
@@ -338,6 +522,26 @@ mod tests {
         assert!(handle.is_ok());
     }
 
+    #[test]
+    #[cfg(unix)]
+    fn test_reaped_child_is_left_alone() {
+        let mut shell = NativePty::new().spawn_command("exit 3", None).unwrap();
+        assert!(shell.process_group().is_some());
+        while !shell.has_exited().unwrap() {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        // Not reaped yet
+        assert!(shell.process_group().is_some());
+        assert_eq!(shell.wait().unwrap(), ExitStatus::Exited(3));
+        assert!(shell.has_exited().unwrap());
+
+        // The pid may belong to another process by now
+        assert!(shell.process_group().is_none());
+        assert!(shell.exit_watcher().is_err());
+        shell.kill().unwrap();
+        assert_eq!(shell.try_wait().unwrap(), Some(ExitStatus::Exited(3)));
+    }
+
     #[test]
     #[cfg(windows)]
     fn test_spawn_cmd() {
@@ -0,0 +1,271 @@
+//! PTY output and child exit driven by tokio's reactor.
+//!
+//! On Unix a duplicate of the PTY master is registered with the reactor
+//! and only read once `poll` reports data, and a child's exit is signalled by a pidfd on Linux or
+//! by `SIGCHLD` elsewhere, so running commands do not tie up threads. Other
+//! platforms fall back to a reader thread and polling.
+
+use std::io;
+
+#[cfg(unix)]
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+#[cfg(unix)]
+use tokio::io::unix::AsyncFd;
+
+/// Non-blocking reader of a PTY master.
+pub struct AsyncMaster {
+    #[cfg(unix)]
+    fd: AsyncFd<OwnedFd>,
+    #[cfg(not(unix))]
+    rx: tokio::sync::mpsc::Receiver<Vec<u8>>,
+    #[cfg(not(unix))]
+    pending: Vec<u8>,
+}
+
+impl AsyncMaster {
+    /// Register a duplicate of the master descriptor with the reactor.
+    ///
+    /// The duplicate shares its file status flags with the master, so it is
+    /// left in blocking mode: setting `O_NONBLOCK` would also make writes to
+    /// the terminal fail with `WouldBlock` once its input buffer is full.
+    ///
+    /// Must be called within a tokio runtime.
+    #[cfg(unix)]
+    pub(crate) fn new(master: RawFd) -> io::Result<Self> {
+        // SAFETY: duplicating a descriptor has no memory safety preconditions.
+        let dup = unsafe { libc::fcntl(master, libc::F_DUPFD_CLOEXEC, 0) };
+        if dup == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: the duplicate is a fresh descriptor owned by nobody else.
+        let fd = unsafe { OwnedFd::from_raw_fd(dup) };
+        Ok(Self {
+            fd: AsyncFd::with_interest(fd, tokio::io::Interest::READABLE)?,
+        })
+    }
+
+    /// Read the master on a thread of its own.
+    #[cfg(not(unix))]
+    pub(crate) fn new(mut reader: Box<dyn io::Read + Send>) -> Self {
+        let (tx, rx) = tokio::sync::mpsc::channel(64);
+        std::thread::spawn(move || {
+            let mut buf = [0u8; 4096];
+            while let Ok(n) = reader.read(&mut buf) {
+                if n == 0 || tx.blocking_send(buf[..n].to_vec()).is_err() {
+                    break;
+                }
+            }
+        });
+        Self {
+            rx,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Read output, waiting until some is available.
+    ///
+    /// Returns 0 once every process holding the terminal has closed it.
+    pub async fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        #[cfg(unix)]
+        loop {
+            let mut guard = self.fd.readable().await?;
+            let result = guard.try_io(|fd| {
+                // The descriptor blocks, so only read once poll says it won't
+                let mut pollfd = libc::pollfd {
+                    fd: fd.as_raw_fd(),
+                    events: libc::POLLIN,
+                    revents: 0,
+                };
+                // SAFETY: pollfd is valid for the one entry passed.
+                match unsafe { libc::poll(&mut pollfd, 1, 0) } {
+                    -1 => return Err(io::Error::last_os_error()),
+                    0 => return Err(io::ErrorKind::WouldBlock.into()),
+                    _ => {}
+                }
+                // SAFETY: buf is valid for writes of its length.
+                let n = unsafe { libc::read(fd.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len()) };
+                if n == -1 {
+                    Err(io::Error::last_os_error())
+                } else {
+                    Ok(n as usize)
+                }
+            });
+            match result {
+                // EIO means the slave side was closed
+                Ok(Err(e)) if e.raw_os_error() == Some(libc::EIO) => return Ok(0),
+                Ok(Err(e)) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Ok(result) => return result,
+                Err(_would_block) => continue,
+            }
+        }
+        #[cfg(not(unix))]
+        {
+            if self.pending.is_empty() {
+                match self.rx.recv().await {
+                    Some(data) => self.pending = data,
+                    None => return Ok(0),
+                }
+            }
+            let n = buf.len().min(self.pending.len());
+            buf[..n].copy_from_slice(&self.pending[..n]);
+            self.pending.drain(..n);
+            Ok(n)
+        }
+    }
+}
+
+/// Wakes up when a child process may have exited.
+///
+/// Whether it actually did is up to the caller to check with `try_wait`:
+/// with the `SIGCHLD` fallback any child's exit wakes every watcher.
+pub struct ExitWatcher {
+    watch: Watch,
+}
+
+enum Watch {
+    #[cfg(target_os = "linux")]
+    Pidfd(AsyncFd<OwnedFd>),
+    #[cfg(unix)]
+    Sigchld {
+        signal: tokio::signal::unix::Signal,
+        /// Whether the child was checked for having exited before the
+        /// handler was installed.
+        checked: bool,
+    },
+    #[cfg(not(unix))]
+    Poll(tokio::time::Interval),
+}
+
+impl ExitWatcher {
+    /// Watch the given child process.
+    ///
+    /// Must be called within a tokio runtime, before the child is reaped.
+    pub(crate) fn new(pid: u32) -> io::Result<Self> {
+        #[cfg(target_os = "linux")]
+        {
+            // SAFETY: pidfd_open has no memory safety preconditions.
+            let pidfd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid as libc::pid_t, 0) };
+            // pidfd_open needs Linux 5.3, fall back to SIGCHLD before that
+            if pidfd >= 0 {
+                // SAFETY: the pidfd is a fresh descriptor owned by nobody else.
+                let fd = unsafe { OwnedFd::from_raw_fd(pidfd as RawFd) };
+                let fd = AsyncFd::with_interest(fd, tokio::io::Interest::READABLE)?;
+                return Ok(Self {
+                    watch: Watch::Pidfd(fd),
+                });
+            }
+        }
+        let _ = pid;
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{signal, SignalKind};
+
+            Ok(Self {
+                watch: Watch::Sigchld {
+                    signal: signal(SignalKind::child())?,
+                    checked: false,
+                },
+            })
+        }
+        #[cfg(not(unix))]
+        {
+            let interval = tokio::time::interval(std::time::Duration::from_millis(50));
+            Ok(Self {
+                watch: Watch::Poll(interval),
+            })
+        }
+    }
+
+    /// Wait until the child may have exited.
+    pub async fn changed(&mut self) {
+        match &mut self.watch {
+            #[cfg(target_os = "linux")]
+            Watch::Pidfd(fd) => {
+                // Stays readable once the child exited
+                if fd.readable().await.is_err() {
+                    std::future::pending::<()>().await;
+                }
+            }
+            #[cfg(unix)]
+            Watch::Sigchld { signal, checked } => {
+                if *checked {
+                    signal.recv().await;
+                }
+                *checked = true;
+            }
+            #[cfg(not(unix))]
+            Watch::Poll(interval) => {
+                interval.tick().await;
+            }
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[tokio::test]
+    async fn test_read_pipe_until_closed() {
+        let mut fds = [0; 2];
+        // SAFETY: fds has room for both ends of the pipe.
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        // SAFETY: the pipe's descriptors are owned by nobody else.
+        let (reader, mut writer) = unsafe {
+            (
+                OwnedFd::from_raw_fd(fds[0]),
+                std::fs::File::from(OwnedFd::from_raw_fd(fds[1])),
+            )
+        };
+        let mut master = AsyncMaster::new(reader.as_raw_fd()).unwrap();
+        drop(reader);
+
+        writer.write_all(b"hello").unwrap();
+        let mut buf = [0u8; 16];
+        let n = master.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"hello");
+
+        drop(writer);
+        assert_eq!(master.read(&mut buf).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_master_stays_blocking() {
+        let mut fds = [0; 2];
+        // SAFETY: fds has room for both ends of the pipe.
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        // SAFETY: the pipe's descriptors are owned by nobody else.
+        let (reader, writer) =
+            unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+        let _master = AsyncMaster::new(reader.as_raw_fd()).unwrap();
+
+        // The duplicate shares the file status flags with the original
+        // SAFETY: only the file status flags are read.
+        let flags = unsafe { libc::fcntl(reader.as_raw_fd(), libc::F_GETFL) };
+        assert_eq!(flags & libc::O_NONBLOCK, 0);
+        drop(writer);
+    }
+
+    #[tokio::test]
+    async fn test_exit_watcher() {
+        let mut child = std::process::Command::new("/bin/sh")
+            .arg("-c")
+            .arg("sleep 0.1")
+            .spawn()
+            .unwrap();
+        let mut watcher = ExitWatcher::new(child.id()).unwrap();
+
+        let status = tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            loop {
+                if let Some(status) = child.try_wait().unwrap() {
+                    return status;
+                }
+                watcher.changed().await;
+            }
+        })
+        .await
+        .unwrap();
+        assert!(status.success());
+    }
+}
@@ -0,0 +1,579 @@
+//! Filesystem and network sandbox for spawned processes.
+//!
+//! A [`Sandbox`] leaves the filesystem readable but only the working
+//! directory and the listed directories writable, and can cut a command
+//! off from the network.
+//!
+//! On Linux the child enters new mount (and network) namespaces before
+//! `exec`, inside an unprivileged user namespace unless it still runs as
+//! root, and remounts the root read-only with the writable directories
+//! bind-mounted on top. Where the kernel supports Landlock, a ruleset
+//! granting write access to the same directories is applied as well, so
+//! that root commands cannot simply remount. When namespaces are not
+//! available, e.g. because unprivileged user namespaces are disabled, the
+//! Landlock ruleset alone confines the command.
+
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::ShellTunnelError;
+use crate::Result;
+
+/// Sandbox settings of a command.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Sandbox {
+    /// Directories writable besides the working directory.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub writable_dirs: Vec<PathBuf>,
+    /// Cut the command off from the network.
+    pub isolate_network: bool,
+}
+
+impl Sandbox {
+    /// A sandbox where only the working directory is writable.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Make a directory writable.
+    pub fn with_writable_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.writable_dirs.push(dir.into());
+        self
+    }
+
+    /// Cut the command off from the network.
+    pub fn with_isolated_network(mut self) -> Self {
+        self.isolate_network = true;
+        self
+    }
+
+    /// Canonicalize the writable directories, which must exist.
+    pub fn canonicalize(&self) -> Result<Self> {
+        let writable_dirs = self
+            .writable_dirs
+            .iter()
+            .map(|dir| {
+                dir.canonicalize().map_err(|e| {
+                    ShellTunnelError::Sandbox(format!(
+                        "writable directory {}: {}",
+                        dir.display(),
+                        e
+                    ))
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            writable_dirs,
+            isolate_network: self.isolate_network,
+        })
+    }
+
+    /// Apply a requested sandbox inside this one.
+    ///
+    /// The network is isolated if either asks for it. Requested writable
+    /// directories are kept only if they lie inside one of this sandbox's;
+    /// without any, this sandbox's directories stay writable.
+    pub fn restrict(&self, requested: &Sandbox) -> Self {
+        let canonical = |dirs: &[PathBuf]| -> Vec<PathBuf> {
+            dirs.iter()
+                .filter_map(|dir| dir.canonicalize().ok())
+                .collect()
+        };
+        let allowed = canonical(&self.writable_dirs);
+        let writable_dirs = if requested.writable_dirs.is_empty() {
+            allowed
+        } else {
+            canonical(&requested.writable_dirs)
+                .into_iter()
+                .filter(|dir| allowed.iter().any(|root| dir.starts_with(root)))
+                .collect()
+        };
+        Self {
+            writable_dirs,
+            isolate_network: self.isolate_network || requested.isolate_network,
+        }
+    }
+
+    /// Prepare the sandbox for a child that runs in `cwd` (the server's
+    /// directory if `None`) as the given ids.
+    ///
+    /// Everything the child needs is allocated and opened here, before the
+    /// fork.
+    #[cfg(target_os = "linux")]
+    pub(crate) fn prepare(
+        &self,
+        cwd: Option<&std::path::Path>,
+        uid: libc::uid_t,
+        gid: libc::gid_t,
+    ) -> Result<linux::PreparedSandbox> {
+        let cwd = match cwd {
+            Some(dir) => dir.canonicalize(),
+            None => std::env::current_dir(),
+        }
+        .map_err(|e| ShellTunnelError::Sandbox(format!("working directory: {}", e)))?;
+
+        let mut writable = self.canonicalize()?.writable_dirs;
+        writable.push(cwd.clone());
+        writable.sort();
+        writable.dedup();
+        linux::PreparedSandbox::new(cwd, &writable, self.isolate_network, uid, gid)
+            .map_err(|e| ShellTunnelError::Sandbox(e.to_string()))
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub(crate) mod linux {
+    use std::ffi::CString;
+    use std::fs::File;
+    use std::io;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::path::{Path, PathBuf};
+
+    const LANDLOCK_CREATE_RULESET_VERSION: libc::c_uint = 1 << 0;
+    const LANDLOCK_RULE_PATH_BENEATH: libc::c_int = 1;
+
+    const ACCESS_FS_WRITE_FILE: u64 = 1 << 1;
+    const ACCESS_FS_REMOVE_DIR: u64 = 1 << 4;
+    const ACCESS_FS_REMOVE_FILE: u64 = 1 << 5;
+    const ACCESS_FS_MAKE_CHAR: u64 = 1 << 6;
+    const ACCESS_FS_MAKE_DIR: u64 = 1 << 7;
+    const ACCESS_FS_MAKE_REG: u64 = 1 << 8;
+    const ACCESS_FS_MAKE_SOCK: u64 = 1 << 9;
+    const ACCESS_FS_MAKE_FIFO: u64 = 1 << 10;
+    const ACCESS_FS_MAKE_BLOCK: u64 = 1 << 11;
+    const ACCESS_FS_MAKE_SYM: u64 = 1 << 12;
+    const ACCESS_FS_REFER: u64 = 1 << 13;
+    const ACCESS_FS_TRUNCATE: u64 = 1 << 14;
+    const ACCESS_NET_BIND_TCP: u64 = 1 << 0;
+    const ACCESS_NET_CONNECT_TCP: u64 = 1 << 1;
+
+    /// Rights that modify the filesystem in the first Landlock ABI.
+    const ACCESS_FS_WRITE: u64 = ACCESS_FS_WRITE_FILE
+        | ACCESS_FS_REMOVE_DIR
+        | ACCESS_FS_REMOVE_FILE
+        | ACCESS_FS_MAKE_CHAR
+        | ACCESS_FS_MAKE_DIR
+        | ACCESS_FS_MAKE_REG
+        | ACCESS_FS_MAKE_SOCK
+        | ACCESS_FS_MAKE_FIFO
+        | ACCESS_FS_MAKE_BLOCK
+        | ACCESS_FS_MAKE_SYM;
+
+    #[repr(C)]
+    struct RulesetAttr {
+        handled_access_fs: u64,
+        handled_access_net: u64,
+    }
+
+    #[repr(C, packed)]
+    struct PathBeneathAttr {
+        allowed_access: u64,
+        parent_fd: libc::c_int,
+    }
+
+    /// A sandbox ready to be entered by a forked child.
+    pub(crate) struct PreparedSandbox {
+        cwd: CString,
+        writable: Vec<CString>,
+        isolate_network: bool,
+        id_maps: Option<IdMaps>,
+        landlock: Option<Landlock>,
+    }
+
+    /// Contents of the id maps of a new user namespace.
+    struct IdMaps {
+        uid_map: Vec<u8>,
+        gid_map: Vec<u8>,
+    }
+
+    /// A Landlock ruleset allowing writes to the writable directories.
+    struct Landlock {
+        ruleset: OwnedFd,
+        isolates_network: bool,
+    }
+
+    impl PreparedSandbox {
+        pub(super) fn new(
+            cwd: PathBuf,
+            writable: &[PathBuf],
+            isolate_network: bool,
+            uid: libc::uid_t,
+            gid: libc::gid_t,
+        ) -> io::Result<Self> {
+            let c_path = |path: &Path| {
+                CString::new(path.as_os_str().as_bytes())
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+            };
+            // Root can create the mount namespace itself; anyone else
+            // needs a user namespace that maps just their own ids
+            let id_maps = (uid != 0).then(|| IdMaps {
+                uid_map: format!("{} {} 1", uid, uid).into_bytes(),
+                gid_map: format!("{} {} 1", gid, gid).into_bytes(),
+            });
+            Ok(Self {
+                cwd: c_path(&cwd)?,
+                writable: writable
+                    .iter()
+                    .map(|dir| c_path(dir))
+                    .collect::<io::Result<_>>()?,
+                isolate_network,
+                id_maps,
+                landlock: Landlock::new(writable, isolate_network)?,
+            })
+        }
+
+        /// Enter the sandbox in the forked child.
+        ///
+        /// Only makes async-signal-safe calls.
+        pub(crate) fn apply(&self) -> io::Result<()> {
+            // SAFETY: plain syscalls on values prepared before the fork.
+            unsafe {
+                if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                match self.unshare() {
+                    Ok(()) => self.mount()?,
+                    Err(e) => match &self.landlock {
+                        // Fall back to Landlock alone if it covers everything
+                        Some(landlock) if landlock.isolates_network || !self.isolate_network => {}
+                        _ => return Err(e),
+                    },
+                }
+                if let Some(landlock) = &self.landlock {
+                    landlock.restrict()?;
+                }
+                // The working directory was entered before the remount
+                if libc::chdir(self.cwd.as_ptr()) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        }
+
+        unsafe fn unshare(&self) -> io::Result<()> {
+            let mut flags = libc::CLONE_NEWNS;
+            if self.id_maps.is_some() {
+                flags |= libc::CLONE_NEWUSER;
+                // Keep /proc/self writable after dropping from root
+                libc::prctl(libc::PR_SET_DUMPABLE, 1, 0, 0, 0);
+            }
+            if self.isolate_network {
+                flags |= libc::CLONE_NEWNET;
+            }
+            if libc::unshare(flags) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        }
+
+        unsafe fn mount(&self) -> io::Result<()> {
+            if let Some(maps) = &self.id_maps {
+                write_proc(b"/proc/self/setgroups\0", b"deny")?;
+                write_proc(b"/proc/self/uid_map\0", &maps.uid_map)?;
+                write_proc(b"/proc/self/gid_map\0", &maps.gid_map)?;
+            }
+
+            let root = b"/\0".as_ptr().cast();
+            let null = std::ptr::null();
+            if libc::mount(
+                null,
+                root,
+                null,
+                libc::MS_REC | libc::MS_PRIVATE,
+                null.cast(),
+            ) == -1
+            {
+                return Err(io::Error::last_os_error());
+            }
+            for dir in &self.writable {
+                let flags = libc::MS_BIND | libc::MS_REC;
+                if libc::mount(dir.as_ptr(), dir.as_ptr(), null, flags, null.cast()) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            set_readonly(root, true, libc::AT_RECURSIVE)?;
+            for dir in &self.writable {
+                set_readonly(dir.as_ptr(), false, 0)?;
+            }
+
+            if self.isolate_network {
+                loopback_up()?;
+            }
+            Ok(())
+        }
+    }
+
+    impl Landlock {
+        /// Build the ruleset, `None` if the kernel lacks Landlock.
+        fn new(writable: &[PathBuf], isolate_network: bool) -> io::Result<Option<Self>> {
+            // SAFETY: querying the ABI version takes no attributes.
+            let abi = unsafe {
+                libc::syscall(
+                    libc::SYS_landlock_create_ruleset,
+                    std::ptr::null::<RulesetAttr>(),
+                    0usize,
+                    LANDLOCK_CREATE_RULESET_VERSION,
+                )
+            };
+            if abi < 1 {
+                return Ok(None);
+            }
+
+            let mut handled_fs = ACCESS_FS_WRITE;
+            if abi >= 2 {
+                handled_fs |= ACCESS_FS_REFER;
+            }
+            if abi >= 3 {
+                handled_fs |= ACCESS_FS_TRUNCATE;
+            }
+            // Network rules need ABI 4, and cover TCP only
+            let handled_net = if isolate_network && abi >= 4 {
+                ACCESS_NET_BIND_TCP | ACCESS_NET_CONNECT_TCP
+            } else {
+                0
+            };
+            let attr = RulesetAttr {
+                handled_access_fs: handled_fs,
+                handled_access_net: handled_net,
+            };
+            let size = if abi >= 4 {
+                std::mem::size_of::<RulesetAttr>()
+            } else {
+                std::mem::size_of::<u64>()
+            };
+            // SAFETY: `attr` is valid for `size` bytes.
+            let fd = unsafe {
+                libc::syscall(
+                    libc::SYS_landlock_create_ruleset,
+                    &attr as *const RulesetAttr,
+                    size,
+                    0,
+                )
+            };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            // SAFETY: the syscall returned a new descriptor we now own.
+            let ruleset = unsafe { OwnedFd::from_raw_fd(fd as libc::c_int) };
+
+            let landlock = Self {
+                ruleset,
+                isolates_network: handled_net != 0,
+            };
+            for dir in writable {
+                landlock.allow(dir, handled_fs)?;
+            }
+            // Terminals and /dev/null are opened for writing
+            landlock.allow(
+                Path::new("/dev"),
+                ACCESS_FS_WRITE_FILE | (handled_fs & ACCESS_FS_TRUNCATE),
+            )?;
+            Ok(Some(landlock))
+        }
+
+        fn allow(&self, path: &Path, access: u64) -> io::Result<()> {
+            let dir = File::options()
+                .read(true)
+                .custom_flags(libc::O_PATH)
+                .open(path)?;
+            let attr = PathBeneathAttr {
+                allowed_access: access,
+                parent_fd: dir.as_raw_fd(),
+            };
+            // SAFETY: `attr` outlives the call and `dir` is open.
+            let status = unsafe {
+                libc::syscall(
+                    libc::SYS_landlock_add_rule,
+                    self.ruleset.as_raw_fd(),
+                    LANDLOCK_RULE_PATH_BENEATH,
+                    &attr as *const PathBeneathAttr,
+                    0,
+                )
+            };
+            if status < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        }
+
+        unsafe fn restrict(&self) -> io::Result<()> {
+            if libc::syscall(
+                libc::SYS_landlock_restrict_self,
+                self.ruleset.as_raw_fd(),
+                0,
+            ) < 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        }
+    }
+
+    unsafe fn write_proc(path: &[u8], contents: &[u8]) -> io::Result<()> {
+        let fd = libc::open(path.as_ptr().cast(), libc::O_WRONLY | libc::O_CLOEXEC);
+        if fd == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let written = libc::write(fd, contents.as_ptr().cast(), contents.len());
+        let error = io::Error::last_os_error();
+        libc::close(fd);
+        if written != contents.len() as isize {
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    unsafe fn set_readonly(
+        path: *const libc::c_char,
+        readonly: bool,
+        flags: libc::c_int,
+    ) -> io::Result<()> {
+        let mut attr: libc::mount_attr = std::mem::zeroed();
+        if readonly {
+            attr.attr_set = libc::MOUNT_ATTR_RDONLY;
+        } else {
+            attr.attr_clr = libc::MOUNT_ATTR_RDONLY;
+        }
+        let status = libc::syscall(
+            libc::SYS_mount_setattr,
+            libc::AT_FDCWD,
+            path,
+            flags,
+            &attr as *const libc::mount_attr,
+            std::mem::size_of::<libc::mount_attr>(),
+        );
+        if status < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Bring up the loopback interface of a new network namespace.
+    unsafe fn loopback_up() -> io::Result<()> {
+        let socket = libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0);
+        if socket == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut request: libc::ifreq = std::mem::zeroed();
+        request.ifr_name[0] = b'l' as libc::c_char;
+        request.ifr_name[1] = b'o' as libc::c_char;
+        let mut status = libc::ioctl(socket, libc::SIOCGIFFLAGS as _, &mut request);
+        if status != -1 {
+            request.ifr_ifru.ifru_flags |= libc::IFF_UP as libc::c_short;
+            status = libc::ioctl(socket, libc::SIOCSIFFLAGS as _, &request);
+        }
+        let error = io::Error::last_os_error();
+        libc::close(socket);
+        if status == -1 {
+            return Err(error);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn test_restrict() {
+        let work = tempfile::tempdir().unwrap();
+        let inner = work.path().join("build");
+        std::fs::create_dir(&inner).unwrap();
+        let other = tempfile::tempdir().unwrap();
+        let work_dir = work.path().canonicalize().unwrap();
+
+        let key = Sandbox::new().with_writable_dir(work.path());
+        let sandbox = key.restrict(&Sandbox::new().with_isolated_network());
+        assert_eq!(sandbox.writable_dirs, vec![work_dir.clone()]);
+        assert!(sandbox.isolate_network);
+
+        let requested = Sandbox::new()
+            .with_writable_dir(&inner)
+            .with_writable_dir(other.path())
+            .with_writable_dir(work.path().join("build/../.."));
+        let sandbox = key.restrict(&requested);
+        assert_eq!(sandbox.writable_dirs, vec![work_dir.join("build")]);
+        assert!(!sandbox.isolate_network);
+    }
+
+    #[test]
+    fn test_serde() {
+        let sandbox: Sandbox =
+            serde_json::from_str(r#"{"writable_dirs": ["/tmp"], "isolate_network": true}"#)
+                .unwrap();
+        assert_eq!(
+            sandbox,
+            Sandbox::new()
+                .with_writable_dir("/tmp")
+                .with_isolated_network()
+        );
+        let sandbox: Sandbox = serde_json::from_str("{}").unwrap();
+        assert_eq!(sandbox, Sandbox::new());
+        assert!(matches!(
+            Sandbox::new()
+                .with_writable_dir("/no/such/dir/here")
+                .canonicalize(),
+            Err(ShellTunnelError::Sandbox(_))
+        ));
+    }
+
+    #[cfg(target_os = "linux")]
+    fn run_sandboxed(sandbox: &Sandbox, cwd: &Path, script: &str) -> std::process::Output {
+        use std::os::unix::process::CommandExt;
+
+        // SAFETY: getuid and getgid have no preconditions.
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        let prepared = sandbox.prepare(Some(cwd), uid, gid).unwrap();
+        let mut command = std::process::Command::new("/bin/sh");
+        command.arg("-c").arg(script);
+        // SAFETY: apply only makes async-signal-safe calls.
+        unsafe {
+            command.pre_exec(move || prepared.apply());
+        }
+        command.output().unwrap()
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    #[ignore] // needs user namespaces or Landlock
+    fn test_sandbox_confines_writes() {
+        let work = tempfile::tempdir().unwrap();
+        let cache = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        let sandbox = Sandbox::new().with_writable_dir(cache.path());
+
+        let script = format!(
+            "touch ok && touch {}/ok && touch {}/escaped",
+            cache.path().display(),
+            outside.path().display()
+        );
+        let output = run_sandboxed(&sandbox, work.path(), &script);
+        assert!(!output.status.success());
+        assert!(work.path().join("ok").exists());
+        assert!(cache.path().join("ok").exists());
+        assert!(!outside.path().join("escaped").exists());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    #[ignore] // needs user namespaces
+    fn test_sandbox_isolates_network() {
+        let work = tempfile::tempdir().unwrap();
+        let sandbox = Sandbox::new().with_isolated_network();
+        let output = run_sandboxed(&sandbox, work.path(), "tail -n +3 /proc/net/dev");
+        assert!(output.status.success());
+        let interfaces = String::from_utf8_lossy(&output.stdout);
+        let names: Vec<&str> = interfaces
+            .lines()
+            .filter_map(|line| line.split(':').next())
+            .map(str::trim)
+            .collect();
+        assert_eq!(names, vec!["lo"]);
+    }
+}
@@ -0,0 +1,519 @@
+//! Child process setup for PTY commands.
+//!
+//! On Unix, commands are spawned on the slave side of the PTY with
+//! `std::process::Command` so that the child can be set up before `exec`:
+//! it becomes a session leader with the PTY as its controlling terminal,
+//! gets its [`ResourceLimits`], drops to the account given by [`RunAs`]
+//! when the server runs as root, and enters its [`Sandbox`].
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use super::limits::ResourceLimits;
+use super::sandbox::Sandbox;
+use crate::error::ShellTunnelError;
+use crate::Result;
+
+/// Unix account a command runs as.
+///
+/// Users and groups are names or numeric ids. A user without a group runs
+/// with the user's primary group; a group without a user keeps the
+/// server's user.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunAs {
+    /// User to switch to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    /// Group to switch to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+}
+
+impl RunAs {
+    /// Run as a user with its primary group.
+    pub fn user(user: impl Into<String>) -> Self {
+        Self {
+            user: Some(user.into()),
+            group: None,
+        }
+    }
+
+    /// Run with the given group.
+    pub fn with_group(mut self, group: impl Into<String>) -> Self {
+        self.group = Some(group.into());
+        self
+    }
+
+    /// Build from optional user and group settings, `None` if neither is
+    /// set.
+    pub fn from_parts(user: Option<String>, group: Option<String>) -> Option<Self> {
+        if user.is_none() && group.is_none() {
+            return None;
+        }
+        Some(Self { user, group })
+    }
+
+    /// Look up the user and group ids.
+    #[cfg(unix)]
+    pub fn resolve(&self) -> Result<Credentials> {
+        let user = match &self.user {
+            Some(user) => Some(lookup_user(user)?),
+            None => None,
+        };
+        let gid = match (&self.group, &user) {
+            (Some(group), _) => lookup_group(group)?,
+            (None, Some(user)) => user.gid,
+            // SAFETY: getgid has no preconditions.
+            (None, None) => unsafe { libc::getgid() },
+        };
+
+        let credentials = match user {
+            Some(user) => Credentials { gid, ..user },
+            None => Credentials {
+                // SAFETY: getuid has no preconditions.
+                uid: unsafe { libc::getuid() },
+                gid,
+                name: None,
+                home: None,
+            },
+        };
+        credentials.check_privileges()?;
+        Ok(credentials)
+    }
+
+    /// Accounts cannot be switched on this platform.
+    #[cfg(not(unix))]
+    pub fn resolve(&self) -> Result<()> {
+        Err(ShellTunnelError::RunAs(
+            "running commands as another user is only supported on Unix".to_string(),
+        ))
+    }
+}
+
+impl std::fmt::Display for RunAs {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (&self.user, &self.group) {
+            (Some(user), Some(group)) => write!(f, "{}:{}", user, group),
+            (Some(user), None) => write!(f, "{}", user),
+            (None, Some(group)) => write!(f, ":{}", group),
+            (None, None) => write!(f, "(server user)"),
+        }
+    }
+}
+
+/// Resolved ids of a [`RunAs`] account.
+#[cfg(unix)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Credentials {
+    /// User id.
+    pub uid: libc::uid_t,
+    /// Group id.
+    pub gid: libc::gid_t,
+    /// User name, for `USER` and `LOGNAME`.
+    pub name: Option<String>,
+    /// Home directory, for `HOME`.
+    pub home: Option<std::path::PathBuf>,
+}
+
+#[cfg(unix)]
+impl Credentials {
+    /// Check if switching to these ids changes anything.
+    fn is_switch(&self) -> bool {
+        // SAFETY: getuid and getgid have no preconditions.
+        unsafe { self.uid != libc::getuid() || self.gid != libc::getgid() }
+    }
+
+    /// Only root may switch accounts.
+    fn check_privileges(&self) -> Result<()> {
+        // SAFETY: geteuid has no preconditions.
+        if self.is_switch() && unsafe { libc::geteuid() } != 0 {
+            return Err(ShellTunnelError::RunAs(format!(
+                "switching to uid {} gid {} requires the server to run as root",
+                self.uid, self.gid
+            )));
+        }
+        Ok(())
+    }
+
+    /// Drop to these ids, clearing supplementary groups.
+    ///
+    /// Runs in the forked child before `exec`, so it may only make
+    /// async-signal-safe calls.
+    pub(crate) fn apply(&self) -> std::io::Result<()> {
+        if !self.is_switch() {
+            return Ok(());
+        }
+        // SAFETY: plain syscalls on values prepared before the fork.
+        unsafe {
+            if libc::setgroups(0, std::ptr::null()) == -1
+                || libc::setgid(self.gid) == -1
+                || libc::setuid(self.uid) == -1
+            {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Settings applied to a child process before `exec`.
+#[derive(Debug, Clone, Default)]
+pub struct SpawnOptions {
+    /// Account to run as.
+    pub run_as: Option<RunAs>,
+    /// Resource limits.
+    pub limits: ResourceLimits,
+    /// Sandbox to confine the child to.
+    pub sandbox: Option<Sandbox>,
+    /// cgroup v2 directory the child joins.
+    pub cgroup: Option<PathBuf>,
+    /// Environment variables set on top of the inherited ones.
+    pub env: HashMap<String, String>,
+}
+
+impl SpawnOptions {
+    /// Run the child as the given account.
+    pub fn with_run_as(mut self, run_as: Option<RunAs>) -> Self {
+        self.run_as = run_as;
+        self
+    }
+
+    /// Limit the child's resources.
+    pub fn with_limits(mut self, limits: ResourceLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Confine the child to a sandbox.
+    pub fn with_sandbox(mut self, sandbox: Option<Sandbox>) -> Self {
+        self.sandbox = sandbox;
+        self
+    }
+
+    /// Place the child in a cgroup.
+    pub fn with_cgroup(mut self, cgroup: Option<PathBuf>) -> Self {
+        self.cgroup = cgroup;
+        self
+    }
+
+    /// Set environment variables for the child.
+    pub fn with_env(mut self, env: HashMap<String, String>) -> Self {
+        self.env = env;
+        self
+    }
+}
+
+/// Spawn a command on the slave side of a PTY.
+#[cfg(unix)]
+pub(crate) fn spawn_on_pty(
+    pair: &portable_pty::PtyPair,
+    builder: portable_pty::CommandBuilder,
+    options: &SpawnOptions,
+) -> Result<Box<dyn portable_pty::Child + Send + Sync>> {
+    use std::os::fd::AsRawFd;
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::os::unix::process::CommandExt;
+    use std::process::Stdio;
+
+    use super::limits::apply_rlimits;
+
+    let pty_error = |e: std::io::Error| ShellTunnelError::Pty(e.to_string());
+    let credentials = match &options.run_as {
+        Some(run_as) => Some(run_as.resolve()?),
+        None => None,
+    };
+    let rlimits = options.limits.to_rlimits();
+    #[cfg(target_os = "linux")]
+    let sandbox = match &options.sandbox {
+        Some(sandbox) => {
+            // SAFETY: getuid and getgid have no preconditions.
+            let (uid, gid) = match &credentials {
+                Some(credentials) => (credentials.uid, credentials.gid),
+                None => unsafe { (libc::getuid(), libc::getgid()) },
+            };
+            let cwd = builder.get_cwd().map(std::path::Path::new);
+            Some(sandbox.prepare(cwd, uid, gid)?)
+        }
+        None => None,
+    };
+    #[cfg(not(target_os = "linux"))]
+    if options.sandbox.is_some() {
+        return Err(ShellTunnelError::Sandbox(
+            "sandboxing is only supported on Linux".to_string(),
+        ));
+    }
+    let cgroup_procs = match &options.cgroup {
+        Some(cgroup) => Some(
+            std::fs::OpenOptions::new()
+                .write(true)
+                .open(cgroup.join("cgroup.procs"))
+                .map_err(|e| ShellTunnelError::Cgroup(format!("{}: {}", cgroup.display(), e)))?,
+        ),
+        None => None,
+    };
+
+    let tty = pair
+        .master
+        .tty_name()
+        .ok_or_else(|| ShellTunnelError::Pty("PTY has no slave device".to_string()))?;
+    let slave = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NOCTTY)
+        .open(&tty)
+        .map_err(pty_error)?;
+
+    let argv = builder.get_argv();
+    let mut command = std::process::Command::new(&argv[0]);
+    command.args(&argv[1..]);
+    if let Some(dir) = builder.get_cwd() {
+        command.current_dir(dir);
+    }
+    if let Some(credentials) = &credentials {
+        if let Some(name) = &credentials.name {
+            command.env("USER", name).env("LOGNAME", name);
+        }
+        if let Some(home) = &credentials.home {
+            command.env("HOME", home);
+        }
+    }
+    command.envs(builder.iter_extra_env_as_str());
+    command.envs(&options.env);
+    command
+        .stdin(Stdio::from(slave.try_clone().map_err(pty_error)?))
+        .stdout(Stdio::from(slave.try_clone().map_err(pty_error)?))
+        .stderr(Stdio::from(slave));
+
+    // SAFETY: the hook only makes async-signal-safe syscalls on values
+    // prepared before the fork.
+    unsafe {
+        command.pre_exec(move || {
+            // Undo signal dispositions and masks inherited from the server
+            for signal in [
+                libc::SIGCHLD,
+                libc::SIGHUP,
+                libc::SIGINT,
+                libc::SIGQUIT,
+                libc::SIGTERM,
+                libc::SIGALRM,
+                libc::SIGPIPE,
+            ] {
+                libc::signal(signal, libc::SIG_DFL);
+            }
+            let mut empty: libc::sigset_t = std::mem::zeroed();
+            libc::sigemptyset(&mut empty);
+            libc::sigprocmask(libc::SIG_SETMASK, &empty, std::ptr::null_mut());
+
+            // Become a session leader with the PTY as controlling terminal
+            if libc::setsid() == -1 || libc::ioctl(0, libc::TIOCSCTTY as _, 0) == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+
+            // Join the cgroup while still privileged ("0" moves the writer)
+            if let Some(procs) = &cgroup_procs {
+                if libc::write(procs.as_raw_fd(), b"0".as_ptr().cast(), 1) == -1 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+
+            // Limits are set while still privileged, then the account drops
+            apply_rlimits(&rlimits)?;
+            if let Some(credentials) = &credentials {
+                credentials.apply()?;
+            }
+            // Entered as the final account, so it cannot undo the mounts
+            #[cfg(target_os = "linux")]
+            if let Some(sandbox) = &sandbox {
+                sandbox.apply()?;
+            }
+            Ok(())
+        });
+    }
+
+    let child = command
+        .spawn()
+        .map_err(|e| match (&options.run_as, &options.sandbox) {
+            (Some(run_as), _) => ShellTunnelError::RunAs(format!("{}: {}", run_as, e)),
+            (None, Some(_)) => ShellTunnelError::Sandbox(e.to_string()),
+            (None, None) => pty_error(e),
+        })?;
+    Ok(Box::new(child))
+}
+
+/// Spawn a command on the slave side of a PTY.
+#[cfg(windows)]
+pub(crate) fn spawn_on_pty(
+    pair: &portable_pty::PtyPair,
+    mut builder: portable_pty::CommandBuilder,
+    options: &SpawnOptions,
+) -> Result<Box<dyn portable_pty::Child + Send + Sync>> {
+    for (key, value) in &options.env {
+        builder.env(key, value);
+    }
+    if let Some(run_as) = &options.run_as {
+        run_as.resolve()?;
+    }
+    if !options.limits.is_empty() {
+        return Err(ShellTunnelError::Pty(
+            "resource limits are only supported on Unix".to_string(),
+        ));
+    }
+    if options.sandbox.is_some() {
+        return Err(ShellTunnelError::Sandbox(
+            "sandboxing is only supported on Linux".to_string(),
+        ));
+    }
+    if options.cgroup.is_some() {
+        return Err(ShellTunnelError::Cgroup(
+            "cgroups are only supported on Linux".to_string(),
+        ));
+    }
+    pair.slave
+        .spawn_command(builder)
+        .map_err(|e| ShellTunnelError::Pty(e.to_string()))
+}
+
+#[cfg(unix)]
+fn lookup_user(user: &str) -> Result<Credentials> {
+    let not_found = || ShellTunnelError::RunAs(format!("unknown user '{}'", user));
+    let mut buf = vec![0; 4096];
+    loop {
+        // SAFETY: passwd is plain data and getpwnam_r/getpwuid_r only write
+        // into it and the buffer, whose length is passed along.
+        let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+        let mut result = std::ptr::null_mut();
+        let status = match user.parse::<libc::uid_t>() {
+            Ok(uid) => unsafe {
+                libc::getpwuid_r(uid, &mut pwd, buf.as_mut_ptr(), buf.len(), &mut result)
+            },
+            Err(_) => {
+                let name = std::ffi::CString::new(user).map_err(|_| not_found())?;
+                unsafe {
+                    libc::getpwnam_r(
+                        name.as_ptr(),
+                        &mut pwd,
+                        buf.as_mut_ptr(),
+                        buf.len(),
+                        &mut result,
+                    )
+                }
+            }
+        };
+        if status == libc::ERANGE && buf.len() < 1 << 20 {
+            buf.resize(buf.len() * 2, 0);
+            continue;
+        }
+        if result.is_null() {
+            // Numeric ids need no passwd entry
+            return match user.parse() {
+                Ok(uid) => Ok(Credentials {
+                    uid,
+                    // SAFETY: getgid has no preconditions.
+                    gid: unsafe { libc::getgid() },
+                    name: None,
+                    home: None,
+                }),
+                Err(_) => Err(not_found()),
+            };
+        }
+
+        // SAFETY: on success the strings point into `buf`.
+        let (name, home) = unsafe {
+            (
+                std::ffi::CStr::from_ptr(pwd.pw_name),
+                std::ffi::CStr::from_ptr(pwd.pw_dir),
+            )
+        };
+        return Ok(Credentials {
+            uid: pwd.pw_uid,
+            gid: pwd.pw_gid,
+            name: Some(name.to_string_lossy().into_owned()),
+            home: Some(std::path::PathBuf::from(
+                home.to_string_lossy().into_owned(),
+            )),
+        });
+    }
+}
+
+#[cfg(unix)]
+fn lookup_group(group: &str) -> Result<libc::gid_t> {
+    if let Ok(gid) = group.parse() {
+        return Ok(gid);
+    }
+    let not_found = || ShellTunnelError::RunAs(format!("unknown group '{}'", group));
+    let name = std::ffi::CString::new(group).map_err(|_| not_found())?;
+    let mut buf = vec![0; 4096];
+    loop {
+        // SAFETY: as in lookup_user.
+        let mut grp: libc::group = unsafe { std::mem::zeroed() };
+        let mut result = std::ptr::null_mut();
+        let status = unsafe {
+            libc::getgrnam_r(
+                name.as_ptr(),
+                &mut grp,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            )
+        };
+        if status == libc::ERANGE && buf.len() < 1 << 20 {
+            buf.resize(buf.len() * 2, 0);
+            continue;
+        }
+        if result.is_null() {
+            return Err(not_found());
+        }
+        return Ok(grp.gr_gid);
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_parts() {
+        assert_eq!(RunAs::from_parts(None, None), None);
+        assert_eq!(
+            RunAs::from_parts(Some("agent".to_string()), None),
+            Some(RunAs::user("agent"))
+        );
+        assert_eq!(
+            RunAs::user("agent").with_group("ci").to_string(),
+            "agent:ci"
+        );
+    }
+
+    #[test]
+    fn test_lookup() {
+        let root = lookup_user("root").unwrap();
+        assert_eq!((root.uid, root.gid), (0, 0));
+        assert_eq!(root.name.as_deref(), Some("root"));
+        assert_eq!(lookup_user("0").unwrap().name.as_deref(), Some("root"));
+        assert_eq!(lookup_group("0").unwrap(), 0);
+
+        assert!(matches!(
+            RunAs::user("no-such-user-here").resolve(),
+            Err(ShellTunnelError::RunAs(_))
+        ));
+        assert!(matches!(
+            RunAs::default().with_group("no-such-group-here").resolve(),
+            Err(ShellTunnelError::RunAs(_))
+        ));
+    }
+
+    #[test]
+    fn test_switch_requires_root() {
+        // SAFETY: geteuid has no preconditions.
+        if unsafe { libc::geteuid() } == 0 {
+            assert!(RunAs::user("nobody").resolve().is_ok());
+        } else {
+            assert!(matches!(
+                RunAs::user("0").resolve(),
+                Err(ShellTunnelError::RunAs(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,165 @@
+//! Exit status of spawned processes.
+
+/// How a child process ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitStatus {
+    /// The process exited with a status code.
+    Exited(i32),
+    /// The process was killed by a signal.
+    Signaled {
+        /// The signal number.
+        signal: i32,
+        /// Whether the process dumped core.
+        core_dumped: bool,
+    },
+}
+
+impl ExitStatus {
+    /// Decode a status reported by `waitpid`.
+    #[cfg(unix)]
+    pub fn from_raw(status: i32) -> Self {
+        if libc::WIFSIGNALED(status) {
+            Self::Signaled {
+                signal: libc::WTERMSIG(status),
+                core_dumped: libc::WCOREDUMP(status),
+            }
+        } else {
+            Self::Exited(libc::WEXITSTATUS(status))
+        }
+    }
+
+    /// Whether the process exited with code 0.
+    pub fn success(&self) -> bool {
+        *self == Self::Exited(0)
+    }
+
+    /// The exit code, unless the process was killed by a signal.
+    pub fn code(&self) -> Option<i32> {
+        match self {
+            Self::Exited(code) => Some(*code),
+            Self::Signaled { .. } => None,
+        }
+    }
+
+    /// The signal that killed the process.
+    pub fn signal(&self) -> Option<i32> {
+        match self {
+            Self::Exited(_) => None,
+            Self::Signaled { signal, .. } => Some(*signal),
+        }
+    }
+}
+
+impl std::fmt::Display for ExitStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Exited(code) => write!(f, "exited with code {}", code),
+            Self::Signaled {
+                signal,
+                core_dumped,
+            } => {
+                write!(f, "killed by {}", signal_name(*signal))?;
+                if *core_dumped {
+                    write!(f, " (core dumped)")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Name of a signal, such as `SIGKILL`.
+///
+/// Signals without a well-known name are reported by number, such as
+/// `SIG42`.
+pub fn signal_name(signal: i32) -> String {
+    #[cfg(unix)]
+    let name = match signal {
+        libc::SIGHUP => Some("SIGHUP"),
+        libc::SIGINT => Some("SIGINT"),
+        libc::SIGQUIT => Some("SIGQUIT"),
+        libc::SIGILL => Some("SIGILL"),
+        libc::SIGTRAP => Some("SIGTRAP"),
+        libc::SIGABRT => Some("SIGABRT"),
+        libc::SIGBUS => Some("SIGBUS"),
+        libc::SIGFPE => Some("SIGFPE"),
+        libc::SIGKILL => Some("SIGKILL"),
+        libc::SIGUSR1 => Some("SIGUSR1"),
+        libc::SIGSEGV => Some("SIGSEGV"),
+        libc::SIGUSR2 => Some("SIGUSR2"),
+        libc::SIGPIPE => Some("SIGPIPE"),
+        libc::SIGALRM => Some("SIGALRM"),
+        libc::SIGTERM => Some("SIGTERM"),
+        libc::SIGCHLD => Some("SIGCHLD"),
+        libc::SIGCONT => Some("SIGCONT"),
+        libc::SIGSTOP => Some("SIGSTOP"),
+        libc::SIGTSTP => Some("SIGTSTP"),
+        libc::SIGTTIN => Some("SIGTTIN"),
+        libc::SIGTTOU => Some("SIGTTOU"),
+        libc::SIGURG => Some("SIGURG"),
+        libc::SIGXCPU => Some("SIGXCPU"),
+        libc::SIGXFSZ => Some("SIGXFSZ"),
+        libc::SIGVTALRM => Some("SIGVTALRM"),
+        libc::SIGPROF => Some("SIGPROF"),
+        libc::SIGWINCH => Some("SIGWINCH"),
+        libc::SIGIO => Some("SIGIO"),
+        libc::SIGSYS => Some("SIGSYS"),
+        _ => None,
+    };
+    #[cfg(not(unix))]
+    let name: Option<&str> = None;
+
+    match name {
+        Some(name) => name.to_string(),
+        None => format!("SIG{}", signal),
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_status() {
+        let exited = ExitStatus::Exited(3);
+        assert!(!exited.success());
+        assert_eq!(exited.code(), Some(3));
+        assert_eq!(exited.signal(), None);
+        assert!(ExitStatus::Exited(0).success());
+
+        let killed = ExitStatus::Signaled {
+            signal: libc::SIGSEGV,
+            core_dumped: true,
+        };
+        assert_eq!(killed.code(), None);
+        assert_eq!(killed.signal(), Some(libc::SIGSEGV));
+        assert_eq!(killed.to_string(), "killed by SIGSEGV (core dumped)");
+    }
+
+    #[test]
+    fn test_from_raw() {
+        // Exit code in the second byte, signal in the low seven bits
+        assert_eq!(ExitStatus::from_raw(7 << 8), ExitStatus::Exited(7));
+        assert_eq!(
+            ExitStatus::from_raw(libc::SIGKILL),
+            ExitStatus::Signaled {
+                signal: libc::SIGKILL,
+                core_dumped: false
+            }
+        );
+        assert_eq!(
+            ExitStatus::from_raw(libc::SIGABRT | 0x80),
+            ExitStatus::Signaled {
+                signal: libc::SIGABRT,
+                core_dumped: true
+            }
+        );
+    }
+
+    #[test]
+    fn test_signal_name() {
+        assert_eq!(signal_name(libc::SIGKILL), "SIGKILL");
+        assert_eq!(signal_name(libc::SIGTERM), "SIGTERM");
+        assert_eq!(signal_name(200), "SIG200");
+    }
+}
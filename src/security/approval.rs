@@ -0,0 +1,387 @@
+//! Human approval of risky commands.
+//!
+//! Commands the validator flags as needing approval are parked in an
+//! [`ApprovalQueue`] under an id while the request that sent them waits.
+//! An operator lists the pending commands and approves or denies them; a
+//! request nobody decides on in time fails as expired. Pending entries are
+//! removed as soon as the waiting request goes away.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime};
+
+use serde::Serialize;
+use tokio::sync::oneshot;
+
+use crate::audit::format_timestamp;
+
+/// A command waiting for approval.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PendingApproval {
+    /// Approval id.
+    pub id: u64,
+    /// Command line, sanitized for display.
+    pub command: String,
+    /// Why the command needs approval.
+    pub reason: String,
+    /// Identity that sent the command.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub identity: Option<String>,
+    /// Client address of the request.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_ip: Option<IpAddr>,
+    /// Session the command would run in.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<u64>,
+    /// RFC 3339 time the command was parked.
+    pub requested_at: String,
+    /// RFC 3339 time the request expires.
+    pub expires_at: String,
+    #[serde(skip)]
+    deadline: SystemTime,
+}
+
+impl PendingApproval {
+    /// Describe a command needing approval.
+    pub fn new(command: impl Into<String>, reason: impl Into<String>) -> Self {
+        Self {
+            id: 0,
+            command: command.into(),
+            reason: reason.into(),
+            identity: None,
+            client_ip: None,
+            session_id: None,
+            requested_at: String::new(),
+            expires_at: String::new(),
+            deadline: SystemTime::UNIX_EPOCH,
+        }
+    }
+
+    /// Set the identity that sent the command.
+    pub fn with_identity(mut self, identity: Option<&str>) -> Self {
+        self.identity = identity.map(str::to_string);
+        self
+    }
+
+    /// Set the client address.
+    pub fn with_client_ip(mut self, ip: Option<IpAddr>) -> Self {
+        self.client_ip = ip;
+        self
+    }
+
+    /// Set the session.
+    pub fn with_session(mut self, session_id: u64) -> Self {
+        self.session_id = Some(session_id);
+        self
+    }
+
+    fn is_expired(&self, now: SystemTime) -> bool {
+        now >= self.deadline
+    }
+}
+
+/// The outcome of an approval request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ApprovalDecision {
+    /// An operator approved the command.
+    Approved { by: Option<String> },
+    /// An operator denied the command.
+    Denied {
+        by: Option<String>,
+        reason: Option<String>,
+    },
+    /// Nobody decided before the request expired.
+    Expired,
+}
+
+/// Errors deciding on a pending approval.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ApprovalError {
+    /// No pending approval with this id (already decided or expired).
+    NotFound(u64),
+    /// The identity that sent the command tried to decide on it.
+    SelfApproval,
+}
+
+impl std::fmt::Display for ApprovalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotFound(id) => write!(f, "Approval {} not found", id),
+            Self::SelfApproval => write!(f, "Commands cannot be approved by their sender"),
+        }
+    }
+}
+
+impl std::error::Error for ApprovalError {}
+
+struct Entry {
+    pending: PendingApproval,
+    decide: oneshot::Sender<ApprovalDecision>,
+}
+
+type Entries = Arc<Mutex<HashMap<u64, Entry>>>;
+
+/// Queue of commands waiting for a human decision.
+pub struct ApprovalQueue {
+    entries: Entries,
+    next_id: AtomicU64,
+    timeout: Option<Duration>,
+}
+
+impl ApprovalQueue {
+    /// Create a queue whose requests expire after `timeout`.
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            entries: Arc::default(),
+            next_id: AtomicU64::new(1),
+            timeout: Some(timeout),
+        }
+    }
+
+    /// Create a queue that accepts nothing, so commands needing approval
+    /// are rejected.
+    pub fn disabled() -> Self {
+        Self {
+            entries: Arc::default(),
+            next_id: AtomicU64::new(1),
+            timeout: None,
+        }
+    }
+
+    /// Check if commands can be parked for approval.
+    pub fn is_enabled(&self) -> bool {
+        self.timeout.is_some()
+    }
+
+    /// How long requests wait for a decision.
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// Park a command, returning a ticket to wait on.
+    ///
+    /// Returns `None` if the queue is disabled.
+    pub fn submit(&self, mut pending: PendingApproval) -> Option<ApprovalTicket> {
+        let timeout = self.timeout?;
+        let now = SystemTime::now();
+        pending.id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        pending.deadline = now + timeout;
+        pending.requested_at = format_timestamp(now);
+        pending.expires_at = format_timestamp(pending.deadline);
+
+        let (decide, decision) = oneshot::channel();
+        let id = pending.id;
+        lock(&self.entries).insert(
+            id,
+            Entry {
+                pending: pending.clone(),
+                decide,
+            },
+        );
+        Some(ApprovalTicket {
+            pending,
+            timeout,
+            decision,
+            entries: Arc::clone(&self.entries),
+        })
+    }
+
+    /// List pending approvals, oldest first.
+    pub fn list(&self) -> Vec<PendingApproval> {
+        let now = SystemTime::now();
+        let mut pending: Vec<_> = lock(&self.entries)
+            .values()
+            .map(|entry| entry.pending.clone())
+            .filter(|pending| !pending.is_expired(now))
+            .collect();
+        pending.sort_by_key(|pending| pending.id);
+        pending
+    }
+
+    /// Number of pending approvals.
+    pub fn len(&self) -> usize {
+        self.list().len()
+    }
+
+    /// Check if nothing is waiting for approval.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Approve a pending command, letting the waiting request run it.
+    ///
+    /// `by` is the deciding identity; it may not be the sender.
+    pub fn approve(&self, id: u64, by: Option<&str>) -> Result<PendingApproval, ApprovalError> {
+        self.decide(
+            id,
+            by,
+            ApprovalDecision::Approved {
+                by: by.map(str::to_string),
+            },
+        )
+    }
+
+    /// Deny a pending command, failing the waiting request.
+    pub fn deny(
+        &self,
+        id: u64,
+        by: Option<&str>,
+        reason: Option<String>,
+    ) -> Result<PendingApproval, ApprovalError> {
+        self.decide(
+            id,
+            by,
+            ApprovalDecision::Denied {
+                by: by.map(str::to_string),
+                reason,
+            },
+        )
+    }
+
+    fn decide(
+        &self,
+        id: u64,
+        by: Option<&str>,
+        decision: ApprovalDecision,
+    ) -> Result<PendingApproval, ApprovalError> {
+        let mut entries = lock(&self.entries);
+        match entries.get(&id) {
+            Some(entry) if entry.pending.is_expired(SystemTime::now()) => {
+                return Err(ApprovalError::NotFound(id))
+            }
+            Some(entry) if by.is_some() && entry.pending.identity.as_deref() == by => {
+                return Err(ApprovalError::SelfApproval)
+            }
+            Some(_) => {}
+            None => return Err(ApprovalError::NotFound(id)),
+        }
+
+        let entry = entries.remove(&id).ok_or(ApprovalError::NotFound(id))?;
+        // The request may have gone away since; the decision is moot then
+        let _ = entry.decide.send(decision);
+        Ok(entry.pending)
+    }
+}
+
+impl Default for ApprovalQueue {
+    fn default() -> Self {
+        Self::disabled()
+    }
+}
+
+impl std::fmt::Debug for ApprovalQueue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ApprovalQueue")
+            .field("pending", &lock(&self.entries).len())
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+/// A parked command. Dropping the ticket withdraws it from the queue.
+pub struct ApprovalTicket {
+    pending: PendingApproval,
+    timeout: Duration,
+    decision: oneshot::Receiver<ApprovalDecision>,
+    entries: Entries,
+}
+
+impl ApprovalTicket {
+    /// The parked command.
+    pub fn pending(&self) -> &PendingApproval {
+        &self.pending
+    }
+
+    /// Wait for a decision or for the request to expire.
+    pub async fn wait(mut self) -> ApprovalDecision {
+        match tokio::time::timeout(self.timeout, &mut self.decision).await {
+            Ok(Ok(decision)) => decision,
+            _ => ApprovalDecision::Expired,
+        }
+    }
+}
+
+impl Drop for ApprovalTicket {
+    fn drop(&mut self) {
+        lock(&self.entries).remove(&self.pending.id);
+    }
+}
+
+fn lock(entries: &Entries) -> MutexGuard<'_, HashMap<u64, Entry>> {
+    match entries.lock() {
+        Ok(entries) => entries,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pending(identity: &str) -> PendingApproval {
+        PendingApproval::new("rm -rf /var/data", "rm -rf /").with_identity(Some(identity))
+    }
+
+    #[tokio::test]
+    async fn test_approve() {
+        let queue = ApprovalQueue::new(Duration::from_secs(60));
+        let ticket = queue.submit(pending("agent")).unwrap();
+        let id = ticket.pending().id;
+
+        assert_eq!(queue.list().len(), 1);
+        assert_eq!(queue.list()[0].reason, "rm -rf /");
+
+        let approved = queue.approve(id, Some("operator")).unwrap();
+        assert_eq!(approved.command, "rm -rf /var/data");
+        assert_eq!(
+            ticket.wait().await,
+            ApprovalDecision::Approved {
+                by: Some("operator".to_string())
+            }
+        );
+        assert!(queue.is_empty());
+        assert_eq!(
+            queue.approve(id, Some("operator")),
+            Err(ApprovalError::NotFound(id))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_deny_and_self_approval() {
+        let queue = ApprovalQueue::new(Duration::from_secs(60));
+        let ticket = queue.submit(pending("agent")).unwrap();
+        let id = ticket.pending().id;
+
+        assert_eq!(
+            queue.approve(id, Some("agent")),
+            Err(ApprovalError::SelfApproval)
+        );
+        queue
+            .deny(id, Some("operator"), Some("not today".to_string()))
+            .unwrap();
+        assert_eq!(
+            ticket.wait().await,
+            ApprovalDecision::Denied {
+                by: Some("operator".to_string()),
+                reason: Some("not today".to_string())
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_expiry_and_withdrawal() {
+        let queue = ApprovalQueue::new(Duration::from_millis(20));
+        let ticket = queue.submit(pending("agent")).unwrap();
+        assert_eq!(ticket.wait().await, ApprovalDecision::Expired);
+        assert!(queue.is_empty());
+
+        let ticket = queue.submit(pending("agent")).unwrap();
+        let id = ticket.pending().id;
+        drop(ticket);
+        assert_eq!(queue.approve(id, None), Err(ApprovalError::NotFound(id)));
+
+        assert!(ApprovalQueue::disabled().submit(pending("agent")).is_none());
+    }
+}
@@ -1,15 +1,19 @@
-//! API Key authentication.
+//! API key and client certificate authentication.
 
-use std::collections::HashSet;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::RwLock;
 
 use axum::{
-    extract::{Request, State},
+    extract::{ConnectInfo, Request, State},
     http::{header::AUTHORIZATION, StatusCode},
     middleware::Next,
     response::Response,
 };
 
+use super::identity::{ClientCertificate, Identity};
+use super::PeerInfo;
+
 /// API key configuration.
 #[derive(Debug, Clone)]
 pub struct AuthConfig {
@@ -49,10 +53,12 @@ impl AuthConfig {
     }
 }
 
-/// Thread-safe API key store.
+/// Thread-safe store of API keys and client certificate identities.
 #[derive(Debug)]
 pub struct ApiKeyStore {
-    keys: RwLock<HashSet<String>>,
+    keys: RwLock<HashMap<String, Identity>>,
+    client_identities: RwLock<HashMap<String, Identity>>,
+    next_key_id: AtomicU64,
     config: AuthConfig,
 }
 
@@ -60,7 +66,9 @@ impl ApiKeyStore {
     /// Create a new API key store.
     pub fn new(config: AuthConfig) -> Self {
         Self {
-            keys: RwLock::new(HashSet::new()),
+            keys: RwLock::new(HashMap::new()),
+            client_identities: RwLock::new(HashMap::new()),
+            next_key_id: AtomicU64::new(1),
             config,
         }
     }
@@ -70,10 +78,18 @@ impl ApiKeyStore {
         Self::new(AuthConfig::disabled())
     }
 
-    /// Add an API key.
+    /// Add an API key with full access.
+    ///
+    /// The key is given an identity named `key-<n>` in registration order.
     pub fn add_key(&self, key: impl Into<String>) {
+        let id = self.next_key_id.fetch_add(1, Ordering::Relaxed);
+        self.add_key_for(key, Identity::full_access(format!("key-{}", id)));
+    }
+
+    /// Add an API key that authenticates as the given identity.
+    pub fn add_key_for(&self, key: impl Into<String>, identity: Identity) {
         if let Ok(mut keys) = self.keys.write() {
-            keys.insert(key.into());
+            keys.insert(key.into(), identity);
         }
     }
 
@@ -81,7 +97,7 @@ impl ApiKeyStore {
     pub fn remove_key(&self, key: &str) -> bool {
         self.keys
             .write()
-            .map(|mut keys| keys.remove(key))
+            .map(|mut keys| keys.remove(key).is_some())
             .unwrap_or(false)
     }
 
@@ -89,10 +105,43 @@ impl ApiKeyStore {
     pub fn is_valid(&self, key: &str) -> bool {
         self.keys
             .read()
-            .map(|keys| keys.contains(key))
+            .map(|keys| keys.contains_key(key))
             .unwrap_or(false)
     }
 
+    /// Look up the identity for an API key.
+    pub fn identify_key(&self, key: &str) -> Option<Identity> {
+        self.keys.read().ok()?.get(key).cloned()
+    }
+
+    /// Map a client certificate name to an identity.
+    ///
+    /// The identity name is matched against the certificate's URI SANs,
+    /// DNS SANs and subject CN.
+    pub fn add_client_identity(&self, identity: Identity) {
+        if let Ok(mut identities) = self.client_identities.write() {
+            identities.insert(identity.name.clone(), identity);
+        }
+    }
+
+    /// Look up the identity for a verified client certificate.
+    ///
+    /// With no client identities configured, any certificate signed by the
+    /// client CA gets full access under its primary name. Otherwise only
+    /// certificates with a configured name are accepted.
+    pub fn identify_client(&self, cert: &ClientCertificate) -> Option<Identity> {
+        let identities = self.client_identities.read().ok()?;
+        if identities.is_empty() {
+            return cert.primary_name().map(Identity::full_access);
+        }
+        cert.names().find_map(|name| identities.get(name).cloned())
+    }
+
+    /// Get the number of configured client identities.
+    pub fn client_identity_count(&self) -> usize {
+        self.client_identities.read().map(|i| i.len()).unwrap_or(0)
+    }
+
     /// Get the number of registered keys.
     pub fn count(&self) -> usize {
         self.keys.read().map(|k| k.len()).unwrap_or(0)
@@ -120,9 +169,13 @@ impl Default for ApiKeyStore {
 }
 
 /// Authentication middleware for axum.
+///
+/// Accepts a bearer API key or, when no `Authorization` header is sent, a
+/// verified TLS client certificate. The caller's [`Identity`] is inserted
+/// into the request extensions.
 pub async fn auth_middleware(
     State(store): State<std::sync::Arc<ApiKeyStore>>,
-    request: Request,
+    mut request: Request,
     next: Next,
 ) -> Result<Response, StatusCode> {
     // Skip auth if disabled
@@ -135,20 +188,27 @@ pub async fn auth_middleware(
         return Ok(next.run(request).await);
     }
 
-    // Extract and validate API key
+    // Extract and validate API key, falling back to the client certificate
     let auth_header = request
         .headers()
         .get(AUTHORIZATION)
         .and_then(|v| v.to_str().ok());
 
-    match auth_header {
-        Some(header) => {
-            if let Some(key) = store.extract_key(header) {
-                if store.is_valid(&key) {
-                    return Ok(next.run(request).await);
-                }
-            }
-            Err(StatusCode::UNAUTHORIZED)
+    let identity = match auth_header {
+        Some(header) => store
+            .extract_key(header)
+            .and_then(|key| store.identify_key(&key)),
+        None => request
+            .extensions()
+            .get::<ConnectInfo<PeerInfo>>()
+            .and_then(|ConnectInfo(peer)| peer.client_cert.as_deref())
+            .and_then(|cert| store.identify_client(cert)),
+    };
+
+    match identity {
+        Some(identity) => {
+            request.extensions_mut().insert(identity);
+            Ok(next.run(request).await)
         }
         None => Err(StatusCode::UNAUTHORIZED),
     }
@@ -230,6 +290,65 @@ mod tests {
         assert_ne!(key1, key2);
     }
 
+    #[test]
+    fn test_api_key_identities() {
+        let store = ApiKeyStore::default();
+
+        store.add_key("key1");
+        store.add_key("key2");
+        store.add_key_for("key3", Identity::new("ci", ["read"]));
+
+        assert_eq!(store.identify_key("key1").unwrap().name, "key-1");
+        assert_eq!(store.identify_key("key2").unwrap().name, "key-2");
+        assert!(store.identify_key("key1").unwrap().is_admin());
+
+        let ci = store.identify_key("key3").unwrap();
+        assert_eq!(ci.name, "ci");
+        assert!(!ci.has_scope("execute"));
+        assert!(store.identify_key("unknown").is_none());
+    }
+
+    #[test]
+    fn test_identify_client_without_mapping() {
+        let store = ApiKeyStore::default();
+        let cert = ClientCertificate {
+            common_name: Some("agent".to_string()),
+            ..Default::default()
+        };
+
+        let identity = store.identify_client(&cert).unwrap();
+        assert_eq!(identity.name, "agent");
+        assert!(identity.is_admin());
+
+        // A certificate without any name cannot own sessions
+        assert!(store
+            .identify_client(&ClientCertificate::default())
+            .is_none());
+    }
+
+    #[test]
+    fn test_identify_client_with_mapping() {
+        let store = ApiKeyStore::default();
+        store.add_client_identity(Identity::new("build.local", ["read", "execute"]));
+        assert_eq!(store.client_identity_count(), 1);
+
+        let cert = ClientCertificate {
+            common_name: Some("agent".to_string()),
+            dns_names: vec!["build.local".to_string()],
+            ..Default::default()
+        };
+        let identity = store.identify_client(&cert).unwrap();
+        assert_eq!(identity.name, "build.local");
+        assert!(identity.has_scope("execute"));
+        assert!(!identity.is_admin());
+
+        let unknown = ClientCertificate {
+            common_name: Some("stranger".to_string()),
+            ..Default::default()
+        };
+        assert!(store.identify_client(&unknown).is_none());
+    }
+
     #[test]
     fn test_api_key_store_multiple_keys() {
         let store = ApiKeyStore::default();
@@ -0,0 +1,251 @@
+//! Authenticated caller identities.
+//!
+//! Every authenticated request carries an [`Identity`] in its extensions.
+//! Identities come from API keys or from verified TLS client certificates,
+//! and carry the scopes that decide which endpoints the caller may use and
+//! which sessions it may see.
+
+use std::path::PathBuf;
+
+use rustls::pki_types::CertificateDer;
+
+use crate::pty::{RunAs, Sandbox};
+
+/// Scope for reading API info and session status.
+pub const SCOPE_READ: &str = "read";
+/// Scope for creating and deleting sessions and executing commands.
+pub const SCOPE_EXECUTE: &str = "execute";
+/// Scope for accessing sessions owned by other identities.
+pub const SCOPE_ADMIN: &str = "admin";
+/// Scope for attaching to a session's terminal, whose keystrokes bypass
+/// command validation.
+pub const SCOPE_ATTACH: &str = "attach";
+/// Wildcard scope granting every other scope.
+pub const SCOPE_ALL: &str = "*";
+
+/// An authenticated caller.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identity {
+    /// Identity name, recorded as the owner of sessions it creates.
+    pub name: String,
+    /// Granted scopes.
+    pub scopes: Vec<String>,
+    /// Directories the identity's commands may run in (the server-wide
+    /// roots if empty).
+    pub root_dirs: Vec<PathBuf>,
+    /// Account the identity's commands run as (the server default if
+    /// `None`).
+    pub run_as: Option<RunAs>,
+    /// Sandbox the identity's commands must run in.
+    pub sandbox: Option<Sandbox>,
+}
+
+impl Identity {
+    /// Create an identity with the given scopes.
+    pub fn new<S: Into<String>>(
+        name: impl Into<String>,
+        scopes: impl IntoIterator<Item = S>,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            scopes: scopes.into_iter().map(Into::into).collect(),
+            root_dirs: Vec::new(),
+            run_as: None,
+            sandbox: None,
+        }
+    }
+
+    /// Confine the identity's commands to the given directories.
+    pub fn with_root_dirs<P: Into<PathBuf>>(mut self, roots: impl IntoIterator<Item = P>) -> Self {
+        self.root_dirs = roots.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Run the identity's commands as the given account.
+    pub fn with_run_as(mut self, run_as: Option<RunAs>) -> Self {
+        self.run_as = run_as;
+        self
+    }
+
+    /// Run the identity's commands in the given sandbox.
+    pub fn with_sandbox(mut self, sandbox: Option<Sandbox>) -> Self {
+        self.sandbox = sandbox;
+        self
+    }
+
+    /// Create an identity with every scope.
+    pub fn full_access(name: impl Into<String>) -> Self {
+        Self::new(name, [SCOPE_ALL])
+    }
+
+    /// Check if the identity was granted a scope.
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes.iter().any(|s| s == scope || s == SCOPE_ALL)
+    }
+
+    /// Check if the identity may access sessions of any owner.
+    pub fn is_admin(&self) -> bool {
+        self.has_scope(SCOPE_ADMIN)
+    }
+
+    /// Check if the identity may access a session with the given owner.
+    pub fn can_access(&self, owner: Option<&str>) -> bool {
+        self.is_admin() || owner == Some(self.name.as_str())
+    }
+}
+
+/// Names presented in a verified TLS client certificate.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientCertificate {
+    /// Subject common name.
+    pub common_name: Option<String>,
+    /// DNS subject alternative names.
+    pub dns_names: Vec<String>,
+    /// URI subject alternative names (e.g. SPIFFE IDs).
+    pub uri_names: Vec<String>,
+}
+
+impl ClientCertificate {
+    /// Parse the names from a DER-encoded end-entity certificate.
+    ///
+    /// The certificate must already have been verified by the TLS layer.
+    pub fn from_der(der: &CertificateDer<'_>) -> Option<Self> {
+        let cert = webpki::EndEntityCert::try_from(der).ok()?;
+        Some(Self {
+            common_name: common_name(cert.subject()),
+            dns_names: cert.valid_dns_names().map(str::to_string).collect(),
+            uri_names: cert.valid_uri_names().map(str::to_string).collect(),
+        })
+    }
+
+    /// All names in matching order: URI SANs, DNS SANs, then the subject CN.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.uri_names
+            .iter()
+            .chain(self.dns_names.iter())
+            .chain(self.common_name.iter())
+            .map(String::as_str)
+    }
+
+    /// The name used for the identity when no mapping is configured.
+    pub fn primary_name(&self) -> Option<&str> {
+        self.names().next()
+    }
+}
+
+/// DER OID for the X.520 commonName attribute (2.5.4.3).
+const OID_COMMON_NAME: &[u8] = &[0x55, 0x04, 0x03];
+
+/// Extract the first commonName from the contents of an X.501 Name.
+fn common_name(mut name: &[u8]) -> Option<String> {
+    // Name ::= SEQUENCE OF SET OF SEQUENCE { type OID, value ANY }
+    while !name.is_empty() {
+        let (tag, mut rdn, rest) = der_next(name)?;
+        name = rest;
+        if tag != 0x31 {
+            return None;
+        }
+
+        while !rdn.is_empty() {
+            let (tag, attr, rest) = der_next(rdn)?;
+            rdn = rest;
+            if tag != 0x30 {
+                return None;
+            }
+
+            let (oid_tag, oid, value) = der_next(attr)?;
+            if oid_tag != 0x06 || oid != OID_COMMON_NAME {
+                continue;
+            }
+
+            // UTF8String, PrintableString, TeletexString or IA5String
+            let (value_tag, value, _) = der_next(value)?;
+            if matches!(value_tag, 0x0c | 0x13 | 0x14 | 0x16) {
+                return std::str::from_utf8(value).ok().map(str::to_string);
+            }
+        }
+    }
+
+    None
+}
+
+/// Split one DER element off the input, returning (tag, contents, rest).
+fn der_next(input: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, input) = input.split_first()?;
+    let (&first, mut input) = input.split_first()?;
+
+    let len = if first < 0x80 {
+        first as usize
+    } else {
+        let count = (first & 0x7f) as usize;
+        if count == 0 || count > 4 || input.len() < count {
+            return None;
+        }
+        let (bytes, rest) = input.split_at(count);
+        input = rest;
+        bytes.iter().fold(0usize, |len, &b| (len << 8) | b as usize)
+    };
+
+    if input.len() < len {
+        return None;
+    }
+    let (contents, rest) = input.split_at(len);
+    Some((tag, contents, rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identity_scopes() {
+        let identity = Identity::new("agent", [SCOPE_READ]);
+        assert!(identity.has_scope(SCOPE_READ));
+        assert!(!identity.has_scope(SCOPE_EXECUTE));
+        assert!(!identity.is_admin());
+
+        let root = Identity::full_access("root");
+        assert!(root.has_scope(SCOPE_EXECUTE));
+        assert!(root.is_admin());
+    }
+
+    #[test]
+    fn test_identity_session_access() {
+        let identity = Identity::new("agent", [SCOPE_EXECUTE]);
+        assert!(identity.can_access(Some("agent")));
+        assert!(!identity.can_access(Some("other")));
+        assert!(!identity.can_access(None));
+
+        let admin = Identity::new("ops", [SCOPE_ADMIN]);
+        assert!(admin.can_access(Some("agent")));
+        assert!(admin.can_access(None));
+    }
+
+    #[test]
+    fn test_common_name() {
+        // SET { SEQUENCE { OID 2.5.4.10, "Org" } }, SET { SEQUENCE { OID 2.5.4.3, "agent" } }
+        let name = [
+            0x31, 0x0c, 0x30, 0x0a, 0x06, 0x03, 0x55, 0x04, 0x0a, 0x0c, 0x03, b'O', b'r', b'g',
+            0x31, 0x0e, 0x30, 0x0c, 0x06, 0x03, 0x55, 0x04, 0x03, 0x0c, 0x05, b'a', b'g', b'e',
+            b'n', b't',
+        ];
+        assert_eq!(common_name(&name), Some("agent".to_string()));
+        assert_eq!(common_name(&name[..14]), None);
+        assert_eq!(common_name(&name[..20]), None);
+    }
+
+    #[test]
+    fn test_client_certificate_names() {
+        let cert = ClientCertificate {
+            common_name: Some("agent".to_string()),
+            dns_names: vec!["agent.build.local".to_string()],
+            uri_names: vec!["spiffe://build/agent".to_string()],
+        };
+        let names: Vec<_> = cert.names().collect();
+        assert_eq!(
+            names,
+            ["spiffe://build/agent", "agent.build.local", "agent"]
+        );
+        assert_eq!(cert.primary_name(), Some("spiffe://build/agent"));
+    }
+}
@@ -0,0 +1,173 @@
+//! Network-level IP allowlist and denylist.
+//!
+//! Requests from addresses outside the allowlist, or inside the denylist,
+//! are rejected before authentication. The lists live behind a lock so the
+//! server can swap them when the configuration is reloaded.
+
+use std::net::IpAddr;
+use std::sync::{Arc, RwLock};
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use ipnet::IpNet;
+
+use super::proxy::{canonical, parse_network};
+use super::PeerInfo;
+use crate::api::types::ErrorResponse;
+
+/// Allow and deny lists.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IpRules {
+    /// Networks allowed to connect (everything when empty).
+    pub allow: Vec<IpNet>,
+    /// Networks that are always rejected.
+    pub deny: Vec<IpNet>,
+}
+
+impl IpRules {
+    /// Parse allow and deny lists of CIDRs or bare addresses.
+    pub fn parse<S: AsRef<str>>(allow: &[S], deny: &[S]) -> Result<Self, String> {
+        let parse_all = |entries: &[S]| -> Result<Vec<IpNet>, String> {
+            entries.iter().map(|e| parse_network(e.as_ref())).collect()
+        };
+        Ok(Self {
+            allow: parse_all(allow)?,
+            deny: parse_all(deny)?,
+        })
+    }
+
+    /// Check if an address may connect.
+    ///
+    /// The denylist takes precedence over the allowlist.
+    pub fn is_allowed(&self, ip: IpAddr) -> bool {
+        let ip = canonical(ip);
+        if self.deny.iter().any(|net| net.contains(&ip)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|net| net.contains(&ip))
+    }
+
+    /// Check if any filtering is configured.
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+}
+
+/// Reloadable IP filter shared by the middleware and the reload handler.
+#[derive(Debug, Default)]
+pub struct IpFilter {
+    rules: RwLock<IpRules>,
+}
+
+impl IpFilter {
+    /// Create a filter with the given rules.
+    pub fn new(rules: IpRules) -> Self {
+        Self {
+            rules: RwLock::new(rules),
+        }
+    }
+
+    /// Replace the rules.
+    pub fn update(&self, rules: IpRules) {
+        match self.rules.write() {
+            Ok(mut current) => *current = rules,
+            Err(poisoned) => *poisoned.into_inner() = rules,
+        }
+    }
+
+    /// Get a copy of the current rules.
+    pub fn rules(&self) -> IpRules {
+        match self.rules.read() {
+            Ok(rules) => rules.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+
+    /// Check if an address may connect.
+    pub fn is_allowed(&self, ip: IpAddr) -> bool {
+        match self.rules.read() {
+            Ok(rules) => rules.is_allowed(ip),
+            Err(poisoned) => poisoned.into_inner().is_allowed(ip),
+        }
+    }
+}
+
+/// Reject requests from addresses the filter does not allow.
+///
+/// Uses the client address resolved from trusted proxies. Unix socket
+/// peers have no address and are gated by the socket permissions instead.
+pub async fn ip_filter_middleware(
+    State(filter): State<Arc<IpFilter>>,
+    ConnectInfo(peer): ConnectInfo<PeerInfo>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if let Some(ip) = peer.client_ip() {
+        if !filter.is_allowed(ip) {
+            tracing::debug!("Rejected request from filtered address {}", ip);
+            return (
+                StatusCode::FORBIDDEN,
+                Json(ErrorResponse::new(
+                    "IP_NOT_ALLOWED",
+                    "Requests from this address are not allowed",
+                )),
+            )
+                .into_response();
+        }
+    }
+
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_empty_rules_allow_all() {
+        let rules = IpRules::default();
+        assert!(rules.is_empty());
+        assert!(rules.is_allowed(ip("192.0.2.1")));
+    }
+
+    #[test]
+    fn test_allowlist() {
+        let rules = IpRules::parse(&["10.0.0.0/8", "::1"], &[]).unwrap();
+        assert!(rules.is_allowed(ip("10.2.3.4")));
+        assert!(rules.is_allowed(ip("::1")));
+        assert!(!rules.is_allowed(ip("192.0.2.1")));
+    }
+
+    #[test]
+    fn test_deny_overrides_allow() {
+        let rules = IpRules::parse(&["10.0.0.0/8"], &["10.66.0.0/16"]).unwrap();
+        assert!(rules.is_allowed(ip("10.1.0.1")));
+        assert!(!rules.is_allowed(ip("10.66.1.1")));
+
+        let rules = IpRules::parse(&[], &["192.0.2.0/24"]).unwrap();
+        assert!(!rules.is_allowed(ip("192.0.2.7")));
+        assert!(!rules.is_allowed(ip("::ffff:192.0.2.7")));
+        assert!(rules.is_allowed(ip("198.51.100.1")));
+
+        assert!(IpRules::parse(&["10.0.0.0/99"], &[]).is_err());
+    }
+
+    #[test]
+    fn test_filter_update() {
+        let filter = IpFilter::new(IpRules::parse(&["10.0.0.0/8"], &[]).unwrap());
+        assert!(!filter.is_allowed(ip("192.0.2.1")));
+
+        filter.update(IpRules::default());
+        assert!(filter.is_allowed(ip("192.0.2.1")));
+        assert!(filter.rules().is_empty());
+    }
+}
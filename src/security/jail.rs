@@ -0,0 +1,197 @@
+//! Working directory jails.
+//!
+//! A [`WorkdirJail`] confines the directories commands run in to a set of
+//! root directories. Requested paths are resolved against a base directory
+//! and canonicalized, so `..` components and symlinks are followed before
+//! the path is checked against the roots.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use super::validation::ValidationError;
+
+/// Directories commands may run in.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WorkdirJail {
+    roots: Vec<PathBuf>,
+}
+
+impl WorkdirJail {
+    /// Create a jail allowing any directory.
+    pub fn unrestricted() -> Self {
+        Self::default()
+    }
+
+    /// Create a jail confined to the given roots, which must exist.
+    ///
+    /// No roots means no restriction.
+    pub fn new<P: AsRef<Path>>(
+        roots: impl IntoIterator<Item = P>,
+    ) -> Result<Self, ValidationError> {
+        let roots = roots
+            .into_iter()
+            .map(|root| canonical_dir(root.as_ref()))
+            .collect::<Result<_, _>>()?;
+        Ok(Self { roots })
+    }
+
+    /// Check if the jail restricts anything.
+    pub fn is_restricted(&self) -> bool {
+        !self.roots.is_empty()
+    }
+
+    /// The canonical root directories.
+    pub fn roots(&self) -> &[PathBuf] {
+        &self.roots
+    }
+
+    /// The directory commands start in when none is requested.
+    pub fn default_dir(&self) -> Option<&Path> {
+        self.roots.first().map(PathBuf::as_path)
+    }
+
+    /// Check if a canonical path is inside one of the roots.
+    pub fn contains(&self, path: &Path) -> bool {
+        !self.is_restricted() || self.roots.iter().any(|root| path.starts_with(root))
+    }
+
+    /// Resolve a requested directory inside the jail.
+    ///
+    /// Relative paths are taken from `base`, or from the first root if
+    /// there is no base. The result is canonical.
+    pub fn resolve(
+        &self,
+        requested: &str,
+        base: Option<&Path>,
+    ) -> Result<PathBuf, ValidationError> {
+        let requested = Path::new(requested);
+        let path = match base.or(self.default_dir()) {
+            Some(base) if requested.is_relative() => base.join(requested),
+            _ => requested.to_path_buf(),
+        };
+        let path = canonical_dir(&path)?;
+        if !self.contains(&path) {
+            return Err(ValidationError::OutsideRoot {
+                path: path.display().to_string(),
+            });
+        }
+        Ok(path)
+    }
+
+    /// Confine the jail further to a directory inside it.
+    pub fn narrow(&self, root: &str) -> Result<Self, ValidationError> {
+        Ok(Self {
+            roots: vec![self.resolve(root, None)?],
+        })
+    }
+
+    /// Check a directory the shell was found in, such as the tracked cwd.
+    pub fn check(&self, path: &Path) -> Result<(), PolicyViolation> {
+        let canonical = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        if self.contains(&canonical) {
+            Ok(())
+        } else {
+            Err(PolicyViolation {
+                cwd: canonical,
+                roots: self.roots.clone(),
+            })
+        }
+    }
+}
+
+/// Canonicalize a path that must be a directory.
+fn canonical_dir(path: &Path) -> Result<PathBuf, ValidationError> {
+    let invalid = |reason: String| ValidationError::InvalidWorkingDir {
+        path: path.display().to_string(),
+        reason,
+    };
+    let canonical = std::fs::canonicalize(path).map_err(|e| invalid(e.to_string()))?;
+    if !canonical.is_dir() {
+        return Err(invalid("not a directory".to_string()));
+    }
+    Ok(canonical)
+}
+
+/// A shell found outside its jail.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PolicyViolation {
+    /// Where the shell was found.
+    pub cwd: PathBuf,
+    /// The roots it is confined to.
+    pub roots: Vec<PathBuf>,
+}
+
+impl fmt::Display for PolicyViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "working directory {} is outside the allowed roots",
+            self.cwd.display()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_inside_root() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("work")).unwrap();
+        let jail = WorkdirJail::new([dir.path()]).unwrap();
+        let root = jail.roots()[0].clone();
+
+        assert_eq!(jail.resolve("work", None).unwrap(), root.join("work"));
+        assert_eq!(jail.resolve("work/..", None).unwrap(), root);
+        assert_eq!(
+            jail.resolve(".", Some(&root.join("work"))).unwrap(),
+            root.join("work")
+        );
+
+        assert!(matches!(
+            jail.resolve("..", None),
+            Err(ValidationError::OutsideRoot { .. })
+        ));
+        assert!(matches!(
+            jail.resolve("/", None),
+            Err(ValidationError::OutsideRoot { .. })
+        ));
+        assert!(matches!(
+            jail.resolve("missing", None),
+            Err(ValidationError::InvalidWorkingDir { .. })
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlinks_are_followed() {
+        let dir = tempfile::tempdir().unwrap();
+        std::os::unix::fs::symlink("/", dir.path().join("escape")).unwrap();
+        let jail = WorkdirJail::new([dir.path()]).unwrap();
+
+        assert!(matches!(
+            jail.resolve("escape", None),
+            Err(ValidationError::OutsideRoot { .. })
+        ));
+        assert!(jail.check(&dir.path().join("escape")).is_err());
+        assert!(jail.check(dir.path()).is_ok());
+    }
+
+    #[test]
+    fn test_narrow_and_unrestricted() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("project")).unwrap();
+        let jail = WorkdirJail::new([dir.path()]).unwrap();
+
+        let session = jail.narrow("project").unwrap();
+        assert_eq!(session.roots(), [jail.roots()[0].join("project")]);
+        assert!(session.resolve("..", None).is_err());
+        assert!(jail.narrow("/").is_err());
+
+        let open = WorkdirJail::unrestricted();
+        assert!(!open.is_restricted());
+        assert!(open.resolve("/", None).is_ok());
+        assert!(open.check(Path::new("/")).is_ok());
+    }
+}
@@ -6,8 +6,13 @@
 //! ## Features
 //!
 //! - **API Key Authentication**: Simple Bearer token authentication
-//! - **Rate Limiting**: IP-based sliding window rate limiter
-//! - **Input Validation**: Command sanitization and dangerous pattern detection
+//! - **Client Certificates**: mTLS identities with scopes and session ownership
+//! - **IP Filtering**: Reloadable allowlist and denylist checked before authentication
+//! - **Trusted Proxies**: Client address resolution from `Forwarded`/`X-Forwarded-For`
+//! - **Rate Limiting**: Token buckets per route class, keyed by IP, uid or identity
+//! - **Input Validation**: Shell-aware command rules and dangerous command detection
+//! - **Working Directory Jails**: Commands confined to root directories per key and session
+//! - **Approvals**: Risky commands held until an operator approves them
 //!
 //! ## Example
 //!
@@ -26,13 +31,37 @@
 //! assert!(validator.validate_command("echo hello").is_ok());
 //! ```
 
+pub mod approval;
 pub mod auth;
+pub mod identity;
+pub mod ip_filter;
+pub mod jail;
+pub mod peer;
+pub mod policy;
+pub mod proxy;
 pub mod rate_limit;
+pub mod shell;
 pub mod validation;
 
 // Re-export commonly used types
+pub use approval::{
+    ApprovalDecision, ApprovalError, ApprovalQueue, ApprovalTicket, PendingApproval,
+};
 pub use auth::{auth_middleware, generate_api_key, ApiKeyStore, AuthConfig};
-pub use rate_limit::{rate_limit_middleware, RateLimitConfig, RateLimitStats, RateLimiter};
+pub use identity::{
+    ClientCertificate, Identity, SCOPE_ADMIN, SCOPE_ALL, SCOPE_ATTACH, SCOPE_EXECUTE, SCOPE_READ,
+};
+pub use ip_filter::{ip_filter_middleware, IpFilter, IpRules};
+pub use jail::{PolicyViolation, WorkdirJail};
+pub use peer::{ClientIp, PeerInfo};
+pub use policy::{Pattern, Rule, RuleAction};
+pub use proxy::{client_ip_middleware, TrustedProxies};
+pub use rate_limit::{
+    auth_failure_limit_middleware, rate_limit_middleware, RateLimitConfig, RateLimitKey,
+    RateLimitKeyBy, RateLimitStats, RateLimiter, RouteClass,
+};
+pub use shell::{ParseError, Redirect, SimpleCommand};
 pub use validation::{
-    looks_like_injection, sanitize_for_display, CommandValidator, ValidationConfig, ValidationError,
+    looks_like_injection, sanitize_for_display, CommandValidator, ValidationConfig,
+    ValidationError, ValidationMode,
 };
@@ -0,0 +1,140 @@
+//! Connection peer information.
+
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+use axum::extract::connect_info::Connected;
+use axum::extract::{ConnectInfo, FromRequestParts};
+use axum::http::request::Parts;
+use axum::serve::IncomingStream;
+use tokio::net::TcpListener;
+
+use super::identity::ClientCertificate;
+use super::rate_limit::RateLimitKey;
+
+/// Information about the remote end of a connection.
+///
+/// The server installs this as `ConnectInfo<PeerInfo>` for every accepted
+/// connection, whichever listener accepted it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerInfo {
+    /// Remote socket address (`None` for Unix socket peers).
+    pub addr: Option<SocketAddr>,
+    /// Peer user id, for Unix socket connections.
+    pub uid: Option<u32>,
+    /// Verified TLS client certificate, if the client presented one.
+    pub client_cert: Option<Arc<ClientCertificate>>,
+    /// Client address reported by a trusted proxy.
+    pub forwarded_for: Option<IpAddr>,
+}
+
+impl PeerInfo {
+    /// Create peer info for a remote address.
+    pub fn new(addr: SocketAddr) -> Self {
+        Self {
+            addr: Some(addr),
+            uid: None,
+            client_cert: None,
+            forwarded_for: None,
+        }
+    }
+
+    /// Create peer info for a Unix socket peer with the given credentials.
+    pub fn unix(uid: Option<u32>) -> Self {
+        Self {
+            addr: None,
+            uid,
+            client_cert: None,
+            forwarded_for: None,
+        }
+    }
+
+    /// Attach a verified client certificate.
+    pub fn with_client_cert(mut self, cert: ClientCertificate) -> Self {
+        self.client_cert = Some(Arc::new(cert));
+        self
+    }
+
+    /// Get the remote IP address.
+    pub fn ip(&self) -> Option<IpAddr> {
+        self.addr.map(|addr| addr.ip())
+    }
+
+    /// Get the client IP address.
+    ///
+    /// This is the address reported by a trusted proxy, if any, and the
+    /// remote IP address otherwise.
+    pub fn client_ip(&self) -> Option<IpAddr> {
+        self.forwarded_for.or_else(|| self.ip())
+    }
+
+    /// Get the key this peer is rate limited by.
+    ///
+    /// Network peers are keyed by client IP address, Unix socket peers by uid.
+    pub fn rate_limit_key(&self) -> RateLimitKey {
+        match (self.client_ip(), self.uid) {
+            (Some(ip), _) => RateLimitKey::Ip(ip),
+            (None, Some(uid)) => RateLimitKey::Uid(uid),
+            (None, None) => RateLimitKey::Local,
+        }
+    }
+}
+
+/// Client IP address of a request, if known.
+///
+/// Taken from `ConnectInfo<PeerInfo>` after trusted proxy resolution. It is
+/// `None` for Unix socket peers and for routers served without connect info.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ClientIp(pub Option<IpAddr>);
+
+impl<S: Send + Sync> FromRequestParts<S> for ClientIp {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let peer = parts.extensions.get::<ConnectInfo<PeerInfo>>();
+        Ok(Self(peer.and_then(|ConnectInfo(peer)| peer.client_ip())))
+    }
+}
+
+impl Connected<IncomingStream<'_, TcpListener>> for PeerInfo {
+    fn connect_info(stream: IncomingStream<'_, TcpListener>) -> Self {
+        Self::new(*stream.remote_addr())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test_peer_info_ip() {
+        let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 7)), 4242);
+        let peer = PeerInfo::new(addr);
+        assert_eq!(peer.ip(), Some(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 7))));
+        assert_eq!(peer.addr.unwrap().port(), 4242);
+        assert!(peer.client_cert.is_none());
+        assert_eq!(peer.rate_limit_key(), RateLimitKey::Ip(addr.ip()));
+    }
+
+    #[test]
+    fn test_unix_peer_rate_limit_key() {
+        let peer = PeerInfo::unix(Some(1000));
+        assert!(peer.ip().is_none());
+        assert_eq!(peer.rate_limit_key(), RateLimitKey::Uid(1000));
+
+        assert_eq!(PeerInfo::unix(None).rate_limit_key(), RateLimitKey::Local);
+    }
+
+    #[test]
+    fn test_forwarded_client_ip() {
+        let mut peer = PeerInfo::new(([127, 0, 0, 1], 4242).into());
+        let client = IpAddr::V4(Ipv4Addr::new(203, 0, 113, 9));
+        peer.forwarded_for = Some(client);
+
+        assert_eq!(peer.ip(), Some(IpAddr::V4(Ipv4Addr::LOCALHOST)));
+        assert_eq!(peer.client_ip(), Some(client));
+        assert_eq!(peer.rate_limit_key(), RateLimitKey::Ip(client));
+    }
+}
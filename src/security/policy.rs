@@ -0,0 +1,767 @@
+//! Command policy rules.
+//!
+//! Rules match the simple commands produced by the [`shell`](super::shell)
+//! tokenizer on the program name, flags, arguments and redirection targets.
+//! Commands run through wrappers (`sudo`, `env`, `xargs`, ...) or passed
+//! to `sh -c`, `eval`, `watch`, `trap` and `alias` are checked at every
+//! layer.
+
+use std::fmt;
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use super::shell::{self, ParseError, SimpleCommand};
+
+/// Deepest `sh -c` / `eval` nesting that is unpacked.
+const MAX_NESTING: usize = 8;
+
+/// Programs that run their arguments as a command, with the options that
+/// take a separate value.
+const WRAPPERS: &[(&str, &[&str])] = &[
+    (
+        "sudo",
+        &["-u", "-g", "-C", "-D", "-h", "-p", "-r", "-t", "-T", "-U"],
+    ),
+    ("doas", &["-u", "-C"]),
+    ("env", &["-u", "-C", "-S"]),
+    ("nice", &["-n"]),
+    ("nohup", &[]),
+    ("time", &["-f", "-o"]),
+    ("timeout", &["-s", "-k"]),
+    ("stdbuf", &["-i", "-o", "-e"]),
+    ("ionice", &["-c", "-n", "-p"]),
+    ("setsid", &[]),
+    ("exec", &["-a"]),
+    ("command", &[]),
+    ("builtin", &[]),
+    ("busybox", &[]),
+    ("xargs", &["-I", "-d", "-n", "-P", "-L", "-s", "-a", "-E"]),
+    ("watch", &["-n", "--interval", "-q", "--equexit"]),
+];
+
+/// Shells whose `-c` argument is a command line.
+const SHELLS: &[&str] = &["sh", "bash", "dash", "zsh", "ksh", "mksh", "ash"];
+
+/// What a matching rule does.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleAction {
+    /// Let the command run, skipping later rules.
+    Allow,
+    /// Hold the command until an operator approves it.
+    Ask,
+    /// Reject the command.
+    #[default]
+    Deny,
+}
+
+/// A string pattern.
+///
+/// Parsed from text as a regex when prefixed with `re:`, otherwise as
+/// `|`-separated alternatives that are globs if they contain `*`, `?` or
+/// `[`, and exact strings if not.
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    /// Exact match.
+    Exact(String),
+    /// Shell-style glob (`*`, `?`, `[a-z]`, `[!a-z]`).
+    Glob(String),
+    /// Regular expression (unanchored).
+    Regex(Regex),
+    /// Any of several patterns.
+    Any(Vec<Pattern>),
+}
+
+impl Pattern {
+    /// Parse a pattern from its text form.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        if let Some(regex) = text.strip_prefix("re:") {
+            return Regex::new(regex)
+                .map(Self::Regex)
+                .map_err(|e| format!("invalid regex '{}': {}", regex, e));
+        }
+
+        Ok(text_pattern(text))
+    }
+
+    /// Check if the pattern matches the whole text (or, for regexes, any part).
+    pub fn matches(&self, text: &str) -> bool {
+        match self {
+            Self::Exact(exact) => exact == text,
+            Self::Glob(glob) => glob_match(glob, text),
+            Self::Regex(regex) => regex.is_match(text),
+            Self::Any(patterns) => patterns.iter().any(|p| p.matches(text)),
+        }
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Exact(text) | Self::Glob(text) => f.write_str(text),
+            Self::Regex(regex) => write!(f, "re:{}", regex),
+            Self::Any(patterns) => {
+                for (i, pattern) in patterns.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str("|")?;
+                    }
+                    write!(f, "{}", pattern)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Match a shell-style glob against the whole text.
+pub fn glob_match(glob: &str, text: &str) -> bool {
+    let glob: Vec<char> = glob.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut g, mut t) = (0, 0);
+    // Position to resume from after the last `*`
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        let step = match glob.get(g) {
+            Some('*') => {
+                backtrack = Some((g, t));
+                g += 1;
+                continue;
+            }
+            Some('?') => Some(1),
+            Some('[') => match_class(&glob[g..], text[t]),
+            Some(c) if *c == text[t] => Some(1),
+            _ => None,
+        };
+        match step {
+            Some(len) => {
+                g += len;
+                t += 1;
+            }
+            None => match backtrack {
+                Some((star, matched)) => {
+                    g = star + 1;
+                    t = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    glob[g..].iter().all(|c| *c == '*')
+}
+
+/// Match a character class at the start of `glob`.
+///
+/// Returns the length of the class if it matches. An unclosed `[` is a
+/// literal.
+fn match_class(glob: &[char], c: char) -> Option<usize> {
+    let mut i = 1;
+    let negate = matches!(glob.get(i), Some('!' | '^'));
+    if negate {
+        i += 1;
+    }
+
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        match glob.get(i) {
+            None => return (c == '[').then_some(1),
+            Some(']') if !first => break,
+            Some(&lo) => {
+                if glob.get(i + 1) == Some(&'-') && glob.get(i + 2).is_some_and(|hi| *hi != ']') {
+                    matched |= (lo..=glob[i + 2]).contains(&c);
+                    i += 3;
+                } else {
+                    matched |= lo == c;
+                    i += 1;
+                }
+            }
+        }
+        first = false;
+    }
+
+    (matched != negate).then_some(i + 1)
+}
+
+/// A rule matching simple commands.
+///
+/// Every condition that is set must hold for the rule to match.
+#[derive(Debug, Clone)]
+pub struct Rule {
+    /// Name reported when the rule fires.
+    pub name: String,
+    /// What happens to matching commands.
+    pub action: RuleAction,
+    /// Program name, matched against `argv[0]`.
+    ///
+    /// Deny and ask rules also match its basename, so `rm` catches
+    /// `/bin/rm`; allow rules only match the name as written, so `ls`
+    /// does not allow `./ls`.
+    pub command: Option<Pattern>,
+    /// Flags that must all be present.
+    ///
+    /// Each entry lists alternatives such as `-f|--force`. Short flags
+    /// match inside clusters, so `-f` matches `-rf`.
+    pub flags: Vec<String>,
+    /// Patterns that must each match some argument.
+    pub args: Vec<Pattern>,
+    /// Patterns for the leading arguments, in order.
+    pub prefix: Vec<Pattern>,
+    /// Pattern for the target of an output redirection.
+    pub redirect: Option<Pattern>,
+}
+
+impl Rule {
+    /// Create a rule that allows matching commands.
+    pub fn allow(name: impl Into<String>) -> Self {
+        Self::new(name, RuleAction::Allow)
+    }
+
+    /// Create a rule that holds matching commands for approval.
+    pub fn ask(name: impl Into<String>) -> Self {
+        Self::new(name, RuleAction::Ask)
+    }
+
+    /// Create a rule that denies matching commands.
+    pub fn deny(name: impl Into<String>) -> Self {
+        Self::new(name, RuleAction::Deny)
+    }
+
+    fn new(name: impl Into<String>, action: RuleAction) -> Self {
+        Self {
+            name: name.into(),
+            action,
+            command: None,
+            flags: Vec::new(),
+            args: Vec::new(),
+            prefix: Vec::new(),
+            redirect: None,
+        }
+    }
+
+    /// Build a rule from a command line such as `rm -rf` or `dd if=*`.
+    ///
+    /// The first word matches the program, words starting with `-` are
+    /// required flags (each letter of a short cluster separately) and the
+    /// remaining words are argument patterns. Words are split on
+    /// whitespace, so `|` separates alternatives (`curl|wget`). The rule is
+    /// named after the line.
+    pub fn from_command_line(action: RuleAction, line: &str) -> Self {
+        let mut rule = Self::new(line.trim(), action);
+        let mut words = line.split_whitespace();
+        if let Some(program) = words.next() {
+            rule.command = Some(text_pattern(program));
+        }
+        for word in words {
+            if word.starts_with("--") && word.len() > 2 {
+                rule.flags.push(word.to_string());
+            } else if word.starts_with('-') && word.len() > 1 {
+                rule.flags
+                    .extend(word.chars().skip(1).map(|c| format!("-{}", c)));
+            } else {
+                rule.args.push(text_pattern(word));
+            }
+        }
+        rule
+    }
+
+    /// Build a rule from a command prefix such as `cargo test` or
+    /// `git status|diff|log`.
+    ///
+    /// The first word matches the program and each further word the
+    /// argument in the same position; arguments after the prefix are not
+    /// checked. The rule is named after the line.
+    pub fn from_prefix(action: RuleAction, line: &str) -> Self {
+        let mut rule = Self::new(line.trim(), action);
+        let mut words = line.split_whitespace();
+        if let Some(program) = words.next() {
+            rule.command = Some(text_pattern(program));
+        }
+        rule.prefix = words.map(text_pattern).collect();
+        rule
+    }
+
+    /// Require the leading arguments to match the patterns, in order.
+    pub fn with_prefix(mut self, patterns: Vec<Pattern>) -> Self {
+        self.prefix = patterns;
+        self
+    }
+
+    /// Match the program name.
+    pub fn with_command(mut self, pattern: Pattern) -> Self {
+        self.command = Some(pattern);
+        self
+    }
+
+    /// Require a flag (alternatives separated by `|`).
+    pub fn with_flag(mut self, flag: impl Into<String>) -> Self {
+        self.flags.push(flag.into());
+        self
+    }
+
+    /// Require an argument matching the pattern.
+    pub fn with_arg(mut self, pattern: Pattern) -> Self {
+        self.args.push(pattern);
+        self
+    }
+
+    /// Match the target of an output redirection.
+    pub fn with_redirect(mut self, pattern: Pattern) -> Self {
+        self.redirect = Some(pattern);
+        self
+    }
+
+    /// Check if the rule matches a command.
+    pub fn matches(&self, command: &SimpleCommand) -> bool {
+        if let Some(ref pattern) = self.command {
+            let Some(program) = command.program() else {
+                return false;
+            };
+            let basename = program.rsplit('/').next().unwrap_or(program);
+            let by_basename = self.action != RuleAction::Allow && pattern.matches(basename);
+            if !pattern.matches(program) && !by_basename {
+                return false;
+            }
+        }
+
+        let args = command.args();
+        args.len() >= self.prefix.len()
+            && self
+                .prefix
+                .iter()
+                .zip(args)
+                .all(|(pattern, arg)| pattern.matches(arg))
+            && self
+                .flags
+                .iter()
+                .all(|flag| flag.split('|').any(|alt| has_flag(args, alt)))
+            && self
+                .args
+                .iter()
+                .all(|pattern| args.iter().any(|arg| pattern.matches(arg)))
+            && self.redirect.as_ref().map_or(true, |pattern| {
+                command
+                    .redirects
+                    .iter()
+                    .any(|r| r.is_output() && pattern.matches(&r.target))
+            })
+    }
+}
+
+/// Pattern for a word from a command line rule: `|`-separated
+/// alternatives that are globs if they have glob characters, exact
+/// otherwise.
+fn text_pattern(word: &str) -> Pattern {
+    let mut alternatives: Vec<Pattern> = word
+        .split('|')
+        .map(|alt| {
+            if alt.contains(['*', '?', '[']) {
+                Pattern::Glob(alt.to_string())
+            } else {
+                Pattern::Exact(alt.to_string())
+            }
+        })
+        .collect();
+    if alternatives.len() == 1 {
+        alternatives.remove(0)
+    } else {
+        Pattern::Any(alternatives)
+    }
+}
+
+/// Check if a flag is among the arguments (before any `--`).
+fn has_flag(args: &[String], flag: &str) -> bool {
+    let mut options = args.iter().take_while(|arg| *arg != "--");
+    if let Some(long) = flag.strip_prefix("--") {
+        return options
+            .filter_map(|arg| arg.strip_prefix("--"))
+            .any(|arg| arg == long || arg.strip_prefix(long).is_some_and(|v| v.starts_with('=')));
+    }
+    match flag
+        .strip_prefix('-')
+        .map(|f| f.chars().collect::<Vec<_>>())
+    {
+        Some(chars) if chars.len() == 1 => options
+            .filter(|arg| arg.starts_with('-') && !arg.starts_with("--"))
+            .any(|arg| arg.chars().skip(1).any(|c| c == chars[0])),
+        // Multi-letter single-dash flags (`-name`) match exactly
+        _ => options.any(|arg| arg == flag),
+    }
+}
+
+/// Split a command line into the commands to check.
+///
+/// Besides every simple command, this includes the command run by each
+/// wrapper (`sudo rm` also yields `rm`) and the commands inside `sh -c`
+/// scripts and `eval` arguments.
+pub fn commands(line: &str) -> Result<Vec<SimpleCommand>, ParseError> {
+    let mut out = Vec::new();
+    expand(shell::parse(line)?, 0, &mut out)?;
+    Ok(out)
+}
+
+fn expand(
+    commands: Vec<SimpleCommand>,
+    depth: usize,
+    out: &mut Vec<SimpleCommand>,
+) -> Result<(), ParseError> {
+    if depth > MAX_NESTING {
+        return Err(ParseError::TooDeep);
+    }
+
+    for command in commands {
+        let mut layer = Some(command);
+        while let Some(command) = layer.take() {
+            if let Some(script) = inline_script(&command) {
+                expand(shell::parse(&script)?, depth + 1, out)?;
+            }
+            layer = unwrap(&command);
+            out.push(command);
+        }
+    }
+    Ok(())
+}
+
+/// The command run by a wrapper program.
+fn unwrap(command: &SimpleCommand) -> Option<SimpleCommand> {
+    let program = command.program()?;
+    let name = program.rsplit('/').next().unwrap_or(program);
+    let (name, value_options) = WRAPPERS.iter().find(|(wrapper, _)| *wrapper == name)?;
+
+    let args = command.args();
+    let mut i = 0;
+    while let Some(arg) = args.get(i) {
+        if arg == "--" {
+            i += 1;
+            break;
+        }
+        if arg.starts_with('-') && arg.len() > 1 {
+            i += if value_options.contains(&arg.as_str()) {
+                2
+            } else {
+                1
+            };
+        } else if *name == "env" && arg.contains('=') {
+            i += 1;
+        } else {
+            break;
+        }
+    }
+    if *name == "timeout" {
+        // Skip the duration
+        i += 1;
+    }
+
+    let argv = args.get(i..).filter(|argv| !argv.is_empty())?.to_vec();
+    let mut assignments = command.assignments.clone();
+    if *name == "env" {
+        assignments.extend(args[..i].iter().filter(|arg| arg.contains('=')).cloned());
+    }
+    Some(SimpleCommand {
+        argv,
+        assignments,
+        ..command.clone()
+    })
+}
+
+/// The script passed to `sh -c`, `eval`, `watch`, `trap` or `alias`.
+fn inline_script(command: &SimpleCommand) -> Option<String> {
+    let program = command.program()?;
+    let name = program.rsplit('/').next().unwrap_or(program);
+    let args = command.args();
+
+    if name == "eval" {
+        return Some(args.join(" "));
+    }
+    if name == "trap" {
+        // The action is the first operand; `-` resets the signals
+        let action = args
+            .iter()
+            .find(|arg| !arg.starts_with('-') || arg.len() == 1)?;
+        return (action != "-").then(|| action.clone());
+    }
+    if name == "alias" {
+        // Every `name=value` defines a command line
+        let values: Vec<&str> = args
+            .iter()
+            .filter_map(|arg| arg.split_once('=').map(|(_, value)| value))
+            .collect();
+        return (!values.is_empty()).then(|| values.join("\n"));
+    }
+    if name == "watch" {
+        // Without -x, watch runs its arguments with `sh -c`
+        return unwrap(command).map(|inner| inner.argv.join(" "));
+    }
+    if !SHELLS.contains(&name) {
+        return None;
+    }
+
+    let mut script_flag = false;
+    for arg in args {
+        if arg.starts_with("--") {
+            continue;
+        }
+        if arg.starts_with('-') && arg.len() > 1 {
+            script_flag |= arg.contains('c');
+            continue;
+        }
+        // The first operand is the script with -c, a file otherwise
+        return script_flag.then(|| arg.clone());
+    }
+    None
+}
+
+/// Built-in rules for destructive commands, named after what they catch.
+pub fn dangerous_rules() -> Vec<Rule> {
+    let pattern = |text: &str| Pattern::parse(text).expect("built-in pattern");
+    vec![
+        Rule::deny("rm -rf /")
+            .with_command(pattern("rm"))
+            .with_flag("-r|-R|--recursive")
+            .with_flag("-f|--force")
+            // The root, the home directory, the working directory or its
+            // parent, everything in them, or a path from a variable
+            .with_arg(pattern("/*|~|~/|~/[*]|[*]|.|./|./[*]|..|../|../[*]|$*")),
+        Rule::deny("disk formatting")
+            .with_command(pattern("mkfs|mkfs.*|fdisk|sfdisk|cfdisk|parted|wipefs")),
+        Rule::deny("raw disk write")
+            .with_command(pattern("dd"))
+            .with_arg(pattern("if=/dev/*"))
+            .with_arg(pattern("of=/dev/*")),
+        Rule::deny("system shutdown").with_command(pattern("shutdown|reboot|halt|poweroff")),
+        Rule::deny("system shutdown")
+            .with_command(pattern("init|telinit"))
+            .with_arg(pattern("0|6")),
+        Rule::deny("system shutdown")
+            .with_command(pattern("systemctl"))
+            .with_arg(pattern("poweroff|reboot|halt|kexec")),
+        Rule::deny("device overwrite").with_redirect(pattern(
+            "/dev/sd*|/dev/hd*|/dev/vd*|/dev/xvd*|/dev/nvme*|/dev/mmcblk*",
+        )),
+    ]
+}
+
+/// Check if a command is part of a fork bomb: a function that calls itself
+/// through a pipe or in the background.
+pub fn is_fork_bomb(command: &SimpleCommand) -> bool {
+    command.function.is_some()
+        && command.function.as_deref() == command.program()
+        && (command.pipeline || command.background)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn first(line: &str) -> SimpleCommand {
+        shell::parse(line).unwrap().remove(0)
+    }
+
+    fn programs(line: &str) -> Vec<String> {
+        commands(line)
+            .unwrap()
+            .into_iter()
+            .filter_map(|c| c.program().map(str::to_string))
+            .collect()
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("/*", "/"));
+        assert!(glob_match("/*", "/home/user"));
+        assert!(glob_match("mkfs.*", "mkfs.ext4"));
+        assert!(glob_match("if=/dev/*", "if=/dev/sda"));
+        assert!(glob_match("file?.[ch]", "file1.c"));
+        assert!(glob_match("[!a-c]x", "dx"));
+        assert!(glob_match("a*b*c", "aXbYbZc"));
+        assert!(glob_match("[", "["));
+
+        assert!(!glob_match("/*", "relative"));
+        assert!(!glob_match("file?.[ch]", "file1.o"));
+        assert!(!glob_match("[!a-c]x", "bx"));
+        assert!(!glob_match("a*b", "ac"));
+    }
+
+    #[test]
+    fn test_pattern_parse() {
+        assert!(matches!(Pattern::parse("git").unwrap(), Pattern::Exact(_)));
+        assert!(matches!(Pattern::parse("mkfs*").unwrap(), Pattern::Glob(_)));
+
+        let pattern = Pattern::parse("status|diff|log").unwrap();
+        assert!(pattern.matches("diff"));
+        assert!(!pattern.matches("push"));
+        assert_eq!(pattern.to_string(), "status|diff|log");
+
+        let pattern = Pattern::parse("re:^curl.*\\|").unwrap();
+        assert!(pattern.matches("curl x |"));
+        assert!(Pattern::parse("re:(").is_err());
+    }
+
+    #[test]
+    fn test_rule_flags() {
+        let rule = Rule::deny("force push")
+            .with_command(Pattern::parse("git").unwrap())
+            .with_arg(Pattern::parse("push").unwrap())
+            .with_flag("-f|--force|--force-with-lease");
+
+        assert!(rule.matches(&first("git push -f origin main")));
+        assert!(rule.matches(&first("git push --force-with-lease=main origin")));
+        assert!(rule.matches(&first("/usr/bin/git push -uf origin")));
+        assert!(!rule.matches(&first("git push origin main")));
+        assert!(!rule.matches(&first("git push -- -f")));
+        assert!(!rule.matches(&first("git pull --force")));
+    }
+
+    #[test]
+    fn test_rule_from_command_line() {
+        let rule = Rule::from_command_line(RuleAction::Deny, "rm -rf");
+        assert_eq!(rule.name, "rm -rf");
+        assert_eq!(rule.flags, vec!["-r", "-f"]);
+        assert!(rule.matches(&first("rm -f -r build")));
+        assert!(rule.matches(&first("rm -fr build")));
+        assert!(!rule.matches(&first("rm -r build")));
+
+        let rule = Rule::from_command_line(RuleAction::Deny, "dd if=*");
+        assert!(rule.matches(&first("dd if=/dev/zero of=disk.img")));
+        assert!(!rule.matches(&first("echo dd if=x")));
+
+        let rule = Rule::from_command_line(RuleAction::Deny, "curl|wget");
+        assert!(rule.matches(&first("wget -q https://example.com")));
+        assert!(!rule.matches(&first("echo curl")));
+    }
+
+    #[test]
+    fn test_rule_from_prefix() {
+        let rule = Rule::from_prefix(RuleAction::Allow, "git status|diff|log");
+        assert_eq!(rule.name, "git status|diff|log");
+        assert!(rule.matches(&first("git status")));
+        assert!(rule.matches(&first("git log --oneline -5")));
+        assert!(!rule.matches(&first("/usr/bin/git log")));
+        assert!(!rule.matches(&first("git")));
+        assert!(!rule.matches(&first("git push status")));
+
+        let rule = Rule::from_prefix(RuleAction::Allow, "ls");
+        assert!(rule.matches(&first("ls -la /tmp")));
+        assert!(!rule.matches(&first("lsof")));
+    }
+
+    #[test]
+    fn test_wrappers_are_unwrapped() {
+        assert_eq!(programs("sudo -u root rm -rf /"), vec!["sudo", "rm"]);
+        assert_eq!(
+            programs("env FOO=1 nice -n 5 timeout 10 make"),
+            vec!["env", "nice", "timeout", "make"]
+        );
+        let commands = commands("FOO=1 env -u HOME BAR=2 make").unwrap();
+        assert_eq!(commands[1].assignments, vec!["FOO=1", "BAR=2"]);
+        assert_eq!(
+            programs("find . | xargs -I{} rm {}"),
+            vec!["find", "xargs", "rm"]
+        );
+    }
+
+    #[test]
+    fn test_inline_scripts_are_parsed() {
+        assert_eq!(
+            programs("bash -ec 'cd /; reboot'"),
+            vec!["cd", "reboot", "bash"]
+        );
+        assert_eq!(
+            programs("eval \"shutdown -h now\""),
+            vec!["shutdown", "eval"]
+        );
+        assert_eq!(programs("sh script.sh"), vec!["sh"]);
+        assert_eq!(
+            programs("watch -n 5 'df -h; reboot'"),
+            vec!["df", "reboot", "watch", "df -h; reboot"]
+        );
+        assert_eq!(programs("trap reboot EXIT"), vec!["reboot", "trap"]);
+        assert_eq!(
+            programs("trap -- 'rm -rf /tmp/x; reboot' INT TERM"),
+            vec!["rm", "reboot", "trap"]
+        );
+        assert_eq!(programs("trap - EXIT"), vec!["trap"]);
+        assert_eq!(
+            programs("alias ll='ls -l' r=reboot"),
+            vec!["ls", "reboot", "alias"]
+        );
+        assert_eq!(programs("alias ll"), vec!["alias"]);
+    }
+
+    #[test]
+    fn test_dangerous_rules() {
+        let fired = |line: &str| {
+            commands(line).unwrap().iter().find_map(|command| {
+                dangerous_rules()
+                    .into_iter()
+                    .find(|rule| rule.matches(command))
+                    .map(|rule| rule.name)
+            })
+        };
+
+        assert_eq!(fired("rm  -rf /").as_deref(), Some("rm -rf /"));
+        assert_eq!(fired("rm -r --force /home").as_deref(), Some("rm -rf /"));
+        for line in [
+            "rm -rf ~",
+            "rm -rf ~/",
+            "rm -rf *",
+            "rm -rf ./*",
+            "rm -rf .",
+            "rm -rf ..",
+            "rm -rf $HOME",
+            "rm -rf \"${DIR}/\"",
+        ] {
+            assert_eq!(fired(line).as_deref(), Some("rm -rf /"), "{}", line);
+        }
+        assert_eq!(
+            fired("mkfs.ext4 /dev/sdb1").as_deref(),
+            Some("disk formatting")
+        );
+        assert_eq!(
+            fired("dd if=/dev/zero of=/dev/sda").as_deref(),
+            Some("raw disk write")
+        );
+        assert_eq!(
+            fired("sudo systemctl reboot").as_deref(),
+            Some("system shutdown")
+        );
+        assert_eq!(
+            fired("cat image >/dev/nvme0n1").as_deref(),
+            Some("device overwrite")
+        );
+        assert_eq!(fired("watch rm -rf /").as_deref(), Some("rm -rf /"));
+        assert_eq!(fired("time -p reboot").as_deref(), Some("system shutdown"));
+        assert_eq!(fired("coproc reboot").as_deref(), Some("system shutdown"));
+        assert_eq!(
+            fired("trap reboot EXIT").as_deref(),
+            Some("system shutdown")
+        );
+        assert_eq!(
+            fired("alias ls='shutdown -h now'").as_deref(),
+            Some("system shutdown")
+        );
+        assert_eq!(
+            fired("ls ${X:-$(reboot)}").as_deref(),
+            Some("system shutdown")
+        );
+
+        assert_eq!(fired("echo shutdown-notes.txt"), None);
+        assert_eq!(fired("rm -rf build"), None);
+        assert_eq!(fired("rm -rf ./build ~/.cache/pip *.o"), None);
+        assert_eq!(fired("rm -f *"), None);
+        assert_eq!(fired("grep -r mkfs docs"), None);
+        assert_eq!(fired("dd if=/dev/zero of=disk.img"), None);
+    }
+
+    #[test]
+    fn test_fork_bomb() {
+        let commands = commands(":(){ :|:& };:").unwrap();
+        assert!(commands.iter().any(is_fork_bomb));
+
+        let commands = super::commands("retry() { make || retry; }; retry").unwrap();
+        assert!(!commands.iter().any(is_fork_bomb));
+    }
+}
@@ -0,0 +1,233 @@
+//! Client address resolution behind trusted reverse proxies.
+//!
+//! When shell-tunnel runs behind nginx or a sidecar, every connection comes
+//! from the proxy. Requests from a trusted proxy have their client address
+//! taken from `Forwarded` (RFC 7239) or `X-Forwarded-For` instead, so rate
+//! limiting and auditing see the real client.
+
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{HeaderMap, HeaderName},
+    middleware::Next,
+    response::Response,
+};
+use ipnet::IpNet;
+
+use super::PeerInfo;
+
+const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+
+/// Networks whose forwarding headers are trusted.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrustedProxies {
+    networks: Vec<IpNet>,
+}
+
+impl TrustedProxies {
+    /// Create an empty list (forwarding headers are ignored).
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse a list of CIDRs or bare addresses (e.g. `10.0.0.0/8`, `::1`).
+    pub fn parse<S: AsRef<str>>(entries: &[S]) -> Result<Self, String> {
+        let networks = entries
+            .iter()
+            .map(|entry| parse_network(entry.as_ref()))
+            .collect::<Result<_, _>>()?;
+        Ok(Self { networks })
+    }
+
+    /// Trust a network.
+    pub fn with_network(mut self, network: IpNet) -> Self {
+        self.networks.push(network);
+        self
+    }
+
+    /// Check if any proxies are trusted.
+    pub fn is_empty(&self) -> bool {
+        self.networks.is_empty()
+    }
+
+    /// Check if an address belongs to a trusted proxy.
+    pub fn is_trusted(&self, ip: IpAddr) -> bool {
+        let ip = canonical(ip);
+        self.networks.iter().any(|net| net.contains(&ip))
+    }
+
+    /// Resolve the client address of a request received from `peer`.
+    ///
+    /// Forwarded addresses are walked from the nearest hop outwards and the
+    /// first address that is not a trusted proxy is the client. Hops added
+    /// by untrusted parties are never reached, so clients cannot spoof
+    /// their address by sending the header themselves. Returns `None` when
+    /// the peer is not trusted or the headers name no usable address.
+    pub fn resolve(&self, peer: IpAddr, headers: &HeaderMap) -> Option<IpAddr> {
+        if !self.is_trusted(peer) {
+            return None;
+        }
+
+        let hops = forwarded_hops(headers);
+        let mut client = None;
+        for hop in hops.iter().rev() {
+            // Obfuscated or unknown identifiers end the trusted chain
+            let ip = (*hop)?;
+            client = Some(ip);
+            if !self.is_trusted(ip) {
+                break;
+            }
+        }
+        client
+    }
+}
+
+/// Parse a CIDR or a bare address (a single-host network).
+pub(crate) fn parse_network(entry: &str) -> Result<IpNet, String> {
+    let entry = entry.trim();
+    entry
+        .parse::<IpNet>()
+        .or_else(|_| entry.parse::<IpAddr>().map(IpNet::from))
+        .map_err(|_| format!("invalid address or CIDR '{}'", entry))
+}
+
+/// Collect forwarded client addresses, nearest hop last.
+///
+/// `Forwarded` takes precedence over `X-Forwarded-For` when both are
+/// present. Entries that are not IP addresses are kept as `None`.
+fn forwarded_hops(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    let forwarded: Vec<_> = headers
+        .get_all(axum::http::header::FORWARDED)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|element| {
+            element
+                .split(';')
+                .filter_map(|pair| pair.split_once('='))
+                .find(|(name, _)| name.trim().eq_ignore_ascii_case("for"))
+                .and_then(|(_, value)| parse_node(value))
+        })
+        .collect();
+    if !forwarded.is_empty() {
+        return forwarded;
+    }
+
+    headers
+        .get_all(X_FORWARDED_FOR)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(parse_node)
+        .collect()
+}
+
+/// Parse a forwarded node such as `192.0.2.1`, `"[2001:db8::1]:4711"` or `10.0.0.1:80`.
+fn parse_node(value: &str) -> Option<IpAddr> {
+    let value = value.trim().trim_matches('"');
+    if let Ok(ip) = value.parse::<IpAddr>() {
+        return Some(canonical(ip));
+    }
+
+    let host = match value.strip_prefix('[') {
+        Some(rest) => rest.split(']').next()?,
+        None => value.rsplit_once(':')?.0,
+    };
+    host.parse::<IpAddr>().ok().map(canonical)
+}
+
+/// Map IPv4-mapped IPv6 addresses to IPv4.
+pub(crate) fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+        IpAddr::V4(_) => ip,
+    }
+}
+
+/// Resolve the client address of requests from trusted proxies.
+///
+/// Must run before any middleware that reads the client address.
+pub async fn client_ip_middleware(
+    State(proxies): State<Arc<TrustedProxies>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    if !proxies.is_empty() {
+        let headers = request.headers().clone();
+        if let Some(ConnectInfo(peer)) = request.extensions_mut().get_mut::<ConnectInfo<PeerInfo>>()
+        {
+            if let Some(ip) = peer.ip() {
+                peer.forwarded_for = proxies.resolve(ip, &headers);
+            }
+        }
+    }
+
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn header_map(name: &'static str, value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, HeaderValue::from_static(value));
+        headers
+    }
+
+    #[test]
+    fn test_parse_trusted_proxies() {
+        let proxies = TrustedProxies::parse(&["10.0.0.0/8", "::1"]).unwrap();
+        assert!(proxies.is_trusted(ip("10.1.2.3")));
+        assert!(proxies.is_trusted(ip("::1")));
+        assert!(proxies.is_trusted(ip("::ffff:10.0.0.1")));
+        assert!(!proxies.is_trusted(ip("192.168.0.1")));
+
+        assert!(TrustedProxies::parse(&["10.0.0.0/33"]).is_err());
+        assert!(TrustedProxies::parse(&["proxy.local"]).is_err());
+    }
+
+    #[test]
+    fn test_resolve_x_forwarded_for() {
+        let proxies = TrustedProxies::parse(&["10.0.0.0/8"]).unwrap();
+        let headers = header_map("x-forwarded-for", "1.1.1.1, 203.0.113.9, 10.0.0.2");
+
+        // The spoofed leftmost entry is ignored
+        assert_eq!(
+            proxies.resolve(ip("10.0.0.1"), &headers),
+            Some(ip("203.0.113.9"))
+        );
+        // Untrusted peers cannot set their address
+        assert_eq!(proxies.resolve(ip("198.51.100.1"), &headers), None);
+    }
+
+    #[test]
+    fn test_resolve_forwarded() {
+        let proxies = TrustedProxies::parse(&["127.0.0.1"]).unwrap();
+        let headers = header_map(
+            "forwarded",
+            "for=192.0.2.60;proto=http, For=\"[2001:db8:cafe::17]:4711\"",
+        );
+        assert_eq!(
+            proxies.resolve(ip("127.0.0.1"), &headers),
+            Some(ip("2001:db8:cafe::17"))
+        );
+
+        let headers = header_map("forwarded", "for=_hidden");
+        assert_eq!(proxies.resolve(ip("127.0.0.1"), &headers), None);
+    }
+
+    #[test]
+    fn test_parse_node() {
+        assert_eq!(parse_node(" 192.0.2.1:8080"), Some(ip("192.0.2.1")));
+        assert_eq!(parse_node("\"[::1]:80\""), Some(ip("::1")));
+        assert_eq!(parse_node("unknown"), None);
+    }
+}
@@ -1,4 +1,8 @@
-//! Rate limiting implementation.
+//! Token-bucket rate limiting.
+//!
+//! Every client gets one bucket per route class. A bucket holds up to the
+//! class budget in tokens and refills continuously over the window, so each
+//! key costs constant memory no matter how many requests it makes.
 
 use std::collections::HashMap;
 use std::net::IpAddr;
@@ -7,22 +11,29 @@ use std::time::{Duration, Instant};
 
 use axum::{
     extract::{ConnectInfo, Request, State},
-    http::StatusCode,
+    http::{Method, StatusCode},
     middleware::Next,
     response::{IntoResponse, Response},
 };
+use serde::{Deserialize, Serialize};
+
+use super::identity::Identity;
 
 /// Rate limiter configuration.
 #[derive(Debug, Clone)]
 pub struct RateLimitConfig {
-    /// Maximum requests per window.
+    /// Maximum requests per window (default budget for every route class).
     pub max_requests: u32,
     /// Time window duration.
     pub window: Duration,
     /// Whether rate limiting is enabled.
     pub enabled: bool,
-    /// Maximum number of tracked IPs (memory limit).
+    /// Maximum number of tracked keys (memory limit).
     pub max_tracked_ips: usize,
+    /// What requests are counted under.
+    pub key_by: RateLimitKeyBy,
+    /// Per-class budgets overriding `max_requests`.
+    pub class_limits: HashMap<RouteClass, u32>,
 }
 
 impl Default for RateLimitConfig {
@@ -32,6 +43,8 @@ impl Default for RateLimitConfig {
             window: Duration::from_secs(60),
             enabled: true,
             max_tracked_ips: 10000,
+            key_by: RateLimitKeyBy::default(),
+            class_limits: HashMap::new(),
         }
     }
 }
@@ -71,43 +84,150 @@ impl RateLimitConfig {
             ..Default::default()
         }
     }
+
+    /// Set what requests are counted under.
+    pub fn with_key_by(mut self, key_by: RateLimitKeyBy) -> Self {
+        self.key_by = key_by;
+        self
+    }
+
+    /// Set the budget for a route class.
+    pub fn with_class_limit(mut self, class: RouteClass, max_requests: u32) -> Self {
+        self.class_limits.insert(class, max_requests);
+        self
+    }
+
+    /// Get the budget for a route class.
+    pub fn limit_for(&self, class: RouteClass) -> u32 {
+        self.class_limits
+            .get(&class)
+            .copied()
+            .unwrap_or(self.max_requests)
+    }
+}
+
+/// What requests are counted under.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitKeyBy {
+    /// Client address (IP, or uid on Unix sockets).
+    #[default]
+    Ip,
+    /// Authenticated identity, falling back to the client address when
+    /// the request carries none.
+    Identity,
+    /// Identity and client address together.
+    Both,
+}
+
+/// Route classes with separate budgets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RouteClass {
+    /// Reads: API info, session listing and status.
+    Read,
+    /// Command execution, WebSocket streams and session deletion.
+    Execute,
+    /// Session creation.
+    SessionCreate,
+    /// Failed authentication attempts, counted per client address before
+    /// authentication runs.
+    AuthFailure,
+}
+
+impl RouteClass {
+    /// Classify a request by method and path.
+    pub fn of(method: &Method, path: &str) -> Self {
+        let path = path.trim_end_matches('/');
+        let is_ws = path.ends_with("/ws");
+
+        if *method == Method::POST && path == "/api/v1/sessions" {
+            Self::SessionCreate
+        } else if *method == Method::POST || *method == Method::DELETE || is_ws {
+            Self::Execute
+        } else {
+            Self::Read
+        }
+    }
+}
+
+/// Client key that requests are counted under.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RateLimitKey {
+    /// Remote IP address of a network client.
+    Ip(IpAddr),
+    /// Peer user id of a Unix socket client.
+    Uid(u32),
+    /// Unix socket client whose credentials could not be read.
+    Local,
+}
+
+impl From<IpAddr> for RateLimitKey {
+    fn from(ip: IpAddr) -> Self {
+        Self::Ip(ip)
+    }
 }
 
-/// Request record for an IP.
+/// Key of one token bucket.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct BucketKey {
+    class: RouteClass,
+    peer: Option<RateLimitKey>,
+    identity: Option<String>,
+}
+
+/// Token bucket for one key.
 #[derive(Debug, Clone)]
-struct RequestRecord {
-    /// Timestamps of requests in the current window.
-    timestamps: Vec<Instant>,
+struct Bucket {
+    /// Tokens currently available.
+    tokens: f64,
+    /// Time of the last refill.
+    updated: Instant,
 }
 
-impl RequestRecord {
-    fn new() -> Self {
+impl Bucket {
+    fn full(capacity: u32) -> Self {
         Self {
-            timestamps: Vec::new(),
+            tokens: capacity as f64,
+            updated: Instant::now(),
         }
     }
 
-    /// Clean up old timestamps and return current count.
-    fn clean_and_count(&mut self, window: Duration) -> u32 {
+    /// Refill for the time elapsed since the last update.
+    fn refill(&mut self, capacity: u32, window: Duration) {
         let now = Instant::now();
-        let cutoff = now - window;
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        let rate = capacity as f64 / window.as_secs_f64().max(f64::EPSILON);
+        self.tokens = (self.tokens + elapsed * rate).min(capacity as f64);
+        self.updated = now;
+    }
 
-        // Remove timestamps older than the window
-        self.timestamps.retain(|&t| t > cutoff);
+    /// Take a token, or return how long until one is available.
+    fn take(&mut self, capacity: u32, window: Duration) -> Result<u32, Duration> {
+        self.refill(capacity, window);
 
-        self.timestamps.len() as u32
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return Ok(self.tokens as u32);
+        }
+
+        Err(self.wait(capacity, window))
     }
 
-    /// Record a new request.
-    fn record(&mut self) {
-        self.timestamps.push(Instant::now());
+    /// Time until a token is available.
+    fn wait(&self, capacity: u32, window: Duration) -> Duration {
+        let rate = capacity as f64 / window.as_secs_f64().max(f64::EPSILON);
+        if rate <= 0.0 {
+            return window;
+        }
+        Duration::from_secs_f64((1.0 - self.tokens).max(0.0) / rate)
     }
 }
 
 /// Thread-safe rate limiter.
 #[derive(Debug)]
 pub struct RateLimiter {
-    records: RwLock<HashMap<IpAddr, RequestRecord>>,
+    buckets: RwLock<HashMap<BucketKey, Bucket>>,
     config: RateLimitConfig,
     last_cleanup: RwLock<Instant>,
 }
@@ -116,7 +236,7 @@ impl RateLimiter {
     /// Create a new rate limiter.
     pub fn new(config: RateLimitConfig) -> Self {
         Self {
-            records: RwLock::new(HashMap::new()),
+            buckets: RwLock::new(HashMap::new()),
             config,
             last_cleanup: RwLock::new(Instant::now()),
         }
@@ -132,42 +252,109 @@ impl RateLimiter {
         self.config.enabled
     }
 
-    /// Check if a request from the given IP should be allowed.
+    /// Get the limiter configuration.
+    pub fn config(&self) -> &RateLimitConfig {
+        &self.config
+    }
+
+    /// Check if a read request from the given IP should be allowed.
     ///
     /// Returns `Ok(remaining)` if allowed, `Err(retry_after)` if rate limited.
     pub fn check(&self, ip: IpAddr) -> Result<u32, Duration> {
+        self.check_key(RateLimitKey::Ip(ip))
+    }
+
+    /// Check if a read request from the given client should be allowed.
+    ///
+    /// Returns `Ok(remaining)` if allowed, `Err(retry_after)` if rate limited.
+    pub fn check_key(&self, key: RateLimitKey) -> Result<u32, Duration> {
+        self.check_request(key, None, RouteClass::Read)
+    }
+
+    /// Check if a request should be allowed.
+    ///
+    /// The bucket is chosen by the route class and, depending on
+    /// [`RateLimitKeyBy`], the client address and identity name.
+    ///
+    /// Returns `Ok(remaining)` if allowed, `Err(retry_after)` if rate limited.
+    pub fn check_request(
+        &self,
+        peer: RateLimitKey,
+        identity: Option<&str>,
+        class: RouteClass,
+    ) -> Result<u32, Duration> {
+        let capacity = self.config.limit_for(class);
         if !self.config.enabled {
-            return Ok(self.config.max_requests);
+            return Ok(capacity);
         }
 
         // Periodic cleanup
         self.maybe_cleanup();
 
-        let mut records = match self.records.write() {
-            Ok(r) => r,
-            Err(_) => return Ok(self.config.max_requests), // Fail open on lock error
+        let (peer, identity) = match (self.config.key_by, identity) {
+            (RateLimitKeyBy::Ip, _) | (RateLimitKeyBy::Identity, None) => (Some(peer), None),
+            (RateLimitKeyBy::Identity, Some(name)) => (None, Some(name.to_string())),
+            (RateLimitKeyBy::Both, name) => (Some(peer), name.map(str::to_string)),
+        };
+        let key = BucketKey {
+            class,
+            peer,
+            identity,
+        };
+
+        let mut buckets = match self.buckets.write() {
+            Ok(b) => b,
+            Err(_) => return Ok(capacity), // Fail open on lock error
         };
 
-        let record = records.entry(ip).or_insert_with(RequestRecord::new);
-        let current_count = record.clean_and_count(self.config.window);
+        buckets
+            .entry(key)
+            .or_insert_with(|| Bucket::full(capacity))
+            .take(capacity, self.config.window)
+    }
 
-        if current_count >= self.config.max_requests {
-            // Calculate retry-after
-            let oldest = record.timestamps.first().copied();
-            let retry_after = oldest
-                .map(|t| self.config.window.saturating_sub(t.elapsed()))
-                .unwrap_or(self.config.window);
-            return Err(retry_after);
+    /// Check if a client has used up its failed authentication attempts.
+    ///
+    /// Returns how long until it may try again, without using a token.
+    pub fn auth_blocked(&self, peer: RateLimitKey) -> Option<Duration> {
+        let capacity = self.config.limit_for(RouteClass::AuthFailure);
+        if !self.config.enabled {
+            return None;
         }
+        let key = BucketKey {
+            class: RouteClass::AuthFailure,
+            peer: Some(peer),
+            identity: None,
+        };
 
-        // Record this request
-        record.record();
-        let remaining = self.config.max_requests - current_count - 1;
+        let mut buckets = self.buckets.write().ok()?;
+        let bucket = buckets.get_mut(&key)?;
+        bucket.refill(capacity, self.config.window);
+        (bucket.tokens < 1.0).then(|| bucket.wait(capacity, self.config.window))
+    }
 
-        Ok(remaining)
+    /// Count a failed authentication attempt against a client.
+    pub fn record_auth_failure(&self, peer: RateLimitKey) {
+        if !self.config.enabled {
+            return;
+        }
+        self.maybe_cleanup();
+
+        let capacity = self.config.limit_for(RouteClass::AuthFailure);
+        let key = BucketKey {
+            class: RouteClass::AuthFailure,
+            peer: Some(peer),
+            identity: None,
+        };
+        if let Ok(mut buckets) = self.buckets.write() {
+            let _ = buckets
+                .entry(key)
+                .or_insert_with(|| Bucket::full(capacity))
+                .take(capacity, self.config.window);
+        }
     }
 
-    /// Perform cleanup of old records if needed.
+    /// Perform cleanup of idle buckets if needed.
     fn maybe_cleanup(&self) {
         let should_cleanup = self
             .last_cleanup
@@ -188,30 +375,24 @@ impl RateLimiter {
 
             *last = Instant::now();
 
-            if let Ok(mut records) = self.records.write() {
-                let cutoff = Instant::now() - self.config.window * 2;
-
-                // Remove IPs with no recent activity
-                records.retain(|_, record| {
-                    record
-                        .timestamps
-                        .last()
-                        .map(|&t| t > cutoff)
-                        .unwrap_or(false)
-                });
-
-                // If still too many, remove oldest entries
-                if records.len() > self.config.max_tracked_ips {
-                    let mut entries: Vec<_> = records
+            if let Ok(mut buckets) = self.buckets.write() {
+                // A bucket idle for a full window has refilled completely and
+                // is indistinguishable from a new one.
+                let window = self.config.window;
+                buckets.retain(|_, bucket| bucket.updated.elapsed() < window);
+
+                // If still too many, remove the least recently used
+                if buckets.len() > self.config.max_tracked_ips {
+                    let mut entries: Vec<_> = buckets
                         .iter()
-                        .map(|(ip, r)| (*ip, r.timestamps.last().copied()))
+                        .map(|(key, b)| (key.clone(), b.updated))
                         .collect();
 
                     entries.sort_by_key(|(_, t)| *t);
 
-                    let to_remove = records.len() - self.config.max_tracked_ips;
-                    for (ip, _) in entries.into_iter().take(to_remove) {
-                        records.remove(&ip);
+                    let to_remove = buckets.len() - self.config.max_tracked_ips;
+                    for (key, _) in entries.into_iter().take(to_remove) {
+                        buckets.remove(&key);
                     }
                 }
             }
@@ -220,7 +401,7 @@ impl RateLimiter {
 
     /// Get current stats.
     pub fn stats(&self) -> RateLimitStats {
-        let tracked_ips = self.records.read().map(|r| r.len()).unwrap_or(0);
+        let tracked_ips = self.buckets.read().map(|b| b.len()).unwrap_or(0);
         RateLimitStats {
             tracked_ips,
             max_requests: self.config.max_requests,
@@ -239,6 +420,7 @@ impl Default for RateLimiter {
 /// Rate limit statistics.
 #[derive(Debug, Clone)]
 pub struct RateLimitStats {
+    /// Number of tracked buckets.
     pub tracked_ips: usize,
     pub max_requests: u32,
     pub window_secs: u64,
@@ -246,9 +428,13 @@ pub struct RateLimitStats {
 }
 
 /// Rate limit middleware for axum.
+///
+/// Runs inside authentication so requests can be keyed by [`Identity`];
+/// requests failing authentication are limited by
+/// [`auth_failure_limit_middleware`] instead.
 pub async fn rate_limit_middleware(
     State(limiter): State<std::sync::Arc<RateLimiter>>,
-    ConnectInfo(addr): ConnectInfo<std::net::SocketAddr>,
+    ConnectInfo(peer): ConnectInfo<super::PeerInfo>,
     request: Request,
     next: Next,
 ) -> Response {
@@ -257,16 +443,20 @@ pub async fn rate_limit_middleware(
         return next.run(request).await;
     }
 
-    match limiter.check(addr.ip()) {
+    let class = RouteClass::of(request.method(), request.uri().path());
+    let limit = limiter.config.limit_for(class);
+    let identity = request
+        .extensions()
+        .get::<Identity>()
+        .map(|identity| identity.name.as_str());
+
+    match limiter.check_request(peer.rate_limit_key(), identity, class) {
         Ok(remaining) => {
             let mut response = next.run(request).await;
 
             // Add rate limit headers
             let headers = response.headers_mut();
-            headers.insert(
-                "X-RateLimit-Limit",
-                limiter.config.max_requests.to_string().parse().unwrap(),
-            );
+            headers.insert("X-RateLimit-Limit", limit.to_string().parse().unwrap());
             headers.insert(
                 "X-RateLimit-Remaining",
                 remaining.to_string().parse().unwrap(),
@@ -283,12 +473,11 @@ pub async fn rate_limit_middleware(
 
             response.headers_mut().insert(
                 "Retry-After",
-                retry_after.as_secs().to_string().parse().unwrap(),
-            );
-            response.headers_mut().insert(
-                "X-RateLimit-Limit",
-                limiter.config.max_requests.to_string().parse().unwrap(),
+                retry_after.as_secs().max(1).to_string().parse().unwrap(),
             );
+            response
+                .headers_mut()
+                .insert("X-RateLimit-Limit", limit.to_string().parse().unwrap());
             response
                 .headers_mut()
                 .insert("X-RateLimit-Remaining", "0".parse().unwrap());
@@ -298,6 +487,44 @@ pub async fn rate_limit_middleware(
     }
 }
 
+/// Failed authentication limit middleware for axum.
+///
+/// Runs before authentication and counts every `401` response against the
+/// client address, rejecting further requests from it with `429` once its
+/// [`RouteClass::AuthFailure`] budget is used up, so API keys cannot be
+/// guessed at full speed.
+pub async fn auth_failure_limit_middleware(
+    State(limiter): State<std::sync::Arc<RateLimiter>>,
+    ConnectInfo(peer): ConnectInfo<super::PeerInfo>,
+    request: Request,
+    next: Next,
+) -> Response {
+    // Skip rate limiting for health endpoint
+    if request.uri().path() == "/health" {
+        return next.run(request).await;
+    }
+
+    let key = peer.rate_limit_key();
+    if let Some(retry_after) = limiter.auth_blocked(key) {
+        let mut response = (
+            StatusCode::TOO_MANY_REQUESTS,
+            "Too many failed authentication attempts. Please try again later.",
+        )
+            .into_response();
+        response.headers_mut().insert(
+            "Retry-After",
+            retry_after.as_secs().max(1).to_string().parse().unwrap(),
+        );
+        return response;
+    }
+
+    let response = next.run(request).await;
+    if response.status() == StatusCode::UNAUTHORIZED {
+        limiter.record_auth_failure(key);
+    }
+    response
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -388,6 +615,163 @@ mod tests {
         assert!(limiter.check(ip).is_err());
     }
 
+    #[test]
+    fn test_rate_limiter_uid_keys() {
+        let limiter = RateLimiter::new(RateLimitConfig::custom(1, 60));
+
+        assert!(limiter.check_key(RateLimitKey::Uid(1000)).is_ok());
+        assert!(limiter.check_key(RateLimitKey::Uid(1000)).is_err());
+
+        // Other users and IP clients have their own budgets
+        assert!(limiter.check_key(RateLimitKey::Uid(1001)).is_ok());
+        assert!(limiter.check(IpAddr::V4(Ipv4Addr::LOCALHOST)).is_ok());
+        assert_eq!(limiter.stats().tracked_ips, 3);
+    }
+
+    #[test]
+    fn test_token_bucket_refills() {
+        let limiter = RateLimiter::new(RateLimitConfig {
+            window: Duration::from_millis(100),
+            ..RateLimitConfig::custom(2, 0)
+        });
+        let ip = IpAddr::V4(Ipv4Addr::new(10, 1, 1, 1));
+
+        assert!(limiter.check(ip).is_ok());
+        assert!(limiter.check(ip).is_ok());
+        let retry_after = limiter.check(ip).unwrap_err();
+        assert!(retry_after <= Duration::from_millis(50));
+
+        // One token refills every 50ms
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(limiter.check(ip).is_ok());
+        assert!(limiter.check(ip).is_err());
+    }
+
+    #[test]
+    fn test_route_class_budgets() {
+        let config = RateLimitConfig::custom(5, 60)
+            .with_class_limit(RouteClass::Execute, 2)
+            .with_class_limit(RouteClass::SessionCreate, 1);
+        assert_eq!(config.limit_for(RouteClass::Read), 5);
+        let limiter = RateLimiter::new(config);
+        let peer = RateLimitKey::Ip(IpAddr::V4(Ipv4Addr::LOCALHOST));
+
+        assert!(limiter
+            .check_request(peer, None, RouteClass::SessionCreate)
+            .is_ok());
+        assert!(limiter
+            .check_request(peer, None, RouteClass::SessionCreate)
+            .is_err());
+
+        assert_eq!(
+            limiter.check_request(peer, None, RouteClass::Execute),
+            Ok(1)
+        );
+        assert_eq!(
+            limiter.check_request(peer, None, RouteClass::Execute),
+            Ok(0)
+        );
+        assert!(limiter
+            .check_request(peer, None, RouteClass::Execute)
+            .is_err());
+
+        // Reads have their own budget
+        assert_eq!(limiter.check_request(peer, None, RouteClass::Read), Ok(4));
+    }
+
+    #[test]
+    fn test_route_class_of() {
+        assert_eq!(
+            RouteClass::of(&Method::GET, "/api/v1/sessions"),
+            RouteClass::Read
+        );
+        assert_eq!(
+            RouteClass::of(&Method::POST, "/api/v1/sessions/"),
+            RouteClass::SessionCreate
+        );
+        assert_eq!(
+            RouteClass::of(&Method::POST, "/api/v1/sessions/1/execute"),
+            RouteClass::Execute
+        );
+        assert_eq!(
+            RouteClass::of(&Method::DELETE, "/api/v1/sessions/1"),
+            RouteClass::Execute
+        );
+        assert_eq!(
+            RouteClass::of(&Method::GET, "/api/v1/sessions/1/ws"),
+            RouteClass::Execute
+        );
+    }
+
+    #[test]
+    fn test_key_by_identity() {
+        let limiter =
+            RateLimiter::new(RateLimitConfig::custom(1, 60).with_key_by(RateLimitKeyBy::Identity));
+        let nat = RateLimitKey::Ip(IpAddr::V4(Ipv4Addr::new(203, 0, 113, 1)));
+        let other = RateLimitKey::Ip(IpAddr::V4(Ipv4Addr::new(203, 0, 113, 2)));
+
+        // Agents behind one NAT address get separate budgets
+        assert!(limiter
+            .check_request(nat, Some("agent-a"), RouteClass::Read)
+            .is_ok());
+        assert!(limiter
+            .check_request(nat, Some("agent-b"), RouteClass::Read)
+            .is_ok());
+        assert!(limiter
+            .check_request(nat, Some("agent-a"), RouteClass::Read)
+            .is_err());
+
+        // The same identity shares its budget across addresses
+        assert!(limiter
+            .check_request(other, Some("agent-b"), RouteClass::Read)
+            .is_err());
+
+        // Unauthenticated requests fall back to the address
+        assert!(limiter.check_request(nat, None, RouteClass::Read).is_ok());
+        assert!(limiter.check_request(nat, None, RouteClass::Read).is_err());
+    }
+
+    #[test]
+    fn test_key_by_both() {
+        let limiter =
+            RateLimiter::new(RateLimitConfig::custom(1, 60).with_key_by(RateLimitKeyBy::Both));
+        let ip1 = RateLimitKey::Ip(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)));
+        let ip2 = RateLimitKey::Ip(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)));
+
+        assert!(limiter
+            .check_request(ip1, Some("agent"), RouteClass::Read)
+            .is_ok());
+        assert!(limiter
+            .check_request(ip1, Some("agent"), RouteClass::Read)
+            .is_err());
+        assert!(limiter
+            .check_request(ip2, Some("agent"), RouteClass::Read)
+            .is_ok());
+        assert!(limiter
+            .check_request(ip1, Some("other"), RouteClass::Read)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_auth_failures() {
+        let limiter = RateLimiter::new(
+            RateLimitConfig::custom(100, 60).with_class_limit(RouteClass::AuthFailure, 2),
+        );
+        let ip = RateLimitKey::Ip(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)));
+        let other = RateLimitKey::Ip(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)));
+
+        assert!(limiter.auth_blocked(ip).is_none());
+        limiter.record_auth_failure(ip);
+        assert!(limiter.auth_blocked(ip).is_none());
+        limiter.record_auth_failure(ip);
+        assert!(limiter.auth_blocked(ip).is_some());
+        assert!(limiter.auth_blocked(other).is_none());
+
+        // Failures do not use up the request budget
+        assert!(limiter.check_request(ip, None, RouteClass::Read).is_ok());
+        assert!(RateLimiter::disabled().auth_blocked(ip).is_none());
+    }
+
     #[test]
     fn test_rate_limiter_stats() {
         let limiter = RateLimiter::new(RateLimitConfig::custom(10, 30));
@@ -0,0 +1,884 @@
+//! POSIX shell command line tokenizer.
+//!
+//! Splits a command line into the simple commands a shell would run:
+//! quotes and escapes are removed, `;`, `&&`, `||`, `|` and `&` separate
+//! commands, and the contents of subshells, brace groups, function bodies,
+//! command substitutions (`$(...)`, backticks, including those nested in
+//! `${...}`, `$((...))` and here-documents with unquoted delimiters) and
+//! process substitutions are returned as commands of their own. Variables
+//! are not expanded.
+
+use std::fmt;
+
+/// Deepest nesting of subshells and substitutions accepted.
+const MAX_DEPTH: usize = 64;
+
+/// Reserved words that may precede a command without being one.
+const PREFIX_KEYWORDS: &[&str] = &[
+    "!", "if", "then", "elif", "else", "while", "until", "do", "time", "coproc",
+];
+
+/// Reserved words that end a compound command.
+const CLOSING_KEYWORDS: &[&str] = &["fi", "done", "esac", "}"];
+
+/// One simple command: words, assignments and redirections.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SimpleCommand {
+    /// Words after quote removal; `argv[0]` is the program.
+    pub argv: Vec<String>,
+    /// Leading `NAME=value` assignments.
+    pub assignments: Vec<String>,
+    /// Redirections.
+    pub redirects: Vec<Redirect>,
+    /// Connected to another command with `|`.
+    pub pipeline: bool,
+    /// Run in the background with `&`.
+    pub background: bool,
+    /// Function whose body contains the command.
+    pub function: Option<String>,
+}
+
+impl SimpleCommand {
+    /// Program name (`argv[0]`).
+    pub fn program(&self) -> Option<&str> {
+        self.argv.first().map(String::as_str)
+    }
+
+    /// Arguments after the program name.
+    pub fn args(&self) -> &[String] {
+        self.argv.get(1..).unwrap_or_default()
+    }
+}
+
+/// A redirection such as `> file` or `2>&1`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Redirect {
+    /// Operator without the file descriptor (`>`, `>>`, `<`, `&>`, ...).
+    pub op: String,
+    /// Target word after quote removal.
+    pub target: String,
+}
+
+impl Redirect {
+    /// Check if the redirection writes to its target.
+    pub fn is_output(&self) -> bool {
+        self.op.contains('>') && !self.op.starts_with('<')
+    }
+}
+
+/// Errors from tokenizing a command line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// A quote was not closed.
+    UnterminatedQuote(char),
+    /// A subshell, group or substitution was not closed.
+    Unclosed(&'static str),
+    /// Nesting is deeper than the tokenizer accepts.
+    TooDeep,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnterminatedQuote(quote) => write!(f, "unterminated {} quote", quote),
+            Self::Unclosed(open) => write!(f, "unclosed '{}'", open),
+            Self::TooDeep => write!(f, "nesting too deep"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Split a command line into simple commands.
+pub fn parse(line: &str) -> Result<Vec<SimpleCommand>, ParseError> {
+    let mut parser = Parser {
+        chars: line.chars().collect(),
+        pos: 0,
+        depth: 0,
+        backticks: 0,
+        commands: Vec::new(),
+        heredocs: Vec::new(),
+    };
+    parser.list(End::Eof, None)?;
+    Ok(parser.commands)
+}
+
+/// What ends a command list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum End {
+    Eof,
+    Paren,
+    Brace,
+    Backtick,
+}
+
+/// A here-document whose body follows the next newline.
+struct Heredoc {
+    delimiter: String,
+    /// Leading tabs are stripped (`<<-`).
+    strip_tabs: bool,
+    /// The delimiter was unquoted, so substitutions in the body run.
+    expand: bool,
+    /// Function whose body contains the redirection.
+    function: Option<String>,
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    depth: usize,
+    /// Nonzero inside backticks, where an unescaped backtick closes.
+    backticks: usize,
+    commands: Vec<SimpleCommand>,
+    heredocs: Vec<Heredoc>,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.pos + offset).copied()
+    }
+
+    fn starts_with(&self, s: &str) -> bool {
+        s.chars()
+            .enumerate()
+            .all(|(i, c)| self.peek_at(i) == Some(c))
+    }
+
+    fn text(&self, start: usize) -> String {
+        self.chars[start..self.pos].iter().collect()
+    }
+
+    fn skip_blanks(&mut self) {
+        while let Some(c) = self.peek() {
+            match c {
+                ' ' | '\t' => self.pos += 1,
+                '\\' if self.peek_at(1) == Some('\n') => self.pos += 2,
+                '#' => {
+                    while self.peek().is_some_and(|c| c != '\n') {
+                        self.pos += 1;
+                    }
+                }
+                _ => break,
+            }
+        }
+    }
+
+    /// Consume a newline and any here-document bodies that follow it.
+    fn newline(&mut self) -> Result<(), ParseError> {
+        self.pos += 1;
+        for heredoc in std::mem::take(&mut self.heredocs) {
+            while self.pos < self.chars.len() {
+                let start = self.pos;
+                while self.peek().is_some_and(|c| c != '\n') {
+                    self.pos += 1;
+                }
+                let line = self.text(start);
+                let line = if heredoc.strip_tabs {
+                    line.trim_start_matches('\t')
+                } else {
+                    &line
+                };
+                if line == heredoc.delimiter {
+                    self.pos += usize::from(self.peek().is_some());
+                    break;
+                }
+                if heredoc.expand {
+                    self.pos = start;
+                    self.heredoc_line(heredoc.function.as_deref())?;
+                }
+                self.pos += usize::from(self.peek().is_some());
+            }
+        }
+        Ok(())
+    }
+
+    /// Read a line of an expanded here-document body, collecting the
+    /// commands of its substitutions.
+    ///
+    /// Quotes are literal in the body; only `\`, `$(`, `${` and backticks
+    /// are special. A substitution may continue on later lines.
+    fn heredoc_line(&mut self, function: Option<&str>) -> Result<(), ParseError> {
+        let mut text = String::new();
+        while let Some(c) = self.peek() {
+            match c {
+                '\n' => break,
+                '\\' => self.pos += 2.min(self.chars.len() - self.pos),
+                '$' if matches!(self.peek_at(1), Some('(' | '{')) => {
+                    self.dollar(&mut text, &mut true, function)?;
+                }
+                '`' => self.backtick(&mut text, function)?,
+                _ => self.pos += 1,
+            }
+        }
+        Ok(())
+    }
+
+    fn enter(&mut self) -> Result<(), ParseError> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(ParseError::TooDeep);
+        }
+        Ok(())
+    }
+
+    /// Check if the next character ends a word.
+    fn at_word_end(&self, offset: usize) -> bool {
+        matches!(
+            self.peek_at(offset),
+            None | Some(' ' | '\t' | '\n' | ';' | '&' | '|' | '(' | ')' | '<' | '>')
+        )
+    }
+
+    /// Parse commands up to the given end.
+    fn list(&mut self, end: End, function: Option<&str>) -> Result<(), ParseError> {
+        self.enter()?;
+        let saved = self.backticks;
+        self.backticks = usize::from(end == End::Backtick);
+        let result = self.list_items(end, function);
+        self.backticks = saved;
+        self.depth -= 1;
+        result
+    }
+
+    fn list_items(&mut self, end: End, function: Option<&str>) -> Result<(), ParseError> {
+        let mut list_start = self.commands.len();
+        let mut piped = false;
+
+        loop {
+            self.skip_blanks();
+            let Some(c) = self.peek() else {
+                return match end {
+                    End::Eof => Ok(()),
+                    End::Paren => Err(ParseError::Unclosed("(")),
+                    End::Brace => Err(ParseError::Unclosed("{")),
+                    End::Backtick => Err(ParseError::Unclosed("`")),
+                };
+            };
+
+            match (c, end) {
+                (')', End::Paren) | ('`', End::Backtick) => {
+                    self.pos += 1;
+                    return Ok(());
+                }
+                ('}', End::Brace) if self.at_word_end(1) => {
+                    self.pos += 1;
+                    return Ok(());
+                }
+                ('\n', _) => {
+                    self.newline()?;
+                    list_start = self.commands.len();
+                    continue;
+                }
+                // Stray closers, e.g. case patterns, separate commands
+                (';' | ')', _) => {
+                    self.pos += 1;
+                    list_start = self.commands.len();
+                    continue;
+                }
+                _ => {}
+            }
+
+            let start = self.commands.len();
+            let before = self.pos;
+            self.command(function)?;
+            if self.pos == before {
+                // Nothing we recognize; skip it rather than loop
+                self.pos += 1;
+            }
+            if piped {
+                self.mark(start, |cmd| cmd.pipeline = true);
+            }
+            piped = false;
+
+            self.skip_blanks();
+            if self.starts_with("&&") || self.starts_with("||") {
+                self.pos += 2;
+            } else if self.starts_with("|") {
+                self.pos += if self.starts_with("|&") { 2 } else { 1 };
+                self.mark(start, |cmd| cmd.pipeline = true);
+                piped = true;
+            } else if self.starts_with("&") {
+                self.pos += 1;
+                self.mark(list_start, |cmd| cmd.background = true);
+                list_start = self.commands.len();
+            }
+        }
+    }
+
+    fn mark(&mut self, from: usize, f: impl Fn(&mut SimpleCommand)) {
+        for command in &mut self.commands[from..] {
+            f(command);
+        }
+    }
+
+    /// Parse one simple or compound command.
+    fn command(&mut self, function: Option<&str>) -> Result<(), ParseError> {
+        self.skip_blanks();
+        if self.peek() == Some('(') {
+            self.pos += 1;
+            return self.list(End::Paren, function);
+        }
+        if self.peek() == Some('{') && self.at_word_end(1) {
+            self.pos += 1;
+            return self.list(End::Brace, function);
+        }
+
+        let mut command = SimpleCommand {
+            function: function.map(str::to_string),
+            ..Default::default()
+        };
+        let mut timed = false;
+
+        loop {
+            self.skip_blanks();
+            let Some(c) = self.peek() else { break };
+
+            if matches!(c, '<' | '>') && self.peek_at(1) == Some('(') {
+                let word = self.word(function)?;
+                command.argv.push(word.text);
+                continue;
+            }
+            if let Some(op) = self.redirect_op() {
+                self.skip_blanks();
+                let target = self.word(function)?;
+                if op.starts_with("<<") && op != "<<<" {
+                    self.heredocs.push(Heredoc {
+                        delimiter: target.text.clone(),
+                        strip_tabs: op == "<<-",
+                        expand: !target.quoted,
+                        function: function.map(str::to_string),
+                    });
+                }
+                command.redirects.push(Redirect {
+                    op,
+                    target: target.text,
+                });
+                continue;
+            }
+            if self.at_word_end(0) {
+                break;
+            }
+            if c == '`' && self.backticks > 0 {
+                break;
+            }
+
+            let word = self.word(function)?;
+            if command.argv.is_empty() {
+                if !word.quoted && PREFIX_KEYWORDS.contains(&word.text.as_str()) {
+                    timed |= word.text == "time";
+                    if word.text == "coproc" {
+                        self.coproc_name();
+                    }
+                    // A compound command such as `coproc { ...; }`
+                    if command.assignments.is_empty() && command.redirects.is_empty() {
+                        self.skip_blanks();
+                        if self.peek() == Some('(')
+                            || (self.peek() == Some('{') && self.at_word_end(1))
+                        {
+                            return self.command(function);
+                        }
+                    }
+                    continue;
+                }
+                // Options of the `time` keyword (`-p`, `--`)
+                if timed && !word.quoted && word.text.starts_with('-') {
+                    continue;
+                }
+                if !word.quoted && CLOSING_KEYWORDS.contains(&word.text.as_str()) {
+                    continue;
+                }
+                if !word.quoted && is_assignment(&word.text) {
+                    command.assignments.push(word.text);
+                    continue;
+                }
+                if !word.quoted && word.text == "function" {
+                    return self.function_keyword(function);
+                }
+                if self.function_parens() {
+                    self.skip_blanks();
+                    while self.peek() == Some('\n') {
+                        self.newline()?;
+                        self.skip_blanks();
+                    }
+                    return self.command(Some(&word.text));
+                }
+            }
+            command.argv.push(word.text);
+        }
+
+        if !command.argv.is_empty()
+            || !command.redirects.is_empty()
+            || !command.assignments.is_empty()
+        {
+            self.commands.push(command);
+        }
+        Ok(())
+    }
+
+    /// Skip the name in `coproc NAME { ...; }`, which only precedes a
+    /// compound command.
+    fn coproc_name(&mut self) {
+        self.skip_blanks();
+        let mut offset = 0;
+        while self
+            .peek_at(offset)
+            .is_some_and(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            offset += 1;
+        }
+        let mut next = offset;
+        while matches!(self.peek_at(next), Some(' ' | '\t')) {
+            next += 1;
+        }
+        let compound = match self.peek_at(next) {
+            Some('(') => true,
+            Some('{') => self.at_word_end(next + 1),
+            _ => false,
+        };
+        if offset > 0 && compound {
+            self.pos += offset;
+        }
+    }
+
+    /// Consume `()` after a function name.
+    fn function_parens(&mut self) -> bool {
+        let mut offset = 0;
+        while matches!(self.peek_at(offset), Some(' ' | '\t')) {
+            offset += 1;
+        }
+        if self.peek_at(offset) != Some('(') {
+            return false;
+        }
+        offset += 1;
+        while matches!(self.peek_at(offset), Some(' ' | '\t')) {
+            offset += 1;
+        }
+        if self.peek_at(offset) != Some(')') {
+            return false;
+        }
+        self.pos += offset + 1;
+        true
+    }
+
+    /// Parse `function name [()] body`.
+    fn function_keyword(&mut self, function: Option<&str>) -> Result<(), ParseError> {
+        self.skip_blanks();
+        if self.at_word_end(0) {
+            return Ok(());
+        }
+        let name = self.word(function)?.text;
+        self.function_parens();
+        self.skip_blanks();
+        while self.peek() == Some('\n') {
+            self.newline()?;
+            self.skip_blanks();
+        }
+        self.command(Some(&name))
+    }
+
+    /// Read one word, removing quotes and collecting substitutions.
+    fn word(&mut self, function: Option<&str>) -> Result<Word, ParseError> {
+        let mut text = String::new();
+        let mut quoted = false;
+
+        while let Some(c) = self.peek() {
+            match c {
+                ' ' | '\t' | '\n' | ';' | '&' | '|' | ')' => break,
+                '`' if self.backticks > 0 => break,
+                '`' => self.backtick(&mut text, function)?,
+                '<' | '>' if self.peek_at(1) == Some('(') => {
+                    let start = self.pos;
+                    self.pos += 2;
+                    self.list(End::Paren, function)?;
+                    text.push_str(&self.text(start));
+                }
+                '<' | '>' | '(' => break,
+                '\\' => {
+                    self.pos += 1;
+                    match self.peek() {
+                        Some('\n') => self.pos += 1,
+                        Some(c) => {
+                            text.push(c);
+                            quoted = true;
+                            self.pos += 1;
+                        }
+                        None => {}
+                    }
+                }
+                '\'' => {
+                    quoted = true;
+                    self.pos += 1;
+                    loop {
+                        match self.peek() {
+                            None => return Err(ParseError::UnterminatedQuote('\'')),
+                            Some('\'') => break,
+                            Some(c) => text.push(c),
+                        }
+                        self.pos += 1;
+                    }
+                    self.pos += 1;
+                }
+                '"' => {
+                    quoted = true;
+                    self.pos += 1;
+                    self.double_quoted(&mut text, function)?;
+                }
+                '$' => self.dollar(&mut text, &mut quoted, function)?,
+                c => {
+                    text.push(c);
+                    self.pos += 1;
+                }
+            }
+        }
+
+        Ok(Word { text, quoted })
+    }
+
+    /// Read the rest of a double-quoted string.
+    fn double_quoted(
+        &mut self,
+        text: &mut String,
+        function: Option<&str>,
+    ) -> Result<(), ParseError> {
+        loop {
+            match self.peek() {
+                None => return Err(ParseError::UnterminatedQuote('"')),
+                Some('"') => {
+                    self.pos += 1;
+                    return Ok(());
+                }
+                Some('\\') => match self.peek_at(1) {
+                    Some(c @ ('$' | '`' | '"' | '\\')) => {
+                        text.push(c);
+                        self.pos += 2;
+                    }
+                    Some('\n') => self.pos += 2,
+                    _ => {
+                        text.push('\\');
+                        self.pos += 1;
+                    }
+                },
+                Some('$') => self.dollar(text, &mut true, function)?,
+                Some('`') => self.backtick(text, function)?,
+                Some(c) => {
+                    text.push(c);
+                    self.pos += 1;
+                }
+            }
+        }
+    }
+
+    /// Read a backtick command substitution, keeping its source text.
+    fn backtick(&mut self, text: &mut String, function: Option<&str>) -> Result<(), ParseError> {
+        let start = self.pos;
+        self.pos += 1;
+        self.list(End::Backtick, function)?;
+        text.push_str(&self.text(start));
+        Ok(())
+    }
+
+    /// Read a `$` expansion, keeping its source text.
+    ///
+    /// Command substitutions are parsed for the commands they contain.
+    fn dollar(
+        &mut self,
+        text: &mut String,
+        quoted: &mut bool,
+        function: Option<&str>,
+    ) -> Result<(), ParseError> {
+        let start = self.pos;
+        match self.peek_at(1) {
+            Some('(') if self.peek_at(2) == Some('(') => {
+                self.pos += 3;
+                self.expansion('(', ')', 2, "$((", function)?;
+            }
+            Some('(') => {
+                self.pos += 2;
+                self.list(End::Paren, function)?;
+            }
+            Some('{') => {
+                self.pos += 2;
+                self.expansion('{', '}', 1, "${", function)?;
+            }
+            Some('\'') => {
+                // ANSI-C quoting
+                *quoted = true;
+                self.pos += 2;
+                loop {
+                    match self.peek() {
+                        None => return Err(ParseError::UnterminatedQuote('\'')),
+                        Some('\'') => {
+                            self.pos += 1;
+                            return Ok(());
+                        }
+                        Some('\\') => {
+                            match self.peek_at(1) {
+                                Some('n') => text.push('\n'),
+                                Some('t') => text.push('\t'),
+                                Some(c) => text.push(c),
+                                None => return Err(ParseError::UnterminatedQuote('\'')),
+                            }
+                            self.pos += 2;
+                        }
+                        Some(c) => {
+                            text.push(c);
+                            self.pos += 1;
+                        }
+                    }
+                }
+            }
+            _ => self.pos += 1,
+        }
+        text.push_str(&self.text(start));
+        Ok(())
+    }
+
+    /// Skip to the matching close character of a parameter or arithmetic
+    /// expansion, parsing the command substitutions inside it.
+    ///
+    /// Quotes are not special here, so substitutions inside them are
+    /// collected too.
+    fn expansion(
+        &mut self,
+        open: char,
+        close: char,
+        mut depth: usize,
+        name: &'static str,
+        function: Option<&str>,
+    ) -> Result<(), ParseError> {
+        let mut scratch = String::new();
+        while depth > 0 {
+            match self.peek() {
+                None => return Err(ParseError::Unclosed(name)),
+                Some('\\') => {
+                    self.pos += 2;
+                    continue;
+                }
+                // A backtick would close the enclosing substitution
+                Some('`') if self.backticks > 0 => return Err(ParseError::Unclosed(name)),
+                Some('`') => {
+                    self.backtick(&mut scratch, function)?;
+                    continue;
+                }
+                Some('$') if matches!(self.peek_at(1), Some('(' | '{')) => {
+                    self.enter()?;
+                    let result = self.dollar(&mut scratch, &mut false, function);
+                    self.depth -= 1;
+                    result?;
+                    continue;
+                }
+                Some(c) if c == open => depth += 1,
+                Some(c) if c == close => depth -= 1,
+                _ => {}
+            }
+            self.pos += 1;
+        }
+        Ok(())
+    }
+
+    /// Consume a redirection operator, if one starts here.
+    fn redirect_op(&mut self) -> Option<String> {
+        let mut offset = 0;
+        while self.peek_at(offset).is_some_and(|c| c.is_ascii_digit()) {
+            offset += 1;
+        }
+        const OPS: &[&str] = &[
+            "&>>", "<<<", "<<-", "&>", ">>", ">&", ">|", "<<", "<&", "<>", ">", "<",
+        ];
+        for op in OPS {
+            let matches = op
+                .chars()
+                .enumerate()
+                .all(|(i, c)| self.peek_at(offset + i) == Some(c));
+            // `&>` only counts at the start of a word, never after digits
+            if matches && !(op.starts_with('&') && offset > 0) {
+                self.pos += offset + op.len();
+                return Some(op.to_string());
+            }
+        }
+        None
+    }
+}
+
+/// A word after quote removal.
+struct Word {
+    text: String,
+    quoted: bool,
+}
+
+fn is_assignment(word: &str) -> bool {
+    match word.split_once('=') {
+        Some((name, _)) => {
+            let mut chars = name.chars();
+            chars
+                .next()
+                .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+                && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn argvs(line: &str) -> Vec<Vec<String>> {
+        parse(line)
+            .unwrap()
+            .into_iter()
+            .map(|command| command.argv)
+            .collect()
+    }
+
+    fn words(words: &[&str]) -> Vec<String> {
+        words.iter().map(|w| w.to_string()).collect()
+    }
+
+    #[test]
+    fn test_quotes_and_escapes() {
+        assert_eq!(
+            argvs(r#"echo 'a b' "c \"d\"" e\ f $'g\th'"#),
+            vec![words(&["echo", "a b", "c \"d\"", "e f", "g\th"])]
+        );
+        assert_eq!(argvs("rm  -rf   /"), vec![words(&["rm", "-rf", "/"])]);
+        assert_eq!(argvs("r\"m\" -rf /"), vec![words(&["rm", "-rf", "/"])]);
+        assert_eq!(
+            parse("echo 'oops"),
+            Err(ParseError::UnterminatedQuote('\''))
+        );
+    }
+
+    #[test]
+    fn test_separators() {
+        assert_eq!(
+            argvs("cd /src && make || echo failed; ls -l | wc -l & true\nfalse"),
+            vec![
+                words(&["cd", "/src"]),
+                words(&["make"]),
+                words(&["echo", "failed"]),
+                words(&["ls", "-l"]),
+                words(&["wc", "-l"]),
+                words(&["true"]),
+                words(&["false"]),
+            ]
+        );
+
+        let commands = parse("a | b & c").unwrap();
+        assert!(commands[0].pipeline && commands[1].pipeline);
+        assert!(commands[0].background && commands[1].background);
+        assert!(!commands[2].pipeline && !commands[2].background);
+    }
+
+    #[test]
+    fn test_nested_commands() {
+        let argvs = argvs("(cd /tmp; rm x) && { make; } && echo $(whoami) `id -u` >(tee log)");
+        assert!(argvs.contains(&words(&["rm", "x"])));
+        assert!(argvs.contains(&words(&["make"])));
+        assert!(argvs.contains(&words(&["whoami"])));
+        assert!(argvs.contains(&words(&["id", "-u"])));
+        assert!(argvs.contains(&words(&["tee", "log"])));
+        assert!(argvs.contains(&words(&["echo", "$(whoami)", "`id -u`", ">(tee log)"])));
+
+        // Substitutions inside double quotes still run
+        assert!(self::argvs(r#"echo "today is $(date)""#).contains(&words(&["date"])));
+        assert_eq!(parse("echo $(ls"), Err(ParseError::Unclosed("(")));
+        assert_eq!(parse(&"(".repeat(100)), Err(ParseError::TooDeep));
+    }
+
+    #[test]
+    fn test_assignments_keywords_and_redirects() {
+        let commands = parse("FOO=1 BAR=2 make 2>&1 > build.log").unwrap();
+        assert_eq!(commands[0].assignments, words(&["FOO=1", "BAR=2"]));
+        assert_eq!(commands[0].argv, words(&["make"]));
+        assert_eq!(commands[0].redirects.len(), 2);
+        assert_eq!(commands[0].redirects[1].op, ">");
+        assert_eq!(commands[0].redirects[1].target, "build.log");
+        assert!(commands[0].redirects[1].is_output());
+
+        assert_eq!(
+            argvs("if test -f x; then rm x; else echo none; fi"),
+            vec![
+                words(&["test", "-f", "x"]),
+                words(&["rm", "x"]),
+                words(&["echo", "none"]),
+            ]
+        );
+        assert_eq!(argvs("time -p reboot"), vec![words(&["reboot"])]);
+        assert_eq!(argvs("coproc reboot"), vec![words(&["reboot"])]);
+        assert_eq!(argvs("coproc job { reboot; }"), vec![words(&["reboot"])]);
+        assert_eq!(argvs("coproc (reboot)"), vec![words(&["reboot"])]);
+        assert_eq!(
+            argvs("if { reboot; }; then :; fi"),
+            vec![words(&["reboot"]), words(&[":"])]
+        );
+        assert_eq!(argvs("time -- make -j4"), vec![words(&["make", "-j4"])]);
+        assert_eq!(
+            argvs("echo ${HOME} $((1 + 2)) # rm -rf /"),
+            vec![words(&["echo", "${HOME}", "$((1 + 2))"])]
+        );
+    }
+
+    #[test]
+    fn test_substitutions_inside_expansions() {
+        assert!(argvs("ls ${X:-$(id)}").contains(&words(&["id"])));
+        assert!(argvs("ls $((`id` + 1))").contains(&words(&["id"])));
+        assert!(argvs("ls ${X:-${Y:-$(whoami)}}").contains(&words(&["whoami"])));
+        assert!(argvs("echo \"${X:-'$(id)'}\"").contains(&words(&["id"])));
+        assert_eq!(argvs("ls ${X:-$(id)}")[1], words(&["ls", "${X:-$(id)}"]));
+        assert_eq!(parse("ls ${X:-$(id}"), Err(ParseError::Unclosed("(")));
+        assert!(parse("echo `ls ${X:-`id`}`").is_err());
+    }
+
+    #[test]
+    fn test_heredoc_body_is_not_a_command() {
+        assert_eq!(
+            argvs("cat <<EOF > notes\nreboot now\nEOF\nls"),
+            vec![words(&["cat"]), words(&["ls"])]
+        );
+        assert_eq!(
+            argvs("cat <<-EOF\n\tit's \"$HOME\"\n\tEOF\nls"),
+            vec![words(&["cat"]), words(&["ls"])]
+        );
+    }
+
+    #[test]
+    fn test_heredoc_substitutions() {
+        assert_eq!(
+            argvs("cat <<EOF\n$(reboot)\nEOF"),
+            vec![words(&["cat"]), words(&["reboot"])]
+        );
+        assert!(argvs("cat <<EOF\nat `id -u`\nEOF\nls").contains(&words(&["id", "-u"])));
+        assert!(argvs("cat <<EOF\n${X:-$(whoami)}\nEOF").contains(&words(&["whoami"])));
+        assert!(argvs("cat <<EOF\n$(true\nreboot)\nEOF").contains(&words(&["reboot"])));
+        assert_eq!(
+            parse("cat <<EOF\n$(reboot\nEOF"),
+            Err(ParseError::Unclosed("("))
+        );
+
+        // Escaped and quoted-delimiter bodies are not expanded
+        assert_eq!(argvs("cat <<EOF\n\\$(reboot)\nEOF"), vec![words(&["cat"])]);
+        assert_eq!(
+            argvs("cat <<'EOF'\n$(reboot)\n`reboot`\nEOF"),
+            vec![words(&["cat"])]
+        );
+        assert_eq!(argvs("cat <<\"EOF\"\n$(reboot\nEOF"), vec![words(&["cat"])]);
+    }
+
+    #[test]
+    fn test_functions() {
+        let commands = parse(":(){ :|:& };:").unwrap();
+        assert_eq!(commands.len(), 3);
+        assert_eq!(commands[0].function.as_deref(), Some(":"));
+        assert!(commands[0].pipeline && commands[0].background);
+        assert_eq!(commands[2].function, None);
+
+        let commands = parse("function build { make; }; build").unwrap();
+        assert_eq!(commands[0].function.as_deref(), Some("build"));
+        assert_eq!(commands[0].argv, words(&["make"]));
+    }
+}
@@ -1,7 +1,31 @@
 //! Input validation and command sanitization.
-
+//!
+//! Commands are split into simple commands by the [`shell`](super::shell)
+//! tokenizer and checked against the configured [`Rule`]s, the allowlist
+//! (in [`ValidationMode::Allowlist`]), the blocked patterns and the
+//! built-in dangerous command rules, in that order.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
 use std::time::Duration;
 
+use serde::{Deserialize, Serialize};
+
+use super::identity::Identity;
+use super::policy::{self, Rule, RuleAction};
+use super::shell::SimpleCommand;
+
+/// Which commands may run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ValidationMode {
+    /// Anything not blocked may run.
+    #[default]
+    Denylist,
+    /// Only commands matching an allowlist entry may run.
+    Allowlist,
+}
+
 /// Validation configuration.
 #[derive(Debug, Clone)]
 pub struct ValidationConfig {
@@ -15,8 +39,42 @@ pub struct ValidationConfig {
     pub min_timeout_secs: u64,
     /// Whether to block dangerous commands.
     pub block_dangerous: bool,
-    /// Custom blocked patterns (regex-like simple patterns).
+    /// Hold dangerous commands for approval instead of rejecting them.
+    pub approve_dangerous: bool,
+    /// Blocked command lines such as `rm -rf` or `dd if=*`.
+    ///
+    /// See [`Rule::from_command_line`] for how they match.
     pub blocked_patterns: Vec<String>,
+    /// Allow, ask and deny rules, checked in order before anything else.
+    ///
+    /// The first rule matching a command decides; allow and ask rules
+    /// exempt the command from the dangerous command checks.
+    pub rules: Vec<Rule>,
+    /// Whether commands must match [`allowed_commands`](Self::allowed_commands).
+    pub mode: ValidationMode,
+    /// Command prefixes allowed in allowlist mode, such as `ls`,
+    /// `cargo test` or `git status|diff|log`.
+    ///
+    /// Every command in a pipeline or list, behind a wrapper or inside a
+    /// substitution must match one. See [`Rule::from_prefix`] for how they
+    /// match.
+    pub allowed_commands: Vec<String>,
+    /// Allowlists for identities holding a scope.
+    ///
+    /// An identity holding any of these scopes (explicitly, not through
+    /// `*`) is checked in allowlist mode against the union of their lists
+    /// instead of [`allowed_commands`](Self::allowed_commands), whatever the
+    /// [`mode`](Self::mode).
+    pub scope_allowlists: BTreeMap<String, Vec<String>>,
+    /// Environment variables that leading `NAME=value` assignments and
+    /// request `env` maps may set whenever an allowlist applies.
+    ///
+    /// Without an entry, `LD_PRELOAD=x ls` or `PATH=/tmp/evil` would change
+    /// what an allowlisted program runs.
+    pub allowed_env: Vec<String>,
+    /// Directories commands may run in, for identities without their own
+    /// (anywhere if empty).
+    pub root_dirs: Vec<PathBuf>,
 }
 
 impl Default for ValidationConfig {
@@ -27,7 +85,14 @@ impl Default for ValidationConfig {
             max_timeout_secs: 300,             // 5 minutes
             min_timeout_secs: 1,
             block_dangerous: true,
+            approve_dangerous: false,
             blocked_patterns: Vec::new(),
+            rules: Vec::new(),
+            mode: ValidationMode::Denylist,
+            allowed_commands: Vec::new(),
+            scope_allowlists: BTreeMap::new(),
+            allowed_env: Vec::new(),
+            root_dirs: Vec::new(),
         }
     }
 }
@@ -41,7 +106,14 @@ impl ValidationConfig {
             max_timeout_secs: 3600,             // 1 hour
             min_timeout_secs: 1,
             block_dangerous: false,
+            approve_dangerous: false,
             blocked_patterns: Vec::new(),
+            rules: Vec::new(),
+            mode: ValidationMode::Denylist,
+            allowed_commands: Vec::new(),
+            scope_allowlists: BTreeMap::new(),
+            allowed_env: Vec::new(),
+            root_dirs: Vec::new(),
         }
     }
 
@@ -53,31 +125,117 @@ impl ValidationConfig {
             max_timeout_secs: 60,
             min_timeout_secs: 1,
             block_dangerous: true,
+            approve_dangerous: false,
             blocked_patterns: vec![
                 "rm -rf".to_string(),
-                "mkfs".to_string(),
-                "dd if=".to_string(),
-                ":(){".to_string(), // Fork bomb
-                ">(w)".to_string(),
+                "mkfs*".to_string(),
+                "dd if=*".to_string(),
             ],
+            rules: Vec::new(),
+            mode: ValidationMode::Denylist,
+            allowed_commands: Vec::new(),
+            scope_allowlists: BTreeMap::new(),
+            allowed_env: Vec::new(),
+            root_dirs: Vec::new(),
         }
     }
+
+    /// Add a rule.
+    pub fn with_rule(mut self, rule: Rule) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    /// Only allow the listed command prefixes.
+    pub fn allowlist<I, S>(mut self, commands: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.mode = ValidationMode::Allowlist;
+        self.allowed_commands = commands.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Only allow the listed command prefixes for identities with a scope.
+    pub fn with_scope_allowlist<I, S>(mut self, scope: impl Into<String>, commands: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.scope_allowlists
+            .insert(scope.into(), commands.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Let allowlisted commands set the listed environment variables.
+    pub fn with_allowed_env<I, S>(mut self, names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.allowed_env = names.into_iter().map(Into::into).collect();
+        self
+    }
 }
 
 /// Command validator.
 #[derive(Debug)]
 pub struct CommandValidator {
     config: ValidationConfig,
+    blocked: Vec<Rule>,
+    dangerous: Vec<Rule>,
+    allowlist: Option<Vec<Rule>>,
+    scope_allowlists: Vec<(String, Vec<Rule>)>,
 }
 
 impl CommandValidator {
     /// Create a new validator with the given config.
     pub fn new(config: ValidationConfig) -> Self {
-        Self { config }
+        let blocked = config
+            .blocked_patterns
+            .iter()
+            .map(|pattern| Rule::from_command_line(RuleAction::Deny, pattern))
+            .collect();
+        let dangerous = if config.block_dangerous {
+            policy::dangerous_rules()
+        } else {
+            Vec::new()
+        };
+        let prefixes = |commands: &[String]| -> Vec<Rule> {
+            commands
+                .iter()
+                .map(|line| Rule::from_prefix(RuleAction::Allow, line))
+                .collect()
+        };
+        let allowlist =
+            (config.mode == ValidationMode::Allowlist).then(|| prefixes(&config.allowed_commands));
+        let scope_allowlists = config
+            .scope_allowlists
+            .iter()
+            .map(|(scope, commands)| (scope.clone(), prefixes(commands)))
+            .collect();
+        Self {
+            config,
+            blocked,
+            dangerous,
+            allowlist,
+            scope_allowlists,
+        }
     }
 
     /// Validate a command string.
     pub fn validate_command(&self, command: &str) -> Result<(), ValidationError> {
+        self.validate_command_for(command, None)
+    }
+
+    /// Validate a command string for an identity, applying the allowlist
+    /// of its scopes.
+    pub fn validate_command_for(
+        &self,
+        command: &str,
+        identity: Option<&Identity>,
+    ) -> Result<(), ValidationError> {
         // Check length
         if command.len() > self.config.max_command_length {
             return Err(ValidationError::CommandTooLong {
@@ -91,27 +249,126 @@ impl CommandValidator {
             return Err(ValidationError::EmptyCommand);
         }
 
-        // Check for dangerous patterns
-        if self.config.block_dangerous {
-            if let Some(pattern) = self.check_dangerous_patterns(command) {
-                return Err(ValidationError::DangerousCommand {
-                    pattern: pattern.to_string(),
-                });
+        // Check for null bytes
+        if command.contains('\0') {
+            return Err(ValidationError::InvalidCharacter('\0'));
+        }
+
+        let allowlist = self.allowlist_for(identity);
+        if self.config.rules.is_empty()
+            && self.blocked.is_empty()
+            && self.dangerous.is_empty()
+            && allowlist.is_none()
+        {
+            return Ok(());
+        }
+
+        let commands =
+            policy::commands(command).map_err(|e| ValidationError::Unparsable(e.to_string()))?;
+        // Outright rejections win over approval, whichever command they hit
+        let mut approval = None;
+        for command in &commands {
+            match self.check_command(command, allowlist.as_deref()) {
+                Err(e @ ValidationError::RequiresApproval { .. }) => {
+                    approval.get_or_insert(e);
+                }
+                result => result?,
             }
         }
 
-        // Check custom blocked patterns
-        for pattern in &self.config.blocked_patterns {
-            if command.contains(pattern) {
-                return Err(ValidationError::BlockedPattern {
-                    pattern: pattern.clone(),
+        approval.map_or(Ok(()), Err)
+    }
+
+    /// Validate the environment variables a request sets for an identity.
+    ///
+    /// Only variables in [`allowed_env`](ValidationConfig::allowed_env) may
+    /// be set when an allowlist applies; anything goes otherwise.
+    pub fn validate_env_for<'a>(
+        &self,
+        names: impl IntoIterator<Item = &'a str>,
+        identity: Option<&Identity>,
+    ) -> Result<(), ValidationError> {
+        if self.allowlist_for(identity).is_none() {
+            return Ok(());
+        }
+        names.into_iter().try_for_each(|name| self.check_env(name))
+    }
+
+    /// Check that an environment variable is in the allowed list.
+    fn check_env(&self, name: &str) -> Result<(), ValidationError> {
+        if self
+            .config
+            .allowed_env
+            .iter()
+            .any(|allowed| allowed == name)
+        {
+            Ok(())
+        } else {
+            Err(ValidationError::EnvNotAllowed {
+                name: name.to_string(),
+            })
+        }
+    }
+
+    /// The allowlist that applies to an identity, if any.
+    fn allowlist_for(&self, identity: Option<&Identity>) -> Option<Vec<&Rule>> {
+        let scopes = identity.map_or(&[][..], |id| id.scopes.as_slice());
+        let mut held = self
+            .scope_allowlists
+            .iter()
+            .filter(|(scope, _)| scopes.contains(scope))
+            .peekable();
+        if held.peek().is_some() {
+            return Some(held.flat_map(|(_, rules)| rules).collect());
+        }
+        self.allowlist.as_ref().map(|rules| rules.iter().collect())
+    }
+
+    /// Check one simple command against the rules.
+    fn check_command(
+        &self,
+        command: &SimpleCommand,
+        allowlist: Option<&[&Rule]>,
+    ) -> Result<(), ValidationError> {
+        if let Some(rule) = self.config.rules.iter().find(|rule| rule.matches(command)) {
+            return match rule.action {
+                RuleAction::Allow => Ok(()),
+                RuleAction::Ask => Err(ValidationError::RequiresApproval {
+                    reason: rule.name.clone(),
+                }),
+                RuleAction::Deny => Err(ValidationError::DeniedByRule {
+                    rule: rule.name.clone(),
+                    command: command.argv.join(" "),
+                }),
+            };
+        }
+
+        if let Some(allowlist) = allowlist {
+            for assignment in &command.assignments {
+                let (name, _) = assignment.split_once('=').unwrap_or((assignment, ""));
+                self.check_env(name)?;
+            }
+            // Bare assignments and redirections run no program
+            if command.program().is_some() && !allowlist.iter().any(|rule| rule.matches(command)) {
+                return Err(ValidationError::NotAllowed {
+                    command: command.argv.join(" "),
                 });
             }
         }
 
-        // Check for null bytes
-        if command.contains('\0') {
-            return Err(ValidationError::InvalidCharacter('\0'));
+        if let Some(rule) = self.blocked.iter().find(|rule| rule.matches(command)) {
+            return Err(ValidationError::BlockedPattern {
+                pattern: rule.name.clone(),
+            });
+        }
+
+        if let Some(pattern) = self.check_dangerous_patterns(command) {
+            let pattern = pattern.to_string();
+            return Err(if self.config.approve_dangerous {
+                ValidationError::RequiresApproval { reason: pattern }
+            } else {
+                ValidationError::DangerousCommand { pattern }
+            });
         }
 
         Ok(())
@@ -159,41 +416,33 @@ impl CommandValidator {
         Ok(())
     }
 
-    /// Check for common dangerous command patterns.
-    fn check_dangerous_patterns(&self, command: &str) -> Option<&'static str> {
-        let lower = command.to_lowercase();
-
-        // Dangerous file operations
-        if lower.contains("rm -rf /") || lower.contains("rm -fr /") {
-            return Some("rm -rf /");
-        }
-
-        // Format/partition commands
-        if lower.contains("mkfs") || lower.contains("fdisk") || lower.contains("parted") {
-            return Some("disk formatting");
-        }
-
-        // Raw disk access
-        if lower.contains("dd if=/dev") && lower.contains("of=/dev") {
-            return Some("raw disk write");
-        }
-
-        // Fork bomb patterns
-        if lower.contains(":(){") || lower.contains(":(){ :|:& };:") {
+    /// Check for common dangerous commands, returning the rule that fired.
+    fn check_dangerous_patterns(&self, command: &SimpleCommand) -> Option<&str> {
+        if self.config.block_dangerous && policy::is_fork_bomb(command) {
             return Some("fork bomb");
         }
+        self.dangerous
+            .iter()
+            .find(|rule| rule.matches(command))
+            .map(|rule| rule.name.as_str())
+    }
 
-        // Shutdown/reboot
-        if lower.contains("shutdown") || lower.contains("reboot") || lower.contains("init 0") {
-            return Some("system shutdown");
-        }
-
-        // Dangerous redirections
-        if lower.contains("> /dev/sd") || lower.contains("> /dev/nvme") {
-            return Some("device overwrite");
-        }
+    /// Check if some commands must match an allowlist or wait for
+    /// approval.
+    pub fn has_allowlist_or_approvals(&self) -> bool {
+        self.allowlist.is_some()
+            || !self.scope_allowlists.is_empty()
+            || self.config.approve_dangerous
+            || self
+                .config
+                .rules
+                .iter()
+                .any(|rule| rule.action == RuleAction::Ask)
+    }
 
-        None
+    /// Directories commands may run in (anywhere if empty).
+    pub fn root_dirs(&self) -> &[PathBuf] {
+        &self.config.root_dirs
     }
 
     /// Get the max output size.
@@ -219,6 +468,16 @@ pub enum ValidationError {
     DangerousCommand { pattern: String },
     /// Command matches blocked pattern.
     BlockedPattern { pattern: String },
+    /// Command was denied by a configured rule.
+    DeniedByRule { rule: String, command: String },
+    /// Command matches no allowlist entry.
+    NotAllowed { command: String },
+    /// Environment variable may not be set under the allowlist.
+    EnvNotAllowed { name: String },
+    /// Command may only run once an operator approves it.
+    RequiresApproval { reason: String },
+    /// Command line could not be tokenized.
+    Unparsable(String),
     /// Command contains invalid character.
     InvalidCharacter(char),
     /// Timeout is too short.
@@ -227,6 +486,10 @@ pub enum ValidationError {
     TimeoutTooLong { value: u64, max: u64 },
     /// Path contains traversal attempt.
     PathTraversal,
+    /// Working directory resolves outside the allowed roots.
+    OutsideRoot { path: String },
+    /// Working directory does not exist or is not a directory.
+    InvalidWorkingDir { path: String, reason: String },
     /// Path is too long.
     PathTooLong { length: usize, max: usize },
 }
@@ -244,6 +507,19 @@ impl std::fmt::Display for ValidationError {
             Self::BlockedPattern { pattern } => {
                 write!(f, "Command contains blocked pattern: {}", pattern)
             }
+            Self::DeniedByRule { rule, command } => {
+                write!(f, "Command '{}' denied by rule '{}'", command, rule)
+            }
+            Self::NotAllowed { command } => {
+                write!(f, "Command '{}' is not in the allowlist", command)
+            }
+            Self::EnvNotAllowed { name } => {
+                write!(f, "Environment variable '{}' is not in the allowlist", name)
+            }
+            Self::RequiresApproval { reason } => {
+                write!(f, "Command requires approval: {}", reason)
+            }
+            Self::Unparsable(reason) => write!(f, "Command could not be parsed: {}", reason),
             Self::InvalidCharacter(c) => {
                 write!(f, "Command contains invalid character: {:?}", c)
             }
@@ -254,6 +530,12 @@ impl std::fmt::Display for ValidationError {
                 write!(f, "Timeout too long: {}s (max: {}s)", value, max)
             }
             Self::PathTraversal => write!(f, "Path traversal detected"),
+            Self::OutsideRoot { path } => {
+                write!(f, "Working directory {} is outside the allowed roots", path)
+            }
+            Self::InvalidWorkingDir { path, reason } => {
+                write!(f, "Invalid working directory {}: {}", path, reason)
+            }
             Self::PathTooLong { length, max } => {
                 write!(f, "Path too long: {} chars (max: {})", length, max)
             }
@@ -429,6 +711,246 @@ mod tests {
             validator.validate_command("dd if=/dev/zero"),
             Err(ValidationError::BlockedPattern { .. })
         ));
+        assert_eq!(
+            validator.validate_command("cd /tmp && rm -fr build"),
+            Err(ValidationError::BlockedPattern {
+                pattern: "rm -rf".to_string()
+            })
+        );
+        assert!(validator.validate_command("echo 'dd if=x'").is_ok());
+    }
+
+    #[test]
+    fn test_dangerous_commands_are_tokenized() {
+        let validator = CommandValidator::default();
+
+        // Substrings in arguments no longer match
+        assert!(validator
+            .validate_command("echo shutdown-notes.txt")
+            .is_ok());
+        assert!(validator.validate_command("git log --grep reboot").is_ok());
+
+        // Extra whitespace, quoting and nesting no longer hide commands
+        for command in [
+            "rm  -rf /",
+            "'rm' -r -f /",
+            "ls; sudo rm -rf /var",
+            "echo $(reboot)",
+            "sh -c 'shutdown now'",
+            "cat <<EOF\n$(reboot)\nEOF",
+            "cat <<EOF\n`reboot`\nEOF",
+        ] {
+            assert!(
+                matches!(
+                    validator.validate_command(command),
+                    Err(ValidationError::DangerousCommand { .. })
+                ),
+                "{} was not blocked",
+                command
+            );
+        }
+
+        // Quoted here-document delimiters keep the body literal
+        assert!(validator
+            .validate_command("cat <<'EOF'\n$(reboot)\n`reboot`\nEOF")
+            .is_ok());
+
+        assert!(matches!(
+            validator.validate_command("echo 'unterminated"),
+            Err(ValidationError::Unparsable(_))
+        ));
+    }
+
+    #[test]
+    fn test_rules() {
+        use super::super::policy::Pattern;
+
+        let config = ValidationConfig::default()
+            .with_rule(
+                Rule::allow("maintenance reboot")
+                    .with_command(Pattern::parse("reboot").unwrap())
+                    .with_arg(Pattern::parse("--dry-run").unwrap()),
+            )
+            .with_rule(
+                Rule::deny("no force push")
+                    .with_command(Pattern::parse("git").unwrap())
+                    .with_arg(Pattern::parse("push").unwrap())
+                    .with_flag("-f|--force"),
+            );
+        let validator = CommandValidator::new(config);
+
+        assert_eq!(
+            validator.validate_command("cd repo && git push --force origin"),
+            Err(ValidationError::DeniedByRule {
+                rule: "no force push".to_string(),
+                command: "git push --force origin".to_string(),
+            })
+        );
+        assert!(validator.validate_command("git push origin").is_ok());
+
+        // An allow rule takes precedence over the built-in checks
+        assert!(validator.validate_command("reboot --dry-run").is_ok());
+        assert!(validator.validate_command("reboot").is_err());
+    }
+
+    #[test]
+    fn test_requires_approval() {
+        use super::super::policy::Pattern;
+
+        let validator = CommandValidator::new(
+            ValidationConfig {
+                approve_dangerous: true,
+                ..ValidationConfig::default()
+            }
+            .with_rule(
+                Rule::ask("force push")
+                    .with_command(Pattern::parse("git").unwrap())
+                    .with_flag("-f|--force"),
+            )
+            .with_rule(Rule::deny("no curl").with_command(Pattern::parse("curl").unwrap())),
+        );
+
+        assert_eq!(
+            validator.validate_command("git push --force origin main"),
+            Err(ValidationError::RequiresApproval {
+                reason: "force push".to_string()
+            })
+        );
+        assert_eq!(
+            validator.validate_command("rm -rf /"),
+            Err(ValidationError::RequiresApproval {
+                reason: "rm -rf /".to_string()
+            })
+        );
+        assert!(validator.validate_command("git push origin main").is_ok());
+        assert_eq!(
+            validator.validate_command("cat <<EOF\n`git push -f`\nEOF"),
+            Err(ValidationError::RequiresApproval {
+                reason: "force push".to_string()
+            })
+        );
+
+        // An outright rejection anywhere in the line wins
+        assert!(matches!(
+            validator.validate_command("rm -rf /; curl x"),
+            Err(ValidationError::DeniedByRule { .. })
+        ));
+
+        // Without approvals, dangerous commands are rejected as before
+        assert!(matches!(
+            CommandValidator::default().validate_command("rm -rf /"),
+            Err(ValidationError::DangerousCommand { .. })
+        ));
+    }
+
+    #[test]
+    fn test_allowlist() {
+        let validator = CommandValidator::new(ValidationConfig::default().allowlist([
+            "ls",
+            "cargo test",
+            "git status|diff|log",
+        ]));
+
+        assert!(validator.validate_command("ls -la").is_ok());
+        assert!(validator.validate_command("git log --oneline | ls").is_ok());
+        assert!(validator.validate_command("cargo test && git diff").is_ok());
+
+        assert_eq!(
+            validator.validate_command("git status && git push"),
+            Err(ValidationError::NotAllowed {
+                command: "git push".to_string()
+            })
+        );
+        assert!(validator.validate_command("cargo build").is_err());
+        assert!(validator.validate_command("ls | sh").is_err());
+        assert!(validator.validate_command("ls $(curl x)").is_err());
+        assert!(validator.validate_command("ls ${X:-$(id)}").is_err());
+        assert!(validator.validate_command("ls $((`id`))").is_err());
+        assert!(validator
+            .validate_command("ls <<EOF\n$(curl x)\nEOF")
+            .is_err());
+        assert!(validator
+            .validate_command("ls <<'EOF'\n$(curl x)\nEOF")
+            .is_ok());
+        assert!(validator.validate_command("time -p cargo build").is_err());
+        assert!(validator.validate_command("sudo ls").is_err());
+
+        // Programs must be named as listed, not by a path to a lookalike
+        assert!(validator.validate_command("./ls").is_err());
+        assert!(validator.validate_command("/tmp/evil/ls -la").is_err());
+
+        // Allowlisted commands still go through the dangerous command checks
+        let validator = CommandValidator::new(ValidationConfig::default().allowlist(["rm"]));
+        assert!(validator.validate_command("rm build.log").is_ok());
+        assert!(validator.validate_command("rm -rf /").is_err());
+    }
+
+    #[test]
+    fn test_allowlist_env() {
+        let validator = CommandValidator::new(
+            ValidationConfig::default()
+                .allowlist(["ls", "env"])
+                .with_allowed_env(["LANG"]),
+        );
+
+        assert!(validator.validate_command("LANG=C ls").is_ok());
+        assert_eq!(
+            validator.validate_command("LD_PRELOAD=/tmp/x.so ls"),
+            Err(ValidationError::EnvNotAllowed {
+                name: "LD_PRELOAD".to_string()
+            })
+        );
+        assert!(validator.validate_command("PATH=/tmp/evil").is_err());
+        assert!(validator.validate_command("env PATH=/tmp/evil ls").is_err());
+
+        assert!(validator.validate_env_for(["LANG"], None).is_ok());
+        assert!(validator.validate_env_for(["LD_PRELOAD"], None).is_err());
+
+        // Without an allowlist, the environment is not restricted
+        let validator = CommandValidator::default();
+        assert!(validator.validate_command("PATH=/tmp/evil ls").is_ok());
+        assert!(validator.validate_env_for(["LD_PRELOAD"], None).is_ok());
+    }
+
+    #[test]
+    fn test_scope_allowlist() {
+        let validator = CommandValidator::new(
+            ValidationConfig::default()
+                .with_scope_allowlist("ci", ["cargo test"])
+                .with_scope_allowlist("docs", ["mdbook build"]),
+        );
+        let ci = Identity::new("ci", ["execute", "ci"]);
+        let both = Identity::new("release", ["ci", "docs"]);
+        let user = Identity::full_access("alice");
+
+        assert!(validator
+            .validate_command_for("cargo test", Some(&ci))
+            .is_ok());
+        assert!(validator.validate_command_for("ls", Some(&ci)).is_err());
+        assert!(validator
+            .validate_command_for("cargo test; mdbook build", Some(&both))
+            .is_ok());
+
+        // Full access does not pull in scope allowlists
+        assert!(validator.validate_command_for("ls", Some(&user)).is_ok());
+        assert!(validator.validate_command_for("ls", None).is_ok());
+    }
+
+    #[test]
+    fn test_has_allowlist_or_approvals() {
+        let validator = |config| CommandValidator::new(config).has_allowlist_or_approvals();
+        assert!(!validator(ValidationConfig::default()));
+        assert!(validator(ValidationConfig::default().allowlist(["ls"])));
+        assert!(validator(
+            ValidationConfig::default().with_scope_allowlist("ci", ["cargo test"])
+        ));
+        assert!(validator(
+            ValidationConfig::default().with_rule(Rule::ask("push"))
+        ));
+        assert!(validator(ValidationConfig {
+            approve_dangerous: true,
+            ..ValidationConfig::default()
+        }));
     }
 
     #[test]
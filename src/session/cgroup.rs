@@ -0,0 +1,408 @@
+//! cgroup v2 accounting and limits for sessions.
+//!
+//! When the server has a delegated cgroup v2 subtree, every session gets a
+//! child cgroup that its commands join before `exec`. The cgroup caps the
+//! session's memory, CPU and process count, and its counters show what the
+//! session has used.
+//!
+//! The delegated cgroup may not hold processes itself once controllers are
+//! enabled for its children, so the server moves its own processes into a
+//! `server` leaf first.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use super::SessionId;
+use crate::error::ShellTunnelError;
+use crate::Result;
+
+/// Period of `cpu.max` in microseconds.
+const CPU_PERIOD_USEC: u64 = 100_000;
+
+/// cgroup settings for sessions.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CgroupConfig {
+    /// Delegated cgroup to create session cgroups in (the server's own
+    /// cgroup if unset).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+    /// `memory.max` of each session, in bytes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory_max_bytes: Option<u64>,
+    /// `cpu.max` of each session, in percent of one CPU (200 for two).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpu_max_percent: Option<u32>,
+    /// `pids.max` of each session.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pids_max: Option<u64>,
+}
+
+impl CgroupConfig {
+    /// Controllers needed for the configured limits and accounting.
+    fn controllers(&self) -> [(&'static str, bool); 3] {
+        [
+            ("cpu", self.cpu_max_percent.is_some()),
+            ("memory", self.memory_max_bytes.is_some()),
+            ("pids", self.pids_max.is_some()),
+        ]
+    }
+}
+
+/// The delegated cgroup sessions are created in.
+#[derive(Debug)]
+pub struct CgroupRoot {
+    path: PathBuf,
+    config: CgroupConfig,
+}
+
+impl CgroupRoot {
+    /// Prepare the configured cgroup, or the server's own, for session
+    /// cgroups.
+    ///
+    /// Enables the cpu, memory and pids controllers for child cgroups where
+    /// available; fails if one needed for a configured limit is missing.
+    pub fn open(config: &CgroupConfig) -> Result<Self> {
+        let error = |e: io::Error| ShellTunnelError::Cgroup(e.to_string());
+        let path = match &config.path {
+            Some(path) => path.clone(),
+            None => own_cgroup().map_err(error)?,
+        };
+        let controllers = fs::read_to_string(path.join("cgroup.controllers")).map_err(|e| {
+            ShellTunnelError::Cgroup(format!(
+                "{} is not a cgroup v2 directory: {}",
+                path.display(),
+                e
+            ))
+        })?;
+
+        if !is_hierarchy_root(&path) {
+            evacuate(&path).map_err(error)?;
+        }
+        let available: Vec<&str> = controllers.split_whitespace().collect();
+        for (controller, required) in config.controllers() {
+            if !available.contains(&controller) {
+                if required {
+                    return Err(ShellTunnelError::Cgroup(format!(
+                        "the {} controller is not delegated to {}",
+                        controller,
+                        path.display()
+                    )));
+                }
+                continue;
+            }
+            fs::write(
+                path.join("cgroup.subtree_control"),
+                format!("+{}", controller),
+            )
+            .map_err(error)?;
+        }
+
+        Ok(Self {
+            path,
+            config: config.clone(),
+        })
+    }
+
+    /// The delegated cgroup directory.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Create the cgroup of a session and apply the configured limits.
+    pub fn create_session(&self, id: SessionId) -> Result<SessionCgroup> {
+        let cgroup = SessionCgroup {
+            path: self.path.join(id.to_string()),
+        };
+        let error =
+            |e: io::Error| ShellTunnelError::Cgroup(format!("{}: {}", cgroup.path.display(), e));
+        // Session ids restart with the server, so a crashed run may have
+        // left a cgroup behind
+        if cgroup.path.exists() {
+            cgroup.remove().map_err(error)?;
+        }
+        fs::create_dir(&cgroup.path).map_err(error)?;
+
+        let config = &self.config;
+        let limits = [
+            ("memory.max", config.memory_max_bytes.map(|b| b.to_string())),
+            (
+                "cpu.max",
+                config
+                    .cpu_max_percent
+                    .map(|p| format!("{} {}", CPU_PERIOD_USEC * p as u64 / 100, CPU_PERIOD_USEC)),
+            ),
+            ("pids.max", config.pids_max.map(|n| n.to_string())),
+        ];
+        for (file, value) in limits {
+            if let Some(value) = value {
+                if let Err(e) = fs::write(cgroup.path.join(file), value) {
+                    let _ = cgroup.remove();
+                    return Err(error(e));
+                }
+            }
+        }
+        Ok(cgroup)
+    }
+}
+
+/// The cgroup of a session.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionCgroup {
+    path: PathBuf,
+}
+
+impl SessionCgroup {
+    /// The cgroup directory, which commands join.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Read the session's resource usage.
+    pub fn usage(&self) -> io::Result<CgroupUsage> {
+        let cpu_stat = fs::read_to_string(self.path.join("cpu.stat"))?;
+        let cpu_usec = cpu_stat
+            .lines()
+            .find_map(|line| line.strip_prefix("usage_usec "))
+            .and_then(|value| value.trim().parse().ok())
+            .unwrap_or(0);
+        // memory.peak needs Linux 5.19
+        let memory_peak_bytes = read_u64(&self.path.join("memory.peak"))
+            .or_else(|| read_u64(&self.path.join("memory.current")));
+        let processes = match read_u64(&self.path.join("pids.current")) {
+            Some(count) => count,
+            None => self.pids()?.len() as u64,
+        };
+        Ok(CgroupUsage {
+            cpu_seconds: cpu_usec as f64 / 1_000_000.0,
+            memory_peak_bytes,
+            processes,
+        })
+    }
+
+    /// Process ids in the cgroup.
+    pub fn pids(&self) -> io::Result<Vec<u32>> {
+        let procs = fs::read_to_string(self.path.join("cgroup.procs"))?;
+        Ok(procs
+            .lines()
+            .filter_map(|line| line.trim().parse().ok())
+            .collect())
+    }
+
+    /// Kill every process in the cgroup and remove it.
+    pub fn remove(&self) -> io::Result<()> {
+        // cgroup.kill needs Linux 5.14
+        if fs::write(self.path.join("cgroup.kill"), "1").is_err() {
+            for pid in self.pids()? {
+                // SAFETY: kill has no memory safety preconditions.
+                #[cfg(unix)]
+                unsafe {
+                    libc::kill(pid as libc::pid_t, libc::SIGKILL);
+                }
+            }
+        }
+
+        // Killed processes leave the cgroup asynchronously
+        let mut attempts = 0;
+        loop {
+            match fs::remove_dir(&self.path) {
+                Err(e) if is_errno(&e, EBUSY) && attempts < 100 => {
+                    attempts += 1;
+                    std::thread::sleep(Duration::from_millis(10));
+                }
+                Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Resource usage of a session's cgroup.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct CgroupUsage {
+    /// CPU time used by the session's processes, in seconds.
+    pub cpu_seconds: f64,
+    /// Highest memory use, in bytes (current use on older kernels; `None`
+    /// without the memory controller).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory_peak_bytes: Option<u64>,
+    /// Processes (tasks) currently in the session.
+    pub processes: u64,
+}
+
+#[cfg(unix)]
+use libc::{EBUSY, ESRCH};
+#[cfg(not(unix))]
+const EBUSY: i32 = -1;
+#[cfg(not(unix))]
+const ESRCH: i32 = -1;
+
+fn is_errno(error: &io::Error, errno: i32) -> bool {
+    error.raw_os_error() == Some(errno)
+}
+
+fn read_u64(path: &Path) -> Option<u64> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+/// Mount point of the cgroup v2 hierarchy.
+fn hierarchy_mount() -> io::Result<PathBuf> {
+    let mounts = fs::read_to_string("/proc/self/mounts")?;
+    mounts
+        .lines()
+        .map(|line| line.split_whitespace().collect::<Vec<_>>())
+        .find(|fields| fields.len() > 2 && fields[2] == "cgroup2")
+        .map(|fields| PathBuf::from(fields[1]))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no cgroup v2 hierarchy mounted"))
+}
+
+/// The server's cgroup v2 directory.
+fn own_cgroup() -> io::Result<PathBuf> {
+    let cgroups = fs::read_to_string("/proc/self/cgroup")?;
+    let path = cgroups
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "not in a cgroup v2 hierarchy"))?;
+    Ok(hierarchy_mount()?.join(path.trim_start_matches('/')))
+}
+
+fn is_hierarchy_root(path: &Path) -> bool {
+    hierarchy_mount().is_ok_and(|mount| mount == path)
+}
+
+/// Move the processes of a cgroup into its `server` leaf.
+fn evacuate(path: &Path) -> io::Result<()> {
+    let procs = fs::read_to_string(path.join("cgroup.procs"))?;
+    if procs.trim().is_empty() {
+        return Ok(());
+    }
+    let leaf = path.join("server");
+    if !leaf.exists() {
+        fs::create_dir(&leaf)?;
+    }
+    for pid in procs.lines() {
+        // Processes may exit while being moved
+        match fs::write(leaf.join("cgroup.procs"), pid.trim()) {
+            Err(e) if is_errno(&e, ESRCH) => {}
+            result => result?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fake_cgroup(controllers: &str) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("cgroup.controllers"), controllers).unwrap();
+        fs::write(dir.path().join("cgroup.procs"), "").unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_session_limits() {
+        let dir = fake_cgroup("cpuset cpu io memory pids");
+        let config = CgroupConfig {
+            path: Some(dir.path().to_path_buf()),
+            memory_max_bytes: Some(512 << 20),
+            cpu_max_percent: Some(150),
+            pids_max: Some(64),
+        };
+        let root = CgroupRoot::open(&config).unwrap();
+        // The last controller written stays in the fake file
+        assert_eq!(
+            fs::read_to_string(dir.path().join("cgroup.subtree_control")).unwrap(),
+            "+pids"
+        );
+
+        let id = SessionId::new();
+        let cgroup = root.create_session(id).unwrap();
+        assert_eq!(cgroup.path(), dir.path().join(id.to_string()));
+        let read = |file: &str| fs::read_to_string(cgroup.path().join(file)).unwrap();
+        assert_eq!(read("memory.max"), "536870912");
+        assert_eq!(read("cpu.max"), "150000 100000");
+        assert_eq!(read("pids.max"), "64");
+    }
+
+    #[test]
+    fn test_missing_controller() {
+        let dir = fake_cgroup("cpu pids");
+        let config = CgroupConfig {
+            path: Some(dir.path().to_path_buf()),
+            memory_max_bytes: Some(1 << 30),
+            ..Default::default()
+        };
+        assert!(matches!(
+            CgroupRoot::open(&config),
+            Err(ShellTunnelError::Cgroup(_))
+        ));
+
+        let config = CgroupConfig {
+            path: Some(dir.path().join("missing")),
+            ..Default::default()
+        };
+        assert!(matches!(
+            CgroupRoot::open(&config),
+            Err(ShellTunnelError::Cgroup(_))
+        ));
+    }
+
+    #[test]
+    fn test_usage() {
+        let dir = tempfile::tempdir().unwrap();
+        let cgroup = SessionCgroup {
+            path: dir.path().to_path_buf(),
+        };
+        fs::write(
+            dir.path().join("cpu.stat"),
+            "usage_usec 2500000\nuser_usec 2000000\nsystem_usec 500000\n",
+        )
+        .unwrap();
+        fs::write(dir.path().join("memory.peak"), "1048576\n").unwrap();
+        fs::write(dir.path().join("cgroup.procs"), "100\n101\n").unwrap();
+
+        let usage = cgroup.usage().unwrap();
+        assert_eq!(usage.cpu_seconds, 2.5);
+        assert_eq!(usage.memory_peak_bytes, Some(1 << 20));
+        assert_eq!(usage.processes, 2);
+
+        fs::write(dir.path().join("pids.current"), "3\n").unwrap();
+        assert_eq!(cgroup.usage().unwrap().processes, 3);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    #[ignore] // needs a writable cgroup v2 hierarchy
+    fn test_commands_join_session_cgroup() {
+        use crate::execution::{Command, CommandExecutor};
+        use std::sync::Arc;
+
+        let name = format!("shell-tunnel-test-{}", std::process::id());
+        let base = hierarchy_mount().unwrap().join(&name);
+        fs::create_dir(&base).unwrap();
+        let config = CgroupConfig {
+            path: Some(base.clone()),
+            ..Default::default()
+        };
+        let root = CgroupRoot::open(&config).unwrap();
+        let id = SessionId::new();
+        let cgroup = root.create_session(id).unwrap();
+
+        let executor = CommandExecutor::new(Arc::new(crate::session::SessionStore::new()));
+        let cmd = Command::new("cat /proc/self/cgroup").cgroup(Some(cgroup.path().to_path_buf()));
+        let result = executor.execute_sync(&cmd).unwrap();
+        assert!(result.text_output.contains(&format!("0::/{}/{}", name, id)));
+        let usage = cgroup.usage().unwrap();
+        assert_eq!(usage.processes, 0);
+
+        cgroup.remove().unwrap();
+        assert!(!cgroup.path().exists());
+        fs::remove_dir(&base).unwrap();
+    }
+}
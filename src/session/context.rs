@@ -139,6 +139,21 @@ impl StateProbe {
         "set"
     }
 
+    /// Read the working directory of a running process.
+    ///
+    /// Returns `None` if the process is gone or the platform has no
+    /// `/proc` to read it from.
+    #[cfg(unix)]
+    pub fn process_cwd(pid: u32) -> Option<PathBuf> {
+        std::fs::read_link(format!("/proc/{}/cwd", pid)).ok()
+    }
+
+    /// Read the working directory of a running process.
+    #[cfg(windows)]
+    pub fn process_cwd(_pid: u32) -> Option<PathBuf> {
+        None
+    }
+
     /// Parse CWD from command output.
     pub fn parse_cwd(output: &str) -> Option<PathBuf> {
         let trimmed = output.trim();
@@ -272,6 +287,14 @@ mod tests {
         assert!(!cmd.is_empty());
     }
 
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_state_probe_process_cwd() {
+        let cwd = StateProbe::process_cwd(std::process::id()).unwrap();
+        assert_eq!(cwd, std::env::current_dir().unwrap());
+        assert!(StateProbe::process_cwd(u32::MAX).is_none());
+    }
+
     #[test]
     fn test_state_probe_parse_cwd() {
         let output = "/home/user\n";
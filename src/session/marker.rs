@@ -0,0 +1,296 @@
+//! Commands typed into a session's shell, framed by markers.
+//!
+//! The shell prints a start marker before the command and an end marker
+//! holding `$?` after it, so the command's output and exit status can be
+//! picked out of everything else on the terminal: the echo of the typed
+//! line, prompts, and output of background jobs. The markers are typed as
+//! octal escapes for `printf`, so the echo never contains them, and carry a
+//! random nonce, so markers of earlier commands are never mistaken for them.
+//!
+//! This needs a POSIX shell.
+
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::error::ShellTunnelError;
+use crate::execution::Command;
+use crate::pty::ResourceLimits;
+use crate::Result;
+
+/// Separator around markers, a control character terminals ignore.
+const RS: u8 = 0x1e;
+
+/// Picks a command's output and exit status out of terminal output.
+#[derive(Debug)]
+pub(crate) struct Frame {
+    nonce: String,
+    start: Vec<u8>,
+    end: Vec<u8>,
+    /// Output that may hold part of a marker.
+    held: Vec<u8>,
+    started: bool,
+    done: bool,
+    exit_code: Option<i32>,
+}
+
+impl Frame {
+    /// Frame a command for a shell running with `shell_limits`.
+    ///
+    /// Returns the frame and the line to type. The command runs in the
+    /// shell itself, so that `cd` and variables carry over to later
+    /// commands, unless it sets environment variables or tighter limits:
+    /// then it runs in a subshell.
+    pub fn new(command: &Command, shell_limits: &ResourceLimits) -> Result<(Self, String)> {
+        let nonce = nonce();
+        let mut body = String::new();
+        if let Some(ref dir) = command.working_dir {
+            let dir = dir.to_str().ok_or_else(|| {
+                ShellTunnelError::ExecutionFailed("working directory is not UTF-8".to_string())
+            })?;
+            body.push_str(&format!("cd -- {} && ", quote(dir)));
+        }
+        body.push_str(&format!("eval {}", quote(&command.command_line)));
+
+        let mut env: Vec<_> = command.env.iter().collect();
+        env.sort();
+        let mut setup = Vec::new();
+        for (name, value) in env {
+            if !is_name(name) {
+                return Err(ShellTunnelError::ExecutionFailed(format!(
+                    "invalid environment variable name '{}'",
+                    name
+                )));
+            }
+            setup.push(format!("export {}={}", name, quote(value)));
+        }
+        setup.extend(ulimits(&command.limits, shell_limits));
+        if !setup.is_empty() {
+            body = format!("( {} && {} )", setup.join(" && "), body);
+        }
+
+        let line = format!(
+            "printf '\\036S%s\\036' {nonce}; {body}; printf '\\036E%s:%d\\036' {nonce} \"$?\"\n"
+        );
+        let frame = Self {
+            start: [&[RS, b'S'], nonce.as_bytes(), &[RS]].concat(),
+            end: [&[RS, b'E'], nonce.as_bytes(), b":"].concat(),
+            nonce,
+            held: Vec::new(),
+            started: false,
+            done: false,
+            exit_code: None,
+        };
+        Ok((frame, line))
+    }
+
+    /// Add terminal output, returning the part of it that is the command's.
+    ///
+    /// Output that may be the beginning of the end marker is held back
+    /// until the next call.
+    pub fn push(&mut self, data: &[u8]) -> Vec<u8> {
+        if self.done {
+            return Vec::new();
+        }
+        self.held.extend_from_slice(data);
+        if !self.started {
+            match find(&self.held, &self.start) {
+                Some(i) => {
+                    self.held.drain(..i + self.start.len());
+                    self.started = true;
+                }
+                None => {
+                    let keep = self.start.len() - 1;
+                    self.held.drain(..self.held.len().saturating_sub(keep));
+                    return Vec::new();
+                }
+            }
+        }
+
+        if let Some(i) = find(&self.held, &self.end) {
+            let status = &self.held[i + self.end.len()..];
+            if let Some(len) = status.iter().position(|&b| b == RS) {
+                self.exit_code = std::str::from_utf8(&status[..len])
+                    .ok()
+                    .and_then(|code| code.parse().ok());
+                self.done = true;
+                self.held.truncate(i);
+                return std::mem::take(&mut self.held);
+            }
+            return self.held.drain(..i).collect();
+        }
+        let hold = self
+            .held
+            .iter()
+            .rposition(|&b| b == RS)
+            .filter(|&i| self.end.starts_with(&self.held[i..]))
+            .unwrap_or(self.held.len());
+        self.held.drain(..hold).collect()
+    }
+
+    /// Whether the end marker arrived.
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// The command's exit status, once the end marker arrived.
+    pub fn exit_code(&self) -> Option<i32> {
+        self.exit_code
+    }
+
+    /// Where the command's last marker ends in `text`, the terminal's
+    /// output with control characters stripped.
+    pub fn end_in(&self, text: &str) -> Option<usize> {
+        let end = text.rfind(&self.nonce)? + self.nonce.len();
+        let status = text[end..].strip_prefix(':').map_or(0, |rest| {
+            1 + rest.bytes().take_while(u8::is_ascii_digit).count()
+        });
+        Some(end + status)
+    }
+}
+
+/// A nonce no earlier command used.
+fn nonce() -> String {
+    static COUNT: AtomicU64 = AtomicU64::new(0);
+    let count = COUNT.fetch_add(1, Ordering::Relaxed);
+    format!("{:016x}", RandomState::new().hash_one(count))
+}
+
+/// Quote a string as a single shell word.
+fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+/// Whether `name` can be exported.
+fn is_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// `ulimit` commands setting the limits that are tighter than the shell's.
+///
+/// File and core sizes are counted in blocks of 1024 bytes by bash and of
+/// 512 bytes by other shells.
+fn ulimits(limits: &ResourceLimits, shell_limits: &ResourceLimits) -> Vec<String> {
+    let blocks = "(${BASH_VERSION:+512}+512)";
+    let tighter = |limit: Option<u64>, current: Option<u64>| {
+        // Shell arithmetic is signed
+        limit
+            .filter(|limit| current.map_or(true, |current| *limit < current))
+            .map(|limit| limit.min(i64::MAX as u64))
+    };
+    let mut commands = Vec::new();
+    if let Some(secs) = tighter(limits.cpu_secs, shell_limits.cpu_secs) {
+        commands.push(format!("ulimit -t {}", secs));
+    }
+    if let Some(bytes) = tighter(limits.address_space_bytes, shell_limits.address_space_bytes) {
+        commands.push(format!("ulimit -v {}", bytes / 1024));
+    }
+    if let Some(count) = tighter(limits.max_processes, shell_limits.max_processes) {
+        // dash and mksh call it -p
+        commands.push(format!(
+            "{{ ulimit -u {count} 2>/dev/null || ulimit -p {count}; }}"
+        ));
+    }
+    if let Some(count) = tighter(limits.open_files, shell_limits.open_files) {
+        commands.push(format!("ulimit -n {}", count));
+    }
+    if let Some(bytes) = tighter(limits.file_size_bytes, shell_limits.file_size_bytes) {
+        commands.push(format!("ulimit -f $(({} / {}))", bytes, blocks));
+    }
+    if let Some(bytes) = tighter(limits.core_size_bytes, shell_limits.core_size_bytes) {
+        commands.push(format!("ulimit -c $(({} / {}))", bytes, blocks));
+    }
+    commands
+}
+
+/// Find `needle` in `haystack`.
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(frame: &Frame, status: &str) -> Vec<u8> {
+        [frame.end.as_slice(), status.as_bytes(), &[RS]].concat()
+    }
+
+    #[test]
+    fn test_frame_line() {
+        let command = Command::new("echo 'hi'").working_dir("/tmp");
+        let (frame, line) = Frame::new(&command, &ResourceLimits::default()).unwrap();
+        assert!(line.starts_with(&format!("printf '\\036S%s\\036' {};", frame.nonce)));
+        assert!(line.contains(r"; cd -- '/tmp' && eval 'echo '\''hi'\'''; "));
+        assert!(line.ends_with(&format!(
+            "printf '\\036E%s:%d\\036' {} \"$?\"\n",
+            frame.nonce
+        )));
+        // The echo of the line holds no marker
+        assert!(!line.as_bytes().contains(&RS));
+
+        let (other, _) = Frame::new(&command, &ResourceLimits::default()).unwrap();
+        assert_ne!(frame.nonce, other.nonce);
+    }
+
+    #[test]
+    fn test_frame_subshell() {
+        let shell = ResourceLimits {
+            cpu_secs: Some(60),
+            open_files: Some(64),
+            ..Default::default()
+        };
+        let limits = ResourceLimits {
+            cpu_secs: Some(10),
+            open_files: Some(64),
+            file_size_bytes: Some(1 << 20),
+            ..Default::default()
+        };
+        let command = Command::new("make").env("CC", "it's").limits(limits);
+        let (_, line) = Frame::new(&command, &shell).unwrap();
+        assert!(line.contains(
+            r"( export CC='it'\''s' && ulimit -t 10 && ulimit -f $((1048576 / (${BASH_VERSION:+512}+512))) && eval 'make' )"
+        ));
+
+        // Nothing to set up runs in the shell itself
+        let (_, line) = Frame::new(&Command::new("make").limits(shell), &shell).unwrap();
+        assert!(!line.contains('('));
+
+        let command = Command::new("true").env("A;reboot", "1");
+        assert!(Frame::new(&command, &shell).is_err());
+    }
+
+    #[test]
+    fn test_frame_push() {
+        let (mut frame, _) = Frame::new(&Command::new("x"), &ResourceLimits::default()).unwrap();
+        assert!(frame.push(b"$ printf ... echo\r\n").is_empty());
+
+        // Markers may be split anywhere
+        let start = frame.start.clone();
+        assert!(frame.push(&start[..3]).is_empty());
+        assert_eq!(
+            frame.push(&[&start[3..], b"one\r\n".as_slice()].concat()),
+            b"one\r\n"
+        );
+        assert_eq!(frame.push(b"two\x1e"), b"two");
+        let end = output(&frame, "3");
+        assert_eq!(frame.push(&end[..5]), b"\x1e");
+        assert!(!frame.is_done());
+        assert!(frame
+            .push(&[&end[5..], b"$ ".as_slice()].concat())
+            .is_empty());
+        assert!(frame.is_done());
+        assert_eq!(frame.exit_code(), Some(3));
+        assert!(frame.push(b"later").is_empty());
+
+        let text = format!("x\r\nE{}:3$ ", frame.nonce);
+        assert_eq!(frame.end_in(&text), Some(text.len() - 2));
+        assert_eq!(frame.end_in("unrelated"), None);
+    }
+}
@@ -3,12 +3,17 @@
 //! This module provides types and utilities for managing shell sessions,
 //! including session identification, state tracking, and storage.
 
+mod cgroup;
 mod context;
 mod id;
+mod marker;
 mod state;
 mod store;
+mod terminal;
 
+pub use cgroup::{CgroupConfig, CgroupRoot, CgroupUsage, SessionCgroup};
 pub use context::{SessionContext, StateProbe};
 pub use id::SessionId;
 pub use state::SessionState;
-pub use store::{Session, SessionConfig, SessionStore};
+pub use store::{KilledProcesses, Session, SessionConfig, SessionStore};
+pub use terminal::{Expectation, Terminal, PENDING_LIMIT};
@@ -1,11 +1,14 @@
 //! Session storage and management.
 
 use std::collections::HashMap;
-use std::sync::RwLock;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
 use std::time::Instant;
 
-use super::{SessionContext, SessionId, SessionState};
+use super::{SessionCgroup, SessionContext, SessionId, SessionState, Terminal};
 use crate::error::ShellTunnelError;
+use crate::pty::{ResourceLimits, RunAs, Sandbox};
+use crate::security::{PolicyViolation, ValidationError, WorkdirJail};
 use crate::Result;
 
 /// Configuration for creating a new session.
@@ -17,6 +20,19 @@ pub struct SessionConfig {
     pub working_dir: Option<String>,
     /// Environment variables to set.
     pub env: HashMap<String, String>,
+    /// Name of the identity that created the session.
+    ///
+    /// `None` when the session was created without authentication.
+    pub owner: Option<String>,
+    /// Canonical directories the session's commands are confined to
+    /// (anywhere if empty).
+    pub root_dirs: Vec<PathBuf>,
+    /// Account the session's commands run as.
+    pub run_as: Option<RunAs>,
+    /// Resource limits of the session's commands.
+    pub limits: ResourceLimits,
+    /// Sandbox the session's commands run in.
+    pub sandbox: Option<Sandbox>,
 }
 
 /// A shell session.
@@ -34,6 +50,10 @@ pub struct Session {
     pub created_at: Instant,
     /// Time of last activity.
     pub last_activity: Instant,
+    /// cgroup the session's commands run in.
+    pub cgroup: Option<SessionCgroup>,
+    /// The session's interactive shell, once started.
+    pub terminal: Option<Arc<Terminal>>,
 }
 
 impl Session {
@@ -53,6 +73,8 @@ impl Session {
             context,
             created_at: now,
             last_activity: now,
+            cgroup: None,
+            terminal: None,
         }
     }
 
@@ -61,10 +83,58 @@ impl Session {
         self.last_activity = Instant::now();
     }
 
+    /// Get the name of the identity that owns this session.
+    pub fn owner(&self) -> Option<&str> {
+        self.config.owner.as_deref()
+    }
+
+    /// Get the jail the session's commands run in.
+    pub fn jail(&self) -> std::result::Result<WorkdirJail, ValidationError> {
+        WorkdirJail::new(&self.config.root_dirs)
+    }
+
     /// Get the idle duration since last activity.
     pub fn idle_duration(&self) -> std::time::Duration {
         self.last_activity.elapsed()
     }
+
+    /// Kill the session's terminal with every process it started, including
+    /// background jobs, and remove its cgroup.
+    ///
+    /// Blocks until the processes are gone or
+    /// [`KILL_GRACE`](crate::pty::KILL_GRACE) ran out.
+    pub fn kill_processes(&self) -> KilledProcesses {
+        let mut killed = KilledProcesses::default();
+        #[cfg(unix)]
+        if let Some(terminal) = &self.terminal {
+            let (killed_pids, leftover_pids) = terminal.kill_session();
+            killed.killed_pids.extend(killed_pids);
+            killed.leftover_pids.extend(leftover_pids);
+        }
+        if let Some(cgroup) = &self.cgroup {
+            killed.killed_pids.extend(cgroup.pids().unwrap_or_default());
+            if let Err(e) = cgroup.remove() {
+                tracing::warn!("Failed to remove cgroup of session {}: {}", self.id, e);
+                killed
+                    .leftover_pids
+                    .extend(cgroup.pids().unwrap_or_default());
+            }
+        }
+        for pids in [&mut killed.killed_pids, &mut killed.leftover_pids] {
+            pids.sort_unstable();
+            pids.dedup();
+        }
+        killed
+    }
+}
+
+/// Processes killed along with a session.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KilledProcesses {
+    /// Ids of the processes that were running.
+    pub killed_pids: Vec<u32>,
+    /// Ids of the processes that survived being killed.
+    pub leftover_pids: Vec<u32>,
 }
 
 impl Clone for Session {
@@ -76,6 +146,8 @@ impl Clone for Session {
             context: self.context.clone(),
             created_at: self.created_at,
             last_activity: self.last_activity,
+            cgroup: self.cgroup.clone(),
+            terminal: self.terminal.clone(),
         }
     }
 }
@@ -148,6 +220,78 @@ impl SessionStore {
         Ok(())
     }
 
+    /// Get a session's terminal, starting its shell on first use or after
+    /// it exited.
+    ///
+    /// Must be called within a tokio runtime.
+    pub fn terminal(&self, id: &SessionId) -> Result<Arc<Terminal>> {
+        let session = match self.get(id)? {
+            Some(session) => session,
+            None => return Err(ShellTunnelError::SessionNotFound(id.to_string())),
+        };
+        if let Some(terminal) = session.terminal.as_ref().filter(|t| !t.is_closed()) {
+            return Ok(Arc::clone(terminal));
+        }
+
+        // Starting a shell takes a while, so not while holding the lock
+        let terminal = Arc::new(Terminal::spawn(&session)?);
+
+        let mut sessions = self
+            .sessions
+            .write()
+            .map_err(|_| ShellTunnelError::LockPoisoned)?;
+        let session = sessions
+            .get_mut(id)
+            .ok_or_else(|| ShellTunnelError::SessionNotFound(id.to_string()))?;
+        match &session.terminal {
+            // Someone else started one meanwhile
+            Some(other) if !other.is_closed() => Ok(Arc::clone(other)),
+            _ => {
+                session.terminal = Some(Arc::clone(&terminal));
+                session.touch();
+                Ok(terminal)
+            }
+        }
+    }
+
+    /// Record the working directory a state probe found the shell in.
+    ///
+    /// Returns the policy violation if the shell has left the session's
+    /// root directories; the directory is recorded either way.
+    pub fn track_cwd(&self, id: &SessionId, cwd: &Path) -> Result<Option<PolicyViolation>> {
+        let mut violation = None;
+        self.update(id, |session| {
+            session.context.set_cwd(cwd);
+            if let Ok(jail) = session.jail() {
+                violation = jail.check(cwd).err();
+            }
+        })?;
+
+        if let Some(ref violation) = violation {
+            tracing::warn!("Policy violation in session {}: {}", id, violation);
+        }
+        Ok(violation)
+    }
+
+    /// Probe where the session's shell is, if it has been started, and
+    /// track it.
+    ///
+    /// Returns the policy violation if the shell moved outside the
+    /// session's root directories since it was last tracked.
+    pub fn probe_cwd(&self, id: &SessionId) -> Result<Option<PolicyViolation>> {
+        let Some(session) = self.get(id)? else {
+            return Ok(None);
+        };
+        let cwd = session
+            .terminal
+            .as_ref()
+            .and_then(|terminal| terminal.cwd());
+        match cwd {
+            Some(cwd) if session.context.cwd() != Some(&cwd) => self.track_cwd(id, &cwd),
+            _ => Ok(None),
+        }
+    }
+
     /// Remove a session from the store.
     ///
     /// Returns the removed session, or None if it didn't exist.
@@ -200,6 +344,7 @@ impl Default for SessionStore {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::time::Duration;
 
     #[test]
     fn test_create_session() {
@@ -244,6 +389,105 @@ mod tests {
         assert_eq!(session.state, SessionState::Active);
     }
 
+    #[test]
+    fn test_track_cwd() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(dir.path()).unwrap();
+        let store = SessionStore::new();
+        let id = store
+            .create(SessionConfig {
+                root_dirs: vec![root.clone()],
+                ..Default::default()
+            })
+            .unwrap();
+
+        assert!(store.track_cwd(&id, &root).unwrap().is_none());
+        let violation = store.track_cwd(&id, Path::new("/")).unwrap().unwrap();
+        assert_eq!(violation.cwd, PathBuf::from("/"));
+        assert_eq!(violation.roots, vec![root]);
+
+        let session = store.get(&id).unwrap().unwrap();
+        assert_eq!(session.context.cwd(), Some(&PathBuf::from("/")));
+    }
+
+    #[test]
+    #[ignore] // PTY tests need special handling
+    fn test_probe_cwd() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(dir.path()).unwrap();
+        let store = SessionStore::new();
+        let id = store
+            .create(SessionConfig {
+                root_dirs: vec![root.clone()],
+                ..Default::default()
+            })
+            .unwrap();
+        store.update(&id, |s| s.context.set_cwd(&root)).unwrap();
+
+        // Nothing to probe until the shell is started
+        assert!(store.probe_cwd(&id).unwrap().is_none());
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let terminal = store.terminal(&id).unwrap();
+            assert!(store.probe_cwd(&id).unwrap().is_none());
+
+            let moved = regex::Regex::new("moved").unwrap();
+            terminal.write(b"cd / && echo mo''ved\n").await.unwrap();
+            terminal
+                .expect(&moved, Duration::from_secs(5))
+                .await
+                .unwrap();
+            let violation = store.probe_cwd(&id).unwrap().unwrap();
+            assert_eq!(violation.cwd, PathBuf::from("/"));
+
+            // Reported once per move
+            assert!(store.probe_cwd(&id).unwrap().is_none());
+            terminal.write(b"exit\n").await.unwrap();
+        });
+    }
+
+    #[test]
+    #[ignore] // PTY tests need special handling
+    fn test_terminal_started_once() {
+        let store = Arc::new(SessionStore::new());
+        let id = store
+            .create(SessionConfig {
+                shell: Some("/bin/sh".to_string()),
+                env: HashMap::from([("GREETING".to_string(), "hello".to_string())]),
+                ..Default::default()
+            })
+            .unwrap();
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let starts: Vec<_> = (0..4)
+                .map(|_| {
+                    let store = Arc::clone(&store);
+                    tokio::spawn(async move { store.terminal(&id).unwrap() })
+                })
+                .collect();
+            let mut terminals = Vec::new();
+            for start in starts {
+                terminals.push(start.await.unwrap());
+            }
+            assert!(terminals.iter().all(|t| Arc::ptr_eq(t, &terminals[0])));
+
+            // The shell has the session's environment
+            let greeting = regex::Regex::new("hello world").unwrap();
+            terminals[0]
+                .write(b"echo \"$GREETING\" world\n")
+                .await
+                .unwrap();
+            let expectation = terminals[0]
+                .expect(&greeting, Duration::from_secs(5))
+                .await
+                .unwrap();
+            assert!(!expectation.timed_out);
+            terminals[0].write(b"exit\n").await.unwrap();
+        });
+    }
+
     #[test]
     fn test_update_nonexistent() {
         let store = SessionStore::new();
@@ -0,0 +1,649 @@
+//! Interactive terminals of sessions.
+//!
+//! One-shot commands each get a terminal of their own that goes away when
+//! the command ends. A session's terminal instead holds a long-lived shell,
+//! started on first use, that runs the session's commands as if typed at its
+//! prompt, with their output and exit status told apart from the rest of the
+//! terminal by markers the shell prints around them. Clients can also drive
+//! it by writing input and waiting for output to appear, the way `expect`
+//! drives interactive programs, and people can attach to it to watch its
+//! raw output or take over.
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use regex::{Captures, Regex};
+use tokio::sync::{broadcast, mpsc, Notify};
+
+use super::marker::Frame;
+use super::{Session, StateProbe};
+use crate::error::ShellTunnelError;
+use crate::execution::{
+    record, sleep_until, CancelHandle, Command, ExecutionResult, OutputChunk, DEFAULT_TIMEOUT,
+};
+use crate::output::{AnsiStripper, OutputSanitizer, VirtualScreen};
+use crate::pty::{
+    default_shell, AsyncMaster, ExitStatus, NativePty, ProcessGroup, PtySize, ResourceLimits,
+    SpawnOptions, SpawnedShell, KILL_GRACE,
+};
+use crate::Result;
+
+/// Most bytes of unmatched output kept; older output is dropped.
+pub const PENDING_LIMIT: usize = 1 << 20;
+
+/// Chunks of raw output held for an attached client that falls behind.
+const ATTACH_BACKLOG: usize = 1024;
+
+/// Builds the result of a command that was stopped, such as
+/// [`ExecutionResult::timeout`].
+type StopResult = fn(Vec<u8>, String, Duration) -> ExecutionResult;
+
+/// A long-lived shell on a terminal of its own.
+pub struct Terminal {
+    shared: Arc<Shared>,
+    writer: Arc<Mutex<Box<dyn Write + Send>>>,
+    /// Limits the shell was started with.
+    limits: ResourceLimits,
+    /// Held while a command runs, so that commands take turns.
+    running: tokio::sync::Mutex<()>,
+}
+
+/// State shared with the task reading the terminal.
+struct Shared {
+    shell: Mutex<SpawnedShell>,
+    output: Mutex<Output>,
+    /// Notified whenever output arrives or the terminal closes.
+    changed: Notify,
+    /// Raw output for attached clients; an empty chunk marks the terminal
+    /// closing.
+    raw: broadcast::Sender<Vec<u8>>,
+}
+
+struct Output {
+    /// Output not consumed by a match yet, without ANSI codes.
+    pending: String,
+    stripper: AnsiStripper,
+    screen: VirtualScreen,
+    /// Whether every process closed the terminal.
+    closed: bool,
+    /// Receives raw output while a command runs.
+    tap: Option<mpsc::UnboundedSender<Vec<u8>>>,
+}
+
+/// Outcome of waiting for a pattern.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Expectation {
+    /// Output up to the end of the match, or all pending output if the
+    /// pattern did not match.
+    pub output: String,
+    /// The text the pattern matched.
+    pub matched: Option<String>,
+    /// The pattern's capture groups, `None` for groups that took no part.
+    pub groups: Vec<Option<String>>,
+    /// Named capture groups that took part.
+    pub named: HashMap<String, String>,
+    /// Whether the timeout ran out first.
+    pub timed_out: bool,
+    /// Whether the terminal closed first.
+    pub closed: bool,
+}
+
+impl Expectation {
+    fn from_captures(pattern: &Regex, captures: &Captures<'_>) -> Self {
+        let text = |m: regex::Match<'_>| m.as_str().to_string();
+        Self {
+            matched: captures.get(0).map(text),
+            groups: captures.iter().skip(1).map(|m| m.map(text)).collect(),
+            named: pattern
+                .capture_names()
+                .flatten()
+                .filter_map(|name| Some((name.to_string(), text(captures.name(name)?))))
+                .collect(),
+            ..Self::default()
+        }
+    }
+}
+
+impl Terminal {
+    /// Start the session's shell, confined like the session's commands.
+    ///
+    /// Must be called within a tokio runtime.
+    pub fn spawn(session: &Session) -> Result<Self> {
+        let options = SpawnOptions::default()
+            .with_run_as(session.config.run_as.clone())
+            .with_limits(session.config.limits)
+            .with_sandbox(session.config.sandbox.clone())
+            .with_cgroup(session.cgroup.as_ref().map(|c| c.path().to_path_buf()))
+            .with_env(session.config.env.clone());
+        let program = session.config.shell.as_deref().unwrap_or(default_shell());
+        let size = PtySize::default();
+        let mut shell = NativePty::new().with_options(options).spawn_interactive(
+            program,
+            session.context.cwd().map(PathBuf::as_path),
+            size,
+        )?;
+
+        let started = shell
+            .take_writer()
+            .and_then(|writer| Ok((writer, shell.async_master()?)));
+        let (writer, master) = match started {
+            Ok(started) => started,
+            Err(e) => {
+                let _ = shell.kill();
+                let _ = shell.wait();
+                return Err(e);
+            }
+        };
+
+        let shared = Arc::new(Shared {
+            shell: Mutex::new(shell),
+            output: Mutex::new(Output {
+                pending: String::new(),
+                stripper: AnsiStripper::new(),
+                screen: VirtualScreen::with_size(size.cols, size.rows),
+                closed: false,
+                tap: None,
+            }),
+            changed: Notify::new(),
+            raw: broadcast::channel(ATTACH_BACKLOG).0,
+        });
+        tokio::spawn(pump(master, Arc::clone(&shared)));
+
+        Ok(Self {
+            shared,
+            writer: Arc::new(Mutex::new(writer)),
+            limits: session.config.limits,
+            running: tokio::sync::Mutex::new(()),
+        })
+    }
+
+    /// Write input to the shell.
+    pub async fn write(&self, input: &[u8]) -> Result<()> {
+        let writer = Arc::clone(&self.writer);
+        let input = input.to_vec();
+        tokio::task::spawn_blocking(move || {
+            let mut writer = writer.lock().map_err(|_| ShellTunnelError::LockPoisoned)?;
+            writer.write_all(&input)?;
+            writer.flush()?;
+            Ok(())
+        })
+        .await
+        .map_err(|e| ShellTunnelError::Pty(e.to_string()))?
+    }
+
+    /// Wait up to `timeout` for output matching `pattern`.
+    ///
+    /// Output up to the end of the match is consumed, so the next call
+    /// only sees what came after it. Output is not consumed if the pattern
+    /// did not match in time.
+    pub async fn expect(&self, pattern: &Regex, timeout: Duration) -> Result<Expectation> {
+        // A deadline too far out to represent is no deadline
+        let deadline = tokio::time::Instant::now().checked_add(timeout);
+        loop {
+            // Registered before checking, so no output can slip through
+            let changed = self.shared.changed.notified();
+            {
+                let mut output = self.shared.output()?;
+                if let Some(captures) = pattern.captures(&output.pending) {
+                    let end = captures.get(0).map_or(0, |m| m.end());
+                    let mut expectation = Expectation::from_captures(pattern, &captures);
+                    expectation.output = output.pending.drain(..end).collect();
+                    return Ok(expectation);
+                }
+                if output.closed {
+                    return Ok(Expectation {
+                        output: std::mem::take(&mut output.pending),
+                        closed: true,
+                        ..Expectation::default()
+                    });
+                }
+            }
+            let timed_out = match deadline {
+                Some(deadline) => tokio::time::timeout_at(deadline, changed).await.is_err(),
+                None => {
+                    changed.await;
+                    false
+                }
+            };
+            if timed_out {
+                return Ok(Expectation {
+                    output: self.shared.output()?.pending.clone(),
+                    timed_out: true,
+                    ..Expectation::default()
+                });
+            }
+        }
+    }
+
+    /// Run a command in the shell as if typed at its prompt.
+    ///
+    /// Only the command's own output is collected and forwarded to `tx`,
+    /// and its exit code is the shell's `$?` after it. If the command exits
+    /// the shell, the shell's exit status is reported instead. Commands take
+    /// turns, and are refused while a program the shell started is in the
+    /// foreground, such as one driven with [`expect`](Self::expect).
+    ///
+    /// On timeout, idle timeout, output limit or cancellation the command is
+    /// interrupted like with Ctrl-C, and its job is killed if it does not
+    /// stop.
+    pub async fn run(
+        &self,
+        command: &Command,
+        tx: Option<&mpsc::Sender<OutputChunk>>,
+        cancel: &CancelHandle,
+    ) -> Result<ExecutionResult> {
+        let (mut frame, line) = Frame::new(command, &self.limits)?;
+        let _turn = self.running.lock().await;
+        if self.foreground_job().is_some() {
+            return Err(ShellTunnelError::ExecutionFailed(
+                "the terminal is busy with another program".to_string(),
+            ));
+        }
+
+        let start = Instant::now();
+        let (tap, mut output) = mpsc::unbounded_channel();
+        {
+            let mut output = self.shared.output()?;
+            if output.closed {
+                return Err(ShellTunnelError::SessionTerminated);
+            }
+            output.tap = Some(tap);
+        }
+        let written = self.write(line.as_bytes()).await;
+
+        let deadline = start
+            .checked_add(command.timeout.unwrap_or(DEFAULT_TIMEOUT))
+            .map(tokio::time::Instant::from_std);
+        let idle_after =
+            |now: tokio::time::Instant| command.idle_timeout.and_then(|idle| now.checked_add(idle));
+        let mut idle_until = idle_after(tokio::time::Instant::now());
+        let mut raw_output = Vec::new();
+        // How the command was stopped, if it was
+        let stopped: Option<StopResult> = loop {
+            if written.is_err() {
+                break None;
+            }
+            tokio::select! {
+                _ = cancel.cancelled() => break Some(ExecutionResult::cancelled),
+                _ = sleep_until(deadline) => break Some(ExecutionResult::timeout),
+                _ = sleep_until(idle_until) => break Some(ExecutionResult::idle_timeout),
+                chunk = output.recv() => {
+                    // The terminal closed
+                    let Some(chunk) = chunk else { break None };
+                    let data = frame.push(&chunk);
+                    if !data.is_empty() {
+                        idle_until = idle_after(tokio::time::Instant::now());
+                        let room = command
+                            .max_output
+                            .map_or(data.len(), |max| max - raw_output.len());
+                        record(&data[..data.len().min(room)], &mut raw_output, tx).await;
+                        if data.len() > room {
+                            break Some(ExecutionResult::output_limit);
+                        }
+                    }
+                    if frame.is_done() {
+                        break None;
+                    }
+                }
+            }
+        };
+
+        let leftover_pids = match stopped {
+            Some(_) => self.interrupt().await,
+            None => Vec::new(),
+        };
+        {
+            // The command's output is not left for expect to match
+            let mut output = self.shared.output()?;
+            output.tap = None;
+            if let Some(end) = frame.end_in(&output.pending) {
+                output.pending.drain(..end);
+            }
+        }
+        written?;
+
+        let text = OutputSanitizer::strip_ansi(&raw_output);
+        let result = match stopped {
+            Some(make) => make(raw_output, text, start.elapsed()).with_leftover_pids(leftover_pids),
+            None => {
+                let result = ExecutionResult::new(raw_output, text, start.elapsed());
+                match frame.exit_code() {
+                    Some(code) => result.with_exit_code(code),
+                    None => match self.exit_status().await {
+                        Some(status) => result.with_exit_status(status),
+                        None => result,
+                    },
+                }
+            }
+        };
+        Ok(result)
+    }
+
+    /// The job in the foreground of the terminal, unless the shell itself
+    /// is.
+    fn foreground_job(&self) -> Option<ProcessGroup> {
+        #[cfg(unix)]
+        {
+            let shell = self.shared.shell.lock().ok()?;
+            let foreground = shell.foreground_group()?;
+            (Some(foreground) != shell.process_group()).then_some(foreground)
+        }
+        #[cfg(not(unix))]
+        None
+    }
+
+    /// Interrupt the running command like Ctrl-C does, killing its job if
+    /// it is still in the foreground after [`KILL_GRACE`].
+    ///
+    /// Returns the ids of the job's processes that survived.
+    async fn interrupt(&self) -> Vec<u32> {
+        const INTERRUPT: &[u8] = b"\x03";
+        let _ = self.write(INTERRUPT).await;
+        #[cfg(unix)]
+        {
+            let deadline = Instant::now() + KILL_GRACE;
+            while let Some(job) = self.foreground_job() {
+                if Instant::now() >= deadline {
+                    let leftover_pids = tokio::task::spawn_blocking(move || job.kill())
+                        .await
+                        .unwrap_or_default();
+                    // Keep the shell from going on with the rest of the line
+                    let _ = self.write(INTERRUPT).await;
+                    return leftover_pids;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }
+        Vec::new()
+    }
+
+    /// The exit status of the shell, once the terminal closed.
+    async fn exit_status(&self) -> Option<ExitStatus> {
+        let shared = Arc::clone(&self.shared);
+        tokio::task::spawn_blocking(move || {
+            let mut shell = shared.shell.lock().ok()?;
+            // Jobs left behind could not be told apart once it is reaped
+            #[cfg(unix)]
+            kill_session(&shell);
+            let _ = shell.kill();
+            shell.wait().ok()
+        })
+        .await
+        .ok()
+        .flatten()
+    }
+
+    /// Attach to the terminal's raw output.
+    ///
+    /// Returns escape codes that redraw the screen as it looks now, followed
+    /// on the receiver by all output after it. The receiver gets an empty
+    /// chunk once the terminal closes. A receiver that lagged behind can
+    /// attach again to catch up.
+    pub fn attach(&self) -> Result<(Vec<u8>, broadcast::Receiver<Vec<u8>>)> {
+        // Holding the lock keeps output from arriving in between
+        let output = self.shared.output()?;
+        Ok((output.screen.formatted(), self.shared.raw.subscribe()))
+    }
+
+    /// Change the size of the terminal.
+    pub fn resize(&self, size: PtySize) -> Result<()> {
+        self.shared
+            .shell
+            .lock()
+            .map_err(|_| ShellTunnelError::LockPoisoned)?
+            .resize(size)?;
+        self.shared.output()?.screen.resize(size.cols, size.rows);
+        Ok(())
+    }
+
+    /// The screen as it looks now.
+    pub fn screen(&self) -> Result<String> {
+        Ok(self.shared.output()?.screen.contents())
+    }
+
+    /// Whether every process closed the terminal.
+    pub fn is_closed(&self) -> bool {
+        self.shared.output().map_or(true, |output| output.closed)
+    }
+
+    /// The shell's working directory, if it can be read.
+    pub fn cwd(&self) -> Option<PathBuf> {
+        StateProbe::process_cwd(self.process_group()?.id())
+    }
+
+    /// The process group of the shell and the programs it starts, until
+    /// the shell was reaped.
+    pub fn process_group(&self) -> Option<ProcessGroup> {
+        self.shared.shell.lock().ok()?.process_group()
+    }
+
+    /// Kill the shell with every process in its terminal session, including
+    /// jobs in process groups of their own.
+    ///
+    /// Returns the ids of the processes that were running and of those that
+    /// survived [`KILL_GRACE`].
+    #[cfg(unix)]
+    pub fn kill_session(&self) -> (Vec<u32>, Vec<u32>) {
+        match self.shared.shell.lock() {
+            Ok(shell) => kill_session(&shell),
+            Err(_) => Default::default(),
+        }
+    }
+}
+
+/// Kill every process in the shell's terminal session, unless the shell was
+/// reaped and its session id is free to be reused.
+///
+/// Holding the shell keeps it from being reaped meanwhile.
+#[cfg(unix)]
+fn kill_session(shell: &SpawnedShell) -> (Vec<u32>, Vec<u32>) {
+    match shell.process_group() {
+        Some(group) => (group.session_members(), group.kill_session()),
+        None => Default::default(),
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        if let Ok(mut shell) = self.shared.shell.lock() {
+            if shell.kill().is_ok() {
+                // Reap it so it does not linger as a zombie
+                let _ = shell.wait();
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for Terminal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Terminal")
+            .field("group", &self.process_group())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Shared {
+    fn output(&self) -> Result<std::sync::MutexGuard<'_, Output>> {
+        self.output
+            .lock()
+            .map_err(|_| ShellTunnelError::LockPoisoned)
+    }
+
+    fn push(&self, data: &[u8]) {
+        if let Ok(mut output) = self.output() {
+            let output = &mut *output;
+            output.screen.process(data);
+            output.pending.push_str(&output.stripper.push(data));
+            if self.raw.receiver_count() > 0 {
+                let _ = self.raw.send(data.to_vec());
+            }
+            if let Some(tap) = &output.tap {
+                let _ = tap.send(data.to_vec());
+            }
+            if output.pending.len() > PENDING_LIMIT {
+                let mut cut = output.pending.len() - PENDING_LIMIT;
+                while !output.pending.is_char_boundary(cut) {
+                    cut += 1;
+                }
+                output.pending.drain(..cut);
+            }
+        }
+        self.changed.notify_waiters();
+    }
+
+    fn close(&self) {
+        if let Ok(mut output) = self.output() {
+            output.closed = true;
+            output.tap = None;
+            let _ = self.raw.send(Vec::new());
+        }
+        self.changed.notify_waiters();
+    }
+}
+
+/// Feed the terminal's output to `shared` until it closes, then reap the
+/// shell.
+async fn pump(mut master: AsyncMaster, shared: Arc<Shared>) {
+    let mut buf = [0u8; 4096];
+    loop {
+        match master.read(&mut buf).await {
+            Ok(0) => break,
+            Ok(n) => shared.push(&buf[..n]),
+            Err(e) => {
+                tracing::warn!("Failed to read session terminal: {}", e);
+                break;
+            }
+        }
+    }
+    shared.close();
+
+    let _ = tokio::task::spawn_blocking(move || {
+        if let Ok(mut shell) = shared.shell.lock() {
+            let _ = shell.kill();
+            let _ = shell.wait();
+        }
+    })
+    .await;
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::session::{SessionConfig, SessionId};
+
+    fn session() -> Session {
+        Session::new(
+            SessionId::from_raw(1),
+            SessionConfig {
+                shell: Some("/bin/sh".to_string()),
+                ..Default::default()
+            },
+        )
+    }
+
+    #[test]
+    fn test_expectation_from_captures() {
+        let pattern = Regex::new(r"(?P<key>\w+)=(\d+)?").unwrap();
+        let captures = pattern.captures("x key= y").unwrap();
+        let expectation = Expectation::from_captures(&pattern, &captures);
+        assert_eq!(expectation.matched.as_deref(), Some("key="));
+        assert_eq!(expectation.groups, vec![Some("key".to_string()), None]);
+        assert_eq!(expectation.named["key"], "key");
+    }
+
+    #[tokio::test]
+    #[ignore] // PTY tests need special handling
+    async fn test_expect() {
+        let terminal = Terminal::spawn(&session()).unwrap();
+        terminal
+            .write(b"printf 'name: '; read name; echo \"hi $name\"\n")
+            .await
+            .unwrap();
+
+        let name = Regex::new(r"name: $").unwrap();
+        let expectation = terminal
+            .expect(&name, Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert_eq!(expectation.matched.as_deref(), Some("name: "));
+
+        terminal.write(b"world\n").await.unwrap();
+        let greeting = Regex::new(r"hi (\w+)").unwrap();
+        let expectation = terminal
+            .expect(&greeting, Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert_eq!(expectation.groups, vec![Some("world".to_string())]);
+        // Output before the match was consumed along with it
+        assert!(!expectation.output.contains("name: "));
+        assert!(terminal.screen().unwrap().contains("hi world"));
+
+        let never = Regex::new("never printed").unwrap();
+        let expectation = terminal
+            .expect(&never, Duration::from_millis(200))
+            .await
+            .unwrap();
+        assert!(expectation.timed_out);
+
+        // Attaching redraws the screen, then streams what follows
+        terminal.resize(PtySize::new(30, 100)).unwrap();
+        let (redraw, mut output) = terminal.attach().unwrap();
+        let mut screen = VirtualScreen::with_size(100, 30);
+        screen.process(&redraw);
+        assert!(screen.contents().contains("hi world"));
+        terminal.write(b"stty size\n").await.unwrap();
+        let size = Regex::new("30 100").unwrap();
+        // A timeout too long to represent waits without a deadline
+        terminal.expect(&size, Duration::MAX).await.unwrap();
+        assert!(!output.recv().await.unwrap().is_empty());
+
+        terminal.write(b"exit\n").await.unwrap();
+        let expectation = terminal
+            .expect(&never, Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert!(expectation.closed);
+        assert!(terminal.is_closed());
+        while !output.recv().await.unwrap().is_empty() {}
+    }
+
+    #[tokio::test]
+    #[ignore] // PTY tests need special handling
+    async fn test_run_reports_exit_codes() {
+        let terminal = Terminal::spawn(&session()).unwrap();
+        let cancel = CancelHandle::new(None);
+        let run = |command: Command| async {
+            let command = command.timeout(Duration::from_secs(10));
+            terminal.run(&command, None, &cancel).await.unwrap()
+        };
+
+        let result = run(Command::new("false; true")).await;
+        assert_eq!(result.exit_code, Some(0));
+        assert_eq!(result.output_trimmed(), "");
+        let result = run(Command::new("(exit 3)")).await;
+        assert_eq!(result.exit_code, Some(3));
+
+        // Only the command's output is collected, and state carries over
+        let result = run(Command::new("cd /tmp; x=1; echo hi")).await;
+        assert_eq!(result.output_trimmed(), "hi");
+        let result = run(Command::new("echo \"$x\" && pwd")).await;
+        assert_eq!(result.output_lines().collect::<Vec<_>>(), ["1", "/tmp"]);
+        let result = run(Command::new("echo \"$x\"").env("x", "2")).await;
+        assert_eq!(result.output_trimmed(), "2");
+
+        // A stopped command does not run on
+        let slow = Command::new("sleep 30; echo after").timeout(Duration::from_millis(300));
+        let result = terminal.run(&slow, None, &cancel).await.unwrap();
+        assert!(result.timed_out);
+        let result = run(Command::new("echo ok")).await;
+        assert_eq!(result.output_trimmed(), "ok");
+        assert!(result.success());
+
+        let result = run(Command::new("exit 7")).await;
+        assert_eq!(result.exit_code, Some(7));
+        assert!(terminal.is_closed());
+    }
+}
@@ -0,0 +1,297 @@
+//! TLS integration tests.
+//!
+//! These tests run the API router behind a `TlsListener` and talk to it with
+//! a rustls client using the test CA in `tests/fixtures/tls`. Client
+//! certificates are signed by the separate client CA in the same directory.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use axum::Router;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use serde_json::Value;
+use shell_tunnel::api::{
+    create_router, create_secure_router, AppState, SecurityConfig, TlsConfig, TlsListener,
+    TlsReloader,
+};
+use shell_tunnel::security::{Identity, PeerInfo};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::TlsConnector;
+
+fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/tls")
+        .join(name)
+}
+
+fn server_tls_config() -> TlsConfig {
+    TlsConfig::new(fixture("server.pem"), fixture("server.key"))
+}
+
+fn client_connector() -> TlsConnector {
+    connector_with_cert(None)
+}
+
+/// Build a client, optionally presenting the given certificate and key fixtures.
+fn connector_with_cert(client_cert: Option<(&str, &str)>) -> TlsConnector {
+    let mut roots = rustls::RootCertStore::empty();
+    roots
+        .add(CertificateDer::from_pem_file(fixture("ca.pem")).unwrap())
+        .unwrap();
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = rustls::ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots);
+
+    let config = match client_cert {
+        Some((cert, key)) => builder
+            .with_client_auth_cert(
+                vec![CertificateDer::from_pem_file(fixture(cert)).unwrap()],
+                PrivateKeyDer::from_pem_file(fixture(key)).unwrap(),
+            )
+            .unwrap(),
+        None => builder.with_no_client_auth(),
+    };
+
+    TlsConnector::from(Arc::new(config))
+}
+
+/// Start the router behind TLS on an ephemeral port.
+async fn start_tls_server(tls: Arc<TlsReloader>) -> std::net::SocketAddr {
+    serve_router(tls, create_router()).await
+}
+
+async fn serve_router(tls: Arc<TlsReloader>, router: Router) -> std::net::SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let listener = TlsListener::new(listener, tls).unwrap();
+
+    let service = router.into_make_service_with_connect_info::<PeerInfo>();
+    tokio::spawn(async move {
+        axum::serve(listener, service).await.unwrap();
+    });
+
+    addr
+}
+
+/// Start a secured router that accepts `test-key` and client certificates.
+async fn start_mtls_server(identities: Vec<Identity>) -> std::net::SocketAddr {
+    let tls = server_tls_config().with_client_ca(fixture("client-ca.pem"));
+    let tls = Arc::new(TlsReloader::new(tls).unwrap());
+
+    let mut security = SecurityConfig::secure().with_api_key("test-key");
+    for identity in identities {
+        security = security.with_client_identity(identity);
+    }
+    let (router, _, _) = create_secure_router(AppState::new(), security);
+
+    serve_router(tls, router).await
+}
+
+async fn https_get(addr: std::net::SocketAddr, path: &str) -> String {
+    https_request(addr, &client_connector(), "GET", path, None).await
+}
+
+async fn https_request(
+    addr: std::net::SocketAddr,
+    connector: &TlsConnector,
+    method: &str,
+    path: &str,
+    api_key: Option<&str>,
+) -> String {
+    let tcp = TcpStream::connect(addr).await.unwrap();
+    let server_name = ServerName::try_from("localhost").unwrap();
+    let mut tls = connector.connect(server_name, tcp).await.unwrap();
+
+    let auth = api_key
+        .map(|key| format!("Authorization: Bearer {}\r\n", key))
+        .unwrap_or_default();
+    let body = if method == "POST" { "{}" } else { "" };
+    let request = format!(
+        "{} {} HTTP/1.1\r\nHost: localhost\r\n{}Content-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        method,
+        path,
+        auth,
+        body.len(),
+        body
+    );
+    tls.write_all(request.as_bytes()).await.unwrap();
+
+    let mut response = Vec::new();
+    tls.read_to_end(&mut response).await.unwrap();
+    String::from_utf8_lossy(&response).to_string()
+}
+
+/// Parse the JSON body of a `Connection: close` response.
+fn response_json(response: &str) -> Value {
+    let body = response.split("\r\n\r\n").nth(1).unwrap_or("");
+    serde_json::from_str(body).unwrap_or(Value::Null)
+}
+
+#[tokio::test]
+async fn test_https_health() {
+    let tls = Arc::new(TlsReloader::new(server_tls_config()).unwrap());
+    let addr = start_tls_server(tls).await;
+
+    let response = https_get(addr, "/health").await;
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    assert!(response.ends_with("OK"));
+}
+
+#[tokio::test]
+async fn test_plaintext_rejected() {
+    let tls = Arc::new(TlsReloader::new(server_tls_config()).unwrap());
+    let addr = start_tls_server(tls).await;
+
+    let mut tcp = TcpStream::connect(addr).await.unwrap();
+    tcp.write_all(b"GET /health HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await
+        .unwrap();
+
+    let mut response = Vec::new();
+    let _ = tcp.read_to_end(&mut response).await;
+    assert!(!String::from_utf8_lossy(&response).contains("200 OK"));
+}
+
+#[tokio::test]
+async fn test_reload_keeps_serving() {
+    let dir = tempfile::tempdir().unwrap();
+    let cert = dir.path().join("cert.pem");
+    let key = dir.path().join("key.pem");
+    std::fs::copy(fixture("server.pem"), &cert).unwrap();
+    std::fs::copy(fixture("server.key"), &key).unwrap();
+
+    let tls = Arc::new(TlsReloader::new(TlsConfig::new(&cert, &key)).unwrap());
+    let addr = start_tls_server(Arc::clone(&tls)).await;
+
+    // A broken certificate on disk must not replace the active one
+    std::fs::write(&cert, "garbage").unwrap();
+    assert!(tls.reload().is_err());
+    assert!(https_get(addr, "/health").await.ends_with("OK"));
+
+    // Restoring it reloads cleanly
+    std::fs::copy(fixture("server.pem"), &cert).unwrap();
+    tls.reload().unwrap();
+    assert!(https_get(addr, "/health").await.ends_with("OK"));
+}
+
+#[tokio::test]
+async fn test_client_cert_authenticates() {
+    let addr = start_mtls_server(Vec::new()).await;
+    let agent = connector_with_cert(Some(("client.pem", "client.key")));
+
+    let response = https_request(addr, &agent, "GET", "/api/v1/sessions", None).await;
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+
+    // Bearer keys still work on the same listener
+    let response = https_request(addr, &client_connector(), "GET", "/api/v1/sessions", None).await;
+    assert!(response.starts_with("HTTP/1.1 401"), "{}", response);
+    let response = https_request(
+        addr,
+        &client_connector(),
+        "GET",
+        "/api/v1/sessions",
+        Some("test-key"),
+    )
+    .await;
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+}
+
+#[tokio::test]
+async fn test_client_cert_from_untrusted_ca_rejected() {
+    let addr = start_mtls_server(Vec::new()).await;
+
+    // The server certificate is signed by the server CA, not the client CA
+    let stranger = connector_with_cert(Some(("server.pem", "server.key")));
+    let tcp = TcpStream::connect(addr).await.unwrap();
+    let server_name = ServerName::try_from("localhost").unwrap();
+    let result = async {
+        let mut tls = stranger.connect(server_name, tcp).await?;
+        tls.write_all(b"GET /api/v1/sessions HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await?;
+        let mut response = Vec::new();
+        tls.read_to_end(&mut response).await?;
+        Ok::<_, std::io::Error>(response)
+    }
+    .await;
+
+    if let Ok(response) = result {
+        assert!(!String::from_utf8_lossy(&response).contains("200 OK"));
+    }
+}
+
+#[tokio::test]
+async fn test_client_identity_mapping() {
+    let addr = start_mtls_server(vec![Identity::new("ops", ["*"])]).await;
+    let agent = connector_with_cert(Some(("client.pem", "client.key")));
+
+    // The client certificate's names are not mapped to any identity
+    let response = https_request(addr, &agent, "GET", "/api/v1/sessions", None).await;
+    assert!(response.starts_with("HTTP/1.1 401"), "{}", response);
+}
+
+#[tokio::test]
+async fn test_client_cert_scopes() {
+    let addr = start_mtls_server(vec![Identity::new("agent", ["read"])]).await;
+    let agent = connector_with_cert(Some(("client.pem", "client.key")));
+
+    let response = https_request(addr, &agent, "GET", "/api/v1/sessions", None).await;
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+
+    let response = https_request(addr, &agent, "POST", "/api/v1/sessions", None).await;
+    assert!(response.starts_with("HTTP/1.1 403"), "{}", response);
+    assert_eq!(response_json(&response)["code"], "FORBIDDEN");
+}
+
+#[tokio::test]
+async fn test_client_cert_session_ownership() {
+    let addr = start_mtls_server(vec![Identity::new(
+        "spiffe://build/agent",
+        ["read", "execute"],
+    )])
+    .await;
+    let agent = connector_with_cert(Some(("client.pem", "client.key")));
+    let admin = client_connector();
+
+    // Session created by the certificate identity is owned by it
+    let response = https_request(addr, &agent, "POST", "/api/v1/sessions", None).await;
+    assert!(response.starts_with("HTTP/1.1 201"), "{}", response);
+    let own_id = response_json(&response)["session_id"].as_u64().unwrap();
+
+    // Session created with the API key is invisible to the agent
+    let response = https_request(addr, &admin, "POST", "/api/v1/sessions", Some("test-key")).await;
+    let other_id = response_json(&response)["session_id"].as_u64().unwrap();
+
+    let response = https_request(
+        addr,
+        &agent,
+        "GET",
+        &format!("/api/v1/sessions/{}", own_id),
+        None,
+    )
+    .await;
+    assert_eq!(response_json(&response)["owner"], "spiffe://build/agent");
+
+    let response = https_request(
+        addr,
+        &agent,
+        "GET",
+        &format!("/api/v1/sessions/{}", other_id),
+        None,
+    )
+    .await;
+    assert!(response.starts_with("HTTP/1.1 404"), "{}", response);
+
+    let response = https_request(addr, &agent, "GET", "/api/v1/sessions", None).await;
+    let listed = response_json(&response);
+    assert_eq!(listed["count"], 1);
+    assert_eq!(listed["sessions"][0]["session_id"], own_id);
+
+    // API keys have full access and see both sessions
+    let response = https_request(addr, &admin, "GET", "/api/v1/sessions", Some("test-key")).await;
+    assert_eq!(response_json(&response)["count"], 2);
+}
@@ -0,0 +1,68 @@
+//! Unix domain socket integration tests.
+//!
+//! These tests serve the secured router on a Unix socket in a temporary
+//! directory and talk raw HTTP/1.1 over it.
+
+#![cfg(unix)]
+
+use std::path::Path;
+
+use shell_tunnel::api::{create_secure_router, AppState, SecurityConfig, UnixSocketConfig};
+use shell_tunnel::security::{PeerInfo, RateLimitConfig};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UnixStream;
+
+/// Start the secured router on a Unix socket at `path`.
+fn start_unix_server(path: &Path, security: SecurityConfig) {
+    let listener = UnixSocketConfig::new(path).bind().unwrap();
+    let (router, _, _) = create_secure_router(AppState::new(), security);
+
+    let service = router.into_make_service_with_connect_info::<PeerInfo>();
+    tokio::spawn(async move {
+        axum::serve(listener, service).await.unwrap();
+    });
+}
+
+async fn unix_get(path: &Path, uri: &str) -> String {
+    let mut stream = UnixStream::connect(path).await.unwrap();
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        uri
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await.unwrap();
+    String::from_utf8_lossy(&response).to_string()
+}
+
+#[tokio::test]
+async fn test_unix_socket_serves_api() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("api.sock");
+    start_unix_server(&path, SecurityConfig::development());
+
+    let response = unix_get(&path, "/health").await;
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+
+    let response = unix_get(&path, "/api/v1/sessions").await;
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+}
+
+#[tokio::test]
+async fn test_unix_socket_rate_limited_by_uid() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("api.sock");
+
+    let mut security = SecurityConfig::development();
+    security.rate_limit = RateLimitConfig::custom(2, 60);
+    start_unix_server(&path, security);
+
+    for _ in 0..2 {
+        let response = unix_get(&path, "/api/v1").await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    }
+
+    let response = unix_get(&path, "/api/v1").await;
+    assert!(response.starts_with("HTTP/1.1 429"), "{}", response);
+}